@@ -4,6 +4,7 @@ use super::ring_buffer::RingBuffer;
 use super::filters;
 use super::filters::DspFilter;
 
+#[derive(Clone)]
 pub struct DmcState {
     pub name: String,
     pub chip: String,
@@ -5,8 +5,11 @@ use std::f32::consts::PI;
 pub trait DspFilter: Send {
     fn consume(&mut self, sample: f32);
     fn output(&self) -> f32;
+    // Boxed filters can't derive Clone, so each filter clones itself for savestates
+    fn clone_filter(&self) -> Box<dyn DspFilter>;
 }
 
+#[derive(Clone)]
 pub struct IdentityFilter {
     sample: f32
 }
@@ -27,8 +30,13 @@ impl DspFilter for IdentityFilter {
     fn output(&self) -> f32 {
         return self.sample;
     }
+
+    fn clone_filter(&self) -> Box<dyn DspFilter> {
+        return Box::new(self.clone());
+    }
 }
 
+#[derive(Clone)]
 pub struct HighPassIIR {
     alpha: f32,
     previous_output: f32,
@@ -60,8 +68,13 @@ impl DspFilter for HighPassIIR {
     fn output(&self) -> f32 {
         return self.alpha * self.previous_output + self.alpha * self.delta;
     }
+
+    fn clone_filter(&self) -> Box<dyn DspFilter> {
+        return Box::new(self.clone());
+    }
 }
 
+#[derive(Clone)]
 pub struct LowPassIIR {
     alpha: f32,
     previous_output: f32,
@@ -90,6 +103,10 @@ impl DspFilter for LowPassIIR {
     fn output(&self) -> f32 {
         return self.previous_output + self.alpha * self.delta;
     }
+
+    fn clone_filter(&self) -> Box<dyn DspFilter> {
+        return Box::new(self.clone());
+    }
 }
 
 fn blackman_window(index: usize, window_size: usize) -> f32 {
@@ -123,6 +140,7 @@ fn windowed_sinc_kernel(fc: f32, window_size: usize) -> Vec<f32> {
     return normalize(kernel);
 }
 
+#[derive(Clone)]
 pub struct LowPassFIR {
     kernel: Vec<f32>,
     inputs: Vec<f32>,
@@ -158,6 +176,10 @@ impl DspFilter for LowPassFIR {
         }
         return output;
     }
+
+    fn clone_filter(&self) -> Box<dyn DspFilter> {
+        return Box::new(self.clone());
+    }
 }
 
 // essentially a thin wrapper around a DspFilter, with some bonus data to track
@@ -168,6 +190,17 @@ pub struct ChainedFilter {
     period_counter: f32,
 }
 
+impl Clone for ChainedFilter {
+    fn clone(&self) -> ChainedFilter {
+        return ChainedFilter {
+            wrapped_filter: self.wrapped_filter.clone_filter(),
+            sampling_period: self.sampling_period,
+            period_counter: self.period_counter,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FilterChain {
     filters: Vec<ChainedFilter>,
 }
@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct LengthCounterState {
     pub length: u8,
     pub halt_flag: bool,
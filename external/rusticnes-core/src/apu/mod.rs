@@ -1,4 +1,5 @@
 use mmc::mapper::Mapper;
+use region::Region;
 
 use std::fs::OpenOptions;
 use std::io::prelude::*;
@@ -26,17 +27,36 @@ pub use self::triangle::TriangleChannelState;
 pub use self::filters::DspFilter;
 pub use self::filters::FilterChain;
 
+// CPU cycles of the frame sequencer steps: three quarter frames, then the end of the
+// 4-step and 5-step sequences
+const NTSC_FRAME_STEPS: [u16; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u16; 5] = [8313, 16627, 24939, 33252, 41565];
+
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+const NTSC_DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54];
+const PAL_DMC_PERIODS: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118,  98,  78,  66,  50];
+
 #[derive(Clone, Copy)]
 pub enum FilterType {
     Nes,
     FamiCom,
 }
 
+#[derive(Clone)]
 pub struct ApuState {
     pub current_cycle: u64,
 
     pub frame_sequencer_mode: u8,
     pub frame_sequencer: u16,
+    pub frame_steps: [u16; 5],
+    pub noise_periods: [u16; 16],
+    pub dmc_periods: [u16; 16],
     pub frame_reset_delay: u8,
     pub quarter_frame_counter: u32,
     pub half_frame_counter: u32,
@@ -189,6 +209,9 @@ impl ApuState {
             current_cycle: 0,
             frame_sequencer_mode: 0,
             frame_sequencer: 0,
+            frame_steps: NTSC_FRAME_STEPS,
+            noise_periods: NTSC_NOISE_PERIODS,
+            dmc_periods: NTSC_DMC_PERIODS,
             frame_reset_delay: 0,
             quarter_frame_counter: 0,
             half_frame_counter: 0,
@@ -218,6 +241,7 @@ impl ApuState {
 
     pub fn set_buffer_size(&mut self, buffer_size: usize) {
         self.staging_buffer = RingBuffer::new(buffer_size);
+        self.edge_buffer = RingBuffer::new(buffer_size);
         self.output_buffer = vec!(0i16; buffer_size);
         self.buffer_full = false;
     }
@@ -229,6 +253,24 @@ impl ApuState {
         self.set_buffer_size(output_buffer_size);
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu_clock_rate = region.cpu_clock_rate();
+        self.pulse_1.cpu_clock_rate = self.cpu_clock_rate;
+        self.pulse_2.cpu_clock_rate = self.cpu_clock_rate;
+        self.triangle.cpu_clock_rate = self.cpu_clock_rate;
+        if region.pal_apu_timing() {
+            self.frame_steps = PAL_FRAME_STEPS;
+            self.noise_periods = PAL_NOISE_PERIODS;
+            self.dmc_periods = PAL_DMC_PERIODS;
+        } else {
+            self.frame_steps = NTSC_FRAME_STEPS;
+            self.noise_periods = NTSC_NOISE_PERIODS;
+            self.dmc_periods = NTSC_DMC_PERIODS;
+        }
+        self.next_sample_at = ((self.generated_samples + 1) * self.cpu_clock_rate) / self.sample_rate;
+        self.update_filter();
+    }
+
     pub fn set_filter(&mut self, filter_type: FilterType, hq: bool) {
         self.filter_type = filter_type;
         self.filter_hq = hq;
@@ -417,13 +459,11 @@ impl ApuState {
                 self.noise.envelope.volume_register = data & 0b0000_1111;
             },
             0x400E => {
-                let noise_period = [
-                    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
-
                 let mode =        (data & 0b1000_0000) >> 7;
                 let period_index = data & 0b0000_1111;
                 self.noise.mode = mode;
-                self.noise.period_initial = noise_period[period_index as usize];
+                self.noise.period_index = period_index;
+                self.noise.period_initial = self.noise_periods[period_index as usize];
             },
             0x400F => {
                 let length_index = (data & 0b1111_1000) >> 3;
@@ -435,8 +475,6 @@ impl ApuState {
 
             // DMC Channel
             0x4010 => {
-                let period_table = [
-                    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54];
                 self.dmc.looping = (data & 0b0100_0000) != 0;
                 self.dmc.interrupt_enabled = (data & 0b1000_0000) != 0;
                 if !self.dmc.interrupt_enabled {
@@ -444,7 +482,7 @@ impl ApuState {
                     self.dmc.interrupt_flag = false;
                 }
                 let period_index = data & 0b0000_1111;
-                self.dmc.period_initial = period_table[period_index as usize] / 2;
+                self.dmc.period_initial = self.dmc_periods[period_index as usize] / 2;
             },
             0x4011 => {
                 self.dmc.output_level = data & 0b0111_1111;
@@ -521,28 +559,29 @@ impl ApuState {
             }
         }
 
+        let steps = self.frame_steps;
         if self.frame_sequencer_mode == 0 {
             // 4-step sequence
             match self.frame_sequencer {
-                7457 => self.clock_quarter_frame(),
-                14913 => {
+                cycle if cycle == steps[0] => self.clock_quarter_frame(),
+                cycle if cycle == steps[1] => {
                     self.clock_quarter_frame();
                     self.clock_half_frame();
                 },
-                22371 => self.clock_quarter_frame(),
-                29828 => {
+                cycle if cycle == steps[2] => self.clock_quarter_frame(),
+                cycle if cycle == steps[3] - 1 => {
                     if !self.disable_interrupt {
                         self.frame_interrupt = true;
                     }
                 },
-                29829 => {
+                cycle if cycle == steps[3] => {
                     if !self.disable_interrupt {
                         self.frame_interrupt = true;
                     }
                     self.clock_quarter_frame();
                     self.clock_half_frame();
                 },
-                29830 => {
+                cycle if cycle == steps[3] + 1 => {
                     if !self.disable_interrupt {
                         self.frame_interrupt = true;
                     }
@@ -553,17 +592,17 @@ impl ApuState {
         } else {
             match self.frame_sequencer {
                 // "5-step" sequence (uneven timing)
-                7457 => self.clock_quarter_frame(),
-                14913 => {
+                cycle if cycle == steps[0] => self.clock_quarter_frame(),
+                cycle if cycle == steps[1] => {
                     self.clock_quarter_frame();
                     self.clock_half_frame();
                 },
-                22371 => self.clock_quarter_frame(),
-                37281 => {
+                cycle if cycle == steps[2] => self.clock_quarter_frame(),
+                cycle if cycle == steps[4] => {
                     self.clock_quarter_frame();
                     self.clock_half_frame();
                 },
-                37282 => {
+                cycle if cycle == steps[4] + 1 => {
                   self.frame_sequencer = 0;  
                 },
                 _ => ()
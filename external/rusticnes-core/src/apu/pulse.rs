@@ -8,6 +8,7 @@ use super::ring_buffer::RingBuffer;
 use super::filters;
 use super::filters::DspFilter;
 
+#[derive(Clone)]
 pub struct PulseChannelState {
     pub name: String,
     pub chip: String,
@@ -4,6 +4,7 @@
 
 // Not intended to be generic, or particularly safe beyond rust's usual guarantees.
 
+#[derive(Clone)]
 pub struct RingBuffer {
     buffer: Vec<i16>,
     index: usize
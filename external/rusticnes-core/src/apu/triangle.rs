@@ -7,6 +7,7 @@ use super::ring_buffer::RingBuffer;
 use super::filters;
 use super::filters::DspFilter;
 
+#[derive(Clone)]
 pub struct TriangleChannelState {
     pub name: String,
     pub chip: String,
@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct VolumeEnvelopeState {
     // Volume Envelope
     pub volume_register: u8,
@@ -75,6 +75,7 @@ impl Registers {
     }
 }
 
+#[derive(Clone)]
 pub struct CpuState {
   pub tick: u8,
   pub opcode: u8,
@@ -14,4 +14,5 @@ pub mod opcodes;
 pub mod opcode_info;
 pub mod palettes;
 pub mod ppu;
+pub mod region;
 pub mod unofficial_opcodes;
\ No newline at end of file
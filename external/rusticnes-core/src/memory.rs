@@ -1,5 +1,6 @@
 use nes::NesState;
 
+#[derive(Clone)]
 pub struct CpuMemory {
     pub iram_raw: Vec<u8>,
 
@@ -69,7 +70,7 @@ pub fn read_byte(nes: &mut NesState, address: u16) -> u8 {
                     let ppu_addr = nes.ppu.current_vram_address;
                     nes.ppu.latch = nes.ppu.read_latched_byte(&mut *nes.mapper, ppu_addr);
                     if nes.ppu.rendering_enabled() && 
-                    (nes.ppu.current_scanline == 261 ||
+                    (nes.ppu.current_scanline == nes.ppu.prerender_scanline ||
                      nes.ppu.current_scanline <= 239) {
                         // Glitchy increment, a fine y and a coarse x 
                         nes.ppu.increment_coarse_x();
@@ -175,6 +176,17 @@ fn _read_byte(nes: &NesState, address: u16, mapped_byte: u8) -> u8 {
     }
 }
 
+// The 2A03's sound registers, and anywhere an expansion chip might be listening. Which expansion
+// chip a write belongs to depends on the cartridge, so those are left to whoever reads the log.
+fn is_audio_register(address: u16) -> bool {
+    return match address {
+        0x4000 ..= 0x4013 | 0x4015 | 0x4017 => true,
+        0x4040 ..= 0x5FFF => true,
+        0x8000 ..= 0xFFFF => true,
+        _ => false
+    };
+}
+
 pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
     // Track every byte written, unconditionally
     // (filtering is done inside the tracker)
@@ -183,6 +195,11 @@ pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
     // The mapper *always* sees the write. Even to RAM, and even to internal registers.
     // Most mappers ignore writes to addresses below 0x6000. Some (notably MMC5) do not.
     nes.mapper.write_cpu(address, data);
+    if let Some(log) = nes.audio_write_log.as_mut() {
+        if is_audio_register(address) {
+            log.push((nes.master_clock / 12, address, data));
+        }
+    }
     match address {
         0x0000 ..= 0x1FFF => nes.memory.iram_raw[(address & 0x7FF) as usize] = data,
         0x2000 ..= 0x3FFF => {
@@ -259,7 +276,7 @@ pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
                 7 => {
                     let ppu_addr = nes.ppu.current_vram_address;
                     if nes.ppu.rendering_enabled() && 
-                    (nes.ppu.current_scanline == 261 ||
+                    (nes.ppu.current_scanline == nes.ppu.prerender_scanline ||
                     nes.ppu.current_scanline <= 239) {
                         // Glitchy increment, a fine y and a coarse x 
                         nes.ppu.increment_coarse_x();
@@ -9,6 +9,7 @@ use apu::RingBuffer;
 use apu::filters;
 use apu::filters::DspFilter;
 
+#[derive(Clone)]
 pub struct LowPassRC {
     pub accumulator: f32,
     pub alpha: f32
@@ -31,8 +32,13 @@ impl DspFilter for LowPassRC {
     fn output(&self) -> f32 {
         return self.accumulator;
     }
+
+    fn clone_filter(&self) -> Box<dyn DspFilter> {
+        return Box::new(self.clone());
+    }
 }
 
+#[derive(Clone)]
 pub struct FdsModTable {
     pub table: [u8; 64],
     pub frequency: usize,
@@ -96,6 +102,7 @@ impl FdsModTable {
     }
 }
 
+#[derive(Clone)]
 pub struct FdsEnvelope {
     pub mode: bool,
     pub disable: bool,
@@ -145,6 +152,7 @@ impl FdsEnvelope {
     }
 }
 
+#[derive(Clone)]
 pub struct FdsWaveTable {
     pub table: [u8; 64],
     pub frequency: u16,
@@ -240,7 +248,11 @@ impl FdsWaveTable {
     }
 }
 
-fn build_output_filter(sample_rate: f32) -> FilterChain {
+pub const FDS_OUTPUT_LOWPASS_CUTOFF: f32 = 2000.0;
+pub const FDS_EXTRA_LOWPASS_CUTOFF: f32 = 6000.0;
+
+// Either stage can be left out by passing None for its cutoff
+fn build_output_filter(sample_rate: f32, output_lowpass_cutoff: Option<f32>, extra_lowpass_cutoff: Option<f32>) -> FilterChain {
     let mut chain = FilterChain::new();
 
     // The FDS has a 1-pole RC low-pass filter on the output, with a cutoff around 2kHz.
@@ -248,12 +260,17 @@ fn build_output_filter(sample_rate: f32) -> FilterChain {
     // noise can be introduced when modulation is used. An additional low-pass is added here to
     // filter away the modulation noise so it better matches other emulators, though I'm not sure
     // how accurate this is to the real hardware.
-    chain.add(Box::new(LowPassRC::new(1789773.0, 2000.0)), 1789773.0);
-    chain.add(Box::new(LowPassRC::new(sample_rate, 6000.0)), sample_rate);
+    if let Some(cutoff) = output_lowpass_cutoff {
+        chain.add(Box::new(LowPassRC::new(1789773.0, cutoff)), 1789773.0);
+    }
+    if let Some(cutoff) = extra_lowpass_cutoff {
+        chain.add(Box::new(LowPassRC::new(sample_rate, cutoff)), sample_rate);
+    }
 
     chain
 }
 
+#[derive(Clone)]
 pub struct FdsChannel {
     pub name: String,
     pub debug_disable: bool,
@@ -271,7 +288,9 @@ pub struct FdsChannel {
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
-    pub debug_filter: filters::HighPassIIR
+    pub debug_filter: filters::HighPassIIR,
+    // For the frequency shown to visualizations, set from the region
+    pub cpu_clock_rate: u64
 }
 
 impl FdsChannel {
@@ -288,15 +307,20 @@ impl FdsChannel {
             master_envelope_speed: 0xFF,
 
             current_volume: 0.0,
-            output_filter: build_output_filter(44100.0),
+            output_filter: build_output_filter(44100.0, Some(FDS_OUTPUT_LOWPASS_CUTOFF), Some(FDS_EXTRA_LOWPASS_CUTOFF)),
 
             output_buffer: RingBuffer::new(32768),
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
-            debug_filter: filters::HighPassIIR::new(44100.0, 300.0)
+            debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
+            cpu_clock_rate: 1_789_773
         }
     }
 
+    pub fn set_output_filter(&mut self, output_lowpass_cutoff: Option<f32>, extra_lowpass_cutoff: Option<f32>) {
+        self.output_filter = build_output_filter(44100.0, output_lowpass_cutoff, extra_lowpass_cutoff);
+    }
+
     pub fn nsf_init(&mut self) {
         // FDS BIOS: $4080 <- 0x80
         self.vol_envelope.write_config_register(0x80);
@@ -400,7 +424,7 @@ impl AudioChannelState for FdsChannel {
     }
 
     fn rate(&self) -> PlaybackRate {
-        let frequency = (1_789_773.0 / 65535.0) * (self.wave_table.tick_frequency / 64.0);
+        let frequency = (self.cpu_clock_rate as f32 / 65535.0) * (self.wave_table.tick_frequency / 64.0);
         return PlaybackRate::FundamentalFrequency {frequency: frequency};
     }
 
@@ -225,6 +225,7 @@ impl Mapper for Fme7 {
     }
 }
 
+#[derive(Clone)]
 pub struct ToneGenerator {
     pub period_compare: u16,
     pub period_current: u16,
@@ -258,6 +259,7 @@ impl ToneGenerator {
     }
 }
 
+#[derive(Clone)]
 pub struct NoiseGenerator {
     pub period_compare: u16,
     pub period_current: u16,
@@ -295,6 +297,7 @@ impl NoiseGenerator {
     }
 }
 
+#[derive(Clone)]
 pub struct EnvelopeGenerator {
     pub period_compare: u16,
     pub period_current: u16,
@@ -400,6 +403,7 @@ impl EnvelopeGenerator {
     }
 }
 
+#[derive(Clone)]
 pub struct YmChannel {
     pub name: String,
     pub output_buffer: RingBuffer,
@@ -414,6 +418,8 @@ pub struct YmChannel {
     pub static_volume: u8,
     pub effective_volume: usize,
     pub effective_amplitude: f32,
+    // For the frequency shown to visualizations, set from the region
+    pub cpu_clock_rate: u64,
 }
 
 impl YmChannel {
@@ -431,6 +437,7 @@ impl YmChannel {
             static_volume: 0,
             effective_volume: 0,
             effective_amplitude: 0.0,
+            cpu_clock_rate: 1_789_773,
         }
     }
 
@@ -489,7 +496,7 @@ impl AudioChannelState for YmChannel {
     }
 
     fn rate(&self) -> PlaybackRate {
-        let frequency = 1_789_773.0 / (32.0 * (self.tone.period_compare as f32));
+        let frequency = self.cpu_clock_rate as f32 / (32.0 * (self.tone.period_compare as f32));
         return PlaybackRate::FundamentalFrequency {frequency: frequency};
     }
 
@@ -512,6 +519,7 @@ impl AudioChannelState for YmChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct YM2149F {
     pub channel_a: YmChannel,
     pub channel_b: YmChannel,
@@ -535,6 +543,12 @@ impl YM2149F {
         }
     }
 
+    pub fn set_cpu_clock_rate(&mut self, cpu_clock_rate: u64) {
+        self.channel_a.cpu_clock_rate = cpu_clock_rate;
+        self.channel_b.cpu_clock_rate = cpu_clock_rate;
+        self.channel_c.cpu_clock_rate = cpu_clock_rate;
+    }
+
     pub fn generate_volume_lut() -> Vec<f32> {
         let mut lut = vec![0f32; 32];
         lut[0] = 0.0;
@@ -1,4 +1,6 @@
 use apu::AudioChannelState;
+use mmc::fds::FdsChannel;
+use region::Region;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum Mirroring {
@@ -40,6 +42,15 @@ pub trait Mapper: Send {
     fn record_expansion_audio_output(&mut self, _nes_sample: f32) {}
     fn nsf_set_track(&mut self, _track_index: u8) {}
     fn nsf_manual_mode(&mut self) {}
+    fn nsf_set_playback_phase(&mut self, _phase: f32) {}
+    fn nsf_set_region(&mut self, _region: Region) {}
     fn audio_multiplexing(&mut self, _emulate: bool) {}
+    fn n163_wavetables(&self) -> Vec<Vec<u8>> {return Vec::new();}
+    fn fds_set_output_filter(&mut self, _output_lowpass_cutoff: Option<f32>, _extra_lowpass_cutoff: Option<f32>) {}
+    fn fds_channel(&self) -> Option<&FdsChannel> {return None;}
     fn vrc7_set_patches(&mut self, _patches: &[u8]) {}
+    // (chip name, declared in the header, register writes so far) for each expansion chip
+    fn nsf_expansion_writes(&self) -> Vec<(&'static str, bool, u64)> {return Vec::new();}
+    // Only mappers that support savestates return a copy of themselves
+    fn clone_mapper(&self) -> Option<Box<dyn Mapper>> {return None;}
 }
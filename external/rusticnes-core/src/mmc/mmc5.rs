@@ -21,6 +21,7 @@ pub enum PpuMode {
     PpuData
 }
 
+#[derive(Clone)]
 pub struct Mmc5PcmChannel {
     pub level: u8,
     pub read_mode: bool,
@@ -18,6 +18,7 @@ use apu::filters::DspFilter;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
 
+#[derive(Clone)]
 pub struct Namco163AudioChannel {
     pub debug_disable: bool,
     pub channel_address: usize,
@@ -32,6 +33,8 @@ pub struct Namco163AudioChannel {
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
     pub debug_filter: filters::HighPassIIR,
+    // For the frequency shown to visualizations, set from the region
+    pub cpu_clock_rate: u64,
 }
 
 const AUDIO_FREQ_LOW:     usize = 0;
@@ -69,6 +72,7 @@ impl Namco163AudioChannel {
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
             debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
+            cpu_clock_rate: 1_789_773,
         }
     }
 
@@ -129,10 +133,9 @@ impl Namco163AudioChannel {
         self.current_output = (sample - 8.0) * (volume as f32);
 
         // for debug visualizations
-        let ntsc_clockrate: f32 = 1_789_773.0;
         let enabled_channels = (((audio_ram[0x7F] & 0b0111_0000) >> 4) + 1) as u32;
 
-        self.tracked_frequency = (ntsc_clockrate * (frequency as f32)) / (15.0 * 65536.0 * (length as f32) * (enabled_channels as f32));
+        self.tracked_frequency = (self.cpu_clock_rate as f32 * (frequency as f32)) / (15.0 * 65536.0 * (length as f32) * (enabled_channels as f32));
         self.tracked_volume = volume;
         self.tracked_address = sample_address as usize;
         self.tracked_length = length as usize;
@@ -158,6 +161,8 @@ impl AudioChannelState for Namco163AudioChannel {
     }
 
     fn record_current_output(&mut self) {
+        // Scopes are deinterleaved: each channel's output is held between its own updates and
+        // filtered on its own, whether or not the audio is multiplexed
         self.debug_filter.consume(self.current_output);
         self.output_buffer.push((self.debug_filter.output() * -4.0) as i16);
         self.edge_buffer.push(self.last_edge as i16);
@@ -211,6 +216,7 @@ impl AudioChannelState for Namco163AudioChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct Namco163Audio {
     pub internal_ram: Vec<u8>,
     pub channel1: Namco163AudioChannel,
@@ -248,11 +254,31 @@ impl Namco163Audio {
         };
     }
 
+    pub fn set_cpu_clock_rate(&mut self, cpu_clock_rate: u64) {
+        for channel in [&mut self.channel1, &mut self.channel2, &mut self.channel3, &mut self.channel4,
+                &mut self.channel5, &mut self.channel6, &mut self.channel7, &mut self.channel8] {
+            channel.cpu_clock_rate = cpu_clock_rate;
+        }
+    }
+
     pub fn enabled_channels(&self) -> usize {
         let channel_cmp = (self.internal_ram[0x7F] & 0b0111_0000) >> 4;
         return (1 + channel_cmp) as usize;
     }
 
+    // The current wavetable of each enabled channel, as 4-bit samples
+    pub fn wavetables(&self) -> Vec<Vec<u8>> {
+        let channels = [&self.channel1, &self.channel2, &self.channel3, &self.channel4,
+            &self.channel5, &self.channel6, &self.channel7, &self.channel8];
+        let audio_ram = &self.internal_ram;
+        return channels.iter().take(self.enabled_channels()).map(|channel| {
+            let wave_address = channel.wave_address(audio_ram);
+            return (0 .. channel.length(audio_ram))
+                .map(|i| audio_sample(audio_ram, wave_address.wrapping_add(i as u8)))
+                .collect();
+        }).collect();
+    }
+
     pub fn multiplexed_output(&self) -> f32 {
         let active_channel = match self.current_channel {
             0 => &self.channel1,
@@ -662,4 +688,9 @@ impl Mapper for Namco163 {
     fn audio_multiplexing(&mut self, emulate: bool) {
         self.expansion_audio_chip.emulate_multiplexing = emulate;
     }
+
+
+    fn n163_wavetables(&self) -> Vec<Vec<u8>> {
+        return self.expansion_audio_chip.wavetables();
+    }
 }
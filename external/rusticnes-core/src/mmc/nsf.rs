@@ -12,6 +12,7 @@ use mmc::mapper::*;
 use mmc::mirroring;
 use nsf::NsfFile;
 use nsf::NsfHeader;
+use region::Region;
 
 // various expansion audio chips
 use mmc::vrc6::Vrc6PulseChannel;
@@ -61,6 +62,21 @@ const PLAYER_END: u16 = PLAYER_ORIGIN + PLAYER_SIZE - 1;
 
 const JOYPAD1: u16 = 0x4016;
 
+const EXPANSION_CHIPS: [&str; 6] = ["VRC6", "MMC5", "S5B", "N163", "VRC7", "FDS"];
+
+// Audio registers of each expansion chip, indexed like EXPANSION_CHIPS
+fn expansion_chip_index(address: u16) -> Option<usize> {
+    match address {
+        0x9000 ..= 0x9003 | 0xA000 ..= 0xA002 | 0xB000 ..= 0xB002 => Some(0),
+        0x5000 ..= 0x5015 => Some(1),
+        0xC000 | 0xE000 => Some(2),
+        0x4800 | 0xF800 => Some(3),
+        0x9010 | 0x9030 => Some(4),
+        0x4040 ..= 0x408A => Some(5),
+        _ => None
+    }
+}
+
 //const BUTTON_A: u8      = 1 << 7;
 //const BUTTON_B: u8      = 1 << 6;
 //const BUTTON_SELECT: u8 = 1 << 5;
@@ -321,12 +337,14 @@ fn nsf_player(init_address: u16, play_address: u16) -> Vec<Opcode> {
     ]
 }
 
+#[derive(Clone)]
 enum TrackAdvanceMode {
     Timer,
     Silence,
     Manual
 }
 
+#[derive(Clone)]
 pub struct NsfMapper {
     prg: MemoryBlock,
     prg_ram: Vec<u8>,
@@ -387,7 +405,9 @@ pub struct NsfMapper {
     vrc7_audio_register: u8,
 
     fds_enabled: bool,
-    fds_channel: FdsChannel
+    fds_channel: FdsChannel,
+
+    expansion_writes: [u64; 6]
 }
 
 impl NsfMapper {
@@ -412,8 +432,14 @@ impl NsfMapper {
             prg_rom_banks = vec![0, 1, 2, 3, 4, 5, 6, 7];
         }
 
-        let ntsc_clockrate = 1786860.0;
-        let cycles_per_play = (nsf.header.ntsc_playback_speed() as f32) * ntsc_clockrate / 1000000.0;
+        // Same clock nsf_set_region uses, so the play period doesn't shift when the region is applied
+        let ntsc_clockrate = Region::Ntsc.cpu_clock_rate() as f32;
+        // Some rips leave the play speed zeroed out; assume the standard NTSC rate for those
+        let ntsc_playback_speed = match nsf.header.ntsc_playback_speed() {
+            0 => 16639,
+            speed => speed
+        };
+        let cycles_per_play = (ntsc_playback_speed as f32) * ntsc_clockrate / 1000000.0;
         let mut font_chr = include_bytes!("../../assets/troll8x8.chr").to_vec();
         font_chr.resize(0x2000, 0);
 
@@ -477,6 +503,8 @@ impl NsfMapper {
             fds_enabled: nsf.header.fds(),
             fds_channel: FdsChannel::new("Wavetable"),
 
+            expansion_writes: [0; 6],
+
             prg_rom_banks: prg_rom_banks,
 
             mirroring: Mirroring::FourScreen,
@@ -1177,6 +1205,34 @@ impl Mapper for NsfMapper {
         self.advance_mode = TrackAdvanceMode::Manual;
     }
 
+    fn nsf_set_playback_phase(&mut self, phase: f32) {
+        // phase is a fraction of the play period, i.e. how far into the first play call we start
+        self.playback_accumulator = phase.max(0.0).min(1.0) * self.playback_period;
+    }
+
+    fn nsf_set_region(&mut self, region: Region) {
+        // Dendy machines run PAL-rate frames, so PAL tunes expect the PAL play speed there too
+        let playback_speed = match region {
+            Region::Ntsc => self.header.ntsc_playback_speed(),
+            Region::Pal | Region::Dendy => self.header.pal_playback_speed()
+        };
+        let playback_speed = match (region, playback_speed) {
+            (Region::Ntsc, 0) => 16639,
+            (_, 0) => 19997,
+            (_, speed) => speed
+        };
+        self.playback_period = (playback_speed as f32) * (region.cpu_clock_rate() as f32) / 1000000.0;
+        self.playback_accumulator = self.playback_accumulator.min(self.playback_period);
+        self.mmc5_pulse_1.cpu_clock_rate = region.cpu_clock_rate();
+        self.mmc5_pulse_2.cpu_clock_rate = region.cpu_clock_rate();
+        self.vrc6_pulse1.cpu_clock_rate = region.cpu_clock_rate();
+        self.vrc6_pulse2.cpu_clock_rate = region.cpu_clock_rate();
+        self.vrc6_sawtooth.cpu_clock_rate = region.cpu_clock_rate();
+        self.s5b_expansion_audio_chip.set_cpu_clock_rate(region.cpu_clock_rate());
+        self.n163_expansion_audio_chip.set_cpu_clock_rate(region.cpu_clock_rate());
+        self.fds_channel.cpu_clock_rate = region.cpu_clock_rate();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
@@ -1403,6 +1459,9 @@ impl Mapper for NsfMapper {
             0x6000 ..= 0x7FFF => {self.prg_ram[(address - 0x6000) as usize] = data},
             _ => {}
         }
+        if let Some(chip_index) = expansion_chip_index(address) {
+            self.expansion_writes[chip_index] += 1;
+        }
         if self.vrc6_enabled {
             self.vrc6_write(address, data);
         }
@@ -1452,7 +1511,38 @@ impl Mapper for NsfMapper {
         self.n163_expansion_audio_chip.emulate_multiplexing = emulate;
     }
 
+    fn n163_wavetables(&self) -> Vec<Vec<u8>> {
+        if !self.n163_enabled {
+            return Vec::new();
+        }
+        return self.n163_expansion_audio_chip.wavetables();
+    }
+
+    fn fds_set_output_filter(&mut self, output_lowpass_cutoff: Option<f32>, extra_lowpass_cutoff: Option<f32>) {
+        self.fds_channel.set_output_filter(output_lowpass_cutoff, extra_lowpass_cutoff);
+    }
+
+    fn fds_channel(&self) -> Option<&FdsChannel> {
+        if !self.fds_enabled {
+            return None;
+        }
+        return Some(&self.fds_channel);
+    }
+
     fn vrc7_set_patches(&mut self, patches: &[u8]) {
         self.vrc7_audio.set_patches(patches);
     }
+
+    fn nsf_expansion_writes(&self) -> Vec<(&'static str, bool, u64)> {
+        let declared = [self.vrc6_enabled, self.mmc5_enabled, self.s5b_enabled, self.n163_enabled, self.vrc7_enabled, self.fds_enabled];
+        return EXPANSION_CHIPS.iter()
+            .zip(declared.iter())
+            .zip(self.expansion_writes.iter())
+            .map(|((chip, declared), writes)| (*chip, *declared, *writes))
+            .collect();
+    }
+
+    fn clone_mapper(&self) -> Option<Box<dyn Mapper>> {
+        return Some(Box::new(self.clone()));
+    }
 }
@@ -15,6 +15,7 @@ use apu::RingBuffer;
 use apu::filters;
 use apu::filters::DspFilter;
 
+#[derive(Clone)]
 pub struct Vrc6PulseChannel {
     pub name: String,
     pub debug_disable: bool,
@@ -31,6 +32,8 @@ pub struct Vrc6PulseChannel {
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
     pub debug_filter: filters::HighPassIIR,
+    // For the frequency shown to visualizations, set from the region
+    pub cpu_clock_rate: u64,
 }
 
 impl Vrc6PulseChannel {
@@ -51,6 +54,7 @@ impl Vrc6PulseChannel {
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
             debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
+            cpu_clock_rate: 1_789_773,
         };
     }
 
@@ -178,7 +182,7 @@ impl AudioChannelState for Vrc6PulseChannel {
     }
 
     fn rate(&self) -> PlaybackRate {
-        let frequency = 1_789_773.0 / (16.0 * (self.period_initial as f32 + 1.0));
+        let frequency = self.cpu_clock_rate as f32 / (16.0 * (self.period_initial as f32 + 1.0));
         return PlaybackRate::FundamentalFrequency {frequency: frequency};
     }
 
@@ -191,6 +195,7 @@ impl AudioChannelState for Vrc6PulseChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct Vrc6SawtoothChannel {
     pub enabled: bool,
     pub debug_disable: bool,
@@ -206,6 +211,8 @@ pub struct Vrc6SawtoothChannel {
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
     pub debug_filter: filters::HighPassIIR,
+    // For the frequency shown to visualizations, set from the region
+    pub cpu_clock_rate: u64,
 }
 
 impl Vrc6SawtoothChannel {
@@ -225,6 +232,7 @@ impl Vrc6SawtoothChannel {
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
             debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
+            cpu_clock_rate: 1_789_773,
         };
     }
 
@@ -347,7 +355,7 @@ impl AudioChannelState for Vrc6SawtoothChannel {
     }
 
     fn rate(&self) -> PlaybackRate {
-        let frequency = 1_789_773.0 / (14.0 * (self.period_initial as f32 + 1.0));
+        let frequency = self.cpu_clock_rate as f32 / (14.0 * (self.period_initial as f32 + 1.0));
         return PlaybackRate::FundamentalFrequency {frequency: frequency};
     }
 
@@ -404,6 +404,7 @@ pub enum EnvState {
     Sustain
 }
 
+#[derive(Clone)]
 pub struct Vrc7AudioChannel {
     logsin_lut: Vec<u16>,
     exp_lut: Vec<u16>,
@@ -914,6 +915,7 @@ impl Vrc7AudioChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct Vrc7Audio {
     pub custom_patch: [u8; 8],
     pub patches: [u8; 8 * 15],
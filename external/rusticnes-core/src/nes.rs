@@ -8,6 +8,7 @@ use memory::CpuMemory;
 use ppu::PpuState;
 use mmc::mapper::Mapper;
 use tracked_events::EventTracker;
+use region::Region;
 
 pub struct NesState {
     pub apu: ApuState,
@@ -24,6 +25,11 @@ pub struct NesState {
     pub mapper: Box<dyn Mapper>,
     pub last_frame: u32,
     pub event_tracker: EventTracker,
+    pub region: Region,
+    // PPU dots owed to the next CPU cycle, in fifths of a dot
+    pub ppu_dot_remainder: u8,
+    // Writes to sound registers as (CPU cycle, address, data), only collected while Some
+    pub audio_write_log: Option<Vec<(u64, u16, u8)>>,
 }
 
 impl NesState {
@@ -43,9 +49,35 @@ impl NesState {
             mapper: m,
             last_frame: 0,
             event_tracker: EventTracker::new(),
+            region: Region::Ntsc,
+            ppu_dot_remainder: 0,
+            audio_write_log: None,
         }
     }
 
+    // A full copy of the console for savestates, if the mapper supports them
+    pub fn try_clone(&self) -> Option<NesState> {
+        return Some(NesState {
+            apu: self.apu.clone(),
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            ppu: self.ppu.clone(),
+            registers: self.registers,
+            master_clock: self.master_clock,
+            p1_input: self.p1_input,
+            p1_data: self.p1_data,
+            p2_input: self.p2_input,
+            p2_data: self.p2_data,
+            input_latch: self.input_latch,
+            mapper: self.mapper.clone_mapper()?,
+            last_frame: self.last_frame,
+            event_tracker: self.event_tracker.clone(),
+            region: self.region,
+            ppu_dot_remainder: self.ppu_dot_remainder,
+            audio_write_log: self.audio_write_log.clone(),
+        });
+    }
+
     #[deprecated(since="0.2.0", note="please use `::new(mapper)` instead")]
     pub fn from_rom(cart_data: &[u8]) -> Result<NesState, String> {
         let maybe_mapper = cartridge::mapper_from_file(cart_data);
@@ -61,6 +93,13 @@ impl NesState {
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.mapper.nsf_set_region(region);
+    }
+
     pub fn power_on(&mut self) {
         // Initialize CPU register state for power-up sequence
         self.registers.a = 0;
@@ -102,10 +141,12 @@ impl NesState {
     pub fn cycle(&mut self) {
         cycle_cpu::run_one_clock(self);
         self.master_clock = self.master_clock + 12;
-        // Three PPU clocks per every 1 CPU clock
-        self.ppu.clock(&mut *self.mapper);
-        self.ppu.clock(&mut *self.mapper);
-        self.ppu.clock(&mut *self.mapper);
+        // Three PPU clocks per every 1 CPU clock, or 3.2 on PAL
+        self.ppu_dot_remainder += self.region.ppu_dots_per_cpu_cycle_x5();
+        while self.ppu_dot_remainder >= 5 {
+            self.ppu.clock(&mut *self.mapper);
+            self.ppu_dot_remainder -= 5;
+        }
         self.event_tracker.current_scanline = self.ppu.current_scanline;
         self.event_tracker.current_cycle = self.ppu.current_scanline_cycle;
         self.apu.clock_apu(&mut *self.mapper);
@@ -136,10 +177,11 @@ impl NesState {
     }
 
     pub fn run_until_vblank(&mut self) {
-        while self.ppu.current_scanline == 242 {
+        let vblank_end = self.ppu.vblank_scanline + 1;
+        while self.ppu.current_scanline == vblank_end {
             self.step();
         }
-        while self.ppu.current_scanline != 242 {
+        while self.ppu.current_scanline != vblank_end {
             self.step();
         }
     }
@@ -3,6 +3,7 @@
 // and prototype stages.
 
 use mmc::mapper::*;
+use region::Region;
 
 #[derive(Copy, Clone)]
 pub struct SpriteLatch {
@@ -75,6 +76,7 @@ impl SpriteLatch {
     }
 }
 
+#[derive(Clone)]
 pub struct PpuState {
     // PPU Memory (incl. cart CHR ROM for now)
     pub internal_vram: Vec<u8>,
@@ -108,6 +110,11 @@ pub struct PpuState {
     pub frame_starting_cycle: usize,
     pub scanline_ntsc_samples: [f32; 256*8],
 
+    // Region timing
+    pub vblank_scanline: u16,
+    pub prerender_scanline: u16,
+    pub skip_odd_frame_dot: bool,
+
     // Framebuffer
     pub screen: Vec<u16>,
     pub filtered_screen: Vec<u32>,
@@ -208,9 +215,20 @@ impl PpuState {
             // Debug
             recent_reads: Vec::new(),
             recent_writes: Vec::new(),
+
+            vblank_scanline: 241,
+            prerender_scanline: 261,
+            skip_odd_frame_dot: true,
        };
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.vblank_scanline = region.vblank_scanline();
+        self.prerender_scanline = region.scanlines_per_frame() - 1;
+        // Only the NTSC PPU shortens odd frames
+        self.skip_odd_frame_dot = region == Region::Ntsc;
+    }
+
     pub fn read_latched_byte(&mut self, mapper: &mut dyn Mapper, address: u16) -> u8 {
         let masked_address = address & 0x3FFF;
         match masked_address {
@@ -652,7 +670,7 @@ impl PpuState {
                 }
             },
             340 => {
-                if self.rendering_enabled() {
+                if self.rendering_enabled() && self.skip_odd_frame_dot {
                     if self.current_frame & 0x1 != 0 {
                         // Skip ahead one cycle on odd frames. This jitter produces a cleaner image
                         // for NTSC signal generation.
@@ -759,8 +777,8 @@ impl PpuState {
                     let _ = self.read_byte(mapper, vram_address);
                 }
             }
-            241 => self.vblank_scanline(),
-            261 => self.prerender_scanline(mapper),
+            scanline if scanline == self.vblank_scanline => self.vblank_scanline(),
+            scanline if scanline == self.prerender_scanline => self.prerender_scanline(mapper),
             _ => ()
         }
 
@@ -769,7 +787,7 @@ impl PpuState {
         if self.current_scanline_cycle > 340 {
             self.current_scanline_cycle = 0;
             self.current_scanline += 1;
-            if self.current_scanline > 261 {
+            if self.current_scanline > self.prerender_scanline {
                 self.current_scanline = 0;
                 self.current_frame += 1;
             }
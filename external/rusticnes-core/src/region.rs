@@ -0,0 +1,86 @@
+// Console timing variants. PAL and Dendy consoles run a slower CPU and a 312 scanline frame;
+// Dendy clones keep the NTSC APU timings and start vblank later to stay compatible with NTSC games.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn cpu_clock_rate(&self) -> u64 {
+        return match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        };
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        return match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        };
+    }
+
+    pub fn vblank_scanline(&self) -> u16 {
+        return match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        };
+    }
+
+    // PPU dots per CPU cycle, times 5 (PAL runs 3.2 dots per cycle)
+    pub fn ppu_dots_per_cpu_cycle_x5(&self) -> u8 {
+        return match self {
+            Region::Ntsc | Region::Dendy => 15,
+            Region::Pal => 16,
+        };
+    }
+
+    // Frames per second, with NTSC skipping a dot on every other frame
+    pub fn frame_rate(&self) -> f32 {
+        let cpu_cycles_per_frame = match self {
+            Region::Ntsc => 29780.5,
+            _ => 341.0 * self.scanlines_per_frame() as f32 * 5.0 / self.ppu_dots_per_cpu_cycle_x5() as f32,
+        };
+        return self.cpu_clock_rate() as f32 / cpu_cycles_per_frame;
+    }
+
+    pub fn pal_apu_timing(&self) -> bool {
+        return *self == Region::Pal;
+    }
+}
+
+impl Default for Region {
+    fn default() -> Region {
+        return Region::Ntsc;
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Region::Ntsc => write!(f, "ntsc"),
+            Region::Pal => write!(f, "pal"),
+            Region::Dendy => write!(f, "dendy"),
+        };
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Region, String> {
+        return match s.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region {}, valid regions are 'ntsc', 'pal' and 'dendy'", s)),
+        };
+    }
+}
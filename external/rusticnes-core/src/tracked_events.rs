@@ -13,6 +13,7 @@ pub struct TrackedEvent {
     pub event_type: EventType,
 }
 
+#[derive(Clone)]
 pub struct EventTracker {
     pub tracked_events_a: Vec<TrackedEvent>,
     pub size_a: usize,
@@ -10,6 +10,9 @@ use rusticnes_core::mmc::none::NoneMapper;
 use rusticnes_core::cartridge::mapper_from_file;
 
 use rusticnes_core::apu::AudioChannelState;
+use rusticnes_core::mmc::fds::{FDS_OUTPUT_LOWPASS_CUTOFF, FDS_EXTRA_LOWPASS_CUTOFF};
+
+use toml::Value;
 
 
 
@@ -117,6 +120,26 @@ impl RuntimeState {
         return responses;
     }
 
+    // The FDS output filter is rebuilt as a whole, so every stage is re-read when any of them changes
+    fn fds_filter_stage(&self, name: &str, default_cutoff: f32) -> Option<f32> {
+        let enabled = match self.settings.get(format!("audio.fds.{}", name)) {
+            Some(Value::Boolean(enabled)) => *enabled,
+            _ => true
+        };
+        let cutoff = match self.settings.get(format!("audio.fds.{}_cutoff", name)) {
+            Some(Value::Float(cutoff)) => *cutoff as f32,
+            Some(Value::Integer(cutoff)) => *cutoff as f32,
+            _ => default_cutoff
+        };
+        return if enabled {Some(cutoff)} else {None};
+    }
+
+    fn apply_fds_filter_settings(&mut self) {
+        let output_lowpass = self.fds_filter_stage("output_lowpass", FDS_OUTPUT_LOWPASS_CUTOFF);
+        let extra_lowpass = self.fds_filter_stage("extra_lowpass", FDS_EXTRA_LOWPASS_CUTOFF);
+        self.nes.mapper.fds_set_output_filter(output_lowpass, extra_lowpass);
+    }
+
     pub fn handle_event(&mut self, event: Event) -> Vec<Event> {
         let mut responses: Vec<Event> = Vec::new();
         responses.extend(self.settings.handle_event(event.clone()));
@@ -124,6 +147,13 @@ impl RuntimeState {
             Event::ApplyBooleanSetting(path, value) => {
                 match path.as_str() {
                     "audio.multiplexing" => {self.nes.mapper.audio_multiplexing(value)},
+                    "audio.fds.output_lowpass" | "audio.fds.extra_lowpass" => {self.apply_fds_filter_settings()},
+                    _ => {}
+                }
+            },
+            Event::ApplyFloatSetting(path, _) | Event::ApplyIntegerSetting(path, _) => {
+                match path.as_str() {
+                    "audio.fds.output_lowpass_cutoff" | "audio.fds.extra_lowpass_cutoff" => {self.apply_fds_filter_settings()},
                     _ => {}
                 }
             },
@@ -14,6 +14,7 @@ use rusticnes_core::apu::PlaybackRate;
 use rusticnes_core::apu::RingBuffer;
 use rusticnes_core::apu::Timbre;
 use rusticnes_core::mmc::mapper::Mapper;
+use rusticnes_core::region::Region;
 
 use std::collections::VecDeque;
 use std::collections::hash_map::HashMap;
@@ -25,13 +26,54 @@ pub enum NoteType {
     Waveform
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ScrollDirection {
     RightToLeft,
     LeftToRight,
     TopToBottom,
     BottomToTop,
-    PlayerPiano
+    PlayerPiano,
+    // Top to bottom with the oscilloscopes stacked above the keyboard, for portrait canvases
+    Vertical
+}
+
+impl ScrollDirection {
+    pub fn from_string(s: &str) -> Option<ScrollDirection> {
+        return match s {
+            "right_to_left" => Some(ScrollDirection::RightToLeft),
+            "left_to_right" => Some(ScrollDirection::LeftToRight),
+            "top_to_bottom" => Some(ScrollDirection::TopToBottom),
+            "bottom_to_top" => Some(ScrollDirection::BottomToTop),
+            "player_piano" => Some(ScrollDirection::PlayerPiano),
+            "vertical" => Some(ScrollDirection::Vertical),
+            _ => None
+        };
+    }
+}
+
+// Where the keyboard goes, independent of the rest of the scroll direction's layout. Notes always
+// scroll away from the keyboard.
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyboardPosition {
+    // Wherever the scroll direction puts it
+    Auto,
+    Top,
+    Bottom,
+    Left,
+    Right
+}
+
+impl KeyboardPosition {
+    pub fn from_string(s: &str) -> Option<KeyboardPosition> {
+        return match s {
+            "auto" => Some(KeyboardPosition::Auto),
+            "top" => Some(KeyboardPosition::Top),
+            "bottom" => Some(KeyboardPosition::Bottom),
+            "left" => Some(KeyboardPosition::Left),
+            "right" => Some(KeyboardPosition::Right),
+            _ => None
+        };
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -47,6 +89,8 @@ pub enum PollingType {
     PpuScanline,
     ApuQuarterFrame,
     ApuHalfFrame,
+    // Updates per second, spread across scanlines for rates that don't line up with the PPU or APU
+    Rate(f32),
 }
 
 pub struct ChannelSlice {
@@ -54,6 +98,7 @@ pub struct ChannelSlice {
     pub y: f32,
     pub thickness: f32,
     pub color: Color,
+    pub outline_color: Option<Color>,
     pub note_type: NoteType,
 
 }
@@ -65,6 +110,7 @@ impl ChannelSlice {
             y: 0.0,
             thickness: 0.0,
             color: Color::rgb(0,0,0),
+            outline_color: None,
             note_type: NoteType::Frequency,
         };
     }
@@ -73,7 +119,40 @@ impl ChannelSlice {
 #[derive(Clone)]
 pub struct ChannelSettings {
     pub colors: Vec<Color>,
-    pub hidden: bool
+    pub hidden: bool,
+    // Silences the channel independently of whether it is hidden
+    pub muted: bool,
+    // Overrides the global outline color for this channel's notes
+    pub outline_color: Option<Color>,
+    // Note thickness is the channel's amplitude times the scale, clamped to the maximum.
+    // 1.0 is the thickness of a full volume 2A03 pulse.
+    pub amplitude_scale: f32,
+    pub max_thickness: Option<f32>,
+    // Visibility in the oscilloscopes, following `hidden` when unset
+    pub scope_hidden: Option<bool>,
+    // Oscilloscopes are sorted by this, channels with the same order keep their usual place
+    pub scope_order: i32,
+    // Notes are drawn in this order, so a channel with a higher order is drawn over the others
+    pub draw_order: i32,
+    // Scales the note colors, e.g. to dim an echo channel under the lead
+    pub brightness: f32
+}
+
+impl Default for ChannelSettings {
+    fn default() -> ChannelSettings {
+        return ChannelSettings {
+            colors: Vec::new(),
+            hidden: false,
+            muted: false,
+            outline_color: None,
+            amplitude_scale: 1.0,
+            max_thickness: None,
+            scope_hidden: None,
+            scope_order: 0,
+            draw_order: 0,
+            brightness: 1.0
+        };
+    }
 }
 
 
@@ -204,6 +283,30 @@ fn draw_speaker_key_vert(canvas: &mut SimpleBuffer, color: Color, x: u32, y: u32
         color);
 }
 
+// How many frames a VRC7 channel's patch name stays up after it changes
+const PATCH_LABEL_FRAMES: u32 = 60;
+
+fn vrc7_patch_name(index: usize) -> &'static str {
+    return match index {
+        1 => "Bell",
+        2 => "Guitar",
+        3 => "Piano",
+        4 => "Flute",
+        5 => "Clarinet",
+        6 => "Rattling Bell",
+        7 => "Trumpet",
+        8 => "Reed Organ",
+        9 => "Soft Bell",
+        10 => "Xylophone",
+        11 => "Vibraphone",
+        12 => "Brass",
+        13 => "Bass Guitar",
+        14 => "Synthesizer",
+        15 => "Chorus",
+        _ => "Custom"
+    };
+}
+
 fn midi_frequency(midi_index: u32) -> f32 {
     return 440.0 * (2.0_f32).powf(((midi_index as f32) - 69.0) / 12.0);
 }
@@ -258,7 +361,8 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0xFF, 0xA0, 0xA0),   // 12.5
             Color::rgb(0xFF, 0x40, 0xFF),   // 25
             Color::rgb(0xFF, 0x40, 0x40),   // 50
-            Color::rgb(0xFF, 0x40, 0xFF))   // 75 (same as 25)
+            Color::rgb(0xFF, 0x40, 0xFF)),  // 75 (same as 25)
+        ..Default::default()
     }); 
     apu_settings.insert("Pulse 2".to_string(), ChannelSettings{
         hidden: false,
@@ -266,21 +370,25 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0xFF, 0xE0, 0xA0),   // 12.5
             Color::rgb(0xFF, 0xC0, 0x40),   // 25
             Color::rgb(0xFF, 0xFF, 0x40),   // 50
-            Color::rgb(0xFF, 0xC0, 0x40))   // 75 (same as 25)
+            Color::rgb(0xFF, 0xC0, 0x40)),  // 75 (same as 25)
+        ..Default::default()
     }); 
     apu_settings.insert("Triangle".to_string(), ChannelSettings{ 
-        hidden: false, 
-        colors: vec!(Color::rgb(0x40, 0xFF, 0x40)) 
+        hidden: false,
+        colors: vec!(Color::rgb(0x40, 0xFF, 0x40)),
+        ..Default::default()
     });
     apu_settings.insert("Noise".to_string(), ChannelSettings{ 
         hidden: false,
         colors: vec!(
            Color::rgb(192, 192, 192),
-            Color::rgb(128, 240, 255))
+            Color::rgb(128, 240, 255)),
+        ..Default::default()
     });
     apu_settings.insert("DMC".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(96,  32, 192))
+        colors: vec!(Color::rgb(96,  32, 192)),
+        ..Default::default()
     });
 
     let mut vrc6_settings: HashMap<String, ChannelSettings> = HashMap::new();
@@ -294,7 +402,8 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0x97, 0x51, 0x74),   // 31.25%
             Color::rgb(0x80, 0x37, 0x5b),   // 37.5%
             Color::rgb(0x69, 0x1d, 0x42),   // 43.75%
-            Color::rgb(0x53, 0x03, 0x2a))   // 50%
+            Color::rgb(0x53, 0x03, 0x2a)),  // 50%
+        ..Default::default()
     }); 
     vrc6_settings.insert("Pulse 2".to_string(), ChannelSettings{
         hidden: false,
@@ -306,13 +415,15 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0x92, 0x49, 0x90),   // 31.25%
             Color::rgb(0x7c, 0x31, 0x7b),   // 37.5%
             Color::rgb(0x67, 0x1a, 0x65),   // 43.75%
-            Color::rgb(0x52, 0x03, 0x50))   // 50%
+            Color::rgb(0x52, 0x03, 0x50)),  // 50%
+        ..Default::default()
     }); 
     vrc6_settings.insert("Sawtooth".to_string(), ChannelSettings{
         hidden: false,
         colors: vec!(
             Color::rgb(0x07, 0x7d, 0x5a),   // Normal
-            Color::rgb(0x9f, 0xb8, 0xed))   // Distortion
+            Color::rgb(0x9f, 0xb8, 0xed)),  // Distortion
+        ..Default::default()
     }); 
 
     let mut mmc5_settings: HashMap<String, ChannelSettings> = HashMap::new();
@@ -322,7 +433,8 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0xCC, 0x00, 0x29),
             Color::rgb(0xDF, 0x48, 0x67),
             Color::rgb(0xF2, 0x91, 0xA5),
-            Color::rgb(0xDF, 0x48, 0x67))
+            Color::rgb(0xDF, 0x48, 0x67)),
+        ..Default::default()
     });
     mmc5_settings.insert("Pulse 2".to_string(), ChannelSettings{
         hidden: false,
@@ -330,25 +442,30 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
             Color::rgb(0xCC, 0x00, 0x29),
             Color::rgb(0xDF, 0x48, 0x67),
             Color::rgb(0xF2, 0x91, 0xA5),
-            Color::rgb(0xDF, 0x48, 0x67))
+            Color::rgb(0xDF, 0x48, 0x67)),
+        ..Default::default()
     });
     mmc5_settings.insert("PCM".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(224, 24, 64))
+        colors: vec!(Color::rgb(224, 24, 64)),
+        ..Default::default()
     });
 
     let mut s5b_settings: HashMap<String, ChannelSettings> = HashMap::new();
     s5b_settings.insert("A".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(32, 144, 204))
+        colors: vec!(Color::rgb(32, 144, 204)),
+        ..Default::default()
     });
     s5b_settings.insert("B".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(24, 104, 228))
+        colors: vec!(Color::rgb(24, 104, 228)),
+        ..Default::default()
     });
     s5b_settings.insert("C".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(16, 64, 248))
+        colors: vec!(Color::rgb(16, 64, 248)),
+        ..Default::default()
     });
 
     let mut n163_settings: HashMap<String, ChannelSettings> = HashMap::new();
@@ -357,14 +474,14 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
         Color::rgb(0x66, 0x0e, 0x0e),
         Color::rgb(0xc9, 0x9c, 0x9c),
     );
-    n163_settings.insert("NAMCO 1".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 2".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 3".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 4".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 5".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 6".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 7".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
-    n163_settings.insert("NAMCO 8".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone()});
+    n163_settings.insert("NAMCO 1".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 2".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 3".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 4".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 5".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 6".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 7".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
+    n163_settings.insert("NAMCO 8".to_string(), ChannelSettings{ hidden: false, colors: wavetable_gradient.clone(), ..Default::default()});
 
     let mut vrc7_settings: HashMap<String, ChannelSettings> = HashMap::new();
     let patch_colors = vec!(
@@ -385,23 +502,25 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
         Color::rgb(0xFF, 0xD0, 0xD0), // Synthesizer
         Color::rgb(0xFF, 0xD0, 0xD0), // Chorus
     );
-    vrc7_settings.insert("FM 1".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
-    vrc7_settings.insert("FM 2".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
-    vrc7_settings.insert("FM 3".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
-    vrc7_settings.insert("FM 4".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
-    vrc7_settings.insert("FM 5".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
-    vrc7_settings.insert("FM 6".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone()});
+    vrc7_settings.insert("FM 1".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
+    vrc7_settings.insert("FM 2".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
+    vrc7_settings.insert("FM 3".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
+    vrc7_settings.insert("FM 4".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
+    vrc7_settings.insert("FM 5".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
+    vrc7_settings.insert("FM 6".to_string(), ChannelSettings{ hidden: false, colors: patch_colors.clone(), ..Default::default()});
 
     let mut fds_settings: HashMap<String, ChannelSettings> = HashMap::new();
     fds_settings.insert("Wavetable".to_string(), ChannelSettings {
         hidden: false,
-        colors: vec!(Color::rgb(0x42, 0xA5, 0xF5))
+        colors: vec!(Color::rgb(0x42, 0xA5, 0xF5)),
+        ..Default::default()
     });
 
     let mut final_mix_settings: HashMap<String, ChannelSettings> = HashMap::new();
     final_mix_settings.insert("Final Mix".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(224,  224, 224))
+        colors: vec!(Color::rgb(224,  224, 224)),
+        ..Default::default()
     });
 
     channel_settings.insert("2A03".to_string(), apu_settings);
@@ -427,21 +546,56 @@ pub struct PianoRollWindow {
     pub highest_frequency: f32,
     pub highest_index: u32,
     pub time_slices: VecDeque<Vec<ChannelSlice>>,
+    // Newest slices left out when drawing, which scrolls the roll back for frames drawn in between emulated ones
+    pub scroll_offset: u32,
+    // Slices kept past the far edge of the roll, so it's still filled in when scrolled back
+    pub scroll_margin: u32,
     pub polling_counter: usize,
+    // Fraction of an update carried over between scanlines when polling at a Rate
+    pub polling_accumulator: f32,
+    pub final_mix_level: f32,
 
     // user-configurable options
     pub key_thickness: u32,
     pub key_length: u32,
     pub surfboard_height: u32,
     pub scroll_direction: ScrollDirection,
+    pub keyboard_position: KeyboardPosition,
     pub polling_type: PollingType,
     pub speed_multiplier: u32,
     pub surfboard_line_thickness: f32,
     pub surfboard_glow_thickness: f32,
+    // Panel under the oscilloscopes showing each N163 channel's wavetable
+    pub draw_n163_wavetables: bool,
+    pub n163_wavetable_height: u32,
+    // Panel under that showing the FDS wavetable, modulation table and modulation depth
+    pub draw_fds_tables: bool,
+    pub fds_table_height: u32,
     pub draw_piano_strings: bool,
+    // Per scroll direction, overriding draw_piano_strings
+    pub draw_piano_strings_for: HashMap<ScrollDirection, bool>,
+    // Strings for the white keys, the black keys get half the brightness
+    pub piano_string_color: Color,
+    pub piano_string_opacity: f32,
     pub background_color: Color,
     pub outline_color: Color,
     pub outline_thickness: u32,
+    pub divider_color: Color,
+    pub divider_width: u32,
+    pub keyboard_glow_color: Color,
+    pub keyboard_glow_intensity: f32,
+    // Only draw the notes over a transparent background, for compositing
+    pub minimal: bool,
+    // Lines of text under the roll in the vertical layout, e.g. the track title and artist
+    pub caption: Vec<String>,
+    // Briefly show a VRC7 channel's patch name in its oscilloscope when it changes
+    pub show_patch_names: bool,
+    // Keyed on channel name: the last patch shown and how many more frames to show it for
+    pub patch_label_timers: HashMap<String, (usize, u32)>,
+    // Settings that couldn't be applied, for the host to report however it reports problems
+    pub warnings: Vec<String>,
+    // The console being emulated, for converting between polls and seconds
+    pub region: Region,
 
     // Keyed on: chip name, then channel name within that chip
     pub channel_settings: HashMap<String, HashMap<String, ChannelSettings>>,
@@ -467,17 +621,39 @@ impl PianoRollWindow {
             highest_frequency: midi_frequency(midi_index("Cs9").unwrap()), // ~C#8
             highest_index: midi_index("Cs9").unwrap(),
             time_slices: VecDeque::new(),
+            scroll_offset: 0,
+            scroll_margin: 0,
             polling_counter: 1,
+            polling_accumulator: 0.0,
+            final_mix_level: 0.0,
             scroll_direction: ScrollDirection::TopToBottom,
+            keyboard_position: KeyboardPosition::Auto,
             polling_type: PollingType::ApuQuarterFrame,
             speed_multiplier: 6,
             channel_settings: default_channel_settings(),
             surfboard_line_thickness: 0.5,
             surfboard_glow_thickness: 2.5,
+            draw_n163_wavetables: false,
+            n163_wavetable_height: 64,
+            draw_fds_tables: false,
+            fds_table_height: 64,
             draw_piano_strings: true,
+            draw_piano_strings_for: HashMap::new(),
+            piano_string_color: Color::rgb(0x0C, 0x0C, 0x0C),
+            piano_string_opacity: 1.0,
             background_color: Color::rgba(0, 0, 0, 255),
             outline_color: Color::rgba(0, 0, 0, 255),
             outline_thickness: 2,
+            divider_color: Color::rgba(0, 0, 0, 255),
+            divider_width: 5,
+            keyboard_glow_color: Color::rgba(255, 255, 255, 255),
+            keyboard_glow_intensity: 0.0,
+            minimal: false,
+            caption: Vec::new(),
+            show_patch_names: false,
+            patch_label_timers: HashMap::new(),
+            warnings: Vec::new(),
+            region: Region::Ntsc,
         };
     }
 
@@ -493,19 +669,105 @@ impl PianoRollWindow {
                 displayed_channels.push(channel);
             }
         }
+        // Stable, so channels with the same order are drawn in the usual order
+        displayed_channels.sort_by_key(|channel| self.settings_for_channel(*channel).map_or(0, |channel_settings| channel_settings.draw_order));
+
+        return displayed_channels;
+    }
+
+    fn collect_scope_channels<'a>(&self, apu: &'a ApuState, mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
+        let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
+        channels.extend(apu.channels());
+        channels.extend(mapper.channels());
+        channels.push(apu);
+
+        let mut displayed_channels: Vec<& dyn AudioChannelState> = channels.into_iter()
+            .filter(|channel| !self.channel_scope_is_hidden(*channel))
+            .collect();
+        // Stable, so channels with the same order stay in the same place as in the roll
+        displayed_channels.sort_by_key(|channel| self.channel_scope_order(*channel));
 
         return displayed_channels;
     }
 
+    fn key_area_length(&self) -> u32 {
+        if self.minimal {
+            return 0;
+        }
+        return self.key_length;
+    }
+
+    fn surfboard_area_height(&self) -> u32 {
+        if self.minimal {
+            return 0;
+        }
+        return self.surfboard_height;
+    }
+
+    fn wavetable_area_height(&self) -> u32 {
+        if self.minimal || !self.draw_n163_wavetables {
+            return 0;
+        }
+        return self.n163_wavetable_height;
+    }
+
+    fn fds_table_area_height(&self) -> u32 {
+        if self.minimal || !self.draw_fds_tables {
+            return 0;
+        }
+        return self.fds_table_height;
+    }
+
+    fn stacked_scope_area_height(&self) -> u32 {
+        if self.minimal {
+            return 0;
+        }
+        return self.canvas.height / 3;
+    }
+
+    fn caption_area_height(&self) -> u32 {
+        if self.minimal || self.caption.is_empty() {
+            return 0;
+        }
+        return (self.caption.len() as u32) * 12 + 8;
+    }
+
     fn roll_width(&self) -> u32 {
-        return self.canvas.height - self.key_length - self.surfboard_height;
+        if self.scroll_direction == ScrollDirection::Vertical {
+            return self.canvas.height - self.key_area_length() - self.stacked_scope_area_height() - self.caption_area_height();
+        }
+        return self.canvas.height - self.key_area_length() - self.surfboard_area_height() - self.wavetable_area_height() - self.fds_table_area_height();
     }
 
-    fn draw_piano_strings_horiz(&mut self, x: u32, starting_y: u32, width: u32) {
-        let white_string = Color::rgb(0x0C, 0x0C, 0x0C);
-        let black_string = Color::rgb(0x06, 0x06, 0x06);
+    pub fn updates_per_second(&self) -> f32 {
+        let frame_rate = self.region.frame_rate();
+        match self.polling_type {
+            PollingType::PpuFrame => frame_rate,
+            PollingType::PpuScanline => frame_rate * self.region.scanlines_per_frame() as f32,
+            PollingType::ApuQuarterFrame => frame_rate * 4.0,
+            PollingType::ApuHalfFrame => frame_rate * 2.0,
+            PollingType::Rate(rate) => rate,
+        }
+    }
+
+    // Picks the speed multiplier closest to scrolling a note across the whole roll in the given
+    // number of seconds, so the scroll speed is independent of canvas size and polling type
+    pub fn set_scroll_duration(&mut self, seconds: f32) {
+        let slices_per_second = (self.roll_width() as f32) / seconds;
+        let multiplier = (slices_per_second / self.updates_per_second()).round();
+        self.speed_multiplier = multiplier.max(1.0) as u32;
+    }
+
+    fn piano_strings_shown(&self) -> bool {
+        return *self.draw_piano_strings_for.get(&self.scroll_direction).unwrap_or(&self.draw_piano_strings);
+    }
 
-        let string_colors = [
+    fn piano_string_colors(&self) -> [Color; 12] {
+        let alpha = (self.piano_string_color.alpha() as f32 * self.piano_string_opacity.clamp(0.0, 1.0)) as u8;
+        let white_string = Color::rgba(self.piano_string_color.r(), self.piano_string_color.g(), self.piano_string_color.b(), alpha);
+        let black_string = Color::rgba(self.piano_string_color.r() / 2, self.piano_string_color.g() / 2, self.piano_string_color.b() / 2, alpha);
+
+        return [
             white_string, //C
             black_string, //Db
             white_string, //D
@@ -519,58 +781,48 @@ impl PianoRollWindow {
             black_string, //Bb
             white_string, //B
         ];
+    }
+
+    fn draw_piano_strings_horiz(&mut self, x: u32, starting_y: u32, width: u32) {
+        let string_colors = self.piano_string_colors();
 
         let mut key_counter = 0;
         let mut y = starting_y;
         let safety_margin = 0 + self.key_thickness * 2;
         while key_counter < self.keys && y > safety_margin {
             let string_color = string_colors[(key_counter % 12) as usize];
-            drawing::rect(&mut self.canvas, x, y, width, 1, string_color);
+            drawing::blend_rect(&mut self.canvas, x, y, width, 1, string_color);
             y -= self.key_thickness;
             key_counter += 1;
         }
     }
 
     fn draw_piano_strings_vert(&mut self, starting_x: u32, y: u32, height: u32) {
-        let white_string = Color::rgb(0x0C, 0x0C, 0x0C);
-        let black_string = Color::rgb(0x06, 0x06, 0x06);
-
-        let string_colors = [
-            white_string, //C
-            black_string, //Db
-            white_string, //D
-            black_string, //Eb
-            white_string, //E
-            white_string, //F
-            black_string, //Gb
-            white_string, //G
-            black_string, //Ab
-            white_string, //A
-            black_string, //Bb
-            white_string, //B
-        ];
+        let string_colors = self.piano_string_colors();
 
         let mut key_counter = 0;
         let mut x = starting_x;
         let safety_margin = self.canvas.width - self.key_thickness * 2;
         while key_counter < self.keys && x < safety_margin {
             let string_color = string_colors[(key_counter % 12) as usize];
-            drawing::rect(&mut self.canvas, x, y, 1, height, string_color);
+            drawing::blend_rect(&mut self.canvas, x, y, 1, height, string_color);
             x += self.key_thickness; // TODO: it's not "height" anymore, more like key_size?
             key_counter += 1;
         }
     }
 
     fn draw_waveform_string_horiz(&mut self, x: u32, y: u32, width: u32) {
-        let waveform_string = Color::rgb(0x06, 0x06, 0x06);
+        // Same color as the black key strings
+        let waveform_string = self.piano_string_colors()[1];
         // Draw one extra string for the waveform display
-        drawing::rect(&mut self.canvas, x, y, width, 1, waveform_string);
+        drawing::blend_rect(&mut self.canvas, x, y, width, 1, waveform_string);
     }
 
     fn draw_waveform_string_vert(&mut self, x: u32, y: u32, height: u32) {
-        let waveform_string = Color::rgb(0x06, 0x06, 0x06);
+        // Same color as the black key strings
+        let waveform_string = self.piano_string_colors()[1];
         // Draw one extra string for the waveform display
-        drawing::rect(&mut self.canvas, x, y, 1, height, waveform_string);
+        drawing::blend_rect(&mut self.canvas, x, y, 1, height, waveform_string);
     }
 
     // TOTO: this is hard-coded and isn't especially flexible. Shouldn't we use the key spot routines
@@ -812,6 +1064,21 @@ impl PianoRollWindow {
         }
     }
 
+    fn settings_for_channel(&self, channel: &dyn AudioChannelState) -> Option<&ChannelSettings> {
+        return self.channel_settings.get(&channel.chip()).and_then(|chip_settings| chip_settings.get(&channel.name()));
+    }
+
+    pub fn channel_scope_is_hidden(&self, channel: &dyn AudioChannelState) -> bool {
+        match self.settings_for_channel(channel).and_then(|channel_settings| channel_settings.scope_hidden) {
+            Some(scope_hidden) => {return scope_hidden;},
+            None => {return self.channel_is_hidden(channel);}
+        }
+    }
+
+    fn channel_scope_order(&self, channel: &dyn AudioChannelState) -> i32 {
+        return self.settings_for_channel(channel).map_or(0, |channel_settings| channel_settings.scope_order);
+    }
+
     pub fn channel_colors(&self, channel: &dyn AudioChannelState) -> Vec<Color> {
         if channel.muted() {
             return vec!(Color::rgb(32, 32, 32));
@@ -837,6 +1104,29 @@ impl PianoRollWindow {
         }
     }
 
+    fn channel_amplitude(&self, channel: &dyn AudioChannelState) -> f32 {
+        let amplitude = channel.amplitude();
+        match self.channel_settings.get(&channel.chip()).and_then(|chip_settings| chip_settings.get(&channel.name())) {
+            Some(channel_settings) => {
+                let scaled_amplitude = amplitude * channel_settings.amplitude_scale;
+                match channel_settings.max_thickness {
+                    Some(max_thickness) => scaled_amplitude.min(max_thickness),
+                    None => scaled_amplitude
+                }
+            },
+            None => amplitude
+        }
+    }
+
+    fn channel_outline_color(&self, channel: &dyn AudioChannelState) -> Option<Color> {
+        if channel.muted() {
+            return None;
+        }
+        return self.channel_settings.get(&channel.chip())
+            .and_then(|chip_settings| chip_settings.get(&channel.name()))
+            .and_then(|channel_settings| channel_settings.outline_color);
+    }
+
     fn channel_color(&self, channel: &dyn AudioChannelState) -> Color {
         let colors = self.channel_colors(channel);
         let mut color = colors[0]; // default to the first color
@@ -864,7 +1154,7 @@ impl PianoRollWindow {
         }
 
         let y: f32;
-        let thickness: f32 = channel.amplitude() * 6.0;
+        let thickness: f32 = self.channel_amplitude(channel) * 6.0;
         let colors = self.channel_colors(channel);
         let mut color = colors[0]; // default to the first color
         let note_type: NoteType;
@@ -907,11 +1197,17 @@ impl PianoRollWindow {
             None => {},
         }
 
+        let brightness = self.settings_for_channel(channel).map_or(1.0, |channel_settings| channel_settings.brightness);
+        if brightness != 1.0 {
+            color = PianoRollWindow::scale_color(color, brightness.max(0.0).min(1.0));
+        }
+
         return ChannelSlice{
             visible: true,
             y: y,
             thickness: thickness,
             color: color,
+            outline_color: self.channel_outline_color(channel),
             note_type: note_type
         };
     }
@@ -1042,7 +1338,7 @@ impl PianoRollWindow {
 
     fn draw_slices_horiz(&mut self, starting_x: u32, base_y: u32, step_direction: i32) {
         let mut x = starting_x;
-        for channel_slice in self.time_slices.iter() {
+        for channel_slice in self.time_slices.iter().skip(self.scroll_offset as usize) {
             for note in channel_slice.iter() {
                 PianoRollWindow::draw_slice_horiz(&mut self.canvas, &note, x, base_y, self.key_thickness);
             }
@@ -1056,49 +1352,54 @@ impl PianoRollWindow {
 
     fn draw_outlines_vert(&mut self, base_x: u32, starting_y: u32, step_direction: i32, waveform_pos: u32) {
         let mut y = starting_y;
-        for channel_slice in self.time_slices.iter() {
+        for channel_slice in self.time_slices.iter().skip(self.scroll_offset as usize) {
             for note in channel_slice.iter() {
+                let outline_color = note.outline_color.unwrap_or(self.outline_color);
                 if note.note_type == NoteType::Waveform {
-                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness, self.outline_color, self.outline_thickness);
+                    if self.minimal {continue;}
+                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness, outline_color, self.outline_thickness);
                 } else {
-                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, base_x, y, self.key_thickness, self.outline_color, self.outline_thickness);
+                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, base_x, y, self.key_thickness, outline_color, self.outline_thickness);
                 }
             }
-            // bail if we hit either screen edge:
-            if (y as i32 + step_direction) == 0 || y == (self.canvas.height - 1) {
+            // bail if the next slice is past the screen edge we're heading towards:
+            let next_y = y as i32 + step_direction;
+            if next_y < 0 || next_y >= self.canvas.height as i32 {
                 return; //bail! don't draw offscreen
             }
-            y = (y as i32 + step_direction) as u32;
+            y = next_y as u32;
         }
     }
 
     fn draw_slices_vert(&mut self, base_x: u32, starting_y: u32, step_direction: i32, waveform_pos: u32) {
         let mut y = starting_y;
-        for channel_slice in self.time_slices.iter() {
+        for channel_slice in self.time_slices.iter().skip(self.scroll_offset as usize) {
             for note in channel_slice.iter() {
                 if note.note_type == NoteType::Waveform {
+                    if self.minimal {continue;}
                     PianoRollWindow::draw_slice_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness);
                 } else {
                     PianoRollWindow::draw_slice_vert(&mut self.canvas, &note, base_x, y, self.key_thickness);
                 }
             }
-            // bail if we hit either screen edge:
-            if (y as i32 + step_direction) == 0 || y == (self.canvas.height - 1) {
+            // bail if the next slice is past the screen edge we're heading towards:
+            let next_y = y as i32 + step_direction;
+            if next_y < 0 || next_y >= self.canvas.height as i32 {
                 return; //bail! don't draw offscreen
             }
-            y = (y as i32 + step_direction) as u32;
+            y = next_y as u32;
 
         }
     }
 
     fn draw_key_spots_horiz(&mut self, x: u32, base_y: u32) {
-        for note in self.time_slices.front().unwrap_or(&Vec::new()) {
+        for note in self.time_slices.get(self.scroll_offset as usize).unwrap_or(&Vec::new()) {
             PianoRollWindow::draw_key_spot_horiz(&mut self.canvas, &note, self.key_thickness, x, base_y);
         }
     }
 
     fn draw_key_spots_vert(&mut self, base_x: u32, y: u32, waveform_pos: u32) {
-        for note in self.time_slices.front().unwrap_or(&Vec::new()) {
+        for note in self.time_slices.get(self.scroll_offset as usize).unwrap_or(&Vec::new()) {
             if note.note_type == NoteType::Waveform {
                 if note.visible {
                     let mut base_color = note.color;
@@ -1127,7 +1428,45 @@ impl PianoRollWindow {
         }
     }
 
+    // Peak level of the most recent final mix samples, from 0.0 to 1.0
+    fn final_mix_level(apu: &ApuState) -> f32 {
+        let sample_buffer = apu.sample_buffer();
+        let buffer = sample_buffer.buffer();
+        let window_size = buffer.len().min(1024);
+        let mut index = (sample_buffer.index() + buffer.len() - window_size) % buffer.len();
+        let mut peak: i32 = 0;
+        for _i in 0 .. window_size {
+            peak = peak.max((buffer[index] as i32).abs());
+            index = (index + 1) % buffer.len();
+        }
+        return (peak as f32 / apu.max_sample() as f32).min(1.0);
+    }
+
+    fn keyboard_glow_color(&self) -> Option<Color> {
+        let alpha = self.keyboard_glow_color.alpha() as f32 * self.keyboard_glow_intensity * self.final_mix_level;
+        if alpha < 1.0 {
+            return None;
+        }
+        let mut color = self.keyboard_glow_color;
+        color.set_alpha(alpha.min(255.0) as u8);
+        return Some(color);
+    }
+
+    fn draw_keyboard_glow_horiz(&mut self, x: u32, base_y: u32) {
+        if let Some(color) = self.keyboard_glow_color() {
+            let keyboard_height = (self.keys * self.key_thickness - 1).min(base_y);
+            drawing::blend_rect(&mut self.canvas, x, base_y - keyboard_height, self.key_length, keyboard_height, color);
+        }
+    }
+
+    fn draw_keyboard_glow_vert(&mut self, base_x: u32, y: u32) {
+        if let Some(color) = self.keyboard_glow_color() {
+            drawing::blend_rect(&mut self.canvas, base_x, y, self.keys * self.key_thickness, self.key_length, color);
+        }
+    }
+
     fn update(&mut self, apu: &ApuState, mapper: &dyn Mapper) {
+        self.final_mix_level = PianoRollWindow::final_mix_level(apu);
         let channels = self.collect_channels(&apu, &*mapper);
 
         for _i in 0 .. self.speed_multiplier {
@@ -1138,7 +1477,7 @@ impl PianoRollWindow {
             self.time_slices.push_front(frame_notes);
         }
 
-        while self.time_slices.len() > self.roll_width() as usize {
+        while self.time_slices.len() > (self.roll_width() + self.scroll_margin) as usize {
             self.time_slices.pop_back();
         }
     }
@@ -1211,14 +1550,14 @@ impl PianoRollWindow {
     }
 
     fn draw_channel_dividers(&mut self, x: u32, y: u32, width: u32, height: u32) {
-        // TODO: make both of these tweakable settings
-        let mut base_color = Color::rgba(0, 0, 0, 255);
-        let divider_width = 5;
+        let mut base_color = self.divider_color;
+        let divider_width = self.divider_width.min(width / 2);
+        let base_alpha = base_color.alpha() as u32;
 
         for dx in 0 .. divider_width {
             let gradient_index: u32 = (255 * (divider_width - dx)) / divider_width;
             let color_weight: u32 = (gradient_index * gradient_index) / 255;
-            base_color.set_alpha(color_weight as u8);
+            base_color.set_alpha(((color_weight * base_alpha) / 255) as u8);
             drawing::blend_rect(&mut self.canvas, x + dx, y, 1, height, base_color);
             drawing::blend_rect(&mut self.canvas, x + width - dx - 1, y, 1, height, base_color);
         }
@@ -1277,10 +1616,28 @@ impl PianoRollWindow {
         // drawing::text(&mut self.canvas, &self.font, channel_x + 0, channel_y, &channel_label, transparent_color);
         // drawing::text(&mut self.canvas, &self.font, channel_x + 1, channel_y, &channel_label, transparent_color);
         drawing::text(&mut self.canvas, &self.font, channel_x, channel_y, &channel_label, chip_color);
+
+        if self.show_patch_names && channel.chip() == "VRC7" {
+            if let Some(Timbre::PatchIndex{index, ..}) = channel.timbre() {
+                let timer = self.patch_label_timers.entry(channel.name()).or_insert((index, PATCH_LABEL_FRAMES));
+                if timer.0 != index {
+                    *timer = (index, PATCH_LABEL_FRAMES);
+                }
+                if timer.1 > 0 {
+                    // Fades out over the time it's shown
+                    let alpha = ((0xC0 * timer.1) / PATCH_LABEL_FRAMES) as u8;
+                    drawing::text(&mut self.canvas, &self.font, chip_x, chip_y + 12, vrc7_patch_name(index), Color::rgba(0xFF, 0xFF, 0xFF, alpha));
+                }
+            }
+        }
     }
 
     fn draw_audio_surfboard_horiz(&mut self, runtime: &RuntimeState, x: u32, y: u32, width: u32, height: u32) {
-        let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        let channels = self.collect_scope_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        // Every scope can be hidden
+        if channels.is_empty() {
+            return;
+        }
         let channel_width = width / (channels.len() as u32);
         let mut leftover_pixels = width - (channel_width * (channels.len() as u32));
         let mut cx = 0;
@@ -1298,6 +1655,167 @@ impl PianoRollWindow {
         }
     }
 
+    fn draw_channel_wavetable(&mut self, channel: &dyn AudioChannelState, wavetable: &[u8], max_sample: u8, x: u32, y: u32, width: u32, height: u32) {
+        let color = self.channel_color(channel);
+        self.draw_surfboard_background(x, y, width, height, color);
+        self.draw_channel_labels(channel, x, y, width, height);
+
+        // Drawn with the highest sample at the top and a little room for the glow
+        let margin = 4.0;
+        let sample_y = |sample: u8| margin + ((max_sample - sample.min(max_sample)) as f32 * (height as f32 - 2.0 * margin)) / (max_sample as f32);
+        let glow_color = PianoRollWindow::scale_color(color, 0.25);
+        let mut last_y = sample_y(wavetable[0]);
+        for i in 0 .. width {
+            let sample_index = ((i as usize) * wavetable.len()) / (width as usize);
+            let current_y = sample_y(wavetable[sample_index]);
+            let top_edge = last_y.min(current_y);
+            let bottom_edge = last_y.max(current_y);
+            self.draw_vertical_antialiased_line(x + i, y as f32 + top_edge - self.surfboard_glow_thickness, y as f32 + bottom_edge + self.surfboard_glow_thickness, glow_color);
+            self.draw_vertical_antialiased_line(x + i, y as f32 + top_edge - self.surfboard_line_thickness, y as f32 + bottom_edge + self.surfboard_line_thickness, color);
+            last_y = current_y;
+        }
+    }
+
+    fn draw_n163_wavetables(&mut self, runtime: &RuntimeState, x: u32, y: u32, width: u32, height: u32) {
+        // The mapper lists the enabled N163 channels in the same order as their wavetables
+        let wavetables = runtime.nes.mapper.n163_wavetables();
+        let mapper_channels = runtime.nes.mapper.channels();
+        let n163_channels = mapper_channels.iter().filter(|channel| channel.chip() == "N163");
+        let shown: Vec<(&dyn AudioChannelState, &Vec<u8>)> = n163_channels.zip(wavetables.iter())
+            .filter(|&(channel, _)| !self.channel_scope_is_hidden(*channel))
+            .map(|(channel, wavetable)| (*channel, wavetable))
+            .collect();
+        if shown.is_empty() {
+            return;
+        }
+
+        let channel_width = width / (shown.len() as u32);
+        let mut leftover_pixels = width - (channel_width * (shown.len() as u32));
+        let mut cx = 0;
+        for (channel, wavetable) in shown {
+            let mut effective_width = channel_width;
+            if leftover_pixels > 0 {
+                effective_width += 1;
+                leftover_pixels -= 1;
+            }
+            let dx = x + cx;
+            self.draw_channel_wavetable(channel, wavetable, 15, dx, y, effective_width, height);
+            self.draw_channel_dividers(dx, y, effective_width, height);
+            cx = cx + effective_width;
+        }
+    }
+
+    fn draw_fds_tables(&mut self, runtime: &RuntimeState, x: u32, y: u32, width: u32, height: u32) {
+        let fds = match runtime.nes.mapper.fds_channel() {
+            Some(fds) => fds,
+            None => {return;}
+        };
+        if self.channel_scope_is_hidden(fds) {
+            return;
+        }
+
+        // Wavetable on the left, modulation table on the right
+        let wave_width = width / 2;
+        let mod_x = x + wave_width;
+        let mod_width = width - wave_width;
+        self.draw_channel_wavetable(fds, &fds.wave_table.table, 63, x, y, wave_width, height);
+        self.draw_channel_dividers(x, y, wave_width, height);
+
+        let color = self.channel_color(fds);
+        self.draw_surfboard_background(mod_x, y, mod_width, height, color);
+
+        // Each entry bends the pitch by this much, except for 4 which resets the modulation position
+        let mod_offsets: [i32; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+        let mod_position = (fds.mod_table.phase >> 16) & 0x3F;
+        let center_y = y + height / 2;
+        let step_height = (height / 2).saturating_sub(4) / 4;
+        let dim_color = PianoRollWindow::scale_color(color, 0.5);
+        for i in 0 .. 64u32 {
+            let entry = fds.mod_table.table[i as usize] as usize & 0x7;
+            let entry_x = mod_x + (i * mod_width) / 64;
+            let entry_width = (((i + 1) * mod_width) / 64 - (i * mod_width) / 64).max(1);
+            // The entry being applied right now is drawn at full brightness
+            let entry_color = match i as usize == mod_position && !fds.mod_table.mod_halt {
+                true => color,
+                false => dim_color
+            };
+            let offset = mod_offsets[entry];
+            if entry == 4 {
+                drawing::blend_rect(&mut self.canvas, entry_x, y + 4, entry_width, height.saturating_sub(8), PianoRollWindow::scale_color(entry_color, 0.25));
+            } else if offset > 0 {
+                let bar_height = (offset as u32) * step_height;
+                drawing::blend_rect(&mut self.canvas, entry_x, center_y - bar_height, entry_width, bar_height, entry_color);
+            } else if offset < 0 {
+                let bar_height = (-offset as u32) * step_height;
+                drawing::blend_rect(&mut self.canvas, entry_x, center_y, entry_width, bar_height, entry_color);
+            }
+        }
+        drawing::blend_rect(&mut self.canvas, mod_x, center_y, mod_width, 1, dim_color);
+
+        let label_color = Color::rgba(0xFF, 0xFF, 0xFF, 0x33);
+        drawing::text(&mut self.canvas, &self.font, mod_x + 8, y + 4, "MOD", label_color);
+        let depth_label = format!("DEPTH {}", fds.mod_envelope.out);
+        let depth_x = mod_x + mod_width - 8 - (depth_label.len() as u32) * 8;
+        drawing::text(&mut self.canvas, &self.font, depth_x, y + height - 4 - 8, &depth_label, label_color);
+        self.draw_channel_dividers(mod_x, y, mod_width, height);
+    }
+
+    fn draw_row_dividers(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let mut base_color = self.divider_color;
+        let divider_width = self.divider_width.min(height / 2);
+        let base_alpha = base_color.alpha() as u32;
+
+        for dy in 0 .. divider_width {
+            let gradient_index: u32 = (255 * (divider_width - dy)) / divider_width;
+            let color_weight: u32 = (gradient_index * gradient_index) / 255;
+            base_color.set_alpha(((color_weight * base_alpha) / 255) as u8);
+            drawing::blend_rect(&mut self.canvas, x, y + dy, width, 1, base_color);
+            drawing::blend_rect(&mut self.canvas, x, y + height - dy - 1, width, 1, base_color);
+        }
+    }
+
+    // One full-width oscilloscope row per channel
+    fn draw_audio_surfboard_stacked(&mut self, runtime: &RuntimeState, x: u32, y: u32, width: u32, height: u32) {
+        let channels = self.collect_scope_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        if channels.is_empty() {
+            return;
+        }
+        let channel_height = height / (channels.len() as u32);
+        let mut leftover_pixels = height - (channel_height * (channels.len() as u32));
+        let mut cy = 0;
+        for i in 0 .. channels.len() {
+            let mut effective_height = channel_height;
+            if leftover_pixels > 0 {
+                effective_height += 1;
+                leftover_pixels -= 1;
+            }
+            let channel = channels[i];
+            let dy = y + cy;
+            self.draw_channel_surfboard(channel, x, dy, width, effective_height);
+            self.draw_row_dividers(x, dy, width, effective_height);
+            cy = cy + effective_height;
+        }
+    }
+
+    fn draw_caption(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let background_color = self.background_color;
+        drawing::rect(&mut self.canvas, x, y, width, height, background_color);
+
+        let text_color = Color::rgba(0xFF, 0xFF, 0xFF, 0xC0);
+        let max_chars = (width / self.font.glyph_width) as usize;
+        for (i, line) in self.caption.iter().enumerate() {
+            // The font only has printable ASCII
+            let line: String = line.chars()
+                .map(|c| if c == ' ' || c.is_ascii_graphic() { c } else { '?' })
+                .take(max_chars)
+                .collect();
+            let line_width_px = (line.chars().count() as u32) * self.font.glyph_width;
+            let line_x = x + (width - line_width_px) / 2;
+            let line_y = y + 4 + (i as u32) * 12;
+            drawing::text(&mut self.canvas, &self.font, line_x, line_y, &line, text_color);
+        }
+    }
+
     pub fn mouse_mutes_channel_horiz(&mut self, runtime: &RuntimeState, sx: u32, sy: u32, width: u32, height: u32, mouse_x: i32, mouse_y: i32) -> Vec<Event> {
         let mut events: Vec<Event> = Vec::new();
         if mouse_x < 0 || mouse_y < 0 {
@@ -1305,7 +1823,10 @@ impl PianoRollWindow {
         }
         let mx = mouse_x as u32;
         let my = mouse_y as u32;
-        let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        let channels = self.collect_scope_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        if channels.is_empty() {
+            return events;
+        }
         let channel_width = width / (channels.len() as u32);
         for i in 0 .. channels.len() {
             let channel = channels[i];
@@ -1321,82 +1842,145 @@ impl PianoRollWindow {
         return events;
     }
 
-    fn draw_right_to_left(&mut self) {
-        let waveform_area_height = 32;
-        let waveform_string_pos = self.canvas.height - 16;
-        let key_width = 16;
-        let bottom_key = self.canvas.height - waveform_area_height;
-        let string_width = self.canvas.width - key_width;
+    // Top and bottom only apply to vertical scrolling, left and right to horizontal
+    fn keyboard_on_top(&self, default: bool) -> bool {
+        return match self.keyboard_position {
+            KeyboardPosition::Top => true,
+            KeyboardPosition::Bottom => false,
+            _ => default
+        };
+    }
 
-        if self.draw_piano_strings {
-            self.draw_piano_strings_horiz(0, bottom_key, string_width);
-            self.draw_waveform_string_horiz(0, waveform_string_pos, string_width);
-        }
-        self.draw_piano_keys_horiz(string_width, bottom_key);
-        //draw_speaker_key(&mut self.canvas, black_key);
-        self.draw_slices_horiz(string_width, bottom_key, -1);
-        self.draw_key_spots_horiz(string_width, bottom_key);
+    fn keyboard_on_left(&self, default: bool) -> bool {
+        return match self.keyboard_position {
+            KeyboardPosition::Left => true,
+            KeyboardPosition::Right => false,
+            _ => default
+        };
     }
 
-    fn draw_left_to_right(&mut self) {
+    fn draw_horizontal(&mut self, keys_on_left: bool) {
         let waveform_area_height = 32;
         let waveform_string_pos = self.canvas.height - 16;
         let key_width = 16;
         let bottom_key = self.canvas.height - waveform_area_height;
         let string_width = self.canvas.width - key_width;
+        let (key_x, string_x, slice_x, step_direction) = match keys_on_left {
+            true => (0, key_width, key_width, 1),
+            false => (string_width, 0, string_width, -1)
+        };
 
-        if self.draw_piano_strings {
-            self.draw_piano_strings_horiz(key_width, bottom_key, string_width);
-            self.draw_waveform_string_horiz(key_width, waveform_string_pos, string_width);
+        if self.minimal {
+            self.draw_slices_horiz(slice_x, bottom_key, step_direction);
+            return;
         }
-        self.draw_piano_keys_horiz(0, bottom_key);
-        self.draw_slices_horiz(key_width, bottom_key, 1);
-        self.draw_key_spots_horiz(0, bottom_key);
-    }
 
-    fn draw_top_to_bottom(&mut self, runtime: &RuntimeState) {
-        let keyboard_width = self.keys * self.key_thickness;
-        let waveform_area_width = ((self.canvas.width - keyboard_width) / 2).max(20);
+        if self.piano_strings_shown() {
+            self.draw_piano_strings_horiz(string_x, bottom_key, string_width);
+            self.draw_waveform_string_horiz(string_x, waveform_string_pos, string_width);
+        }
+        self.draw_piano_keys_horiz(key_x, bottom_key);
+        self.draw_keyboard_glow_horiz(key_x, bottom_key);
+        //draw_speaker_key(&mut self.canvas, black_key);
+        self.draw_slices_horiz(slice_x, bottom_key, step_direction);
+        self.draw_key_spots_horiz(key_x, bottom_key);
+    }
 
-        let waveform_string_pos = waveform_area_width / 2;
+    // Shared by top to bottom and bottom to top, which only differ in the waveform area and
+    // where the keyboard goes by default. The oscilloscopes are always at the top.
+    fn draw_vertical_roll(&mut self, runtime: &RuntimeState, waveform_area_width: u32, waveform_string_pos: u32, keys_on_top: bool) {
         let waveform_margin = self.key_thickness / 2;
-        let key_height = self.key_length;
+        let key_height = self.key_area_length();
         let leftmost_key = waveform_area_width + waveform_margin;
-        let surfboard_height = self.surfboard_height;
-        let string_height = self.canvas.height - key_height - surfboard_height;
+        let surfboard_height = self.surfboard_area_height();
+        let wavetable_height = self.wavetable_area_height();
+        let fds_table_height = self.fds_table_area_height();
+        let panel_height = surfboard_height + wavetable_height + fds_table_height;
+        let string_height = self.canvas.height - key_height - panel_height;
+
+        if self.minimal {
+            if keys_on_top {
+                self.draw_outlines_vert(leftmost_key, 0, 1, waveform_string_pos);
+                self.draw_slices_vert(leftmost_key, 0, 1, waveform_string_pos);
+            } else {
+                self.draw_outlines_vert(leftmost_key, self.canvas.height - 1, -1, waveform_string_pos);
+                self.draw_slices_vert(leftmost_key, self.canvas.height - 1, -1, waveform_string_pos);
+            }
+            return;
+        }
+
+        let (key_y, string_y, slice_y, step_direction) = match keys_on_top {
+            true => (panel_height, panel_height + key_height, panel_height + key_height, 1),
+            false => (self.canvas.height - key_height, panel_height, self.canvas.height - key_height, -1)
+        };
 
-        if self.draw_piano_strings {
-            self.draw_piano_strings_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, string_height);
-            self.draw_waveform_string_vert(waveform_string_pos, surfboard_height + key_height, string_height);
+        if self.piano_strings_shown() {
+            self.draw_piano_strings_vert(leftmost_key, string_y, string_height);
+            self.draw_waveform_string_vert(waveform_string_pos, string_y, string_height);
         }
 
-        self.draw_outlines_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, 1, waveform_string_pos);
-        self.draw_piano_keys_vert(leftmost_key, surfboard_height);
-        self.draw_slices_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, 1, waveform_string_pos);
-        self.draw_key_spots_vert(leftmost_key, surfboard_height, waveform_string_pos);
-        
+        self.draw_outlines_vert(leftmost_key, slice_y, step_direction, waveform_string_pos);
+        self.draw_piano_keys_vert(leftmost_key, key_y);
+        self.draw_keyboard_glow_vert(leftmost_key, key_y);
+        self.draw_slices_vert(leftmost_key, slice_y, step_direction, waveform_string_pos);
+        self.draw_key_spots_vert(leftmost_key, key_y, waveform_string_pos);
+
         self.draw_audio_surfboard_horiz(runtime, 0, 0, self.canvas.width, surfboard_height);
+        if wavetable_height > 0 {
+            self.draw_n163_wavetables(runtime, 0, surfboard_height, self.canvas.width, wavetable_height);
+        }
+        if fds_table_height > 0 {
+            self.draw_fds_tables(runtime, 0, surfboard_height + wavetable_height, self.canvas.width, fds_table_height);
+        }
+    }
+
+    fn draw_top_to_bottom(&mut self, runtime: &RuntimeState) {
+        let keyboard_width = self.keys * self.key_thickness;
+        let waveform_area_width = ((self.canvas.width - keyboard_width) / 2).max(20);
+        let keys_on_top = self.keyboard_on_top(true);
+        self.draw_vertical_roll(runtime, waveform_area_width, waveform_area_width / 2, keys_on_top);
     }
 
     fn draw_bottom_to_top(&mut self, runtime: &RuntimeState) {
         let waveform_area_width = self.key_thickness * 4;
-        let waveform_string_pos = self.key_thickness * 2;
+        let keys_on_top = self.keyboard_on_top(false);
+        self.draw_vertical_roll(runtime, waveform_area_width, self.key_thickness * 2, keys_on_top);
+    }
+
+    fn draw_vertical(&mut self, runtime: &RuntimeState) {
+        let keyboard_width = self.keys * self.key_thickness;
+        let waveform_area_width = ((self.canvas.width - keyboard_width) / 2).max(20);
+
+        let waveform_string_pos = waveform_area_width / 2;
         let waveform_margin = self.key_thickness / 2;
-        let key_height = self.key_length;
+        let key_height = self.key_area_length();
         let leftmost_key = waveform_area_width + waveform_margin;
-        let surfboard_height = self.surfboard_height;
-        let string_height = self.canvas.height - key_height - surfboard_height;
+        let scope_height = self.stacked_scope_area_height();
+        let caption_height = self.caption_area_height();
+        let string_height = self.canvas.height - scope_height - key_height - caption_height;
 
-        if self.draw_piano_strings {
-            self.draw_piano_strings_vert(waveform_area_width + waveform_margin, 0, string_height);
-            self.draw_waveform_string_vert(waveform_string_pos, 0, string_height);
+        if self.minimal {
+            self.draw_outlines_vert(waveform_area_width + waveform_margin, 0, 1, waveform_string_pos);
+            self.draw_slices_vert(waveform_area_width + waveform_margin, 0, 1, waveform_string_pos);
+            return;
         }
-        self.draw_outlines_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, 1, waveform_string_pos);
-        self.draw_piano_keys_vert(leftmost_key, self.canvas.height - key_height);
-        self.draw_slices_vert(waveform_area_width + waveform_margin, self.canvas.height - key_height, -1, waveform_string_pos);
-        self.draw_key_spots_vert(leftmost_key, self.canvas.height - key_height, waveform_string_pos);
 
-        self.draw_audio_surfboard_horiz(runtime, 0, 0, self.canvas.width, surfboard_height);
+        if self.piano_strings_shown() {
+            self.draw_piano_strings_vert(waveform_area_width + waveform_margin, scope_height + key_height, string_height);
+            self.draw_waveform_string_vert(waveform_string_pos, scope_height + key_height, string_height);
+        }
+
+        self.draw_outlines_vert(waveform_area_width + waveform_margin, scope_height + key_height, 1, waveform_string_pos);
+        self.draw_piano_keys_vert(leftmost_key, scope_height);
+        self.draw_keyboard_glow_vert(leftmost_key, scope_height);
+        self.draw_slices_vert(waveform_area_width + waveform_margin, scope_height + key_height, 1, waveform_string_pos);
+        self.draw_key_spots_vert(leftmost_key, scope_height, waveform_string_pos);
+
+        self.draw_audio_surfboard_stacked(runtime, 0, 0, self.canvas.width, scope_height);
+        if caption_height > 0 {
+            let caption_y = self.canvas.height - caption_height;
+            self.draw_caption(0, caption_y, self.canvas.width, caption_height);
+        }
     }
 
     fn draw_player_piano(&mut self) {
@@ -1406,9 +1990,17 @@ impl PianoRollWindow {
         let leftmost_key = waveform_area_width;
         let string_height = self.canvas.height - key_height;
 
-        self.draw_piano_strings_vert(waveform_area_width, 0, string_height);
-        self.draw_waveform_string_vert(waveform_string_pos, 0, string_height);
+        if self.minimal {
+            self.draw_slices_vert(waveform_area_width, 1, 1, waveform_string_pos);
+            return;
+        }
+
+        if self.piano_strings_shown() {
+            self.draw_piano_strings_vert(waveform_area_width, 0, string_height);
+            self.draw_waveform_string_vert(waveform_string_pos, 0, string_height);
+        }
         self.draw_piano_keys_vert(leftmost_key, self.canvas.height - key_height);
+        self.draw_keyboard_glow_vert(leftmost_key, self.canvas.height - key_height);
 
         self.draw_slices_vert(waveform_area_width, 1, 1, waveform_string_pos);
         self.draw_key_spots_vert_inverted(leftmost_key, self.canvas.height - key_height, waveform_string_pos);
@@ -1417,13 +2009,18 @@ impl PianoRollWindow {
     fn draw(&mut self, runtime: &RuntimeState) {
         let width = self.canvas.width;
         let height = self.canvas.height;
-        drawing::rect(&mut self.canvas, 0, 0, width, height, self.background_color);
+        let background_color = match self.minimal {
+            true => Color::rgba(0, 0, 0, 0),
+            false => self.background_color
+        };
+        drawing::rect(&mut self.canvas, 0, 0, width, height, background_color);
         match self.scroll_direction {
-            ScrollDirection::RightToLeft => {self.draw_right_to_left()},
-            ScrollDirection::LeftToRight => {self.draw_left_to_right()},
+            ScrollDirection::RightToLeft => {let keys_on_left = self.keyboard_on_left(false); self.draw_horizontal(keys_on_left)},
+            ScrollDirection::LeftToRight => {let keys_on_left = self.keyboard_on_left(true); self.draw_horizontal(keys_on_left)},
             ScrollDirection::TopToBottom => {self.draw_top_to_bottom(runtime)},
             ScrollDirection::BottomToTop => {self.draw_bottom_to_top(runtime)},
-            ScrollDirection::PlayerPiano => {self.draw_player_piano()}
+            ScrollDirection::PlayerPiano => {self.draw_player_piano()},
+            ScrollDirection::Vertical => {self.draw_vertical(runtime)}
         }
     }
 
@@ -1477,18 +2074,65 @@ impl PianoRollWindow {
                             "hidden" => {
                                 channel_settings.hidden = new_value;
                             },
+                            "muted" => {
+                                channel_settings.muted = new_value;
+                            },
+                            "scope_hidden" => {
+                                channel_settings.scope_hidden = Some(new_value);
+                            },
+                            _ => {
+                                self.warnings.push(format!("Failed to apply unrecognized setting {} to channel {}", setting_name, channel_name));
+                            }
+                        }
+                    },
+                    None => {
+                        self.warnings.push(format!("Failed to apply setting {} to unknown channel {}", setting_name, channel_name));
+                    }
+                }
+            },
+            None => {
+                self.warnings.push(format!("Failed to apply setting {} to unknown audio chip {}", setting_name, chip_name));
+            }
+        }
+    }
+
+    fn apply_channel_float_setting(&mut self, chip_name: &str, channel_name: &str, setting_name: &str, new_value: f32) {
+        match self.channel_settings.get_mut(chip_name) {
+            Some(chip_settings) => {
+                match chip_settings.get_mut(channel_name) {
+                    Some(channel_settings) => {
+                        match setting_name {
+                            "amplitude_scale" => {
+                                channel_settings.amplitude_scale = new_value;
+                            },
+                            "max_thickness" => {
+                                // Zero or less turns the clamp off
+                                channel_settings.max_thickness = match new_value > 0.0 {
+                                    true => Some(new_value),
+                                    false => None
+                                };
+                            },
+                            "scope_order" => {
+                                channel_settings.scope_order = new_value as i32;
+                            },
+                            "draw_order" => {
+                                channel_settings.draw_order = new_value as i32;
+                            },
+                            "brightness" => {
+                                channel_settings.brightness = new_value;
+                            },
                             _ => {
-                                println!("Warning: Failed to apply unrecognized setting {} to channel {}", setting_name, channel_name);
+                                self.warnings.push(format!("Failed to apply unrecognized setting {} to channel {}", setting_name, channel_name));
                             }
                         }
                     },
                     None => {
-                        println!("Warning: Failed to apply setting {} to unknown channel {}", setting_name, channel_name);
+                        self.warnings.push(format!("Failed to apply setting {} to unknown channel {}", setting_name, channel_name));
                     }
                 }
             },
             None => {
-                println!("Warning: Failed to apply setting {} to unknown audio chip {}", setting_name, chip_name);
+                self.warnings.push(format!("Failed to apply setting {} to unknown audio chip {}", setting_name, chip_name));
             }
         }
     }
@@ -1535,6 +2179,20 @@ impl PianoRollWindow {
             Some(chip_settings) => {
                 match chip_settings.get_mut(channel_name) {
                     Some(channel_settings) => {
+                        if setting_name == "outline" {
+                            // "none" falls back to the global outline color
+                            if color_string == "none" {
+                                channel_settings.outline_color = None;
+                                return;
+                            }
+                            match Color::from_string(&color_string) {
+                                Ok(color) => {channel_settings.outline_color = Some(color)},
+                                Err(_) => {
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", color_string));
+                                }
+                            }
+                            return;
+                        }
                         match setting_to_index_mapping.get(setting_name) {
                             Some(setting_index) => {
                                 match Color::from_string(&color_string) {
@@ -1542,22 +2200,22 @@ impl PianoRollWindow {
                                         channel_settings.colors[*setting_index] = color;
                                     },
                                     Err(_) => {
-                                        println!("Warning: Invalid color string {}, ignoring.", color_string);
+                                        self.warnings.push(format!("Invalid color string {}, ignoring.", color_string));
                                     }
                                 }
                             },
                             None => {
-                                println!("Warning: setting {} does not correspond to any color slot for channel {} on chip {}", setting_name, channel_name, chip_name);
+                                self.warnings.push(format!("setting {} does not correspond to any color slot for channel {} on chip {}", setting_name, channel_name, chip_name));
                             }
                         }
                     },
                     None => {
-                        println!("Warning: Failed to apply color string {} to unknown channel {}", color_string, channel_name);
+                        self.warnings.push(format!("Failed to apply color string {} to unknown channel {}", color_string, channel_name));
                     }
                 }
             },
             None => {
-                println!("Warning: Failed to apply color string {} to unknown audio chip {}", color_string, chip_name);
+                self.warnings.push(format!("Failed to apply color string {} to unknown audio chip {}", color_string, chip_name));
             }
         }
     }
@@ -1588,6 +2246,15 @@ impl Panel for PianoRollWindow {
                 if self.polling_type == PollingType::PpuScanline {
                     self.update(&runtime.nes.apu, &*runtime.nes.mapper);
                 }
+                if let PollingType::Rate(rate) = self.polling_type {
+                    let region = runtime.nes.region;
+                    let scanlines_per_second = region.cpu_clock_rate() as f32 * region.ppu_dots_per_cpu_cycle_x5() as f32 / 5.0 / 341.0;
+                    self.polling_accumulator += rate / scanlines_per_second;
+                    while self.polling_accumulator >= 1.0 {
+                        self.update(&runtime.nes.apu, &*runtime.nes.mapper);
+                        self.polling_accumulator -= 1.0;
+                    }
+                }
             },
             Event::NesNewApuQuarterFrame => {
                 if self.polling_type == PollingType::ApuQuarterFrame {
@@ -1599,6 +2266,12 @@ impl Panel for PianoRollWindow {
                     self.update(&runtime.nes.apu, &*runtime.nes.mapper);
                 }
             },
+            Event::Update => {
+                // Patch labels count down once per emulated frame, however often they're drawn
+                for timer in self.patch_label_timers.values_mut() {
+                    timer.1 = timer.1.saturating_sub(1);
+                }
+            },
             Event::MouseClick(x, y) => {events.extend(self.mouse_click(runtime, x, y));},
             Event::RequestFrame => {self.draw(runtime)},
             Event::ShowPianoRollWindow => {self.shown = true},
@@ -1608,37 +2281,64 @@ impl Panel for PianoRollWindow {
                 let components = path.split(".").collect::<Vec<&str>>();
                 if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
                     self.apply_channel_boolean_setting(components[2], components[3], components[4], value);
+                } else if components.len() == 3 && components[0] == "piano_roll" && components[1] == "draw_piano_strings_for" {
+                    match ScrollDirection::from_string(components[2]) {
+                        Some(direction) => {self.draw_piano_strings_for.insert(direction, value);},
+                        None => {
+                            self.warnings.push(format!("Invalid scroll direction {}, ignoring.", components[2]));
+                        }
+                    }
                 } else {
                     match path.as_str() {
                         "piano_roll.draw_piano_strings" => {self.draw_piano_strings = value},
+                        "piano_roll.minimal" => {self.minimal = value},
+                        "piano_roll.show_patch_names" => {self.show_patch_names = value},
+                        "piano_roll.draw_n163_wavetables" => {self.draw_n163_wavetables = value},
+                        "piano_roll.draw_fds_tables" => {self.draw_fds_tables = value},
                         _ => {}
                     }
                 }
             },
 
             Event::ApplyIntegerSetting(path, value) => {
-                match path.as_str() {
-                    "piano_roll.canvas_width" => {self.set_canvas_height(value as u32, self.canvas.height)},
-                    "piano_roll.canvas_height" => {self.set_canvas_height(self.canvas.width, value as u32)},
-                    "piano_roll.key_thickness" => {self.key_thickness = value as u32},
-                    "piano_roll.key_length" => {self.key_length = value as u32},
-                    "piano_roll.octave_count" => {self.set_octave_count(value as u32)},
-                    "piano_roll.scale_factor" => {self.scale = value as u32},
-                    "piano_roll.speed_multiplier" => {self.speed_multiplier = value as u32},
-                    "piano_roll.starting_octave" => {self.set_starting_octave(value as u32)},
-                    "piano_roll.waveform_height" => {self.surfboard_height = value as u32},
-                    "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
-                    "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
-                    "piano_roll.outline_thickness" => {self.outline_thickness = value as u32},
-                    _ => {}
+                let components = path.split(".").collect::<Vec<&str>>();
+                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
+                    self.apply_channel_float_setting(components[2], components[3], components[4], value as f32);
+                } else {
+                    match path.as_str() {
+                        "piano_roll.canvas_width" => {self.set_canvas_height(value as u32, self.canvas.height)},
+                        "piano_roll.canvas_height" => {self.set_canvas_height(self.canvas.width, value as u32)},
+                        "piano_roll.key_thickness" => {self.key_thickness = value as u32},
+                        "piano_roll.key_length" => {self.key_length = value as u32},
+                        "piano_roll.octave_count" => {self.set_octave_count(value as u32)},
+                        "piano_roll.scale_factor" => {self.scale = value as u32},
+                        "piano_roll.speed_multiplier" => {self.speed_multiplier = value as u32},
+                        "piano_roll.starting_octave" => {self.set_starting_octave(value as u32)},
+                        "piano_roll.waveform_height" => {self.surfboard_height = value as u32},
+                        "piano_roll.n163_wavetable_height" => {self.n163_wavetable_height = value as u32},
+                        "piano_roll.fds_table_height" => {self.fds_table_height = value as u32},
+                        "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
+                        "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
+                        "piano_roll.outline_thickness" => {self.outline_thickness = value as u32},
+                        "piano_roll.divider_width" => {self.divider_width = value as u32},
+                        "piano_roll.piano_string_opacity" => {self.piano_string_opacity = value as f32},
+                        _ => {}
+                    }
                 }
             },
 
             Event::ApplyFloatSetting(path, value) => {
-                match path.as_str() {
-                    "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
-                    "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
-                    _ => {}
+                let components = path.split(".").collect::<Vec<&str>>();
+                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
+                    self.apply_channel_float_setting(components[2], components[3], components[4], value as f32);
+                } else {
+                    match path.as_str() {
+                        "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
+                        "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
+                        "piano_roll.keyboard_glow_intensity" => {self.keyboard_glow_intensity = value as f32},
+                        "piano_roll.piano_string_opacity" => {self.piano_string_opacity = value as f32},
+                        _ => {}
+                    }
                 }
             },
 
@@ -1652,7 +2352,7 @@ impl Panel for PianoRollWindow {
                             match Color::from_string(&value) {
                                 Ok(color) => {self.background_color = color},
                                 Err(_) => {
-                                    println!("Warning: Invalid color string {}, ignoring.", value);
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", value));
                                 }
                             }
                         },
@@ -1660,7 +2360,47 @@ impl Panel for PianoRollWindow {
                             match Color::from_string(&value) {
                                 Ok(color) => {self.outline_color = color},
                                 Err(_) => {
-                                    println!("Warning: Invalid color string {}, ignoring.", value);
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.divider_color" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.divider_color = color},
+                                Err(_) => {
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.piano_string_color" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.piano_string_color = color},
+                                Err(_) => {
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard_glow_color" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.keyboard_glow_color = color},
+                                Err(_) => {
+                                    self.warnings.push(format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.scroll_direction" => {
+                            match ScrollDirection::from_string(&value) {
+                                Some(direction) => {self.scroll_direction = direction},
+                                None => {
+                                    self.warnings.push(format!("Invalid scroll direction {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard_position" => {
+                            match KeyboardPosition::from_string(&value) {
+                                Some(position) => {self.keyboard_position = position},
+                                None => {
+                                    self.warnings.push(format!("Invalid keyboard position {}, ignoring.", value));
                                 }
                             }
                         },
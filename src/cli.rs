@@ -1,19 +1,64 @@
-use std::collections::HashMap;
-use clap::{arg, ArgAction, value_parser, Command};
-use std::path::PathBuf;
+use clap::{arg, ArgAction, ArgMatches, value_parser, Command};
+use clap::parser::ValueSource;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fmt::Write as _;
-use indicatif::{FormattedDuration, HumanBytes, ProgressBar, ProgressStyle};
-use rusticnes_ui_common::piano_roll_window::ChannelSettings;
+use std::io::{IsTerminal, Write as _};
+use std::process;
+use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+use anyhow::{Result, anyhow, bail};
+use indicatif::{FormattedDuration, HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use rusticnes_ui_common::drawing;
 use csscolorparser::Color as CssColor;
-use crate::renderer::{Renderer, options::{RendererOptions, StopCondition}};
-use crate::emulator::Emulator;
+use crate::renderer::{Renderer, options::{RendererOptions, StartCondition, StopCondition, SplitCondition, LayoutPreset, OutputFrameRate}, metadata_lookup::ExternalMetadata, channel_settings::ChannelSettingsBuilder, overlay::{OverlayPosition, TextOverlayOptions}, preflight, snapshot, contact_sheet, waveform, clip, vgm, midi, presets, gain, batch, summary::{RenderSummary, quote_arg}, monitor::{AudioMonitor, MonitorSpeed}};
+use crate::video_builder::video_options::Resampler;
+use crate::emulator::{Emulator, chips, RamInit, Region, SongPosition, frame_rate, NES_NTSC_FRAMERATE};
+use crate::gui;
+use crate::help;
+use crate::project::Project;
+use crate::manifest;
+use crate::progress::{self, JsonProgress};
 
-fn get_default_channel_settings() -> HashMap<(String, String), ChannelSettings> {
+// First Ctrl+C fades out and finalizes the video, second one stops immediately (still finalizing).
+// Shared by every render in a batch, since the handler can only be set once.
+static INTERRUPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPT_HANDLER: Once = Once::new();
+
+fn install_interrupt_handler() {
+    INTERRUPT_HANDLER.call_once(|| {
+        ctrlc::set_handler(|| {
+            INTERRUPT_COUNT.fetch_add(1, Ordering::SeqCst);
+        }).expect("Failed to set Ctrl+C handler");
+    });
+}
+
+// Shows progress in the terminal tab/taskbar and tmux window list. Written to stderr so it
+// doesn't end up in redirected output.
+fn set_terminal_title(title: &str) {
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\x1b]0;{}\x07", title);
+        let _ = stderr.flush();
+    }
+}
+
+fn get_starting_song(path: &str) -> Result<u8> {
     let mut emulator = Emulator::new();
     emulator.init(None);
+    emulator.open(path)?;
 
-    emulator.channel_settings()
+    Ok(emulator.starting_song())
+}
+
+fn get_preferred_region(path: &str) -> Result<Region> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    emulator.open(path)?;
+
+    Ok(emulator.preferred_region())
 }
 
 fn color_value_parser(s: &str) -> Result<drawing::Color, String> {
@@ -35,8 +80,32 @@ fn codec_option_value_parser(s: &str) -> Result<(String, String), String> {
     Ok((key.to_string(), value.to_string()))
 }
 
-fn get_renderer_options() -> RendererOptions {
-    let matches = Command::new("NSFPresenter")
+fn positive_seconds_value_parser(s: &str) -> Result<f64, String> {
+    let seconds = s.parse::<f64>().map_err(|e| e.to_string())?;
+    match seconds.is_finite() && seconds > 0.0 {
+        true => Ok(seconds),
+        false => Err("Must be a number of seconds greater than 0.".to_string())
+    }
+}
+
+fn unit_interval_value_parser(s: &str) -> Result<f32, String> {
+    let value = s.parse::<f32>().map_err(|e| e.to_string())?;
+    match (0.0..=1.0).contains(&value) {
+        true => Ok(value),
+        false => Err("Must be a number from 0.0 to 1.0.".to_string())
+    }
+}
+
+// MODULE or MODULE:TRACK, checking for a number so Windows drive letters aren't taken for tracks
+fn appended_track_value_parser(s: &str) -> Result<(PathBuf, u8), String> {
+    match s.rsplit_once(':') {
+        Some((path, track)) if !path.is_empty() && track.parse::<u8>().is_ok() => Ok((PathBuf::from(path), track.parse().unwrap())),
+        _ => Ok((PathBuf::from(s), 1))
+    }
+}
+
+fn cli_command() -> Command {
+    Command::new("NSFPresenter")
         .arg(arg!(-c --"video-codec" <CODEC> "Set the output video codec")
             .required(false)
             .default_value("libx264"))
@@ -53,11 +122,35 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(value_parser!(i32))
             .default_value("44100"))
+        .arg(arg!(--"oversampling" <FACTOR> "Run the emulator's audio at this multiple of the sample rate and resample it down. Reduces aliasing on high notes.")
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..=8))
+            .default_value("1"))
+        .arg(arg!(--"resampler" <RESAMPLER> "Resampler used with --oversampling: linear (fast) or sinc (libsoxr if available).")
+            .required(false)
+            .value_parser(value_parser!(Resampler))
+            .default_value("sinc"))
+        .arg(arg!(--"audio-buffer-size" <SAMPLES> "Set the emulator's audio buffer size in samples. It must hold at least one frame of audio.")
+            .required(false)
+            .value_parser(value_parser!(usize))
+            .default_value("65536"))
+        .arg(arg!(--"av-offset" <MS> "Delay the audio relative to the video by this many milliseconds (negative values delay the video)")
+            .required(false)
+            .value_parser(value_parser!(i64))
+            .allow_negative_numbers(true)
+            .default_value("0"))
         .arg(arg!(-T --"nsf-track" <TRACK> "Select the 1-indexed NSF track to play")
             .required(false)
             .value_parser(value_parser!(u8))
             .default_value("1"))
+        .arg(arg!(--"starting-song" "Play the track the NSF header marks as the starting song instead of -T")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("nsf-track"))
+        .arg(arg!(--"start-at" <CONDITION> "Emulate without encoding until this point, e.g. time:30 or loops:1")
+            .required(false)
+            .value_parser(value_parser!(StartCondition)))
         .arg(arg!(-s --"stop-at" <CONDITION> "Set the stop condition")
+            .long_help(help::STOP_CONDITION)
             .required(false)
             .value_parser(value_parser!(StopCondition))
             .default_value("time:300"))
@@ -65,6 +158,56 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(value_parser!(u64))
             .default_value("180"))
+        .arg(arg!(--"fade-in" <FRAMES> "Fade the audio in over this many frames at the start")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("0"))
+        .arg(arg!(--"fade-in-video" "Also fade the visualization in with the audio")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"append" <MODULE> "Play another track after this one in the same video, as MODULE or MODULE:TRACK (default track 1). Can be repeated.")
+            .required(false)
+            .value_parser(appended_track_value_parser)
+            .action(ArgAction::Append)
+            .conflicts_with("all-tracks"))
+        .arg(arg!(--"track-gap" <FRAMES> "Frames of silence between appended tracks")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("0"))
+        .arg(arg!(--"crossfade" <FRAMES> "Start appended tracks this many frames before the end of the previous one's fadeout, mixing the two")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("0"))
+        .arg(arg!(--"tracklist" <FILE> "Read track titles and durations from a CUE sheet or plain tracklist")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"visual-lead" <FRAMES> "Advance the piano roll ahead of the audio by this many frames")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("0"))
+        .arg(arg!(--"frame-step" <FRAMES> "Only encode every Nth frame for a silent timelapse of the whole module")
+            .required(false)
+            .value_parser(value_parser!(u64).range(1..))
+            .default_value("1"))
+        .arg(arg!(--"interpolate" <FACTOR> "Scroll the piano roll between emulated frames to output at N times the frame rate, e.g. 2 for ~120 fps")
+            .required(false)
+            .value_parser(value_parser!(u64).range(1..=4))
+            .default_value("1"))
+        .arg(arg!(--"output-fps" <FPS> "Encode video at this frame rate (e.g. 30, 50, 120 or ntsc), resampling the emulated frames")
+            .required(false)
+            .value_parser(value_parser!(OutputFrameRate))
+            .conflicts_with_all(["interpolate", "frame-step"]))
+        .arg(arg!(--"scroll-time" <SECONDS> "Set how long a note stays visible in the piano roll, overriding the speed multiplier")
+            .required(false)
+            .value_parser(positive_seconds_value_parser))
+        .arg(arg!(--"background-color" <COLOR> "Fill the piano roll with this color instead of the configured background, covering the cover art background.")
+            .required(false)
+            .value_parser(color_value_parser)
+            .conflicts_with("transparent"))
+        .arg(arg!(--"piano-roll-setting" <SETTING> "Change a piano roll setting from the configuration (setting=value), e.g. starting_octave=2")
+            .long_help(help::PIANO_ROLL)
+            .required(false)
+            .value_parser(codec_option_value_parser)
+            .action(ArgAction::Append))
         .arg(arg!(--"ow" <WIDTH> "Set the output video width")
             .required(false)
             .value_parser(value_parser!(u32))
@@ -73,6 +216,18 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(value_parser!(u32))
             .default_value("1080"))
+        .arg(arg!(--"layout" <PRESET> "Pick the output resolution and piano roll layout for an aspect ratio (16:9, 9:16, 1:1 or 21:9)")
+            .required(false)
+            .value_parser(value_parser!(LayoutPreset))
+            .conflicts_with_all(["ow", "oh"]))
+        .arg(arg!(-g --"keyframe-interval" <FRAMES> "Set the maximum number of frames between keyframes (GOP size)")
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..))
+            .default_value("120"))
+        .arg(arg!(-B --"max-b-frames" <FRAMES> "Set the maximum number of consecutive B-frames")
+            .required(false)
+            .value_parser(value_parser!(usize))
+            .default_value("2"))
         .arg(arg!(-o --"video-option" <OPTION> "Pass an option to the video codec (option=value)")
             .required(false)
             .value_parser(codec_option_value_parser)
@@ -91,36 +246,251 @@ fn get_renderer_options() -> RendererOptions {
             .num_args(2)
             .value_names(&["CHIP", "CHANNEL"])
             .action(ArgAction::Append))
-        .arg(arg!(-i --"import-config" <CONFIGFILE> "Import configuration from a RusticNES TOML file.")
+        .arg(arg!(-M --"mute-channel" "Mute a channel in the audio. It is still shown unless it is also hidden.")
+            .required(false)
+            .num_args(2)
+            .value_names(&["CHIP", "CHANNEL"])
+            .action(ArgAction::Append))
+        .arg(arg!(--"echo-pulse" <PULSE> "Draw this 2A03 pulse (1 or 2) under the other one, for songs that use it as an echo of the lead.")
+            .required(false)
+            .value_parser(value_parser!(u8).range(1..=2)))
+        .arg(arg!(--"echo-brightness" <BRIGHTNESS> "Dim the echo pulse's notes, from 0.0 to 1.0")
+            .required(false)
+            .requires("echo-pulse")
+            .value_parser(value_parser!(f32)))
+        .arg(arg!(--"keyboard-glow" <INTENSITY> "Make the keyboard glow with the final mix level, from 0.0 (off) to 1.0")
+            .required(false)
+            .value_parser(unit_interval_value_parser))
+        .arg(arg!(--"keyboard-glow-color" <COLOR> "Set the color of the keyboard glow")
+            .required(false)
+            .value_parser(color_value_parser)
+            .default_value("#FFFFFF"))
+        .arg(arg!(--"loop-marker" <SECONDS> "Flash a border and show the loop number (e.g. \"Loop 2/3\") for this long each time the song loops.")
+            .required(false)
+            .value_parser(value_parser!(f64)))
+        .arg(arg!(--"loop-marker-color" <COLOR> "Set the color of the loop marker")
+            .required(false)
+            .value_parser(color_value_parser)
+            .default_value("#FFFFFF"))
+        .arg(arg!(--"overlay-text" <TEXT> "Draw text over the video. Placeholders: {title}, {artist}, {copyright}, {track}, {elapsed}, {total}, {frame}, {row}. Separate lines with \\n.")
+            .required(false))
+        .arg(arg!(--"overlay-position" <POSITION> "Corner or edge the overlay text is drawn at: top-left, top, top-right, bottom-left, bottom, or bottom-right")
+            .required(false)
+            .value_parser(value_parser!(OverlayPosition))
+            .default_value("bottom-left"))
+        .arg(arg!(--"overlay-font" <PNGFILE> "Draw the overlay text with a bitmap font: a PNG strip of the 96 printable ASCII characters")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"overlay-font-width" <PIXELS> "Width of each character in the overlay font")
+            .required(false)
+            .value_parser(value_parser!(u32))
+            .default_value("8"))
+        .arg(arg!(--"overlay-scale" <SCALE> "Size of the overlay text, in multiples of the font size")
+            .required(false)
+            .value_parser(value_parser!(u32))
+            .default_value("2"))
+        .arg(arg!(--"overlay-color" <COLOR> "Set the color of the overlay text")
+            .required(false)
+            .value_parser(|s: &str| color_value_parser(s).map(|_| s.to_string()))
+            .default_value("#FFFFFF"))
+        .arg(arg!(--"overlay-fade" <SECONDS> "Fade the overlay text in and out over this long")
+            .required(false)
+            .value_parser(value_parser!(f64))
+            .default_value("1"))
+        .arg(arg!(--"overlay-duration" <SECONDS> "Only show the overlay text for this long at the start, instead of the whole video")
+            .required(false)
+            .value_parser(value_parser!(f64)))
+        .arg(arg!(--"tracker-position" <POSITION> "Show the current FamiTracker frame and row at this corner or edge: top-left, top, top-right, bottom-left, bottom, or bottom-right")
+            .required(false)
+            .value_parser(value_parser!(OverlayPosition)))
+        .arg(arg!(--"minimal" "Only draw the notes over a transparent background, without the keyboard, oscilloscopes or waveform lane.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(-i --"import-config" <CONFIGFILE> "Import configuration from a RusticNES TOML file or FamiTracker theme INI file.")
              .value_parser(value_parser!(PathBuf))
             .required(false))
+        .arg(arg!(--"cover-art" <IMAGE> "Embed a PNG or JPEG cover image in the output. Also used as the background if none is set.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"metadata-file" <JSONFILE> "Override output metadata (title, artist, composer, album, date, alternate_title, alternate_artist) from a JSON file.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"prefer-alternate-names" "Use the metadata file's alternate title and artist for the video and filenames, keeping the main ones as sort names.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"vgmdb-lookup" "Look up album metadata on VGMdb using the NSF title.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"sidecar-audio" <AUDIOFILE> "Also write a lossless WAV or FLAC copy of the audio.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"attach-sources" "Store the module and the emulator config in the output. MKV only.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"checkpoint-interval" <SECONDS> "Render in segments of this length and save progress after each one, so the render can be resumed.")
+            .value_parser(value_parser!(f64))
+            .conflicts_with("sidecar-audio")
+            .required(false))
+        .arg(arg!(--"split" <CONDITION> "Write the output as numbered parts, starting a new one every N minutes or N GB: 'minutes:30', 'frames:108000', 'gb:2', or 'mb:500'.")
+            .value_parser(value_parser!(SplitCondition))
+            .conflicts_with_all(["sidecar-audio", "checkpoint-interval"])
+            .required(false))
+        .arg(arg!(--"resume" "Continue an interrupted checkpointed render of the same output from its last checkpoint.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"audio-only" "Leave out the video and write a .flac, .wav, .mp3 or .ogg file. Implied by those extensions.")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["video-only", "frame-step"]))
+        .arg(arg!(--"video-only" "Omit the audio stream from the output video.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"transparent" "Render over a transparent background for compositing: ProRes 4444 for .mov, VP9 for .webm/.mkv, or a PNG sequence like frame_%05d.png.")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("audio-only"))
+        .arg(arg!(--"seed" <SEED> "Seed for power-on state that varies between consoles. 0 uses a cleared state.")
+            .value_parser(value_parser!(u64))
+            .required(false)
+            .default_value("0"))
+        .arg(arg!(--"ram-init" <PATTERN> "Set the power-on RAM contents: 00, ff (or any hex byte), random, or pattern:file")
+            .value_parser(value_parser!(RamInit))
+            .required(false)
+            .default_value("00"))
+        .arg(arg!(--"region" <REGION> "Console to emulate: ntsc, pal or dendy. Defaults to the region the module is flagged for.")
+            .value_parser(value_parser!(Region))
+            .required(false))
         .arg(arg!(-J --"famicom" "Simulate the Famicom's filter chain instead of the NES'.")
+            .long_help(help::FAMICOM)
             .action(ArgAction::SetTrue))
         .arg(arg!(-L --"lq-filters" "Use low-quality filter chain. Speeds up renders but has dirtier sound.")
+            .long_help(help::HIGH_QUALITY)
             .action(ArgAction::SetTrue))
+        .arg(arg!(--"output-gain" <DB> "Set the output gain in dB. 0 is the emulator's unboosted level.")
+            .required(false)
+            .allow_negative_numbers(true)
+            .value_parser(value_parser!(f64)))
+        .arg(arg!(--"auto-gain" "Analyze the track before rendering and lower the volume if it would clip.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"auto-speed" "Analyze the track before rendering and scroll faster the more notes it plays.")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("scroll-time"))
         .arg(arg!(-X --"multiplexing" "Emulate multiplexing for audio mixing (e.g. w/ N163). More accurate, but can introduce sound artifacts.")
+            .long_help(help::MULTIPLEXING)
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"monitor" <SPEED> "Play the audio through the default output device while rendering: 'render' to keep up with the render, skipping audio, or 'realtime' to slow the render down to play all of it.")
+            .required(false)
+            .value_parser(value_parser!(MonitorSpeed)))
+        .arg(arg!(--"progress-format" <FORMAT> "Show progress as a progress bar ('bar') or as newline-delimited JSON records for other programs ('json')")
+            .required(false)
+            .value_parser(["bar", "json"])
+            .default_value("bar"))
+        .arg(arg!(--"progress-output" <PATH> "Write the JSON progress records to this file or named pipe instead of stdout")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"verbose" "Print extra details while loading modules, like the contents of unknown NSFe chunks.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"diagnostics" "Log register writes to each expansion chip at the end of the render.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"snapshot" <SECONDS> "Save the frame at this time to the output as a PNG or JPEG image instead of rendering a video.")
+            .value_parser(value_parser!(f64))
+            .required(false))
+        .arg(arg!(--"frames-dir" <DIRECTORY> "Also save every frame to this directory as a numbered PNG image, at the internal resolution.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"snapshot-position" <POSITION> "Like --snapshot, but at the first time the song reaches a FamiTracker frame:row (in hexadecimal, e.g. 0A:1F).")
+            .value_parser(value_parser!(SongPosition))
+            .conflicts_with("snapshot")
+            .required(false))
+        .arg(arg!(--"snapshot-frame" <FRAME> "Like --snapshot, but at this exact frame number.")
+            .value_parser(value_parser!(u64))
+            .conflicts_with_all(["snapshot", "snapshot-position"])
+            .required(false))
+        .arg(arg!(--"snapshot-count" <COUNT> "With --snapshot or --snapshot-frame, save this many frames in a row, numbered by frame, to step through a moment.")
+            .value_parser(value_parser!(u64).range(1..))
+            .default_value("1"))
+        .arg(arg!(--"contact-sheet" <SECONDS> "Save the frame at this time from every track to the output as a grid PNG image with track titles instead of rendering a video.")
+            .value_parser(value_parser!(f64))
+            .conflicts_with_all(["snapshot", "snapshot-position", "snapshot-frame"])
+            .required(false))
+        .arg(arg!(--"contact-sheet-columns" <COLUMNS> "Number of tracks per row in the contact sheet.")
+            .value_parser(value_parser!(u32))
+            .default_value("4"))
+        .arg(arg!(--"waveform" "Save each channel's waveform over the whole track to the output as a wide PNG image instead of rendering a video.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"waveform-width" <PIXELS> "Width of the waveform image.")
+            .value_parser(value_parser!(u32))
+            .default_value("4000"))
+        .arg(arg!(--"waveform-row-height" <PIXELS> "Height of each channel's row in the waveform image.")
+            .value_parser(value_parser!(u32))
+            .default_value("64"))
+        .arg(arg!(--"clip" <SECONDS> "Save a short stretch starting at this time to the output as an animated GIF or WebP image instead of rendering a video.")
+            .value_parser(value_parser!(f64))
+            .conflicts_with_all(["snapshot", "snapshot-position", "snapshot-frame", "contact-sheet", "waveform"])
+            .required(false))
+        .arg(arg!(--"clip-length" <SECONDS> "Length of the clip.")
+            .value_parser(value_parser!(f64))
+            .default_value("5"))
+        .arg(arg!(--"clip-fps" <FPS> "Frame rate of the clip. Emulated frames are skipped to get close to it.")
+            .value_parser(value_parser!(f64))
+            .default_value("20"))
+        .arg(arg!(--"clip-width" <PIXELS> "Width of the clip, the height keeps the aspect ratio.")
+            .value_parser(value_parser!(u32))
+            .default_value("480"))
+        .arg(arg!(--"vgm" "Save the sound chip register writes over the whole track to the output as a VGM file instead of rendering a video.")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["snapshot", "snapshot-position", "snapshot-frame", "contact-sheet", "waveform", "clip"]))
+        .arg(arg!(--"export-midi" <FILE> "Save each channel's notes over the whole track to this MIDI file instead of rendering a video.")
+            .value_parser(value_parser!(PathBuf))
+            .conflicts_with_all(["snapshot", "snapshot-position", "snapshot-frame", "contact-sheet", "waveform", "clip", "vgm"])
+            .required(false))
+        .arg(arg!(--"project" <PROJECTFILE> "Render the tracks in a .nsfp project file. Other options are taken from the project.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"options" <OPTIONSFILE> "Load all render options from a JSON or TOML file saved with --save-options. Only the module, output and track are taken from the command line.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"save-options" <OPTIONSFILE> "Save the render options to a JSON or TOML file instead of rendering.")
+            .value_parser(value_parser!(PathBuf))
+            .required(false))
+        .arg(arg!(--"preset" <NAME> "Load all render options from a preset saved with --save-preset. Only the module, output and track are taken from the command line.")
+            .conflicts_with("options")
+            .required(false))
+        .arg(arg!(--"save-preset" <NAME> "Save the render options as a named preset instead of rendering.")
+            .required(false))
+        .arg(arg!(--"list-presets" "Print the names of the saved presets.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"manifest" <MANIFESTFILE> "Render every entry in a TOML or CSV batch manifest, then report which ones failed.")
+            .value_parser(value_parser!(PathBuf))
+            .conflicts_with("project")
+            .required(false))
+        .arg(arg!(--"jobs" <JOBS> "Number of manifest entries to render at the same time.")
+            .value_parser(value_parser!(usize))
+            .default_value("1"))
+        .arg(arg!(--"all-tracks" "Render every track (except NSFe sound effects) to its own file. The output is a directory.")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["nsf-track", "starting-song"]))
+        .arg(arg!(--"filename-template" <TEMPLATE> "Output filename for each track with --all-tracks. Placeholders: {title}, {artist}, {track} ({track:02} to pad), {trackname}, {part} (with --split).")
+            .required(false)
+            .default_value(batch::DEFAULT_FILENAME_TEMPLATE))
+        .arg(arg!(--"include-sound-effects" "Also render NSFe sound effects with --all-tracks.")
+            .action(ArgAction::SetTrue)
+            .requires("all-tracks"))
+        .arg(arg!(--"gui" "Open the GUI with the module, track, output and imported configuration filled in instead of rendering.")
+            .action(ArgAction::SetTrue))
+        .arg(arg!(--"list-channels" "Print every chip and channel with the config key and default color of each of its colors as JSON, for tools that generate settings UIs.")
             .action(ArgAction::SetTrue))
         .arg(arg!(<nsf> "NSF to render")
             .value_parser(value_parser!(PathBuf))
-            .required(true))
+            .required_unless_present_any(["gui", "project", "manifest", "save-options", "save-preset", "list-channels", "list-presets"]))
         .arg(arg!(<output> "Output video file")
             .value_parser(value_parser!(PathBuf))
-            .required(true))
-        .get_matches();
+            .required_unless_present_any(["gui", "project", "manifest", "save-options", "save-preset", "list-channels", "list-presets"]))
+}
 
+fn get_renderer_options(matches: &ArgMatches) -> Result<RendererOptions> {
     let mut options = RendererOptions::default();
 
+    // Both paths are only optional with --gui
     options.input_path = matches.get_one::<PathBuf>("nsf")
-        .expect("Input path required")
-        .to_str()
-        .unwrap()
-        .to_string();
+        .map(|p| p.to_str().unwrap().to_string())
+        .unwrap_or_default();
 
     options.video_options.output_path = matches.get_one::<PathBuf>("output")
-        .expect("Output path required")
-        .to_str()
-        .unwrap()
-        .to_string();
+        .map(|p| p.to_str().unwrap().to_string())
+        .unwrap_or_default();
 
     options.video_options.video_codec = matches.get_one::<String>("video-codec")
         .cloned()
@@ -140,16 +510,48 @@ fn get_renderer_options() -> RendererOptions {
         .unwrap();
     options.video_options.sample_rate = sample_rate;
     options.video_options.audio_time_base = (1, sample_rate).into();
+    options.video_options.av_offset_ms = matches.get_one::<i64>("av-offset")
+        .cloned()
+        .unwrap();
 
     options.track_index = matches.get_one::<u8>("nsf-track")
         .cloned()
         .unwrap();
+    if matches.get_flag("starting-song") && !options.input_path.is_empty() {
+        options.track_index = get_starting_song(&options.input_path)?;
+        status!("Starting song: {}", options.track_index);
+    }
+    options.start_condition = matches.get_one::<StartCondition>("start-at")
+        .cloned();
     options.stop_condition = matches.get_one::<StopCondition>("stop-at")
         .cloned()
         .unwrap();
     options.fadeout_length = matches.get_one::<u64>("stop-fadeout")
         .cloned()
         .unwrap();
+    options.fadein_length = matches.get_one::<u64>("fade-in")
+        .cloned()
+        .unwrap();
+    options.fadein_video = matches.get_flag("fade-in-video");
+    options.track_gap = matches.get_one::<u64>("track-gap")
+        .cloned()
+        .unwrap();
+    options.crossfade_length = matches.get_one::<u64>("crossfade")
+        .cloned()
+        .unwrap();
+    options.tracklist_path = matches.get_one::<PathBuf>("tracklist")
+        .map(|p| p.to_str().unwrap().to_string());
+    options.visual_lead_frames = matches.get_one::<u64>("visual-lead")
+        .cloned()
+        .unwrap();
+    options.frame_step = matches.get_one::<u64>("frame-step")
+        .cloned()
+        .unwrap();
+    options.interpolation = matches.get_one::<u64>("interpolate")
+        .cloned()
+        .unwrap();
+    options.output_frame_rate = matches.get_one::<OutputFrameRate>("output-fps")
+        .cloned();
 
     let ow = matches.get_one::<u32>("ow")
         .cloned()
@@ -157,7 +559,27 @@ fn get_renderer_options() -> RendererOptions {
     let oh = matches.get_one::<u32>("oh")
         .cloned()
         .unwrap();
-    options.set_resolution_smart(ow, oh);
+    match matches.get_one::<LayoutPreset>("layout") {
+        Some(preset) => options.apply_layout_preset(*preset),
+        None => options.set_resolution_smart(ow, oh)
+    };
+    options.scroll_duration = matches.get_one::<f64>("scroll-time").cloned();
+    if let Some(color) = matches.get_one::<drawing::Color>("background-color") {
+        let hex_color = format!("#{:02X}{:02X}{:02X}{:02X}", color.r(), color.g(), color.b(), color.alpha());
+        options.piano_roll_settings.insert("background_color".to_string(), hex_color);
+    }
+    if let Some(piano_roll_settings) = matches.get_many::<(String, String)>("piano-roll-setting") {
+        for (k, v) in piano_roll_settings.cloned() {
+            options.piano_roll_settings.insert(k, v);
+        }
+    }
+
+    options.video_options.gop_size = matches.get_one::<u32>("keyframe-interval")
+        .cloned()
+        .unwrap();
+    options.video_options.max_b_frames = matches.get_one::<usize>("max-b-frames")
+        .cloned()
+        .unwrap();
 
     if let Some(video_options) = matches.get_many::<(String, String)>("video-option") {
         for (k, v) in video_options.cloned() {
@@ -170,73 +592,584 @@ fn get_renderer_options() -> RendererOptions {
         }
     }
 
-    options.channel_settings = get_default_channel_settings();
-
-    if let Some(channel_settings) = matches.get_occurrences::<String>("channel-color") {
-        for channel_setting_parts in channel_settings.map(Iterator::collect::<Vec<&String>>) {
-            let chip = channel_setting_parts
-                .get(0)
-                .expect("Channel setting must have chip name");
-            let channel = channel_setting_parts
-                .get(1)
-                .expect("Channel setting must have channel name");
+    let mut channel_settings = ChannelSettingsBuilder::new();
 
-            let setting = options.channel_settings.get_mut(&(chip.as_str().to_string(), channel.as_str().to_string()))
-                .expect(format!("Unknown chip/channel specified: {} {}", chip, channel).as_str());
-
-            if setting.colors.len() != channel_setting_parts.len() - 2 {
-                panic!("Wrong number of colors specified for chip/channel {} {}: expected {} colors", chip, channel, setting.colors.len());
-            }
-            setting.colors = channel_setting_parts.iter()
+    if let Some(channel_colors) = matches.get_occurrences::<String>("channel-color") {
+        for channel_color_parts in channel_colors.map(Iterator::collect::<Vec<&String>>) {
+            let colors: Vec<drawing::Color> = channel_color_parts.iter()
                 .skip(2)
-                .map(|c| color_value_parser(c.as_str()).expect("Invalid color"))
-                .collect();
+                .map(|c| color_value_parser(c.as_str()).map_err(|e| anyhow!("Invalid color {}: {}", c, e)))
+                .collect::<Result<_>>()?;
+            channel_settings = channel_settings.set_colors(channel_color_parts[0], channel_color_parts[1], &colors)?;
         }
     }
 
     if let Some(hidden_channels) = matches.get_occurrences::<String>("hide-channel") {
         for hidden_channel_parts in hidden_channels.map(Iterator::collect::<Vec<&String>>) {
-            let chip = hidden_channel_parts
-                .get(0)
-                .expect("Hidden channel must have chip name");
-            let channel = hidden_channel_parts
-                .get(1)
-                .expect("Hidden channel must have channel name");
-
-            let setting = options.channel_settings.get_mut(&(chip.as_str().to_string(), channel.as_str().to_string()))
-                .expect(format!("Unknown chip/channel specified: {} {}", chip, channel).as_str());
+            channel_settings = channel_settings.hide(hidden_channel_parts[0], hidden_channel_parts[1])?;
+        }
+    }
 
-            setting.hidden = true;
+    if let Some(muted_channels) = matches.get_occurrences::<String>("mute-channel") {
+        for muted_channel_parts in muted_channels.map(Iterator::collect::<Vec<&String>>) {
+            channel_settings = channel_settings.mute(muted_channel_parts[0], muted_channel_parts[1])?;
         }
     }
 
+    if let Some(pulse) = matches.get_one::<u8>("echo-pulse") {
+        let brightness = matches.get_one::<f32>("echo-brightness").cloned().unwrap_or(1.0);
+        channel_settings = channel_settings.echo("2A03", &format!("Pulse {}", pulse), brightness)?;
+    }
+
+    options.channel_settings = channel_settings.build();
+
+    options.keyboard_glow = matches.get_one::<f32>("keyboard-glow")
+        .map(|intensity| (matches.get_one::<drawing::Color>("keyboard-glow-color").cloned().unwrap(), *intensity));
+
+    options.loop_marker = matches.get_one::<f64>("loop-marker")
+        .map(|seconds| (matches.get_one::<drawing::Color>("loop-marker-color").cloned().unwrap(), (seconds * NES_NTSC_FRAMERATE).max(1.0) as u64));
+
+    let seconds_to_frames = |seconds: f64| (seconds * NES_NTSC_FRAMERATE).max(0.0) as u64;
+    options.text_overlay = matches.get_one::<String>("overlay-text").map(|text| TextOverlayOptions {
+        text: text.replace("\\n", "\n"),
+        position: matches.get_one::<OverlayPosition>("overlay-position").cloned().unwrap(),
+        font_path: matches.get_one::<PathBuf>("overlay-font").map(|p| p.to_str().unwrap().to_string()),
+        glyph_width: matches.get_one::<u32>("overlay-font-width").cloned().unwrap(),
+        scale: matches.get_one::<u32>("overlay-scale").cloned().unwrap(),
+        color: matches.get_one::<String>("overlay-color").cloned().unwrap(),
+        fade_in: seconds_to_frames(matches.get_one::<f64>("overlay-fade").cloned().unwrap()),
+        fade_out: seconds_to_frames(matches.get_one::<f64>("overlay-fade").cloned().unwrap()),
+        duration: matches.get_one::<f64>("overlay-duration").cloned().map(seconds_to_frames)
+    });
+    options.tracker_overlay = matches.get_one::<OverlayPosition>("tracker-position")
+        .map(|position| TextOverlayOptions::tracker_position(*position));
+
+    options.minimal = matches.get_flag("minimal");
+
     options.config_import_path = matches.get_one::<PathBuf>("import-config")
         .map(|p| p.to_str().unwrap().to_string());
 
+    options.video_options.cover_art_path = matches.get_one::<PathBuf>("cover-art")
+        .map(|p| p.to_str().unwrap().to_string());
+
+    options.external_metadata = matches.get_one::<PathBuf>("metadata-file")
+        .map(ExternalMetadata::from_json_file)
+        .transpose()?;
+    options.vgmdb_lookup = matches.get_flag("vgmdb-lookup");
+    options.prefer_alternate_names = matches.get_flag("prefer-alternate-names");
+
+    options.video_options.sidecar_audio_path = matches.get_one::<PathBuf>("sidecar-audio")
+        .map(|p| p.to_str().unwrap().to_string());
+    options.video_options.video_only = matches.get_flag("video-only");
+    options.video_options.audio_only = matches.get_flag("audio-only");
+    options.video_options.transparent = matches.get_flag("transparent");
+    options.attach_sources = matches.get_flag("attach-sources");
+    options.checkpoint_interval = matches.get_one::<f64>("checkpoint-interval")
+        .map(|seconds| (seconds * NES_NTSC_FRAMERATE).max(1.0) as u64);
+    options.split_condition = matches.get_one::<SplitCondition>("split").cloned();
+    options.resume = matches.get_flag("resume");
+
+    options.seed = matches.get_one::<u64>("seed")
+        .cloned()
+        .unwrap();
+
+    options.ram_init = matches.get_one::<RamInit>("ram-init")
+        .cloned()
+        .unwrap();
+
+    options.region = match matches.get_one::<Region>("region") {
+        Some(region) => *region,
+        None if !options.input_path.is_empty() => get_preferred_region(&options.input_path).unwrap_or_default(),
+        None => Region::Ntsc
+    };
+    if options.region != Region::Ntsc {
+        status!("Region: {}", options.region.to_string().to_uppercase());
+    }
+
     options.famicom = matches.get_flag("famicom");
     options.high_quality = !(matches.get_flag("lq-filters"));
     options.multiplexing = matches.get_flag("multiplexing");
+    options.oversampling = matches.get_one::<u32>("oversampling").cloned().unwrap();
+    options.resampler = matches.get_one::<Resampler>("resampler").cloned().unwrap();
+    options.audio_buffer_size = matches.get_one::<usize>("audio-buffer-size").cloned().unwrap();
+    options.auto_gain = matches.get_flag("auto-gain");
+    options.auto_speed = matches.get_flag("auto-speed");
+    if let Some(output_gain_db) = matches.get_one::<f64>("output-gain") {
+        options.output_gain_db = *output_gain_db;
+    }
+
+    Ok(options)
+}
+
+fn color_arg(color: &drawing::Color) -> String {
+    match color.alpha() {
+        255 => format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b()),
+        _ => format!("#{:02X}{:02X}{:02X}{:02X}", color.r(), color.g(), color.b(), color.alpha())
+    }
+}
+
+// The options that render the same thing as the renderer options, by long name with their values,
+// for the GUI's "Copy CLI command". Only options that differ from the defaults are included.
+// The background image and the outline and divider styles don't have flags, so they are left out.
+fn equivalent_options(options: &RendererOptions) -> Vec<(&'static str, Vec<String>)> {
+    let defaults = RendererOptions::default();
+    let mut args: Vec<(&'static str, Vec<String>)> = vec![
+        ("nsf-track", vec![options.track_index.to_string()]),
+        ("stop-at", vec![options.stop_condition.to_string()])
+    ];
+
+    if options.fadeout_length != defaults.fadeout_length {
+        args.push(("stop-fadeout", vec![options.fadeout_length.to_string()]));
+    }
+    if options.fadein_length > 0 {
+        args.push(("fade-in", vec![options.fadein_length.to_string()]));
+    }
+
+    let layout_preset = LayoutPreset::ALL.iter()
+        .find(|preset| Some(preset.piano_roll_layout()) == options.piano_roll_layout);
+    let (ow, oh) = options.video_options.resolution_out;
+    match layout_preset {
+        Some(preset) => args.push(("layout", vec![preset.to_string()])),
+        None if (ow, oh) != defaults.video_options.resolution_out => {
+            args.push(("ow", vec![ow.to_string()]));
+            args.push(("oh", vec![oh.to_string()]));
+        },
+        None => ()
+    }
+    if options.interpolation > 1 {
+        args.push(("interpolate", vec![options.interpolation.to_string()]));
+    }
+
+    // Without --region the module's preferred region is used, which may not be the selected one
+    args.push(("region", vec![options.region.to_string()]));
+
+    let flags = [
+        (options.fadein_video, "fade-in-video"),
+        (options.video_options.transparent, "transparent"),
+        (options.famicom, "famicom"),
+        (!options.high_quality, "lq-filters"),
+        (options.multiplexing, "multiplexing"),
+        (options.auto_gain, "auto-gain"),
+        (options.auto_speed, "auto-speed")
+    ];
+    args.extend(flags.iter().filter(|(set, _)| *set).map(|(_, flag)| (*flag, Vec::new())));
+
+    if let Some(config_import_path) = &options.config_import_path {
+        args.push(("import-config", vec![config_import_path.clone()]));
+    }
+    let mut piano_roll_settings: Vec<(&String, &String)> = options.piano_roll_settings.iter().collect();
+    piano_roll_settings.sort();
+    for (key, value) in piano_roll_settings {
+        args.push(("piano-roll-setting", vec![format!("{}={}", key, value)]));
+    }
+
+    // The CLI always starts from the default palette, even with an imported configuration
+    let default_channel_settings = ChannelSettingsBuilder::new().build();
+    let mut channel_settings: Vec<_> = options.channel_settings.iter().collect();
+    channel_settings.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for ((chip, channel), settings) in channel_settings {
+        let default_settings = match default_channel_settings.get(&(chip.clone(), channel.clone())) {
+            Some(default_settings) => default_settings,
+            None => continue
+        };
+        let colors: Vec<String> = settings.colors.iter().map(color_arg).collect();
+        if colors != default_settings.colors.iter().map(color_arg).collect::<Vec<String>>() {
+            args.push(("channel-color", [vec![chip.clone(), channel.clone()], colors].concat()));
+        }
+        if settings.hidden && !default_settings.hidden {
+            args.push(("hide-channel", vec![chip.clone(), channel.clone()]));
+        }
+        if settings.muted && !default_settings.muted {
+            args.push(("mute-channel", vec![chip.clone(), channel.clone()]));
+        }
+    }
+
+    args
+}
+
+// The command line for the GUI's "Copy CLI command"
+pub fn equivalent_command(options: &RendererOptions) -> Result<String> {
+    if options.input_path.is_empty() {
+        bail!("Load a module before copying the command");
+    }
+
+    let mut args: Vec<String> = vec!["nsf-presenter-rs".to_string()];
+    for (name, values) in equivalent_options(options) {
+        args.push(format!("--{}", name));
+        args.extend(values);
+    }
+    args.push(options.input_path.clone());
+    args.push(match options.video_options.output_path.is_empty() {
+        true => "output.mp4".to_string(),
+        false => options.video_options.output_path.clone()
+    });
+
+    Ok(args.iter()
+        .map(|arg| quote_arg(arg))
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+pub fn open_project<P: AsRef<Path>>(project_path: P) {
+    let options = Project::open(project_path)
+        .and_then(|project| project.first_track_options())
+        .unwrap_or_else(|e| exit_with_error(e));
+    gui::run(Some(options));
+}
+
+// Flags for the render itself, like --monitor, come from the command line that opened the project
+fn run_project<P: AsRef<Path>>(project_path: P, matches: &ArgMatches) {
+    if matches.get_flag("gui") {
+        open_project(project_path);
+        return;
+    }
+
+    let project = Project::open(project_path).unwrap_or_else(|e| exit_with_error(e));
+    let multi_progress = MultiProgress::new();
+    for track in project.tracks.iter() {
+        status!("Rendering track {} of {} to {}", track, project.module_path.display(), project.track_output_path(*track).display());
+        let rendered = project.renderer_options(*track)
+            .and_then(|options| render_options(options, matches, &multi_progress))
+            .unwrap();
+        if !rendered {
+            break;
+        }
+    }
+}
+
+// Prints an error that ends a CLI run without a render to report it to, and exits with a failure status
+fn exit_with_error(error: anyhow::Error) -> ! {
+    eprintln!("Error: {:#}", error);
+    process::exit(1);
+}
+
+// Renders every entry in a manifest, several at a time with more than one job, and reports
+// all failures at the end instead of stopping at the first one
+fn run_manifest<P: AsRef<Path>>(manifest_path: P, matches: &ArgMatches) {
+    let projects = manifest::open(manifest_path).unwrap_or_else(|e| exit_with_error(e));
+    // Entries with invalid options fail on their own when their turn comes
+    let renders: Vec<(String, Result<RendererOptions>)> = projects.iter()
+        .flat_map(|project| project.tracks.iter().map(move |track| (
+            format!("{} track {} -> {}", project.module_path.display(), track, project.track_output_path(*track).display()),
+            project.renderer_options(*track)
+        )))
+        .collect();
+    let jobs = matches.get_one::<usize>("jobs").cloned().unwrap();
+
+    let start = Instant::now();
+    let multi_progress = MultiProgress::new();
+    let next_render = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Result<bool>)>> = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, renders.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next_render.fetch_add(1, Ordering::SeqCst);
+                // Interrupting stops the batch after the renders in progress are finalized
+                if i >= renders.len() || INTERRUPT_COUNT.load(Ordering::SeqCst) > 0 {
+                    break;
+                }
+
+                let (label, options) = &renders[i];
+                let options = match options {
+                    Ok(options) => options,
+                    Err(e) => {
+                        results.lock().unwrap().push((i, Err(anyhow!("{:#}", e))));
+                        continue;
+                    }
+                };
+                multi_progress.println(format!("Rendering {}", label)).unwrap();
+                // A panic only fails its own entry instead of taking the whole batch down with it
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    render_options(options.clone(), matches, &multi_progress)
+                })).unwrap_or_else(|_| Err(anyhow!("The render panicked")));
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    let rendered = results.iter().filter(|(_, r)| matches!(r, Ok(true))).count();
+    let interrupted = results.iter().filter(|(_, r)| matches!(r, Ok(false))).count();
+
+    status!(
+        "Batch finished in {}: {} rendered, {} failed, {} interrupted, {} not started",
+        FormattedDuration(start.elapsed()),
+        rendered,
+        results.len() - rendered - interrupted,
+        interrupted,
+        renders.len() - results.len()
+    );
+    for (i, result) in results.iter() {
+        if let Err(e) = result {
+            status!("Failed: {}: {:?}", renders[*i].0, e);
+        }
+    }
+}
+
+fn print_channels() {
+    let channels: Vec<serde_json::Value> = chips::supported_channels().into_iter()
+        .map(|info| serde_json::json!({
+            "chip": info.chip,
+            "channel": info.channel,
+            "colors": info.colors.iter()
+                .map(|(key, color)| serde_json::json!({
+                    "key": key,
+                    "default": format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b())
+                }))
+                .collect::<Vec<_>>()
+        }))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&channels).unwrap());
+}
+
+// Also sets up JSON progress, which changes where messages are printed
+pub fn parse_args() -> ArgMatches {
+    let matches = cli_command().get_matches();
+    if matches.get_one::<String>("progress-format").unwrap() == "json" {
+        progress::enable_json(matches.get_one::<PathBuf>("progress-output").cloned());
+    }
+    if matches.get_flag("verbose") {
+        progress::enable_verbose();
+    }
+    matches
+}
+
+pub fn run(matches: ArgMatches) {
+    if matches.get_flag("list-channels") {
+        print_channels();
+        return;
+    }
+    if matches.get_flag("list-presets") {
+        let names = presets::list_presets().unwrap_or_else(|e| exit_with_error(e));
+        for name in names {
+            println!("{}", name);
+        }
+        return;
+    }
+    if let Some(manifest_path) = matches.get_one::<PathBuf>("manifest") {
+        run_manifest(manifest_path, &matches);
+        return;
+    }
+    match matches.get_one::<PathBuf>("project") {
+        Some(project_path) => run_project(project_path, &matches),
+        None => {
+            render(&matches, &MultiProgress::new()).unwrap();
+        }
+    }
+}
+
+// Saved options with the module, output and track from the command line
+fn load_renderer_options(mut options: RendererOptions, matches: &ArgMatches) -> RendererOptions {
+    if let Some(input_path) = matches.get_one::<PathBuf>("nsf") {
+        options.input_path = input_path.to_str().unwrap().to_string();
+    }
+    if let Some(output_path) = matches.get_one::<PathBuf>("output") {
+        options.video_options.output_path = output_path.to_str().unwrap().to_string();
+    }
+    if matches.value_source("nsf-track") == Some(ValueSource::CommandLine) {
+        options.track_index = matches.get_one::<u8>("nsf-track").cloned().unwrap();
+    }
+    options.resume = matches.get_flag("resume");
 
     options
 }
 
-pub fn run() {
-    let options = get_renderer_options();
-    let mut renderer = Renderer::new(options).unwrap();
+// Returns false if the render was interrupted with Ctrl+C
+fn render(matches: &ArgMatches, multi_progress: &MultiProgress) -> Result<bool> {
+    let result = try_render(matches, multi_progress);
+    if let Err(e) = &result {
+        let output_path = matches.get_one::<PathBuf>("output")
+            .map(|p| p.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        progress::report_error(&output_path, e);
+    }
+    result
+}
+
+fn try_render(matches: &ArgMatches, multi_progress: &MultiProgress) -> Result<bool> {
+    let options = match (matches.get_one::<PathBuf>("options"), matches.get_one::<String>("preset")) {
+        (Some(options_path), _) => load_renderer_options(RendererOptions::load(options_path)?, matches),
+        (None, Some(preset)) => load_renderer_options(presets::load_preset(preset)?, matches),
+        (None, None) => get_renderer_options(matches)?
+    };
+    if let Some(save_path) = matches.get_one::<PathBuf>("save-options") {
+        options.save(save_path)?;
+        status!("Saved options to {}", save_path.display());
+        return Ok(true);
+    }
+    if let Some(preset) = matches.get_one::<String>("save-preset") {
+        let preset_path = presets::save_preset(preset, &options)?;
+        status!("Saved preset {} to {}", preset, preset_path.display());
+        return Ok(true);
+    }
+    if matches.get_flag("gui") {
+        gui::run(Some(options));
+        return Ok(true);
+    }
+
+    let output_path = options.video_options.output_path.clone();
+    let snapshot_frame = matches.get_one::<f64>("snapshot")
+        .map(|seconds| (seconds * frame_rate(options.region)).max(0.0) as u64)
+        .or(matches.get_one::<u64>("snapshot-frame").cloned());
+    if let Some(frame) = snapshot_frame {
+        let count = matches.get_one::<u64>("snapshot-count").cloned().unwrap();
+        let paths = snapshot::render_snapshot(&options, frame, count, &output_path)?;
+        for (path, frame) in paths.iter().zip(frame..) {
+            status!("Saved snapshot of frame {} to {}", frame, path);
+        }
+        return Ok(true);
+    }
+    if let Some(seconds) = matches.get_one::<f64>("contact-sheet").cloned() {
+        let frame = (seconds * frame_rate(options.region)).max(0.0) as u64;
+        let columns = matches.get_one::<u32>("contact-sheet-columns").cloned().unwrap();
+        contact_sheet::render_contact_sheet(&options, frame, columns, &output_path)?;
+        status!("Saved contact sheet to {}", output_path);
+        return Ok(true);
+    }
+    if matches.get_flag("waveform") {
+        let width = matches.get_one::<u32>("waveform-width").cloned().unwrap();
+        let row_height = matches.get_one::<u32>("waveform-row-height").cloned().unwrap();
+        waveform::render_waveform_strip(&options, width, row_height, &output_path)?;
+        status!("Saved waveform to {}", output_path);
+        return Ok(true);
+    }
+    if let Some(seconds) = matches.get_one::<f64>("clip").cloned() {
+        let start_frame = (seconds * frame_rate(options.region)).max(0.0) as u64;
+        let frame_count = (matches.get_one::<f64>("clip-length").cloned().unwrap() * frame_rate(options.region)).max(0.0) as u64;
+        let fps = matches.get_one::<f64>("clip-fps").cloned().unwrap();
+        let width = matches.get_one::<u32>("clip-width").cloned().unwrap();
+        clip::render_clip(&options, start_frame, frame_count, fps, width, &output_path)?;
+        status!("Saved clip to {}", output_path);
+        return Ok(true);
+    }
+    if matches.get_flag("vgm") {
+        vgm::export_vgm(&options, &output_path)?;
+        status!("Saved VGM to {}", output_path);
+        return Ok(true);
+    }
+    if let Some(midi_path) = matches.get_one::<PathBuf>("export-midi") {
+        midi::export_midi(&options, midi_path.to_str().unwrap())?;
+        status!("Saved MIDI to {}", midi_path.display());
+        return Ok(true);
+    }
+    if let Some(position) = matches.get_one::<SongPosition>("snapshot-position").cloned() {
+        let frame = snapshot::render_snapshot_at_position(&options, position, &output_path)?;
+        status!("Saved snapshot of {} (frame {}) to {}", position, frame, output_path);
+        return Ok(true);
+    }
+    if matches.get_flag("all-tracks") {
+        let template = matches.get_one::<String>("filename-template").unwrap();
+        std::fs::create_dir_all(&output_path)?;
+        let include_sound_effects = matches.get_flag("include-sound-effects");
+        for job in batch::track_jobs(&options, Path::new(&output_path), template, include_sound_effects)? {
+            status!("Rendering track {} to {}", job.track_index, job.video_options.output_path);
+            if !render_job(job, matches, multi_progress)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    render_job(options, matches, multi_progress)
+}
+
+// Renders options that didn't come from the command line, e.g. from a project
+fn render_options(options: RendererOptions, matches: &ArgMatches, multi_progress: &MultiProgress) -> Result<bool> {
+    let output_path = options.video_options.output_path.clone();
+    let result = render_job(options, matches, multi_progress);
+    if let Err(e) = &result {
+        progress::report_error(&output_path, e);
+    }
+    result
+}
 
-    let pb = ProgressBar::new(0);
+// Returns false if the render was interrupted with Ctrl+C
+fn render_job(options: RendererOptions, matches: &ArgMatches, multi_progress: &MultiProgress) -> Result<bool> {
+    let output_path = options.video_options.output_path.clone();
+    for warning in preflight::check_output(&options)? {
+        status!("Warning: {}", warning);
+    }
+    let appended_tracks: Vec<RendererOptions> = matches.get_many::<(PathBuf, u8)>("append")
+        .into_iter()
+        .flatten()
+        .map(|(input_path, track_index)| {
+            let mut track_options = options.clone();
+            track_options.input_path = input_path.to_str().unwrap().to_string();
+            track_options.track_index = *track_index;
+            // The tracklist and metadata file belong to the first module
+            track_options.tracklist_path = None;
+            track_options.external_metadata = None;
+            track_options
+        })
+        .collect();
+    let sample_rate = options.emulation_sample_rate() as u32;
+    let mut renderer = Renderer::new(options)?;
+    for track_options in appended_tracks {
+        renderer.queue_track(track_options)?;
+    }
+    if let Some(frames_dir) = matches.get_one::<PathBuf>("frames-dir").cloned() {
+        std::fs::create_dir_all(&frames_dir)?;
+        renderer.set_frame_callback(move |info| {
+            let frame_path = frames_dir.join(format!("{:06}.png", info.frame));
+            if let Err(e) = image::save_buffer(&frame_path, info.rgba, info.width, info.height, image::ColorType::RGBA(8)) {
+                status!("Warning: failed to save {}: {}", frame_path.display(), e);
+            }
+        });
+    }
+    // Kept until the render is done, the renderer only gets its sink
+    let monitor = matches.get_one::<MonitorSpeed>("monitor")
+        .map(|speed| AudioMonitor::start(sample_rate, *speed))
+        .transpose()
+        .unwrap_or_else(|e| {
+            status!("Warning: audio monitor unavailable: {:#}", e);
+            None
+        });
+    if let Some(monitor) = monitor.as_ref() {
+        let sink = monitor.sink();
+        renderer.set_audio_callback(move |samples| sink.push(samples));
+    }
+    for warning in renderer.take_warnings() {
+        status!("Warning: {}", warning);
+    }
+    if renderer.gain() != 1.0 {
+        status!("Auto gain: {:.2} dB", gain::gain_to_db(renderer.gain()));
+    }
+
+    install_interrupt_handler();
+
+    let mut json_progress = JsonProgress::start(&output_path)?;
+    // The JSON records take the place of the progress bar
+    let pb = match json_progress {
+        Some(_) => ProgressBar::hidden(),
+        None => multi_progress.add(ProgressBar::new(0))
+    };
     let pb_style_initial = ProgressStyle::with_template("{msg}\n{spinner} Running until duration is known...")
         .unwrap();
     let pb_style = ProgressStyle::with_template("{msg}\n{wide_bar} {percent}%")
         .unwrap();
     pb.set_style(pb_style_initial);
 
-    renderer.start_encoding().unwrap();
+    renderer.start_encoding()?;
 
+    let mut terminal_title = String::new();
     loop {
-        if !renderer.step().unwrap() {
+        match INTERRUPT_COUNT.load(Ordering::SeqCst) {
+            0 => (),
+            1 => renderer.stop_early(30),
+            _ => break
+        }
+
+        if !renderer.step()? {
             break;
         }
+        for warning in renderer.take_warnings() {
+            match json_progress.as_mut() {
+                Some(json_progress) => json_progress.warning(&warning)?,
+                None => pb.println(format!("Warning: {}", warning))
+            }
+        }
 
         if pb.length().unwrap() == 0 {
             if let Some(duration) = renderer.expected_duration_frames() {
@@ -245,6 +1178,16 @@ pub fn run() {
             }
         }
         pb.set_position(renderer.current_frame());
+        if let Some(json_progress) = json_progress.as_mut() {
+            json_progress.progress(&renderer)?;
+            continue;
+        }
+
+        let progress_title = format!("NSFPresenter - {}", renderer.progress_title());
+        if progress_title != terminal_title {
+            set_terminal_title(&progress_title);
+            terminal_title = progress_title;
+        }
 
         let current_video_duration = FormattedDuration(renderer.encoded_duration());
         let current_video_size = HumanBytes(renderer.encoded_size() as u64);
@@ -263,6 +1206,11 @@ pub fn run() {
         write!(message, " enc_time={}/{}", current_video_duration, expected_video_duration).unwrap();
         write!(message, " size={}", current_video_size).unwrap();
         write!(message, " rate={:.2}", current_encode_rate).unwrap();
+        let (video_queue_peak, audio_queue_peak) = renderer.encoder_queue_peaks();
+        write!(message, " queue_peak={}v/{}a", video_queue_peak, audio_queue_peak).unwrap();
+        if renderer.mux_stalls() > 0 {
+            write!(message, " mux_stalls={} ({:.1}s)", renderer.mux_stalls(), renderer.mux_stall_time().as_secs_f64()).unwrap();
+        }
 
         write!(message, "\nEMU]").unwrap();
         write!(message, " {}", renderer.emulator_progress()).unwrap();
@@ -275,7 +1223,51 @@ pub fn run() {
     }
 
     pb.finish_with_message("Finalizing encode...");
-    renderer.finish_encoding().unwrap();
+    renderer.finish_encoding()?;
+    set_terminal_title("");
+
+    if renderer.mux_stalls() > 0 {
+        status!(
+            "Warning: muxer blocked {} times for {:.2}s in total (longest {:.2}s), output storage may be too slow",
+            renderer.mux_stalls(),
+            renderer.mux_stall_time().as_secs_f64(),
+            renderer.longest_mux_time().as_secs_f64()
+        );
+    }
 
-    println!("Done!");
+    if matches.get_flag("diagnostics") {
+        for (chip, declared, writes) in renderer.expansion_register_writes() {
+            if declared || writes > 0 {
+                status!("{}: {} register writes{}", chip, writes, if declared { "" } else { " (not declared)" });
+            }
+        }
+    }
+    for warning in renderer.expansion_usage_warnings() {
+        status!("Warning: {}", warning);
+    }
+
+    if let Some(clipping_db) = renderer.clipping_db() {
+        status!(
+            "Warning: audio clipped on {} samples (peak {:.2} dB over full scale), try --auto-gain",
+            renderer.clipped_samples(),
+            clipping_db
+        );
+    }
+
+    let summary = RenderSummary::new(&renderer);
+    status!("{}", summary);
+    if let Err(e) = summary.save() {
+        status!("Warning: failed to save render summary: {}", e);
+    }
+
+    if let Some(json_progress) = json_progress.as_mut() {
+        json_progress.finished(&renderer, INTERRUPT_COUNT.load(Ordering::SeqCst) > 0)?;
+    }
+    if INTERRUPT_COUNT.load(Ordering::SeqCst) > 0 {
+        status!("Render interrupted, partial video saved to {}", output_path);
+        Ok(false)
+    } else {
+        status!("Done!");
+        Ok(true)
+    }
 }
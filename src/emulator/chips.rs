@@ -0,0 +1,64 @@
+use rusticnes_ui_common::drawing::Color;
+use super::Emulator;
+
+// Every chip and channel the piano roll can show, in the order they're listed. RusticNES calls the
+// Sunsoft 5B by its YM2149F sound chip, and APU is the final mix.
+const CHIP_CHANNELS: &[(&str, &[&str])] = &[
+    ("2A03", &["Pulse 1", "Pulse 2", "Triangle", "Noise", "DMC"]),
+    ("MMC5", &["Pulse 1", "Pulse 2", "PCM"]),
+    ("N163", &["NAMCO 1", "NAMCO 2", "NAMCO 3", "NAMCO 4", "NAMCO 5", "NAMCO 6", "NAMCO 7", "NAMCO 8"]),
+    ("VRC6", &["Pulse 1", "Pulse 2", "Sawtooth"]),
+    ("VRC7", &["FM 1", "FM 2", "FM 3", "FM 4", "FM 5", "FM 6"]),
+    ("YM2149F", &["A", "B", "C"]),
+    ("FDS", &["Wavetable"]),
+    ("APU", &["Final Mix"])
+];
+
+pub struct ChannelInfo {
+    pub chip: String,
+    pub channel: String,
+    // (config key, default color) for each of the channel's colors. The key says what the color
+    // is used for, e.g. duty0 for the first duty cycle, patch3 for a VRC7 patch or gradient_low.
+    pub colors: Vec<(String, Color)>
+}
+
+// The key a channel's color is stored under in piano_roll.settings.<chip>.<channel>
+pub fn color_key(chip: &str, channel: &str, index: usize) -> String {
+    match (chip, channel, index) {
+        ("2A03" | "MMC5" | "VRC6", "Pulse 1" | "Pulse 2", i) => format!("duty{}", i),
+        ("2A03", "Noise", i) => format!("mode{}", i),
+        ("VRC6", "Sawtooth", i) => format!("mode{}", i),
+        ("N163", _, 0) => "gradient_low".to_string(),
+        ("N163", _, 1) => "gradient_high".to_string(),
+        ("VRC7", _, i) => format!("patch{:X}", i),
+        (_, _, i) => {
+            debug_assert!(i == 0, "Settings not mapped properly for {} {}: missing color {}", chip, channel, i);
+            "static".to_string()
+        }
+    }
+}
+
+// The chips and channels with their default colors, for building settings UIs without
+// hard-coding them
+pub fn supported_channels() -> Vec<ChannelInfo> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    let settings = emulator.channel_settings();
+
+    CHIP_CHANNELS.iter()
+        .flat_map(|(chip, channels)| channels.iter().map(move |channel| (*chip, *channel)))
+        .map(|(chip, channel)| {
+            let colors = settings.get(&(chip.to_string(), channel.to_string()))
+                .map(|settings| settings.colors.clone())
+                .unwrap_or_default();
+            ChannelInfo {
+                chip: chip.to_string(),
+                channel: channel.to_string(),
+                colors: colors.into_iter()
+                    .enumerate()
+                    .map(|(i, color)| (color_key(chip, channel, i), color))
+                    .collect()
+            }
+        })
+        .collect()
+}
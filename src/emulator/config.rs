@@ -1,6 +1,15 @@
+use anyhow::{Result, Context};
+use std::fs;
+use std::path::Path;
+use toml::Value;
+use toml::map::Map;
+use super::famitracker_theme::theme_to_config;
+
 pub const DEFAULT_CONFIG: &str = r###"
 [piano_roll]
 draw_piano_strings = false
+piano_string_color = "#0C0C0C"
+piano_string_opacity = 1.0
 key_length = 24
 key_thickness = 5
 octave_count = 9
@@ -10,8 +19,49 @@ starting_octave = 0
 waveform_height = 48
 oscilloscope_glow_thickness = 2.0
 oscilloscope_line_thickness = 0.75
+scroll_direction = "top_to_bottom"
+keyboard_position = "auto"
+
+[piano_roll.draw_piano_strings_for]
+player_piano = true
+
+[audio.fds]
+output_lowpass = true
+output_lowpass_cutoff = 2000.0
+extra_lowpass = true
+extra_lowpass_cutoff = 6000.0
 "###;
 
+// `piano_roll.*` settings that can be changed without importing a configuration. The canvas size
+// is left out since it always follows the output resolution.
+pub const PIANO_ROLL_SETTINGS: &[&str] = &[
+    "background_color",
+    "draw_fds_tables",
+    "draw_n163_wavetables",
+    "draw_piano_strings",
+    "draw_piano_strings_for.bottom_to_top",
+    "draw_piano_strings_for.left_to_right",
+    "draw_piano_strings_for.player_piano",
+    "draw_piano_strings_for.right_to_left",
+    "draw_piano_strings_for.top_to_bottom",
+    "draw_piano_strings_for.vertical",
+    "fds_table_height",
+    "key_length",
+    "key_thickness",
+    "keyboard_position",
+    "n163_wavetable_height",
+    "octave_count",
+    "oscilloscope_glow_thickness",
+    "oscilloscope_line_thickness",
+    "piano_string_color",
+    "piano_string_opacity",
+    "scroll_direction",
+    "show_patch_names",
+    "speed_multiplier",
+    "starting_octave",
+    "waveform_height"
+];
+
 pub const REQUIRED_CONFIG: &str = r###"
 [piano_roll]
 background_color = "rgba(0, 0, 0, 0)"
@@ -20,4 +70,40 @@ canvas_height = 540
 
 [piano_roll.settings.APU."Final Mix"]
 hidden = true
-"###;
\ No newline at end of file
+"###;
+
+// Reads a configuration file, converting FamiTracker theme files into RusticNES configuration
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let config = fs::read_to_string(path.as_ref())
+        .with_context(|| format!("Failed to read configuration: {}", path.as_ref().display()))?;
+
+    match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("ini") => theme_to_config(&config),
+        _ => Ok(config)
+    }
+}
+
+// Returns only the parts of `config` that differ from `base`, or None if they are identical.
+pub fn config_diff(base: &Value, config: &Value) -> Option<Value> {
+    match (base, config) {
+        (Value::Table(base), Value::Table(config)) => {
+            let mut result = Map::new();
+            for (key, value) in config.iter() {
+                let value_diff = match base.get(key) {
+                    Some(base_value) => config_diff(base_value, value),
+                    None => Some(value.clone())
+                };
+                if let Some(value_diff) = value_diff {
+                    result.insert(key.clone(), value_diff);
+                }
+            }
+
+            match result.is_empty() {
+                true => None,
+                false => Some(Value::Table(result))
+            }
+        },
+        (base, config) if base == config => None,
+        (_, config) => Some(config.clone())
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use super::{Emulator, NsfDriverType, Region, frame_rate};
+
+// Long enough for most drivers to have touched every chip they use
+const DIAGNOSTICS_FRAMES: u32 = 600;
+// Track analysis stops at whichever of these comes first, so tracks that never loop stay quick
+const ANALYSIS_MAX_FRAMES: u32 = 10 * 60 * 60;
+const ANALYSIS_TIME_BUDGET: Duration = Duration::from_secs(4);
+
+pub struct TrackAnalysis {
+    pub driver: NsfDriverType,
+    // Intro and loop length in frames, if the driver looped within the analyzed part
+    pub loop_duration: Option<(usize, usize)>,
+    pub nsfe_duration: Option<usize>,
+    pub analyzed_frames: u32,
+    pub frame_rate: f64
+}
+
+// Runs a track without drawing anything to find out which expansion chips it actually writes to
+pub fn expansion_usage_warnings(path: &str, track_index: u8) -> Result<Vec<String>> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    emulator.open(path)?;
+    emulator.select_track(track_index);
+
+    for _ in 0..DIAGNOSTICS_FRAMES {
+        emulator.step();
+        emulator.buffer_audio_samples();
+        emulator.clear_sample_buffer();
+    }
+
+    Ok(emulator.expansion_usage_warnings())
+}
+
+// Plays a track headlessly until its loop is found, to suggest a stop condition before rendering
+pub fn analyze_track(path: &str, track_index: u8, region: Region) -> Result<TrackAnalysis> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    emulator.set_region(region);
+    emulator.open(path)?;
+    emulator.select_track(track_index);
+
+    let analysis_start = Instant::now();
+    let mut analyzed_frames = 0;
+    // Loops are only detected for known drivers, there's nothing to wait for otherwise
+    if emulator.driver_type() != NsfDriverType::Unknown {
+        while analyzed_frames < ANALYSIS_MAX_FRAMES && analysis_start.elapsed() < ANALYSIS_TIME_BUDGET {
+            emulator.step();
+            emulator.buffer_audio_samples();
+            emulator.clear_sample_buffer();
+            analyzed_frames += 1;
+
+            let song_ended = emulator.get_song_position().map_or(false, |p| p.end);
+            if song_ended || emulator.loop_duration().is_some() {
+                break;
+            }
+        }
+    }
+
+    Ok(TrackAnalysis {
+        driver: emulator.driver_type(),
+        loop_duration: emulator.loop_duration(),
+        nsfe_duration: emulator.nsfe_duration(),
+        analyzed_frames,
+        frame_rate: frame_rate(region)
+    })
+}
@@ -6,16 +6,59 @@ use std::collections::vec_deque::VecDeque;
 use std::fs;
 use std::str;
 use std::rc::Rc;
-use anyhow::{Result, Context};
-use rusticnes_core::apu::FilterType;
+use std::mem;
+use std::ops::RangeInclusive;
+use anyhow::{Result, Context, bail};
+use rusticnes_core::apu::{FilterType, PlaybackRate};
+use rusticnes_core::memory;
+use rusticnes_core::nes::NesState;
 use rusticnes_ui_common::application::RuntimeState as RusticNESRuntimeState;
+use rusticnes_ui_common::drawing::Color;
 use rusticnes_ui_common::events::Event;
 use rusticnes_ui_common::panel::Panel;
 use rusticnes_ui_common::piano_roll_window::{ChannelSettings, PianoRollWindow, PollingType};
-use super::SongPosition;
+use super::{SongPosition, Region, NES_NTSC_FRAMERATE, frame_rate};
 use super::nsf::{Nsf, NsfDriverType};
-use super::nsfeparser::{NsfeMetadata, nsfe_to_nsf2};
-use super::config::{DEFAULT_CONFIG, REQUIRED_CONFIG};
+use super::nsfeparser::NsfeMetadata;
+use super::config::{DEFAULT_CONFIG, REQUIRED_CONFIG, PIANO_ROLL_SETTINGS, config_diff};
+use super::power_on::{PowerOnRng, RamInit};
+use super::chips::color_key;
+
+// The player idles in its own code between PLAY calls, so the CPU only gets here once INIT has returned
+const NSF_PLAYER_ADDRESSES: RangeInclusive<u16> = 0x4A00..=0x4BFF;
+const INIT_TIME_BUDGET_FRAMES: u32 = 120;
+// Savestates are taken this often while stepping so seeks can rewind without replaying the whole song
+const CHECKPOINT_INTERVAL_FRAMES: u32 = 600;
+// Each savestate is several MB, so past this many every other one is dropped, spreading them out further
+const MAX_CHECKPOINTS: usize = 32;
+// How long a seek runs looking for a song position that hasn't been played yet
+const MAX_SEEK_FRAMES: u32 = 20 * 60 * 60;
+// FamiTracker 0.4.x songs have at most 128 frames, forks raised that to 256
+const FT_CLASSIC_MAX_FRAMES: usize = 128;
+// Implausible positions read before giving up on position detection for the track
+const MAX_POSITION_ERRORS: u32 = 8;
+
+// Everything step() changes, so restoring one puts the emulator back exactly where it was.
+// The piano roll isn't included, it catches up as the emulator runs from the restored frame.
+struct Checkpoint {
+    nes: NesState,
+    runtime_frame: u32,
+    runtime_scanline: u16,
+    runtime_quarter_frame_count: u32,
+    runtime_half_frame_count: u32,
+    last_position: Option<SongPosition>,
+    loop_duration: Option<(usize, usize)>,
+    loop_count: usize,
+    init_frames: Option<u32>,
+    position_errors: u32
+}
+
+fn color_string(color: &Color) -> String {
+    match color.alpha() {
+        255 => format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b()),
+        _ => format!("rgba({}, {}, {}, {})", color.r(), color.g(), color.b(), color.alpha())
+    }
+}
 
 pub struct Emulator {
     runtime: RusticNESRuntimeState,
@@ -28,7 +71,20 @@ pub struct Emulator {
     song_positions: HashMap<SongPosition, u32>,
     last_position: Option<SongPosition>,
     loop_duration: Option<(usize, usize)>,
-    loop_count: usize
+    loop_count: usize,
+    seed: u64,
+    ram_init: RamInit,
+    region: Region,
+    gain: f64,
+    output_gain: f64,
+    peak_level: i32,
+    clipped_samples: usize,
+    init_frames: Option<u32>,
+    position_errors: u32,
+    warnings: Vec<String>,
+    // Only set once something seeks, so renders that never do don't pay for the savestates
+    seekable: bool,
+    checkpoints: Vec<Checkpoint>
 }
 
 impl Emulator {
@@ -44,7 +100,19 @@ impl Emulator {
             song_positions: HashMap::new(),
             last_position: None,
             loop_duration: None,
-            loop_count: 0
+            loop_count: 0,
+            seed: 0,
+            ram_init: RamInit::Zero,
+            region: Region::Ntsc,
+            gain: 1.0,
+            output_gain: 4.0 / 3.0,
+            peak_level: 0,
+            clipped_samples: 0,
+            init_frames: None,
+            position_errors: 0,
+            warnings: Vec::new(),
+            seekable: false,
+            checkpoints: Vec::new()
         }
     }
 
@@ -80,6 +148,20 @@ impl Emulator {
         toml::to_string(&self.runtime.settings.root).unwrap()
     }
 
+    pub fn dump_minimal_config(&self) -> String {
+        let mut default_emulator = Emulator::new();
+        default_emulator.init(None);
+        let default_channel_settings = default_emulator.channel_settings();
+        default_emulator.apply_channel_settings(&default_channel_settings);
+
+        // The settings tree comes from a different version of the toml crate, so convert it first
+        let base = toml::Value::try_from(&default_emulator.runtime.settings.root).unwrap();
+        let config = toml::Value::try_from(&self.runtime.settings.root).unwrap();
+        let diff = config_diff(&base, &config).unwrap_or(toml::Value::Table(toml::map::Map::new()));
+
+        toml::to_string(&diff).unwrap()
+    }
+
     pub fn init(&mut self, import_config: Option<&str>) {
         // RusticNES default config
         self.load_config(None);
@@ -95,55 +177,123 @@ impl Emulator {
         self.load_config(Some(REQUIRED_CONFIG));
     }
 
-    fn load(&mut self, cart_data: &[u8]) {
+    fn load(&mut self, cart_data: &[u8]) -> Result<()> {
         let empty_vec: Vec<u8> = Vec::new();
-        let data_vec: Vec<u8> = match &cart_data[0..4] {
-            b"NSFE" => nsfe_to_nsf2(&cart_data).unwrap(),
-            _ => cart_data.to_vec()
-        };
-
-        let nsf = Nsf::from(&data_vec);
+        let nsf = Nsf::from(cart_data)?;
+        let data_vec: Vec<u8> = nsf.raw_bytes().to_vec();
+        self.warnings.extend(nsf.compatibility_warnings());
         if nsf.magic_valid() {
             self.nsf = Some(nsf);
             self.nsfe_metadata = self.nsf.as_ref().unwrap().nsfe_metadata();
         }
 
         self.dispatch(Event::LoadCartridge("cartridge".to_string(), Rc::new(data_vec), Rc::new(empty_vec)));
+        // Loading replaces the console, so the region has to be applied again
+        self.runtime.nes.set_region(self.region);
+        if let Some(nsf) = &self.nsf {
+            if !nsf.supports_region(self.region) {
+                self.warnings.push(format!("Is not marked as supporting {}, it may play at the wrong speed and pitch", self.region.to_string().to_uppercase()));
+            }
+        }
+        self.apply_power_on_state()?;
 
         if self.nsf.is_some() {
-            println!("NSF Version: {}", self.nsf.as_ref().unwrap().version());
-            println!("Title: {}", self.nsf.as_ref().unwrap().title().unwrap());
-            println!("Artist: {}", self.nsf.as_ref().unwrap().artist().unwrap());
-            println!("Copyright: {}", self.nsf.as_ref().unwrap().copyright().unwrap());
-
-            print!("Chips: 2A03");
-            if self.nsf.as_ref().unwrap().fds() { print!(", FDS"); }
-            if self.nsf.as_ref().unwrap().n163() { print!(", N163"); }
-            if self.nsf.as_ref().unwrap().mmc5() { print!(", MMC5"); }
-            if self.nsf.as_ref().unwrap().vrc6() { print!(", VRC6"); }
-            if self.nsf.as_ref().unwrap().vrc7() { print!(", VRC7"); }
-            if self.nsf.as_ref().unwrap().s5b() { print!(", S5B"); }
-            println!();
+            status!("NSF Version: {}", self.nsf.as_ref().unwrap().version());
+            status!("Title: {}", self.nsf.as_ref().unwrap().title().unwrap());
+            status!("Artist: {}", self.nsf.as_ref().unwrap().artist().unwrap());
+            status!("Copyright: {}", self.nsf.as_ref().unwrap().copyright().unwrap());
+            status!("Region: {}", self.region.to_string().to_uppercase());
+            status!("Play rate: {:.2} Hz", self.play_rate());
+
+            let nsf = self.nsf.as_ref().unwrap();
+            let mut chips = vec!["2A03"];
+            if nsf.fds() { chips.push("FDS"); }
+            if nsf.n163() { chips.push("N163"); }
+            if nsf.mmc5() { chips.push("MMC5"); }
+            if nsf.vrc6() { chips.push("VRC6"); }
+            if nsf.vrc7() { chips.push("VRC7"); }
+            if nsf.s5b() { chips.push("S5B"); }
+            status!("Chips: {}", chips.join(", "));
 
             match self.driver_type() {
-                NsfDriverType::Unknown => println!("Driver type: unknown"),
-                NsfDriverType::FTClassic => println!("Driver type: classic FamiTracker"),
-                NsfDriverType::FT0CC => println!("Driver type: 0CC-FamiTracker"),
-                NsfDriverType::FTDn => println!("Driver type: Dn-FamiTracker")
+                NsfDriverType::Unknown => status!("Driver type: unknown"),
+                NsfDriverType::FTClassic => status!("Driver type: classic FamiTracker"),
+                NsfDriverType::FT0CC => status!("Driver type: 0CC-FamiTracker"),
+                NsfDriverType::FTDn => status!("Driver type: Dn-FamiTracker")
             }
         }
+
+        Ok(())
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    // Must be set before the module is opened
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.piano_roll_window.region = region;
+    }
+
+    // The region the module is flagged for, NTSC if it supports that
+    pub fn preferred_region(&self) -> Region {
+        self.nsf.as_ref().map_or(Region::Ntsc, |nsf| nsf.preferred_region())
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        frame_rate(self.region)
+    }
+
+    fn apply_power_on_state(&mut self) -> Result<()> {
+        let mut rng = PowerOnRng::new(self.seed);
+
+        if self.ram_init != RamInit::Zero {
+            let ram_size = self.runtime.nes.memory.iram_raw.len();
+            self.runtime.nes.memory.iram_raw = self.ram_init.ram_contents(ram_size, &mut rng)
+                .map_err(anyhow::Error::msg)?;
+        }
+
+        if self.seed != 0 {
+            // The NSF play timer is free-running on hardware, so the first play call can land anywhere
+            // relative to the APU frame counter
+            self.runtime.nes.mapper.nsf_set_playback_phase(rng.next_f32());
+
+            // The frame counter also powers up partway through its sequence, and the CPU/APU cycle
+            // alignment decides whether the player's $4017 write takes 3 or 4 cycles to reset it
+            let apu = &mut self.runtime.nes.apu;
+            apu.frame_sequencer = (rng.next_u64() % apu.frame_steps[3] as u64) as u16;
+            apu.current_cycle = rng.next_u64() & 1;
+        }
+
+        Ok(())
     }
 
     pub fn open(&mut self, path: &str) -> Result<()> {
         let cart_data = fs::read(path)
             .with_context(|| format!("Failed to read input file: {}", path))?;
-        self.load(&cart_data);
-        Ok(())
+        self.load(&cart_data)
     }
 
     pub fn select_track(&mut self, index: u8) {
         if index > 0 && index <= self.track_count() {
             self.nsf_track_index = index;
+            // Non-returning INIT routines are already reported when the NSF is loaded
+            self.init_frames = match self.nsf.as_ref().map_or(false, |nsf| nsf.nsf2_nonreturning_init()) {
+                true => None,
+                false => Some(0)
+            };
+            // A new track gets its own chance at song position readings and loop detection
+            self.position_errors = 0;
+            self.last_position = None;
             self.runtime.nes.mapper.nsf_set_track(index);
             self.runtime.nes.mapper.nsf_manual_mode();
             if let Some(nsfe_metadata) = &self.nsfe_metadata {
@@ -154,6 +304,30 @@ impl Emulator {
         }
     }
 
+    // The track the NSF header says to play first, which isn't always track 1
+    pub fn starting_song(&self) -> u8 {
+        match &self.nsf {
+            Some(nsf) => nsf.starting_song().clamp(1, nsf.songs().max(1)),
+            None => 1
+        }
+    }
+
+    // Tracks marked as sound effects in the NSFe psfx chunk
+    pub fn is_sound_effect(&self, index: u8) -> bool {
+        self.nsfe_metadata.as_ref().map_or(false, |m| m.track_is_sound_effect(index as _))
+    }
+
+    // Only NSFe and NSF2 modules have per-track titles
+    pub fn track_title(&self, index: u8) -> Option<String> {
+        self.nsfe_metadata.as_ref()?.track_title(index as usize)
+    }
+
+    // The module's own title, where nsf_metadata() prefers the current track's
+    pub fn module_title(&self) -> Option<String> {
+        self.nsfe_metadata.as_ref().and_then(|m| m.title())
+            .or_else(|| self.nsf.as_ref()?.title().ok())
+    }
+
     pub fn track_count(&self) -> u8 {
         match &self.nsf {
             Some(nsf) => nsf.songs(),
@@ -161,6 +335,30 @@ impl Emulator {
         }
     }
 
+    pub fn play_rate(&self) -> f64 {
+        match (&self.nsf, self.region) {
+            (Some(nsf), Region::Ntsc) => nsf.ntsc_play_rate(),
+            (Some(nsf), Region::Pal | Region::Dendy) => nsf.pal_play_rate(),
+            (None, _) => self.frame_rate()
+        }
+    }
+
+    pub fn nonstandard_play_rate(&self) -> bool {
+        (self.play_rate() - self.frame_rate()).abs() > 0.1
+    }
+
+    // How many times the piano roll takes a slice of the channels per video frame
+    pub fn polls_per_frame(&self) -> f64 {
+        self.piano_roll_window.updates_per_second() as f64 / self.frame_rate()
+    }
+
+    // Each poll draws speed_multiplier slices of the same channel state at once, so a note takes
+    // that many slices' worth of scrolling, one poll, to show up at the keyboard
+    pub fn scroll_latency_frames(&self) -> f64 {
+        let frame_slices = self.piano_roll_window.speed_multiplier as f64 * self.polls_per_frame();
+        self.piano_roll_window.speed_multiplier as f64 / frame_slices
+    }
+
     pub fn nsf_metadata(&self) -> Result<Option<(String, String, String)>> {
         Ok(match (&self.nsf, &self.nsfe_metadata) {
             (None, _) => None,
@@ -181,38 +379,94 @@ impl Emulator {
         })
     }
 
-    fn get_famitracker_song_position(&self, mut ptr: usize) -> SongPosition {
+    fn get_famitracker_song_position(&self, mut ptr: usize, max_frames: usize) -> Option<SongPosition> {
         if let Some(nsf) = &self.nsf {
             if nsf.fds() {
                 ptr += 2;
             }
         }
 
-        let player_flags = self.runtime.nes.memory.iram_raw[ptr];
-        let row = self.runtime.nes.memory.iram_raw[ptr+1];
-        let frame = self.runtime.nes.memory.iram_raw[ptr+2];
-        let engine_flags = self.runtime.nes.memory.iram_raw[ptr+3];
+        // Drivers that were only detected as FamiTracker may keep something else here entirely
+        let [player_flags, row, frame, engine_flags] = match self.runtime.nes.memory.iram_raw.get(ptr..ptr + 4) {
+            Some(&[player_flags, row, frame, engine_flags]) => [player_flags, row, frame, engine_flags],
+            _ => return None
+        };
+        if frame as usize >= max_frames {
+            return None;
+        }
 
         if (player_flags & 0x2) != 0 {
             // If a Cxx was issued, report that the song has ended.
-            SongPosition::at_end()
+            Some(SongPosition::at_end())
         } else if (engine_flags & 0x1) != 0 {
             // If the engine is loading the next frame, the row number will be wrong - correct it
-            SongPosition::new(frame, 0)
+            Some(SongPosition::new(frame, 0))
         } else {
-            SongPosition::new(frame, row)
+            Some(SongPosition::new(frame, row))
         }
     }
 
-    pub fn get_song_position(&self) -> Option<SongPosition> {
+    fn read_song_position(&self) -> Option<SongPosition> {
         match self.driver_type() {
-            NsfDriverType::FTClassic => Some(self.get_famitracker_song_position(0x211)),
-            NsfDriverType::FT0CC => Some(self.get_famitracker_song_position(0x215)),
-            NsfDriverType::FTDn => Some(self.get_famitracker_song_position(0x215)),
+            NsfDriverType::FTClassic => self.get_famitracker_song_position(0x211, FT_CLASSIC_MAX_FRAMES),
+            NsfDriverType::FT0CC => self.get_famitracker_song_position(0x215, 256),
+            NsfDriverType::FTDn => self.get_famitracker_song_position(0x215, 256),
             NsfDriverType::Unknown => None
         }
     }
 
+    // Reported as unknown once too many readings didn't look like a FamiTracker song
+    pub fn get_song_position(&self) -> Option<SongPosition> {
+        if self.position_errors >= MAX_POSITION_ERRORS {
+            return None;
+        }
+        self.read_song_position()
+    }
+
+    // The engine moves at most one row per PLAY call, and only jumps within a frame back to its first row
+    fn plausible_position(&self, position: Option<SongPosition>) -> bool {
+        let (last_position, position) = match (self.last_position, position) {
+            (_, None) => return false,
+            (Some(last_position), Some(position)) => (last_position, position),
+            (None, Some(_)) => return true
+        };
+        if position.end || last_position.end || position.frame != last_position.frame || position.row == 0 {
+            return true;
+        }
+
+        let max_rows = (self.play_rate() / self.frame_rate()).ceil() as u8;
+        position.row >= last_position.row && position.row - last_position.row <= max_rows
+    }
+
+    fn check_song_position(&mut self) {
+        if self.driver_type() == NsfDriverType::Unknown || self.position_errors >= MAX_POSITION_ERRORS {
+            return;
+        }
+        // RAM holds whatever it was initialized with until INIT has set the player up
+        if self.init_frames.is_some() {
+            return;
+        }
+
+        let position = self.read_song_position();
+        if self.plausible_position(position) {
+            return;
+        }
+
+        self.position_errors += 1;
+        if self.position_errors == MAX_POSITION_ERRORS {
+            self.warnings.push(format!(
+                "{} song position readings looked wrong (last {}), the driver may not be standard FamiTracker. Loop detection is disabled for this track.",
+                MAX_POSITION_ERRORS,
+                position.map_or("out of range".to_string(), |p| p.to_string())
+            ));
+            // Anything detected so far can't be trusted either
+            self.song_positions.clear();
+            self.last_position = None;
+            self.loop_duration = None;
+            self.loop_count = 0;
+        }
+    }
+
     pub fn step(&mut self) {
         while self.runtime.nes.ppu.current_scanline == 242 {
             self.dispatch(Event::NesRunScanline);
@@ -221,6 +475,8 @@ impl Emulator {
             self.dispatch(Event::NesRunScanline);
         }
         self.dispatch(Event::Update);
+        self.check_init_time();
+        self.check_song_position();
 
         if let Some(position) = self.get_song_position() {
             let last_frame = self.last_frame();
@@ -253,6 +509,172 @@ impl Emulator {
 
             self.last_position = Some(position);
         }
+
+        if self.seekable && self.last_frame() % CHECKPOINT_INTERVAL_FRAMES == 0 {
+            self.save_checkpoint();
+        }
+    }
+
+    fn save_checkpoint(&mut self) {
+        let last_frame = self.last_frame();
+        if self.checkpoints.last().map_or(false, |c| c.nes.last_frame >= last_frame) {
+            return;
+        }
+
+        // Only the NSF mapper supports savestates, anything else just can't seek backwards
+        if let Some(nes) = self.runtime.nes.try_clone() {
+            self.checkpoints.push(Checkpoint {
+                nes,
+                runtime_frame: self.runtime.last_frame,
+                runtime_scanline: self.runtime.last_scanline,
+                runtime_quarter_frame_count: self.runtime.last_apu_quarter_frame_count,
+                runtime_half_frame_count: self.runtime.last_apu_half_frame_count,
+                last_position: self.last_position,
+                loop_duration: self.loop_duration,
+                loop_count: self.loop_count,
+                init_frames: self.init_frames,
+                position_errors: self.position_errors
+            });
+        }
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            let mut index = 0;
+            self.checkpoints.retain(|_| {
+                index += 1;
+                index % 2 == 0
+            });
+        }
+    }
+
+    fn restore_checkpoint(&mut self, index: usize) {
+        let checkpoint = &self.checkpoints[index];
+        let nes = match checkpoint.nes.try_clone() {
+            Some(nes) => nes,
+            None => return
+        };
+
+        self.runtime.nes = nes;
+        self.runtime.last_frame = checkpoint.runtime_frame;
+        self.runtime.last_scanline = checkpoint.runtime_scanline;
+        self.runtime.last_apu_quarter_frame_count = checkpoint.runtime_quarter_frame_count;
+        self.runtime.last_apu_half_frame_count = checkpoint.runtime_half_frame_count;
+        self.last_position = checkpoint.last_position;
+        self.loop_duration = checkpoint.loop_duration;
+        self.loop_count = checkpoint.loop_count;
+        self.init_frames = checkpoint.init_frames;
+        self.position_errors = checkpoint.position_errors;
+
+        // Positions reached after the checkpoint will be reached again
+        let checkpoint_frame = checkpoint.nes.last_frame;
+        self.song_positions.retain(|_, frame| *frame <= checkpoint_frame);
+        self.sample_buffer.clear();
+    }
+
+    fn step_silently(&mut self) {
+        self.step();
+        self.buffer_audio_samples();
+        self.clear_sample_buffer();
+    }
+
+    // Runs or rewinds the emulator to the first frame the song reaches a position, and returns that frame.
+    // Positions that were already played are found by binary searching the checkpoints for the closest one before it.
+    // Savestates are only taken after the first seek, so earlier positions can only be reached going forwards.
+    pub fn seek_to_position(&mut self, target: SongPosition) -> Result<u32> {
+        if self.get_song_position().is_none() {
+            bail!("Seeking by song position is not supported for this NSF's driver");
+        }
+        self.seekable = true;
+
+        if let Some(&target_frame) = self.song_positions.get(&target) {
+            let index = self.checkpoints.partition_point(|c| c.nes.last_frame <= target_frame);
+            if index > 0 {
+                let checkpoint_frame = self.checkpoints[index - 1].nes.last_frame;
+                if target_frame < self.last_frame() || checkpoint_frame > self.last_frame() {
+                    self.restore_checkpoint(index - 1);
+                }
+            }
+            if target_frame < self.last_frame() {
+                bail!("Cannot seek back to {}, no savestate was taken before it", target);
+            }
+
+            while self.last_frame() < target_frame {
+                self.step_silently();
+            }
+            return Ok(self.last_frame());
+        }
+
+        let seek_start = self.last_frame();
+        while self.get_song_position() != Some(target) {
+            let song_ended = self.get_song_position().map_or(false, |p| p.end);
+            if song_ended || self.last_frame() - seek_start >= MAX_SEEK_FRAMES {
+                bail!("The song never reaches {}", target);
+            }
+            self.step_silently();
+        }
+
+        Ok(self.last_frame())
+    }
+
+    fn check_init_time(&mut self) {
+        let init_frames = match self.init_frames {
+            Some(f) => f + 1,
+            None => return
+        };
+
+        if NSF_PLAYER_ADDRESSES.contains(&self.runtime.nes.registers.pc) {
+            self.init_frames = None;
+        } else if init_frames >= INIT_TIME_BUDGET_FRAMES {
+            self.warnings.push(format!("INIT routine for track {} has not returned after {} frames, the track may be silent", self.nsf_track_index, init_frames));
+            self.init_frames = None;
+        } else {
+            self.init_frames = Some(init_frames);
+        }
+    }
+
+    // (chip, declared in the header, register writes so far) for each expansion chip
+    pub fn expansion_register_writes(&self) -> Vec<(String, bool, u64)> {
+        self.runtime.nes.mapper.nsf_expansion_writes()
+            .into_iter()
+            .map(|(chip, declared, writes)| (chip.to_string(), declared, writes))
+            .collect()
+    }
+
+    // Chips that are declared but never written to, or written to without being declared,
+    // usually mean the rip is misconfigured and some channels are silent or misrouted
+    pub fn expansion_usage_warnings(&self) -> Vec<String> {
+        self.expansion_register_writes()
+            .into_iter()
+            .filter_map(|(chip, declared, writes)| match (declared, writes) {
+                (true, 0) => Some(format!("Declares {} but never uses it", chip)),
+                (false, writes) if writes > 0 => Some(format!("Writes to {} registers {} times but doesn't declare {}", chip, writes, chip)),
+                _ => None
+            })
+            .collect()
+    }
+
+    // Starts collecting writes to the sound registers for take_audio_writes
+    pub fn log_audio_writes(&mut self) {
+        self.runtime.nes.audio_write_log = Some(Vec::new());
+    }
+
+    // (CPU cycle, address, data) for each sound register write since the last call
+    pub fn take_audio_writes(&mut self) -> Vec<(u64, u16, u8)> {
+        self.runtime.nes.audio_write_log.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    // Reads CPU memory without side effects, e.g. to copy DPCM samples
+    pub fn peek_memory(&self, address: u16) -> u8 {
+        memory::debug_read_byte(&self.runtime.nes, address)
+    }
+
+    pub fn cpu_clock_rate(&self) -> u64 {
+        self.region.cpu_clock_rate()
+    }
+
+    // Problems found since the last call that might make the render silent or wrong
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        let mut warnings = mem::take(&mut self.warnings);
+        warnings.append(&mut self.piano_roll_window.warnings);
+        warnings
     }
 
     pub fn set_piano_roll_size(&mut self, w: u32, h: u32) {
@@ -260,12 +682,107 @@ impl Emulator {
         self.dispatch(Event::ApplyIntegerSetting("piano_roll.canvas_height".to_string(), h as i64));
     }
 
+    pub fn set_piano_roll_layout(&mut self, octave_count: u32, key_thickness: u32, key_length: u32, waveform_height: u32) {
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.octave_count".to_string(), octave_count as i64));
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.key_thickness".to_string(), key_thickness as i64));
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.key_length".to_string(), key_length as i64));
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.waveform_height".to_string(), waveform_height as i64));
+    }
+
+    // Current value of a `piano_roll.*` setting, formatted the way set_piano_roll_setting takes it
+    pub fn piano_roll_setting(&self, key: &str) -> Option<String> {
+        let value = self.runtime.settings.get(format!("piano_roll.{}", key))?;
+        // The settings tree comes from a different version of the toml crate
+        match toml::Value::try_from(value).ok()? {
+            toml::Value::String(s) => Some(s),
+            value => Some(value.to_string())
+        }
+    }
+
+    // Sets a `piano_roll.*` setting given as text, typed the way it would be written in a configuration
+    pub fn set_piano_roll_setting(&mut self, key: &str, value: &str) -> Result<()> {
+        if !PIANO_ROLL_SETTINGS.contains(&key) {
+            bail!("Unknown piano roll setting: {} (expected one of: {})", key, PIANO_ROLL_SETTINGS.join(", "));
+        }
+
+        let path = format!("piano_roll.{}", key);
+        let event = if let Ok(value) = value.parse::<i64>() {
+            Event::StoreIntegerSetting(path, value)
+        } else if let Ok(value) = value.parse::<f64>() {
+            Event::StoreFloatSetting(path, value)
+        } else if let Ok(value) = value.parse::<bool>() {
+            Event::StoreBooleanSetting(path, value)
+        } else {
+            Event::StoreStringSetting(path, value.to_string())
+        };
+        self.dispatch(event);
+
+        Ok(())
+    }
+
+    // Shown under the roll in the vertical layout
+    pub fn set_piano_roll_caption(&mut self, lines: Vec<String>) {
+        self.piano_roll_window.caption = lines;
+    }
+
+    pub fn set_piano_roll_scroll_duration(&mut self, seconds: f64) {
+        self.piano_roll_window.set_scroll_duration(seconds as f32);
+    }
+
+    pub fn outline_style(&self) -> (Color, u32) {
+        (self.piano_roll_window.outline_color, self.piano_roll_window.outline_thickness)
+    }
+
+    pub fn set_outline_style(&mut self, color: Color, thickness: u32) {
+        self.dispatch(Event::StoreStringSetting("piano_roll.outline_color".to_string(), color_string(&color)));
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.outline_thickness".to_string(), thickness as i64));
+    }
+
+    pub fn divider_style(&self) -> (Color, u32) {
+        (self.piano_roll_window.divider_color, self.piano_roll_window.divider_width)
+    }
+
+    pub fn set_divider_style(&mut self, color: Color, width: u32) {
+        self.dispatch(Event::StoreStringSetting("piano_roll.divider_color".to_string(), color_string(&color)));
+        self.dispatch(Event::StoreIntegerSetting("piano_roll.divider_width".to_string(), width as i64));
+    }
+
+    pub fn keyboard_glow(&self) -> (Color, f32) {
+        (self.piano_roll_window.keyboard_glow_color, self.piano_roll_window.keyboard_glow_intensity)
+    }
+
+    pub fn set_keyboard_glow(&mut self, color: Color, intensity: f32) {
+        self.dispatch(Event::StoreStringSetting("piano_roll.keyboard_glow_color".to_string(), color_string(&color)));
+        self.dispatch(Event::StoreFloatSetting("piano_roll.keyboard_glow_intensity".to_string(), intensity as f64));
+    }
+
+    pub fn minimal(&self) -> bool {
+        self.piano_roll_window.minimal
+    }
+
+    pub fn set_minimal(&mut self, minimal: bool) {
+        self.dispatch(Event::StoreBooleanSetting("piano_roll.minimal".to_string(), minimal));
+    }
+
     pub fn get_piano_roll_frame(&mut self) -> Vec<u8> {
         self.dispatch(Event::RequestFrame);
 
         self.piano_roll_window.active_canvas().buffer.clone()
     }
 
+    // The piano roll part of the way (t from 0 to 1) from the previous emulated frame to this one,
+    // drawn by scrolling it back by the rest of the frame's slices
+    pub fn get_piano_roll_subframe(&mut self, t: f64) -> Vec<u8> {
+        // Every poll adds speed_multiplier slices, and there are several polls per frame
+        let frame_slices = self.piano_roll_window.speed_multiplier as f64 * self.polls_per_frame();
+        self.piano_roll_window.scroll_margin = frame_slices.ceil() as u32;
+        self.piano_roll_window.scroll_offset = ((1.0 - t) * frame_slices).round() as u32;
+        let frame = self.get_piano_roll_frame();
+        self.piano_roll_window.scroll_offset = 0;
+
+        frame
+    }
+
     pub fn config_audio(&mut self, sample_rate: u64, buffer_size: usize, famicom: bool, high_quality: bool, multiplexing: bool) {
         self.runtime.nes.apu.set_sample_rate(sample_rate);
 
@@ -283,32 +800,85 @@ impl Emulator {
             self.sample_buffer.reserve(buffer_size);
         }
 
-        self.piano_roll_window.polling_type = PollingType::ApuQuarterFrame;
+        // Drivers that play faster than the APU frame counter would have play calls fall between
+        // quarter frame polls, so those are polled at the play rate instead, several times per video frame
+        let play_rate = self.play_rate();
+        self.piano_roll_window.polling_type = match play_rate > self.frame_rate() * 4.0 {
+            true => PollingType::Rate(play_rate as f32),
+            false => PollingType::ApuQuarterFrame
+        };
+        self.piano_roll_window.polling_accumulator = 0.0;
     }
 
-    pub fn get_audio_samples(&mut self, sample_count: usize, volume_divisor: i16) -> Option<Vec<i16>> {
+    pub fn get_audio_samples(&mut self, sample_count: usize, volume: f64) -> Option<Vec<i16>> {
         if self.runtime.nes.apu.samples_queued() < 256 {
             return None;
         }
 
-        let samples: Vec<i16> = self.runtime.nes.apu.consume_samples();
-        self.sample_buffer.extend(samples);
+        self.buffer_audio_samples();
 
         if self.sample_buffer.len() < sample_count {
             return None;
         }
 
-        let volume_divisor = match volume_divisor {
-            0 => 1,
-            v => v
-        };
+        Some(self.drain_audio_samples(sample_count, volume))
+    }
+
+    // Takes up to `sample_count` samples, however many are buffered, e.g. for the end of the audio
+    pub fn get_remaining_audio_samples(&mut self, sample_count: usize, volume: f64) -> Vec<i16> {
+        self.buffer_audio_samples();
+        self.drain_audio_samples(sample_count.min(self.sample_buffer.len()), volume)
+    }
+
+    fn drain_audio_samples(&mut self, sample_count: usize, volume: f64) -> Vec<i16> {
+        let mut samples: Vec<i16> = Vec::with_capacity(sample_count);
+        for s in self.sample_buffer.drain(0..sample_count) {
+            let s = (s as f64 * volume * self.gain * self.output_gain) as i32;
+
+            self.peak_level = self.peak_level.max(s.abs());
+            if s > i16::MAX as i32 || s < i16::MIN as i32 {
+                self.clipped_samples += 1;
+            }
+            samples.push(s.clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+        }
+        samples
+    }
+
+    pub fn gain(&self) -> f64 {
+        self.gain
+    }
+
+    pub fn set_gain(&mut self, gain: f64) {
+        self.gain = gain;
+    }
+
+    pub fn output_gain_db(&self) -> f64 {
+        20.0 * self.output_gain.log10()
+    }
+
+    // 0 dB is the level the core mixes at, which is quieter than most recordings
+    pub fn set_output_gain_db(&mut self, gain_db: f64) {
+        self.output_gain = 10.0_f64.powf(gain_db / 20.0);
+    }
+
+    // Loudest output sample so far, which can exceed the i16 range if the mix clipped
+    pub fn peak_level(&self) -> i32 {
+        self.peak_level
+    }
+
+    pub fn clipped_samples(&self) -> usize {
+        self.clipped_samples
+    }
+
+    pub fn buffer_audio_samples(&mut self) {
+        let samples: Vec<i16> = self.runtime.nes.apu.consume_samples();
+        self.sample_buffer.extend(samples);
+    }
 
-        let samples: Vec<i16> = self.sample_buffer
-            .drain(0..sample_count)
-            .map(|s| s / volume_divisor)
-            .map(|s| s.saturating_add(s / 3))
-            .collect();
-        Some(samples)
+    // Drops the oldest buffered samples, e.g. to shorten a lead to a fraction of a frame
+    pub fn discard_audio_samples(&mut self, sample_count: usize) {
+        let sample_count = sample_count.min(self.sample_buffer.len());
+        self.sample_buffer.drain(0..sample_count);
     }
 
     pub fn clear_sample_buffer(&mut self) {
@@ -319,19 +889,34 @@ impl Emulator {
         self.runtime.nes.last_frame
     }
 
+    // None once the song position readings turned out to be unreliable, since loops aren't counted after that
     pub fn loop_count(&self) -> Option<usize> {
         match self.driver_type() {
             NsfDriverType::Unknown => None,
+            _ if self.position_errors >= MAX_POSITION_ERRORS => None,
             _ => Some(self.loop_count)
         }
     }
 
+    // NSFe times are stored as NTSC frames, these are in frames of the current region
+    fn region_frames(&self, ntsc_frames: usize) -> usize {
+        (ntsc_frames as f64 * self.frame_rate() / NES_NTSC_FRAMERATE).round() as usize
+    }
+
     pub fn nsfe_duration(&self) -> Option<usize> {
-        self.nsfe_metadata.as_ref()?.track_duration(self.nsf_track_index as _).clone()
+        let duration = self.nsfe_metadata.as_ref()?.track_duration(self.nsf_track_index as _).clone()?;
+        Some(self.region_frames(duration))
+    }
+
+    // The duration as stored, which doesn't have to land on a frame boundary
+    pub fn nsfe_duration_seconds(&self) -> Option<f64> {
+        let duration_ms = self.nsfe_metadata.as_ref()?.track_duration_ms(self.nsf_track_index as _)?;
+        Some(duration_ms as f64 / 1000.0)
     }
 
     pub fn nsfe_fadeout(&self) -> Option<usize> {
-        self.nsfe_metadata.as_ref()?.track_fadeout(self.nsf_track_index as _).clone()
+        let fadeout = self.nsfe_metadata.as_ref()?.track_fadeout(self.nsf_track_index as _).clone()?;
+        Some(self.region_frames(fadeout))
     }
 
     pub fn loop_duration(&self) -> Option<(usize, usize)> {
@@ -347,7 +932,16 @@ impl Emulator {
     }
 
     pub fn progress(&self) -> String {
-        let generic_progress = format!("frame={}", self.runtime.nes.last_frame);
+        let mut generic_progress = format!("frame={}", self.runtime.nes.last_frame);
+        if self.nonstandard_play_rate() {
+            // Several play calls (or none) may land in a single video frame. Show the mapping so that's not a surprise.
+            generic_progress.push_str(&format!(
+                " play_rate={:.2}Hz plays/frame={:.2} polls/frame={:.2}",
+                self.play_rate(),
+                self.play_rate() / self.frame_rate(),
+                self.polls_per_frame()
+            ));
+        }
 
         match self.driver_progress() {
             Some(driver_progress) => format!("{} {}", generic_progress, driver_progress),
@@ -355,6 +949,37 @@ impl Emulator {
         }
     }
 
+    // The newest samples each channel has output, scaled to 0..1, as (chip, channel, playing, samples).
+    // These are the same per-channel buffers the piano roll draws its waveforms from.
+    pub fn channel_samples(&self, count: usize) -> Vec<(String, String, bool, Vec<f32>)> {
+        let mut channels = self.runtime.nes.apu.channels();
+        channels.extend(self.runtime.nes.mapper.channels());
+
+        channels.iter()
+            .map(|channel| {
+                let buffer = channel.sample_buffer().buffer();
+                let count = count.min(buffer.len());
+                let start = channel.sample_buffer().index() + buffer.len() - count;
+                let (min, max) = (channel.min_sample() as f32, channel.max_sample() as f32);
+                let samples = (start..start + count)
+                    .map(|i| ((buffer[i % buffer.len()] as f32 - min) / (max - min).max(1.0)).clamp(0.0, 1.0))
+                    .collect();
+
+                (channel.chip(), channel.name(), channel.playing(), samples)
+            })
+            .collect()
+    }
+
+    // (chip, channel, playing, rate, amplitude) for each channel, the same values the piano roll draws from
+    pub fn channel_rates(&self) -> Vec<(String, String, bool, PlaybackRate, f32)> {
+        let mut channels = self.runtime.nes.apu.channels();
+        channels.extend(self.runtime.nes.mapper.channels());
+
+        channels.iter()
+            .map(|channel| (channel.chip(), channel.name(), channel.playing(), channel.rate(), channel.amplitude()))
+            .collect()
+    }
+
     pub fn channel_settings(&self) -> HashMap<(String, String), ChannelSettings> {
         let mut result: HashMap<(String, String), ChannelSettings> = HashMap::new();
 
@@ -370,35 +995,66 @@ impl Emulator {
     pub fn apply_channel_settings(&mut self, settings: &HashMap<(String, String), ChannelSettings>) {
         for ((chip, channel), channel_settings) in settings.iter() {
             self.dispatch(Event::StoreBooleanSetting(format!("piano_roll.settings.{}.{}.hidden", chip, channel), channel_settings.hidden));
+            self.dispatch(Event::StoreBooleanSetting(format!("piano_roll.settings.{}.{}.muted", chip, channel), channel_settings.muted));
 
-            if channel_settings.hidden && chip != "APU" {
+            // Hiding a channel only affects the visualization, so the final mix can't be muted
+            if channel_settings.muted && chip != "APU" {
                 self.dispatch(Event::MuteChannel(chip.clone(), channel.clone()));
             } else {
                 self.dispatch(Event::UnmuteChannel(chip.clone(), channel.clone()));
             }
 
             for (idx, color) in channel_settings.colors.iter().enumerate() {
-                let color_key = match (chip.as_str(), channel.as_str(), idx) {
-                    ("2A03" | "MMC5" | "VRC6", "Pulse 1" | "Pulse 2", i) => format!("duty{}", i),
-                    ("2A03", "Noise", i) => format!("mode{}", i),
-                    ("VRC6", "Sawtooth", i) => format!("mode{}", i),
-                    ("N163", _, 0) => "gradient_low".to_string(),
-                    ("N163", _, 1) => "gradient_high".to_string(),
-                    ("VRC7", _, i) => format!("patch{:X}", i),
-                    (_, _, i) => {
-                        debug_assert!(i == 0, "Settings not mapped properly for {} {}: missing color {}", chip, channel, i);
-                        "static".to_string()
-                    }
-                };
-                let color_value = match color.alpha() {
-                    255 => format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b()),
-                    _ => format!("rgba({}, {}, {}, {})", color.r(), color.g(), color.b(), color.alpha())
-                };
+                let color_key = color_key(chip, channel, idx);
                 self.dispatch(Event::StoreStringSetting(
                     format!("piano_roll.settings.{}.{}.{}", chip, channel, color_key),
-                    color_value
+                    color_string(color)
+                ));
+            }
+
+            // Only write the optional settings when they change so configs without them stay unchanged
+            let current_settings = self.piano_roll_window.channel_settings.get(chip)
+                .and_then(|chip_settings| chip_settings.get(channel))
+                .cloned();
+            let current_outline_color = current_settings.as_ref().and_then(|settings| settings.outline_color);
+            let outline_color = match (channel_settings.outline_color, current_outline_color) {
+                (Some(color), _) => Some(color_string(&color)),
+                (None, Some(_)) => Some("none".to_string()),
+                (None, None) => None
+            };
+            if let Some(outline_color) = outline_color {
+                self.dispatch(Event::StoreStringSetting(format!("piano_roll.settings.{}.{}.outline", chip, channel), outline_color));
+            }
+
+            let (current_amplitude_scale, current_max_thickness, current_scope_hidden, current_scope_order, current_draw_order, current_brightness) = match &current_settings {
+                Some(settings) => (settings.amplitude_scale, settings.max_thickness, settings.scope_hidden, settings.scope_order, settings.draw_order, settings.brightness),
+                None => (1.0, None, None, 0, 0, 1.0)
+            };
+            if channel_settings.amplitude_scale != current_amplitude_scale {
+                self.dispatch(Event::StoreFloatSetting(format!("piano_roll.settings.{}.{}.amplitude_scale", chip, channel), channel_settings.amplitude_scale as f64));
+            }
+            if channel_settings.max_thickness != current_max_thickness {
+                self.dispatch(Event::StoreFloatSetting(
+                    format!("piano_roll.settings.{}.{}.max_thickness", chip, channel),
+                    channel_settings.max_thickness.unwrap_or(0.0) as f64
+                ));
+            }
+            // There's no setting to unset it, but following `hidden` is the same as copying it
+            if channel_settings.scope_hidden != current_scope_hidden {
+                self.dispatch(Event::StoreBooleanSetting(
+                    format!("piano_roll.settings.{}.{}.scope_hidden", chip, channel),
+                    channel_settings.scope_hidden.unwrap_or(channel_settings.hidden)
                 ));
             }
+            if channel_settings.scope_order != current_scope_order {
+                self.dispatch(Event::StoreIntegerSetting(format!("piano_roll.settings.{}.{}.scope_order", chip, channel), channel_settings.scope_order as i64));
+            }
+            if channel_settings.draw_order != current_draw_order {
+                self.dispatch(Event::StoreIntegerSetting(format!("piano_roll.settings.{}.{}.draw_order", chip, channel), channel_settings.draw_order as i64));
+            }
+            if channel_settings.brightness != current_brightness {
+                self.dispatch(Event::StoreFloatSetting(format!("piano_roll.settings.{}.{}.brightness", chip, channel), channel_settings.brightness as f64));
+            }
         }
     }
 }
@@ -0,0 +1,90 @@
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use rusticnes_ui_common::drawing::Color;
+use super::Emulator;
+
+fn channel_key(ft_channel: &str) -> Option<(&'static str, String)> {
+    let ft_channel = ft_channel.trim().to_lowercase();
+    let (name, number) = match ft_channel.rsplit_once(' ') {
+        Some((name, number)) if number.chars().all(|c| c.is_ascii_digit()) => (name, number.parse::<u8>().ok()),
+        _ => (ft_channel.as_str(), None)
+    };
+
+    match (name, number) {
+        ("pulse" | "square", Some(n @ 1..=2)) => Some(("2A03", format!("Pulse {}", n))),
+        ("triangle", None) => Some(("2A03", "Triangle".to_string())),
+        ("noise", None) => Some(("2A03", "Noise".to_string())),
+        ("dpcm" | "dmc", None) => Some(("2A03", "DMC".to_string())),
+        ("vrc6 pulse" | "vrc6 square", Some(n @ 1..=2)) => Some(("VRC6", format!("Pulse {}", n))),
+        ("sawtooth" | "vrc6 sawtooth", None) => Some(("VRC6", "Sawtooth".to_string())),
+        ("mmc5 pulse" | "mmc5 square", Some(n @ 1..=2)) => Some(("MMC5", format!("Pulse {}", n))),
+        ("mmc5 pcm", None) => Some(("MMC5", "PCM".to_string())),
+        ("namco" | "n163", Some(n @ 1..=8)) => Some(("N163", format!("NAMCO {}", n))),
+        ("fds", None) => Some(("FDS", "Wavetable".to_string())),
+        ("fm channel" | "fm" | "vrc7 fm", Some(n @ 1..=6)) => Some(("VRC7", format!("FM {}", n))),
+        ("5b square" | "s5b square", Some(n @ 1..=3)) => Some(("YM2149F", ["A", "B", "C"][n as usize - 1].to_string())),
+        _ => None
+    }
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim().trim_matches('"');
+
+    // FamiTracker stores colors as Windows COLORREF values (0x00BBGGRR)
+    let colorref = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u32>().ok()
+    };
+    match colorref {
+        Some(c) => Some(Color::rgb(c as u8, (c >> 8) as u8, (c >> 16) as u8)),
+        None => Color::from_string(value).ok()
+    }
+}
+
+fn parse_theme(theme: &str) -> HashMap<(String, String), Color> {
+    let mut result: HashMap<(String, String), Color> = HashMap::new();
+
+    let lines = theme.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';') && !line.starts_with('#') && !line.starts_with('['));
+
+    for line in lines {
+        let (key, value) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => continue
+        };
+        let (chip, channel) = match channel_key(key) {
+            Some(k) => k,
+            None => continue
+        };
+        match parse_color(value) {
+            Some(color) => {
+                result.insert((chip.to_string(), channel), color);
+            },
+            None => status!("Warning: Invalid color {} for channel {} in theme, ignoring.", value.trim(), key.trim())
+        }
+    }
+
+    result
+}
+
+pub fn theme_to_config(theme: &str) -> Result<String> {
+    let theme_colors = parse_theme(theme);
+    if theme_colors.is_empty() {
+        bail!("Theme file does not contain any recognized channel colors");
+    }
+
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+
+    // FamiTracker only has one color per channel, so use it for every color slot
+    let mut channel_settings = emulator.channel_settings();
+    for (key, color) in theme_colors.iter() {
+        if let Some(settings) = channel_settings.get_mut(key) {
+            settings.colors.fill(*color);
+        }
+    }
+    emulator.apply_channel_settings(&channel_settings);
+
+    Ok(emulator.dump_minimal_config())
+}
@@ -7,8 +7,8 @@ use std::time::Duration;
 use glob::{glob_with, MatchOptions};
 use encoding_rs::{CoderResult, WINDOWS_1252, SHIFT_JIS};
 
-fn read_m3u_file<P: AsRef<Path>>(m3u_path: P) -> Result<String> {
-    let data = fs::read(m3u_path)?;
+pub(super) fn read_playlist_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let data = fs::read(path)?;
     let mut result = String::with_capacity(data.len() * 4);
 
     let mut cp1252_decoder = WINDOWS_1252.new_decoder();
@@ -24,7 +24,7 @@ fn read_m3u_file<P: AsRef<Path>>(m3u_path: P) -> Result<String> {
         return Ok(result);
     }
 
-    String::from_utf8(data).context("M3U string is not valid CP-1252, Shift-JIS, or UTF-8")
+    String::from_utf8(data).context("Playlist is not valid CP-1252, Shift-JIS, or UTF-8")
 }
 
 pub fn search<P: AsRef<Path>>(nsf_path: P) -> Result<HashMap<u8, (String, Option<Duration>)>> {
@@ -51,9 +51,9 @@ pub fn search<P: AsRef<Path>>(nsf_path: P) -> Result<HashMap<u8, (String, Option
     };
     for glob_entry in glob_with(&nsf_dir, options)? {
         let m3u_path = glob_entry?;
-        println!("Discovered M3U file: {}", m3u_path.file_name().unwrap().to_str().unwrap());
+        status!("Discovered M3U file: {}", m3u_path.file_name().unwrap().to_str().unwrap());
 
-        for line in read_m3u_file(m3u_path)?.lines() {
+        for line in read_playlist_file(m3u_path)?.lines() {
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
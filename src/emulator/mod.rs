@@ -2,14 +2,41 @@ mod nsf;
 mod nsfeparser;
 mod emulator;
 pub mod m3u_searcher;
+pub mod tracklist;
 mod config;
+mod famitracker_theme;
+mod power_on;
+pub mod diagnostics;
+pub mod chips;
 
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 pub use emulator::Emulator;
 pub use nsf::{Nsf, NsfDriverType};
+pub use config::read_config_file;
+pub use power_on::RamInit;
+pub use rusticnes_core::region::Region;
 pub const NES_NTSC_FRAMERATE: f64 = 1789772.7272727 / 29780.5;
-// pub const NES_PAL_FRAMERATE: f64 = 1662607.0 / 33247.5;
+pub const NES_PAL_FRAMERATE: f64 = 1662607.0 / 33247.5;
+pub const NES_DENDY_FRAMERATE: f64 = 1773448.0 / 35464.0;
+
+pub fn frame_rate(region: Region) -> f64 {
+    match region {
+        Region::Ntsc => NES_NTSC_FRAMERATE,
+        Region::Pal => NES_PAL_FRAMERATE,
+        Region::Dendy => NES_DENDY_FRAMERATE
+    }
+}
+
+// One video frame per emulated frame, as a fraction of a second
+pub fn frame_time_base(region: Region) -> (i32, i32) {
+    match region {
+        Region::Ntsc => (29_781, 1_789_773),
+        Region::Pal => (66_495, 3_325_214),
+        Region::Dendy => (35_464, 1_773_448)
+    }
+}
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
 pub struct SongPosition {
@@ -41,3 +68,17 @@ impl Display for SongPosition {
         write!(f, "{:02X}:{:02X}", self.frame, self.row)
     }
 }
+
+// Parses the same hexadecimal frame:row format it is displayed in, e.g. "0A:1F"
+impl FromStr for SongPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (frame, row) = s.split_once(':')
+            .ok_or("Song position format invalid, try e.g. '0A:1F' (hexadecimal frame:row).".to_string())?;
+        let frame = u8::from_str_radix(frame, 16).map_err(|e| e.to_string())?;
+        let row = u8::from_str_radix(row, 16).map_err(|e| e.to_string())?;
+
+        Ok(SongPosition::new(frame, row))
+    }
+}
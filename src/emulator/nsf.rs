@@ -1,6 +1,7 @@
-use anyhow::Result;
+use anyhow::{Result, Context, ensure};
 use std::str;
 use crate::emulator::nsfeparser::{nsfe_to_nsf2, NsfeMetadata};
+use crate::emulator::Region;
 use encoding_rs::{CoderResult, SHIFT_JIS};
 
 pub fn find_subsequence<T>(haystack: &[T], needle: &[T]) -> Option<usize>
@@ -20,7 +21,9 @@ pub enum NsfDriverType {
 #[derive(Clone)]
 pub struct Nsf {
     raw_bytes: Vec<u8>,
-    memoized_driver_type: NsfDriverType
+    memoized_driver_type: NsfDriverType,
+    // Chunks dropped while converting an NSFe, which don't make it into raw_bytes
+    nsfe_warnings: Vec<String>
 }
 
 fn determine_driver_type(raw_bytes: &[u8]) -> NsfDriverType {
@@ -78,21 +81,29 @@ macro_rules! nsf2_feature_fn {
 }
 
 impl Nsf {
-    pub fn from(data: &[u8]) -> Nsf {
-        let raw_bytes = match &data[0..4] {
-            b"NSFE" => nsfe_to_nsf2(data).unwrap(),
-            _ => data.to_vec()
+    pub fn from(data: &[u8]) -> Result<Nsf> {
+        let (raw_bytes, nsfe_warnings) = match data.get(0..4) {
+            Some(b"NSFE") => nsfe_to_nsf2(data).context("Failed to read NSFe")?,
+            _ => (data.to_vec(), Vec::new())
         };
+        // Everything else reads the header without checking its length
+        ensure!(!raw_bytes.starts_with(b"NESM\x1A") || raw_bytes.len() >= 0x80, "NSF header is truncated");
         let memoized_driver_type = determine_driver_type(&raw_bytes);
 
-        Nsf {
+        Ok(Nsf {
             raw_bytes,
             memoized_driver_type,
-        }
+            nsfe_warnings
+        })
+    }
+
+    // The NSF2 data, converted from NSFe if needed
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.raw_bytes
     }
 
     pub fn magic_valid(&self) -> bool {
-        &self.raw_bytes[..5] == b"NESM\x1A"
+        self.raw_bytes.starts_with(b"NESM\x1A")
     }
 
     pub fn version(&self) -> u8 {
@@ -107,6 +118,54 @@ impl Nsf {
         self.raw_bytes[7]
     }
 
+    pub fn load_address(&self) -> u16 {
+        u16::from_le_bytes(self.raw_bytes[0x08..0x0A].try_into().unwrap())
+    }
+
+    pub fn bank_switched(&self) -> bool {
+        self.raw_bytes[0x70..0x78].iter().any(|b| *b != 0)
+    }
+
+    pub fn ntsc_play_speed(&self) -> u16 {
+        match u16::from_le_bytes(self.raw_bytes[0x6E..0x70].try_into().unwrap()) {
+            0 => 16_639,
+            speed => speed
+        }
+    }
+
+    pub fn ntsc_play_rate(&self) -> f64 {
+        1_000_000.0 / self.ntsc_play_speed() as f64
+    }
+
+    pub fn pal_play_speed(&self) -> u16 {
+        match u16::from_le_bytes(self.raw_bytes[0x78..0x7A].try_into().unwrap()) {
+            0 => 19_997,
+            speed => speed
+        }
+    }
+
+    pub fn pal_play_rate(&self) -> f64 {
+        1_000_000.0 / self.pal_play_speed() as f64
+    }
+
+    // Dendy clones have no header flag of their own, they run PAL rips at close to PAL speed
+    pub fn supports_region(&self, region: Region) -> bool {
+        if let Some(supported) = self.nsfe_metadata().and_then(|m| m.supports_region(region)) {
+            return supported;
+        }
+        match region {
+            Region::Ntsc => !self.pal() || self.dual_region(),
+            Region::Pal | Region::Dendy => self.pal() || self.dual_region()
+        }
+    }
+
+    // The region to play in when none is chosen
+    pub fn preferred_region(&self) -> Region {
+        [Region::Ntsc, Region::Pal, Region::Dendy].into_iter()
+            .find(|r| self.supports_region(*r))
+            .unwrap_or(Region::Ntsc)
+    }
+
     fn parse_string(&self, offset: usize, max_len: usize) -> Result<String> {
         let end = (offset..offset+max_len)
             .position(|i| self.raw_bytes[i] == 0)
@@ -128,6 +187,10 @@ impl Nsf {
     expansion_chip_fn!(mmc5, 0b0000_1000);
     expansion_chip_fn!(n163, 0b0001_0000);
     expansion_chip_fn!(s5b, 0b0010_0000);
+    expansion_chip_fn!(unknown_chips, 0b1100_0000);
+
+    bitflag_fn!(0x7A, pal, 0b0000_0001);
+    bitflag_fn!(0x7A, dual_region, 0b0000_0010);
 
     pub fn driver_type(&self) -> NsfDriverType {
         if self.magic_valid() {
@@ -146,6 +209,39 @@ impl Nsf {
         (u32::from_le_bytes((&self.raw_bytes[0x7C..0x80]).try_into().unwrap()) & 0xFFFFFF00) >> 8
     }
 
+    // Header features that the player can't handle, which usually result in silence or wrong playback
+    pub fn compatibility_warnings(&self) -> Vec<String> {
+        let mut warnings: Vec<String> = Vec::new();
+
+        if !self.magic_valid() {
+            warnings.push("Not a valid NSF file".to_string());
+            return warnings;
+        }
+        warnings.extend(self.nsfe_warnings.iter().cloned());
+        if let Some(nsfe_metadata) = self.nsfe_metadata() {
+            warnings.extend(nsfe_metadata.warnings());
+        }
+        if self.unknown_chips() {
+            warnings.push("Uses an unknown expansion audio chip, which will be silent".to_string());
+        }
+        if !self.bank_switched() && self.load_address() < 0x8000 {
+            warnings.push(format!("Load address ${:04X} is below $8000, which the player can't load", self.load_address()));
+        }
+        if self.version() >= 2 {
+            if self.nsf2_irq() {
+                warnings.push("Uses NSF2 IRQs, which are not emulated".to_string());
+            }
+            if self.nsf2_nonreturning_init() {
+                warnings.push("Uses a non-returning INIT routine, which is not supported".to_string());
+            }
+            if self.nsf2_no_play_subroutine() {
+                warnings.push("Has no PLAY routine, which is not supported".to_string());
+            }
+        }
+
+        warnings
+    }
+
     pub fn nsfe_metadata(&self) -> Option<NsfeMetadata> {
         let metadata_offset = match (self.version(), self.nsf2_has_metadata()) {
             (2, true) => self.nsf2_program_length() as usize + 0x80,
@@ -155,7 +251,7 @@ impl Nsf {
         match NsfeMetadata::from(&self.raw_bytes[metadata_offset..]) {
             Ok(d) => Some(d),
             Err(e) => {
-                println!("NSFe metadata parse error: {}", e);
+                status!("NSFe metadata parse error: {}", e);
                 None
             }
         }
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::collections::vec_deque::VecDeque;
 use std::str;
 use std::mem;
-use crate::emulator::NES_NTSC_FRAMERATE;
+use crate::emulator::{NES_NTSC_FRAMERATE, Region};
 
 #[derive(Clone, Debug)]
 pub enum NsfeChunk {
@@ -20,6 +20,7 @@ pub enum NsfeChunk {
     BankInit(Vec<u8>),
     NSF2Flags(u8),
     Rate(Vec<u16>),
+    Region(u8),
     VRC7 { use_ym2413: bool, patches: Option<[u8; 8 * 15]>, rhythm_patches: Option<[u8; 8 * 3]> }
 }
 
@@ -66,87 +67,137 @@ fn chunk_data_as_string_vec(chunk_data: &[u8]) -> Result<Vec<String>> {
 
 const DEFAULT_FIELD: &str = "<?>";
 
-fn extract_fourcc_chunks(data: &[u8]) -> Result<Vec<([u8; 4], Vec<u8>)>> {
+// Without these there is nothing to play, so problems with them are errors instead of warnings
+const REQUIRED_CHUNKS: [&[u8; 4]; 2] = [b"INFO", b"DATA"];
+
+fn fourcc_name(four_cc: &[u8; 4]) -> String {
+    String::from_utf8_lossy(four_cc).to_string()
+}
+
+// Chunks cut off by the end of the file are dropped with a warning, along with anything after them
+fn extract_fourcc_chunks(data: &[u8]) -> Result<(Vec<([u8; 4], Vec<u8>)>, Vec<String>)> {
     let mut data_deque: VecDeque<u8> = VecDeque::from_iter(data.into_iter().cloned());
     let mut result: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
 
     while !data_deque.is_empty() {
-        ensure!(data_deque.len() >= 8, "Not enough data left for next NSFe chunk!");
+        if data_deque.len() < 8 {
+            warnings.push(format!("NSFe data ends with {} stray bytes, ignoring them", data_deque.len()));
+            break;
+        }
 
         let chunk_len = u32::from_le_bytes(data_deque.drain(0..4).collect::<Vec<_>>().try_into().unwrap()) as usize;
-        let four_cc = data_deque.drain(0..4).collect::<Vec<_>>().try_into().unwrap();
-        let chunk_data: Vec<u8> = data_deque.drain(0..chunk_len).collect();
+        let four_cc: [u8; 4] = data_deque.drain(0..4).collect::<Vec<_>>().try_into().unwrap();
+        // Anything after NEND is ignored, per the spec
+        if &four_cc == b"NEND" {
+            break;
+        }
 
-        ensure!(chunk_data.len() == chunk_len, "NSFe chunk is too short");
+        if data_deque.len() < chunk_len {
+            ensure!(!REQUIRED_CHUNKS.contains(&&four_cc), "NSFe {} chunk is too short", fourcc_name(&four_cc));
+            warnings.push(format!(
+                "NSFe {} chunk is truncated ({} of {} bytes), ignoring it",
+                fourcc_name(&four_cc),
+                data_deque.len(),
+                chunk_len
+            ));
+            break;
+        }
+        let chunk_data: Vec<u8> = data_deque.drain(0..chunk_len).collect();
 
         result.push((four_cc, chunk_data));
     }
 
-    Ok(result)
+    Ok((result, warnings))
 }
 
-fn parse_nsfe_metadata(data: &[u8]) -> Result<Vec<NsfeChunk>> {
+// The first 16 bytes of a chunk, for logging chunks that aren't supported yet
+fn hex_preview(chunk_data: &[u8]) -> String {
+    let preview: Vec<String> = chunk_data.iter()
+        .take(16)
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    match chunk_data.len() > 16 {
+        true => format!("{} ...", preview.join(" ")),
+        false => preview.join(" ")
+    }
+}
+
+fn parse_vrc7_chunk(chunk_data: &[u8]) -> Result<NsfeChunk> {
+    let use_ym2413 = (chunk_data.get(0).cloned().context("VRC7 section missing YM2413 flag")?) != 0;
+    let (patches, rhythm_patches) = match (use_ym2413, chunk_data.len()) {
+        (_, 1) => (None, None),
+        (_, 129) => (Some(chunk_data[9..129].try_into()?), None),
+        (true, 153) => (Some(chunk_data[9..129].try_into()?), Some(chunk_data[129..153].try_into()?)),
+        (false, 153) => bail!("VRC7 section specifies rhythm instruments in non-YM2413 mode"),
+        _ => bail!("VRC7 section has invalid length {}", chunk_data.len())
+    };
+
+    Ok(NsfeChunk::VRC7 { use_ym2413, patches, rhythm_patches })
+}
+
+// Malformed optional chunks are skipped with a warning so the rest of the metadata is still usable
+fn parse_nsfe_chunks(chunks: Vec<([u8; 4], Vec<u8>)>) -> (Vec<NsfeChunk>, Vec<String>) {
     let mut result: Vec<NsfeChunk> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
 
-    for (four_cc, chunk_data) in extract_fourcc_chunks(data)? {
+    for (four_cc, chunk_data) in chunks {
         let chunk = match &four_cc {
             b"plst" => {
                 let playlist: Vec<usize> = chunk_data
-                    .into_iter()
-                    .map(|t| 1 + t as usize)
+                    .iter()
+                    .map(|t| 1 + *t as usize)
                     .collect();
-                NsfeChunk::Playlist(playlist)
+                Ok(NsfeChunk::Playlist(playlist))
             },
             b"psfx" => {
                 let sound_effects: Vec<usize> = chunk_data
-                    .into_iter()
-                    .map(|t| 1 + t as usize)
+                    .iter()
+                    .map(|t| 1 + *t as usize)
                     .collect();
-                NsfeChunk::SoundEffects(sound_effects)
+                Ok(NsfeChunk::SoundEffects(sound_effects))
             },
-            b"time" => NsfeChunk::Time(chunk_data_as_i32_vec(&chunk_data)?),
-            b"fade" => NsfeChunk::Fadeout(chunk_data_as_i32_vec(&chunk_data)?),
-            b"tlbl" => NsfeChunk::TrackLabels(chunk_data_as_string_vec(&chunk_data)?),
-            b"taut" => NsfeChunk::TrackAuthors(chunk_data_as_string_vec(&chunk_data)?),
-            b"auth" => {
-                let strings = chunk_data_as_string_vec(&chunk_data)?;
-
+            b"time" => chunk_data_as_i32_vec(&chunk_data).map(NsfeChunk::Time),
+            b"fade" => chunk_data_as_i32_vec(&chunk_data).map(NsfeChunk::Fadeout),
+            b"tlbl" => chunk_data_as_string_vec(&chunk_data).map(NsfeChunk::TrackLabels),
+            b"taut" => chunk_data_as_string_vec(&chunk_data).map(NsfeChunk::TrackAuthors),
+            b"auth" => chunk_data_as_string_vec(&chunk_data).map(|strings| {
                 let title = strings.get(0).unwrap_or(&DEFAULT_FIELD.to_string()).clone();
                 let artist = strings.get(1).unwrap_or(&DEFAULT_FIELD.to_string()).clone();
                 let copyright = strings.get(2).unwrap_or(&DEFAULT_FIELD.to_string()).clone();
                 let ripper = strings.get(3).unwrap_or(&DEFAULT_FIELD.to_string()).clone();
 
                 NsfeChunk::Author { title, artist, copyright, ripper }
-            },
-            b"text" => NsfeChunk::Text(chunk_data_as_string_vec(&chunk_data)?.get(0).unwrap_or(&DEFAULT_FIELD.to_string()).clone()),
-            b"INFO" => NsfeChunk::Info(chunk_data),
-            b"DATA" => NsfeChunk::Data(chunk_data),
-            b"BANK" => NsfeChunk::BankInit(chunk_data),
-            b"NSF2" => NsfeChunk::NSF2Flags(chunk_data.get(0).cloned().unwrap_or_default()),
-            b"RATE" => NsfeChunk::Rate(chunk_data_as_u16_vec(&chunk_data)?),
-            b"VRC7" => {
-                let use_ym2413 = (chunk_data.get(0).cloned().context("VRC7 section missing YM2413 flag")?) != 0;
-                let (patches, rhythm_patches) = match (use_ym2413, chunk_data.len()) {
-                    (_, 1) => (None, None),
-                    (_, 129) => (Some(chunk_data[9..129].try_into()?), None),
-                    (true, 153) => (Some(chunk_data[9..129].try_into()?), Some(chunk_data[129..153].try_into()?)),
-                    (false, 153) => bail!("VRC7 section specifies rhythm instruments in non-YM2413 mode"),
-                    _ => bail!("VRC7 section has invalid length {}", chunk_data.len())
-                };
-
-                NsfeChunk::VRC7 { use_ym2413, patches, rhythm_patches }
-            }
-            b"NEND" => break,
+            }),
+            b"text" => chunk_data_as_string_vec(&chunk_data)
+                .map(|strings| NsfeChunk::Text(strings.get(0).unwrap_or(&DEFAULT_FIELD.to_string()).clone())),
+            b"INFO" => Ok(NsfeChunk::Info(chunk_data)),
+            b"DATA" => Ok(NsfeChunk::Data(chunk_data)),
+            b"BANK" => Ok(NsfeChunk::BankInit(chunk_data)),
+            b"NSF2" => Ok(NsfeChunk::NSF2Flags(chunk_data.get(0).cloned().unwrap_or_default())),
+            b"RATE" => chunk_data_as_u16_vec(&chunk_data).map(NsfeChunk::Rate),
+            b"VRC7" => parse_vrc7_chunk(&chunk_data),
+            // Only the supported regions are needed, the preferred region in the second byte is ignored
+            b"regn" => chunk_data.get(0).cloned()
+                .map(NsfeChunk::Region)
+                .context("regn section is empty"),
             unk_four_cc => {
-                println!("Warning: unknown fourcc {}", str::from_utf8(unk_four_cc).unwrap());
+                verbose!("NSFe: unknown {} chunk ({} bytes): {}", fourcc_name(unk_four_cc), chunk_data.len(), hex_preview(&chunk_data));
+                // Chunks starting with an uppercase letter are the ones a player must understand
+                if unk_four_cc[0].is_ascii_uppercase() {
+                    warnings.push(format!("Uses the NSFe {} chunk, which is needed to play it correctly but isn't supported", fourcc_name(unk_four_cc)));
+                }
                 continue;
             }
         };
 
-        result.push(chunk);
+        match chunk {
+            Ok(chunk) => result.push(chunk),
+            Err(e) => warnings.push(format!("Skipping malformed NSFe {} chunk: {}", fourcc_name(&four_cc), e))
+        }
     }
 
-    Ok(result)
+    (result, warnings)
 }
 
 #[derive(Clone)]
@@ -154,7 +205,9 @@ pub struct NsfeTrack {
     pub label: Option<String>,
     pub author: Option<String>,
     pub duration: Option<usize>,
-    pub fadeout: Option<usize>
+    pub duration_ms: Option<i32>,
+    pub fadeout: Option<usize>,
+    pub sound_effect: bool
 }
 
 impl NsfeTrack {
@@ -163,7 +216,9 @@ impl NsfeTrack {
             label: None,
             author: None,
             duration: None,
-            fadeout: None
+            duration_ms: None,
+            fadeout: None,
+            sound_effect: false
         }
     }
 }
@@ -177,7 +232,9 @@ pub struct NsfeMetadata {
     copyright: Option<String>,
     ripper: Option<String>,
     text: Option<String>,
-    vrc7_patches: Option<[u8; 8 * 15]>
+    vrc7_patches: Option<[u8; 8 * 15]>,
+    regions: Option<u8>,
+    warnings: Vec<String>
 }
 
 macro_rules! track {
@@ -188,8 +245,12 @@ macro_rules! track {
 
 impl NsfeMetadata {
     pub fn from(data: &[u8]) -> Result<Self> {
+        let (raw_chunks, mut warnings) = extract_fourcc_chunks(data)?;
+        let (chunks, parse_warnings) = parse_nsfe_chunks(raw_chunks);
+        warnings.extend(parse_warnings);
+
         let mut metadata = Self {
-            chunks: parse_nsfe_metadata(data)?,
+            chunks,
             tracks: HashMap::new(),
             playlist: None,
             title: None,
@@ -197,15 +258,23 @@ impl NsfeMetadata {
             copyright: None,
             ripper: None,
             text: None,
-            vrc7_patches: None
+            vrc7_patches: None,
+            regions: None,
+            warnings
         };
 
         for chunk in &metadata.chunks {
             match chunk {
                 NsfeChunk::Playlist(playlist) => metadata.playlist = Some(playlist.to_owned()),
+                NsfeChunk::SoundEffects(sound_effects) => {
+                    for i in sound_effects.iter() {
+                        track!(metadata, *i).sound_effect = true;
+                    }
+                },
                 NsfeChunk::Time(times) => {
                     for (i, t) in times.iter().enumerate() {
                         track!(metadata, i+1).duration = Some((t.clone() as f64 * NES_NTSC_FRAMERATE / 1000.0) as usize);
+                        track!(metadata, i+1).duration_ms = Some(t.clone());
                     }
                 },
                 NsfeChunk::Fadeout(fadeouts) => {
@@ -232,10 +301,11 @@ impl NsfeMetadata {
                 NsfeChunk::Text(text) => {
                     metadata.text = Some(text.to_owned());
                 },
+                NsfeChunk::Region(regions) => metadata.regions = Some(*regions),
                 NsfeChunk::VRC7 { use_ym2413, patches, .. } => {
                     metadata.vrc7_patches = patches.to_owned();
                     if *use_ym2413 {
-                        println!("Warning: YM2413 mode currently not supported");
+                        status!("Warning: YM2413 mode currently not supported");
                     }
                 }
                 _ => ()
@@ -277,26 +347,52 @@ impl NsfeMetadata {
         self.track(index)?.duration
     }
 
+    pub fn track_duration_ms(&self, index: usize) -> Option<i32> {
+        self.track(index)?.duration_ms
+    }
+
     pub fn track_fadeout(&self, index: usize) -> Option<usize> {
         self.track(index)?.fadeout
     }
 
+    pub fn track_is_sound_effect(&self, index: usize) -> bool {
+        self.track(index).map_or(false, |t| t.sound_effect)
+    }
+
     pub fn vrc7_patches(&self) -> Option<[u8; 8 * 15]> {
         self.vrc7_patches.clone()
     }
+
+    // None for modules without a regn chunk, or with an empty one
+    pub fn supports_region(&self, region: Region) -> Option<bool> {
+        let mask = match region {
+            Region::Ntsc => 0x01,
+            Region::Pal => 0x02,
+            Region::Dendy => 0x04
+        };
+        self.regions.filter(|r| *r != 0).map(|r| (r & mask) != 0)
+    }
+
+    // Problems with optional chunks that were skipped while parsing
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
 }
 
-pub fn nsfe_to_nsf2(data: &[u8]) -> Result<Vec<u8>> {
-    ensure!(&data[0..4] == b"NSFE", "Malformed header");
+// Also returns warnings about chunks that had to be dropped. Malformed chunks that can still be read
+// are carried over to the NSF2 and reported when its metadata is parsed.
+pub fn nsfe_to_nsf2(data: &[u8]) -> Result<(Vec<u8>, Vec<String>)> {
+    ensure!(data.len() >= 4 && &data[0..4] == b"NSFE", "Malformed header");
 
     let mut result: Vec<u8> = Vec::new();
-    let chunks = extract_fourcc_chunks(&data[4..])?;
-    let parsed_chunks = parse_nsfe_metadata(&data[4..])?;
+    let (chunks, warnings) = extract_fourcc_chunks(&data[4..])?;
+    let (parsed_chunks, _) = parse_nsfe_chunks(chunks.clone());
 
     let info = parsed_chunks.iter().find_map(|c| match c {
         NsfeChunk::Info(i) => Some(i.clone()),
         _ => None
     }).context("Missing INFO chunk")?;
+    ensure!(info.len() >= 9, "NSFe INFO chunk is too short");
 
     let rom_data = parsed_chunks.iter().find_map(|c| match c {
         NsfeChunk::Data(i) => Some(i.clone()),
@@ -371,6 +467,160 @@ pub fn nsfe_to_nsf2(data: &[u8]) -> Result<Vec<u8>> {
         result.extend_from_slice(&four_cc);
         result.extend(chunk_data);
     }
+    // Other chunks, unknown ones included, keep their order, and NEND always closes the metadata
+    result.extend_from_slice(&0u32.to_le_bytes());
+    result.extend_from_slice(b"NEND");
+
+    Ok((result, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::nsf::Nsf;
+
+    const INFO: [u8; 10] = [
+        0x00, 0x80,  // load address
+        0x00, 0x80,  // init address
+        0x03, 0x80,  // play address
+        0x00,        // NTSC
+        0x00,        // no expansion audio
+        0x02,        // 2 songs
+        0x00         // starting at the first
+    ];
+    const DATA: [u8; 4] = [0x60, 0x00, 0x00, 0x60];
+
+    fn chunk(four_cc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut result = (data.len() as u32).to_le_bytes().to_vec();
+        result.extend_from_slice(four_cc);
+        result.extend_from_slice(data);
+        result
+    }
+
+    fn nsfe(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut result = b"NSFE".to_vec();
+        for c in chunks {
+            result.extend(c);
+        }
+        result.extend(chunk(b"NEND", &[]));
+        result
+    }
+
+    #[test]
+    fn unknown_optional_chunk_is_kept_without_warnings() {
+        let data = nsfe(&[chunk(b"INFO", &INFO), chunk(b"DATA", &DATA), chunk(b"zzzz", &[1, 2, 3])]);
+        let (nsf2, warnings) = nsfe_to_nsf2(&data).unwrap();
+        assert!(warnings.is_empty());
+        // Carried over to the NSF2 metadata after the ROM data
+        assert!(nsf2.windows(7).any(|w| w == b"zzzz\x01\x02\x03"));
+
+        let metadata = NsfeMetadata::from(&data[4..]).unwrap();
+        assert!(metadata.warnings().is_empty());
+    }
+
+    #[test]
+    fn unknown_mandatory_chunk_warns() {
+        let data = nsfe(&[chunk(b"INFO", &INFO), chunk(b"DATA", &DATA), chunk(b"ZZZZ", &[1, 2, 3])]);
+        assert!(nsfe_to_nsf2(&data).is_ok());
+
+        let metadata = NsfeMetadata::from(&data[4..]).unwrap();
+        let warnings = metadata.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("ZZZZ"));
+    }
+
+    #[test]
+    fn truncated_optional_chunk_is_dropped() {
+        let mut data = nsfe(&[chunk(b"INFO", &INFO), chunk(b"DATA", &DATA)]);
+        // Drop the NEND chunk and end on a track label chunk that claims more bytes than are left
+        data.truncate(data.len() - 8);
+        data.extend_from_slice(&[0x10, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"tlbl");
+        data.extend_from_slice(b"Int");
+
+        let (_, warnings) = nsfe_to_nsf2(&data).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("truncated"));
+    }
 
-    Ok(result)
+    #[test]
+    fn truncated_required_chunk_fails() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(b"INFO", &INFO));
+        data.extend_from_slice(&[0x10, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"DATA");
+        data.extend_from_slice(&DATA);
+
+        assert!(nsfe_to_nsf2(&data).is_err());
+    }
+
+    #[test]
+    fn missing_info_fails() {
+        let data = nsfe(&[chunk(b"DATA", &DATA)]);
+        let error = nsfe_to_nsf2(&data).unwrap_err();
+        assert!(error.to_string().contains("INFO"));
+    }
+
+    #[test]
+    fn missing_data_fails() {
+        let data = nsfe(&[chunk(b"INFO", &INFO)]);
+        let error = nsfe_to_nsf2(&data).unwrap_err();
+        assert!(error.to_string().contains("DATA"));
+    }
+
+    // Reference files: an NSFe for PAL and Dendy only, and an NSF2 whose header says dual region
+    // but whose regn chunk limits it to NTSC
+    const PAL_DENDY_NSFE: &[u8] = include_bytes!("../../test-data/pal-dendy.nsfe");
+    const NTSC_ONLY_NSF2: &[u8] = include_bytes!("../../test-data/ntsc-only.nsf");
+
+    #[test]
+    fn reference_nsfe_converts_to_nsf2() {
+        let nsf = Nsf::from(PAL_DENDY_NSFE).unwrap();
+        assert_eq!(nsf.version(), 2);
+        assert_eq!(nsf.songs(), 2);
+        assert_eq!(nsf.title().unwrap(), "Fixture Song");
+        assert!(nsf.compatibility_warnings().is_empty());
+
+        let metadata = nsf.nsfe_metadata().unwrap();
+        assert_eq!(metadata.track_title(1).unwrap(), "Intro");
+        assert_eq!(metadata.track_duration_ms(2), Some(2000));
+        assert!(metadata.warnings().is_empty());
+        // The unknown chunk is carried over, followed by the closing NEND
+        assert!(nsf.raw_bytes().ends_with(b"zzzz\x01\x02\x03\x00\x00\x00\x00NEND"));
+    }
+
+    #[test]
+    fn reference_nsfe_regn_chunk_sets_regions() {
+        let nsf = Nsf::from(PAL_DENDY_NSFE).unwrap();
+        let metadata = nsf.nsfe_metadata().unwrap();
+        assert_eq!(metadata.supports_region(Region::Ntsc), Some(false));
+        assert_eq!(metadata.supports_region(Region::Pal), Some(true));
+        assert_eq!(metadata.supports_region(Region::Dendy), Some(true));
+        assert_eq!(nsf.preferred_region(), Region::Pal);
+    }
+
+    #[test]
+    fn reference_nsf2_regn_chunk_overrides_header() {
+        let nsf = Nsf::from(NTSC_ONLY_NSF2).unwrap();
+        assert!(nsf.dual_region());
+        assert!(nsf.supports_region(Region::Ntsc));
+        assert!(!nsf.supports_region(Region::Pal));
+        assert!(!nsf.supports_region(Region::Dendy));
+
+        let metadata = nsf.nsfe_metadata().unwrap();
+        assert_eq!(metadata.track_title(2).unwrap(), "Loop");
+    }
+
+    #[test]
+    fn missing_or_empty_regn_chunk_leaves_regions_to_the_header() {
+        let data = nsfe(&[chunk(b"INFO", &INFO), chunk(b"DATA", &DATA)]);
+        let metadata = NsfeMetadata::from(&data[4..]).unwrap();
+        assert_eq!(metadata.supports_region(Region::Pal), None);
+
+        let data = nsfe(&[chunk(b"INFO", &INFO), chunk(b"DATA", &DATA), chunk(b"regn", &[])]);
+        let metadata = NsfeMetadata::from(&data[4..]).unwrap();
+        assert_eq!(metadata.supports_region(Region::Pal), None);
+        assert_eq!(metadata.warnings().len(), 1);
+        assert!(metadata.warnings()[0].contains("regn"));
+    }
 }
@@ -0,0 +1,89 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::str::FromStr;
+
+// Power-on state that varies between real consoles. Everything is derived from a seed so that
+// renders are reproducible; seed 0 keeps the all-zero state RusticNES starts with.
+// RAM contents are chosen separately with RamInit, which also uses the seed when random.
+
+pub struct PowerOnRng {
+    state: u64
+}
+
+impl PowerOnRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed
+        }
+    }
+
+    // SplitMix64
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum RamInit {
+    Zero,
+    Fill(u8),
+    Random,
+    Pattern(String)
+}
+
+impl RamInit {
+    pub fn ram_contents(&self, size: usize, rng: &mut PowerOnRng) -> Result<Vec<u8>, String> {
+        match self {
+            RamInit::Zero => Ok(vec![0u8; size]),
+            RamInit::Fill(value) => Ok(vec![*value; size]),
+            RamInit::Random => Ok((0..size).map(|_| rng.next_u64() as u8).collect()),
+            RamInit::Pattern(path) => {
+                let pattern = fs::read(path)
+                    .map_err(|e| format!("Failed to read RAM pattern {}: {}", path, e))?;
+                if pattern.is_empty() {
+                    return Err(format!("RAM pattern {} is empty", path));
+                }
+                // Short patterns are repeated to fill the whole RAM
+                Ok(pattern.iter().cycle().take(size).cloned().collect())
+            }
+        }
+    }
+}
+
+impl Display for RamInit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RamInit::Zero => write!(f, "00"),
+            RamInit::Fill(value) => write!(f, "{:02x}", value),
+            RamInit::Random => write!(f, "random"),
+            RamInit::Pattern(path) => write!(f, "pattern:{}", path)
+        }
+    }
+}
+
+impl FromStr for RamInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("pattern:") {
+            return Ok(RamInit::Pattern(path.to_string()));
+        }
+
+        match s {
+            "random" => Ok(RamInit::Random),
+            _ => match u8::from_str_radix(s, 16) {
+                Ok(0) => Ok(RamInit::Zero),
+                Ok(value) => Ok(RamInit::Fill(value)),
+                Err(_) => Err("RAM init format invalid, try one of '00', 'ff', 'random', or 'pattern:ram.bin'.".to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Result, Context};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use super::m3u_searcher::read_playlist_file;
+
+fn parse_timestamp(s: &str) -> Option<Duration> {
+    if !s.contains(':') {
+        return None;
+    }
+
+    let mut seconds = 0.0_f64;
+    for component in s.split(':') {
+        seconds = (seconds * 60.0) + f64::from_str(component).ok()?;
+    }
+
+    Some(Duration::from_secs_f64(seconds))
+}
+
+fn parse_cue_index(s: &str) -> Option<Duration> {
+    // CUE indices are mm:ss:ff, where ff is in 1/75ths of a second
+    let components: Vec<u64> = s.split(':')
+        .map(u64::from_str)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match components.as_slice() {
+        [m, s, f] => Some(Duration::from_secs(m * 60 + s) + Duration::from_secs_f64(*f as f64 / 75.0)),
+        _ => None
+    }
+}
+
+fn parse_cue_sheet(cue: &str) -> Result<HashMap<u8, (String, Option<Duration>)>> {
+    let mut tracks: Vec<(u8, String, Option<Duration>)> = Vec::new();
+
+    for line in cue.lines().map(str::trim) {
+        let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+        match command.to_uppercase().as_str() {
+            "TRACK" => {
+                let index = u8::from_str(arguments.split_whitespace().next().unwrap_or(""))
+                    .context("CUE track index is missing/invalid")?
+                    .saturating_sub(1);
+                tracks.push((index, format!("Track {}", index + 1), None));
+            },
+            "TITLE" => {
+                if let Some((_, title, _)) = tracks.last_mut() {
+                    *title = arguments.trim().trim_matches('"').to_string();
+                }
+            },
+            "INDEX" => {
+                let mut index_parts = arguments.split_whitespace();
+                if index_parts.next() != Some("01") {
+                    continue;
+                }
+                if let Some((_, _, start)) = tracks.last_mut() {
+                    *start = index_parts.next().and_then(parse_cue_index);
+                }
+            },
+            _ => ()
+        }
+    }
+
+    // Each track lasts until the next one starts. The final track has no known duration.
+    let mut result: HashMap<u8, (String, Option<Duration>)> = HashMap::new();
+    for (i, (index, title, start)) in tracks.iter().enumerate() {
+        let next_start = tracks.get(i + 1).and_then(|(_, _, s)| *s);
+        let duration = match (start, next_start) {
+            (Some(start), Some(next_start)) => next_start.checked_sub(*start),
+            _ => None
+        };
+        result.insert(*index, (title.clone(), duration));
+    }
+
+    Ok(result)
+}
+
+fn parse_plain_tracklist(tracklist: &str) -> Result<HashMap<u8, (String, Option<Duration>)>> {
+    let mut result: HashMap<u8, (String, Option<Duration>)> = HashMap::new();
+
+    let lines = tracklist.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    for (index, line) in lines.enumerate() {
+        let (title, duration) = match line.rsplit_once(char::is_whitespace) {
+            Some((title, timestamp)) => match parse_timestamp(timestamp) {
+                Some(duration) => (title.trim_end().to_string(), Some(duration)),
+                None => (line.to_string(), None)
+            },
+            None => (line.to_string(), None)
+        };
+
+        let index = u8::try_from(index).context("Tracklist has more than 256 tracks")?;
+        result.insert(index, (title, duration));
+    }
+
+    Ok(result)
+}
+
+pub fn parse<P: AsRef<Path>>(tracklist_path: P) -> Result<HashMap<u8, (String, Option<Duration>)>> {
+    let tracklist = read_playlist_file(tracklist_path.as_ref())
+        .with_context(|| format!("Failed to read tracklist: {}", tracklist_path.as_ref().display()))?;
+
+    match tracklist_path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("cue") => parse_cue_sheet(&tracklist),
+        _ => parse_plain_tracklist(&tracklist)
+    }
+}
+
+// A CUE sheet that wasn't asked for shouldn't stop the render, so problems with it are only warnings
+pub fn search<P: AsRef<Path>>(nsf_path: P) -> HashMap<u8, (String, Option<Duration>)> {
+    // Plain text files are too often unrelated readmes, so only CUE sheets are picked up automatically
+    let cue_path = nsf_path.as_ref().with_extension("cue");
+    if !cue_path.exists() {
+        return HashMap::new();
+    }
+
+    status!("Discovered CUE sheet: {}", cue_path.file_name().unwrap().to_str().unwrap());
+    match parse(&cue_path) {
+        Ok(tracklist) => tracklist,
+        Err(e) => {
+            status!("Warning: ignoring CUE sheet {}: {:#}", cue_path.display(), e);
+            HashMap::new()
+        }
+    }
+}
@@ -3,20 +3,33 @@ mod render_thread;
 use anyhow::{Result, Context};
 use slint;
 use slint::{Color, Model};
+use slint::winit_030::{winit, WinitWindowAccessor, WinitWindowEventResult};
 use native_dialog::{FileDialog, MessageDialog, MessageType};
+use copypasta::{ClipboardContext, ClipboardProvider};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time::Duration;
 use indicatif::{FormattedDuration, HumanBytes, HumanDuration};
 use rusticnes_ui_common::piano_roll_window::ChannelSettings;
 use rusticnes_ui_common::drawing;
-use crate::emulator::{Emulator, m3u_searcher, Nsf, NsfDriverType};
+use crate::emulator::{self, Emulator, chips, m3u_searcher, tracklist, diagnostics, read_config_file, Nsf, NsfDriverType, Region};
 use crate::gui::render_thread::{RenderThreadMessage, RenderThreadRequest};
-use crate::renderer::options::{FRAME_RATE, RendererOptions, StopCondition};
+use crate::renderer::options::{FRAME_RATE, RendererOptions, StopCondition, LayoutPreset};
+use crate::renderer::{preflight, batch, presets, metadata_lookup::ExternalMetadata};
+use crate::renderer::monitor::MonitorSpeed;
+use crate::renderer::channel_settings::ChannelSettingsBuilder;
+use crate::renderer::palette::ChannelPalette;
+use crate::cli;
+use crate::project::Project;
+use crate::help;
+use crate::video_builder::video_options::transparent_format;
 
 slint::include_modules!();
 
@@ -49,10 +62,378 @@ fn slint_color_component_arr<I: IntoIterator<Item = drawing::Color>>(a: I) -> sl
     slint::ModelRc::new(slint::VecModel::from(color_vecs))
 }
 
-fn get_module_metadata(path: &str) -> Result<ModuleMetadata> {
+fn slint_color_from_components(components: slint::ModelRc<i32>) -> drawing::Color {
+    let mut component_iter = components.iter();
+    let r = component_iter.next().unwrap_or(0) as u8;
+    let g = component_iter.next().unwrap_or(0) as u8;
+    let b = component_iter.next().unwrap_or(0) as u8;
+
+    drawing::Color::rgb(r, g, b)
+}
+
+fn slint_outline_color(color: Option<drawing::Color>) -> slint::ModelRc<i32> {
+    match color {
+        Some(c) => slint_int_arr([c.r(), c.g(), c.b()]),
+        None => slint_int_arr(Vec::<i32>::new())
+    }
+}
+
+fn outline_color_from_slint(components: slint::ModelRc<i32>) -> Option<drawing::Color> {
+    match components.row_count() {
+        3 => Some(slint_color_from_components(components)),
+        _ => None
+    }
+}
+
+// The GUI shows scroll directions and keyboard positions as e.g. "Top to bottom" instead of top_to_bottom
+fn setting_label(value: &str) -> String {
+    let mut label = value.replace('_', " ");
+    if let Some(first) = label.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    label
+}
+
+fn setting_from_label(label: &str) -> String {
+    label.to_lowercase().replace(' ', "_")
+}
+
+const SCROLL_DIRECTIONS: [&str; 6] = ["top_to_bottom", "bottom_to_top", "left_to_right", "right_to_left", "player_piano", "vertical"];
+
+fn hex_color(color: drawing::Color) -> String {
+    format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b())
+}
+
+// The piano strings checkbox only shows the selected scroll direction, so the window keeps the
+// directions with strings as a comma separated list
+fn piano_strings_for(main_window: &MainWindow) -> Vec<String> {
+    main_window.get_piano_strings_for()
+        .split(',')
+        .filter(|direction| !direction.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn get_piano_roll_settings(main_window: &MainWindow, emulator: &Emulator) -> HashMap<String, String> {
+    let strings_for = piano_strings_for(main_window);
+    let piano_strings = SCROLL_DIRECTIONS.iter().map(|direction| (
+        format!("draw_piano_strings_for.{}", direction),
+        strings_for.iter().any(|d| d == direction).to_string()
+    ));
+    let settings = [
+        ("key_length", main_window.get_key_length().to_string()),
+        ("key_thickness", main_window.get_key_thickness().to_string()),
+        ("starting_octave", main_window.get_starting_octave().to_string()),
+        ("octave_count", main_window.get_octave_count().to_string()),
+        ("speed_multiplier", main_window.get_speed_multiplier().to_string()),
+        ("scroll_direction", setting_from_label(&main_window.get_scroll_direction())),
+        ("keyboard_position", setting_from_label(&main_window.get_keyboard_position())),
+        ("piano_string_color", hex_color(slint_color_from_components(main_window.get_piano_string_color())))
+    ];
+
+    // Settings left as configured aren't passed on so the layout preset still applies
+    let mut piano_roll_settings: HashMap<String, String> = settings.into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .chain(piano_strings)
+        .filter(|(key, value)| emulator.piano_roll_setting(key).as_deref() != Some(value.as_str()))
+        .collect();
+    if main_window.get_solid_background() {
+        let color = slint_color_from_components(main_window.get_background_color());
+        piano_roll_settings.insert("background_color".to_string(), hex_color(color));
+    }
+    piano_roll_settings
+}
+
+fn set_piano_roll_settings(main_window: &MainWindow, emulator: &Emulator) {
+    let setting = |key: &str| emulator.piano_roll_setting(key).unwrap_or_default();
+    main_window.set_key_length(setting("key_length").parse().unwrap_or_default());
+    main_window.set_key_thickness(setting("key_thickness").parse().unwrap_or_default());
+    main_window.set_starting_octave(setting("starting_octave").parse().unwrap_or_default());
+    main_window.set_octave_count(setting("octave_count").parse().unwrap_or_default());
+    main_window.set_speed_multiplier(setting("speed_multiplier").parse().unwrap_or_default());
+    main_window.set_scroll_direction(setting_label(&setting("scroll_direction")).into());
+    main_window.set_keyboard_position(setting_label(&setting("keyboard_position")).into());
+
+    let draw_piano_strings = setting("draw_piano_strings").parse().unwrap_or(false);
+    let strings_for: Vec<&str> = SCROLL_DIRECTIONS.iter()
+        .filter(|direction| setting(&format!("draw_piano_strings_for.{}", direction)).parse().unwrap_or(draw_piano_strings))
+        .cloned()
+        .collect();
+    main_window.set_piano_strings_for(strings_for.join(",").into());
+    main_window.set_piano_strings(strings_for.contains(&setting("scroll_direction").as_str()));
+    if let Ok(color) = drawing::Color::from_string(&setting("piano_string_color")) {
+        main_window.set_piano_string_color(slint_int_arr([color.r(), color.g(), color.b()]));
+    }
+    match drawing::Color::from_string(&setting("background_color")) {
+        Ok(color) if color.alpha() == 255 => {
+            main_window.set_solid_background(true);
+            main_window.set_background_color(slint_int_arr([color.r(), color.g(), color.b()]));
+        },
+        _ => main_window.set_solid_background(false)
+    };
+}
+
+fn show_channel_settings(config: &mut ChannelConfig, settings: &ChannelSettings) {
+    config.hidden = settings.hidden;
+    config.muted = settings.muted;
+    config.colors = slint_color_component_arr(settings.colors.clone());
+    config.outline_color = slint_outline_color(settings.outline_color);
+}
+
+// Fills in the options set in the window, everything except the module, track and output
+fn read_window_options(main_window: &MainWindow, options: &RefCell<RendererOptions>) {
+    main_window.invoke_update_channel_configs(true);
+
+    let mut options = options.borrow_mut();
+    options.region = main_window.get_region().parse::<Region>().unwrap_or_default();
+    options.video_options.transparent = main_window.get_transparent();
+    options.fadeout_length = main_window.get_fadeout_duration() as u64;
+    options.fadein_length = main_window.get_fadein_duration() as u64;
+    options.fadein_video = main_window.get_fadein_video();
+
+    options.set_resolution_smart(main_window.get_output_width() as u32, main_window.get_output_height() as u32);
+    // The preset already filled in the output size when it was selected
+    options.piano_roll_layout = main_window.get_layout_preset()
+        .parse::<LayoutPreset>()
+        .ok()
+        .map(|preset| preset.piano_roll_layout());
+
+    options.famicom = main_window.get_famicom_mode();
+    options.high_quality = main_window.get_hq_filtering();
+    options.multiplexing = main_window.get_multiplexing();
+    options.auto_gain = main_window.get_auto_gain();
+    options.auto_speed = main_window.get_auto_speed();
+    options.interpolation = match main_window.get_smooth_scroll() {
+        true => 2,
+        false => 1
+    };
+
+    if main_window.get_background_path().is_empty() {
+        options.video_options.background_path = None;
+    }
+    if main_window.get_metadata_path().is_empty() {
+        options.external_metadata = None;
+    }
+    options.vgmdb_lookup = main_window.get_vgmdb_lookup();
+}
+
+// The other way around, for loading presets. The region stays as it is since it depends on the module.
+fn show_window_options(main_window: &MainWindow, options: &RendererOptions) -> Result<(), String> {
+    main_window.set_transparent(options.video_options.transparent);
+    main_window.set_fadeout_duration(options.fadeout_length as i32);
+    main_window.set_fadein_duration(options.fadein_length as i32);
+    main_window.set_fadein_video(options.fadein_video);
+    main_window.set_output_width(options.video_options.resolution_out.0 as i32);
+    main_window.set_output_height(options.video_options.resolution_out.1 as i32);
+    let layout_preset = LayoutPreset::ALL.iter()
+        .find(|preset| Some(preset.piano_roll_layout()) == options.piano_roll_layout)
+        .map_or("Default layout".to_string(), |preset| preset.to_string());
+    main_window.set_layout_preset(layout_preset.into());
+    main_window.set_famicom_mode(options.famicom);
+    main_window.set_hq_filtering(options.high_quality);
+    main_window.set_multiplexing(options.multiplexing);
+    main_window.set_auto_gain(options.auto_gain);
+    main_window.set_auto_speed(options.auto_speed);
+    main_window.set_smooth_scroll(options.interpolation > 1);
+    main_window.set_background_path(options.video_options.background_path.clone().unwrap_or_default().into());
+    // Presets don't keep the metadata file, it belongs to the module
+    main_window.set_metadata_path("".into());
+    main_window.set_vgmdb_lookup(options.vgmdb_lookup);
+
+    // Starts from the imported configuration like the options do
+    main_window.invoke_update_channel_configs(false);
+    for ((chip, channel), settings) in options.channel_settings.iter() {
+        if let Some(mut configs) = get_chip_configs(main_window, chip) {
+            if let Some(config) = configs.iter_mut().find(|config| config.name.as_str() == channel.as_str()) {
+                show_channel_settings(config, settings);
+            }
+            set_chip_configs(main_window, chip, configs);
+        }
+    }
+
+    let mut emulator = get_emulator(options.config_import_path.clone())?;
+    for (key, value) in options.piano_roll_settings.iter() {
+        emulator.set_piano_roll_setting(key, value).map_err(|e| e.to_string())?;
+    }
+    set_piano_roll_settings(main_window, &emulator);
+    if let Some((color, thickness)) = options.outline_style {
+        main_window.set_outline_color(slint_int_arr([color.r(), color.g(), color.b()]));
+        main_window.set_outline_thickness(thickness as i32);
+    }
+    if let Some((color, width)) = options.divider_style {
+        main_window.set_divider_color(slint_int_arr([color.r(), color.g(), color.b()]));
+        main_window.set_divider_width(width as i32);
+    }
+    Ok(())
+}
+
+// Fills in the render duration, which loading a module resets
+fn show_stop_condition(main_window: &MainWindow, stop_condition: StopCondition) {
+    let (duration_num, duration_type) = match stop_condition {
+        StopCondition::Frames(frames) if frames % FRAME_RATE as u64 == 0 => ((frames / FRAME_RATE as u64).to_string(), "seconds"),
+        StopCondition::Frames(frames) => (frames.to_string(), "frames"),
+        StopCondition::Loops(loops) => (loops.to_string(), "loops"),
+        StopCondition::NsfeLength => (main_window.get_track_duration_num().to_string(), "NSFe/NSF2 duration")
+    };
+    main_window.set_track_duration_num(duration_num.into());
+    main_window.set_track_duration_type(duration_type.into());
+}
+
+fn refresh_presets(main_window: &MainWindow) {
+    match presets::list_presets() {
+        Ok(names) => main_window.set_presets(slint_string_arr(names)),
+        Err(e) => status!("Warning: failed to list presets: {}", e)
+    }
+}
+
+fn get_chip_configs(main_window: &MainWindow, chip: &str) -> Option<Vec<ChannelConfig>> {
+    let configs_model = match chip {
+        "2A03" => main_window.get_config_2a03(),
+        "MMC5" => main_window.get_config_mmc5(),
+        "N163" => main_window.get_config_n163(),
+        "VRC6" => main_window.get_config_vrc6(),
+        "VRC7" => main_window.get_config_vrc7(),
+        "YM2149F" => main_window.get_config_s5b(),
+        "FDS" => main_window.get_config_fds(),
+        "APU" => main_window.get_config_apu(),
+        _ => return None
+    };
+    Some(configs_model.iter().collect())
+}
+
+fn set_chip_configs(main_window: &MainWindow, chip: &str, configs: Vec<ChannelConfig>) {
+    // Hack to force Slint to recreate the ChannelConfigRow components
+    // since the Switch component sometimes ignores the model update.
+    // It can be removed when Slint adds 2-way bindings to struct elements.
+    for configs in [Vec::new(), configs] {
+        let new_config_model = slint::ModelRc::new(slint::VecModel::from(configs));
+        match chip {
+            "2A03" => main_window.set_config_2a03(new_config_model),
+            "MMC5" => main_window.set_config_mmc5(new_config_model),
+            "N163" => main_window.set_config_n163(new_config_model),
+            "VRC6" => main_window.set_config_vrc6(new_config_model),
+            "VRC7" => main_window.set_config_vrc7(new_config_model),
+            "YM2149F" => main_window.set_config_s5b(new_config_model),
+            "FDS" => main_window.set_config_fds(new_config_model),
+            "APU" => main_window.set_config_apu(new_config_model),
+            _ => ()
+        }
+    }
+}
+
+// The channel lists start out empty in the Slint models and are filled from chips::CHIP_CHANNELS.
+// A new chip also needs a config property and tab in main.slint and an arm in get/set_chip_configs.
+fn init_chip_configs(main_window: &MainWindow) {
+    let channels = chips::supported_channels();
+    let mut config_chips: Vec<&str> = channels.iter().map(|info| info.chip.as_str()).collect();
+    config_chips.dedup();
+
+    for chip in config_chips {
+        let configs = channels.iter()
+            .filter(|info| info.chip == chip)
+            .map(|info| ChannelConfig {
+                name: info.channel.clone().into(),
+                hidden: false,
+                muted: false,
+                selected: false,
+                colors: slint_color_component_arr(info.colors.iter().map(|(_, color)| *color)),
+                outline_color: slint_outline_color(None)
+            })
+            .collect();
+        set_chip_configs(main_window, chip, configs);
+    }
+}
+
+// The GUI refers to the S5B by its mapper name, but RusticNES uses the chip name
+fn chip_config_name(chip: &str) -> &str {
+    match chip {
+        "S5B" => "YM2149F",
+        chip => chip
+    }
+}
+
+const CONFIG_CHIPS: [&str; 7] = ["2A03", "MMC5", "N163", "VRC6", "VRC7", "YM2149F", "FDS"];
+
+fn driver_name(driver: NsfDriverType) -> &'static str {
+    match driver {
+        NsfDriverType::Unknown => "Unknown",
+        NsfDriverType::FTClassic => "FamiTracker",
+        NsfDriverType::FT0CC => "0CC-FamiTracker",
+        NsfDriverType::FTDn => "Dn-FamiTracker"
+    }
+}
+
+fn format_frames(frames: usize, frame_rate: f64) -> String {
+    FormattedDuration(Duration::from_secs_f64(frames as f64 / frame_rate)).to_string()
+}
+
+fn describe_track_analysis(analysis: &diagnostics::TrackAnalysis) -> String {
+    let mut parts = vec![format!("Driver: {}", driver_name(analysis.driver))];
+    match (analysis.driver, analysis.loop_duration) {
+        (NsfDriverType::Unknown, _) => (),
+        (_, Some((intro, length))) => parts.push(format!(
+            "Loop: {} intro + {} loop",
+            format_frames(intro, analysis.frame_rate),
+            format_frames(length, analysis.frame_rate)
+        )),
+        (_, None) => parts.push(format!(
+            "No loop in the first {}",
+            format_frames(analysis.analyzed_frames as usize, analysis.frame_rate)
+        ))
+    }
+    if let Some(duration) = analysis.nsfe_duration {
+        parts.push(format!("NSFe/NSF2 duration: {}", format_frames(duration, analysis.frame_rate)));
+    }
+
+    parts.join(", ")
+}
+
+// Module metadata without Slint models, so it can be probed on another thread
+#[derive(Clone)]
+struct ModuleInfo {
+    title: String,
+    artist: String,
+    copyright: String,
+    driver: String,
+    play_rate: String,
+    extended_metadata: bool,
+    loop_detection: bool,
+    extended_durations: Vec<i32>,
+    chips: Vec<String>,
+    tracks: Vec<String>,
+    starting_track: i32,
+    region: String,
+    warnings: Vec<String>
+}
+
+// Probed modules by path, kept for the session so switching back to a module is instant
+type ModuleCache = Arc<Mutex<HashMap<String, ModuleInfo>>>;
+
+impl From<ModuleInfo> for ModuleMetadata {
+    fn from(info: ModuleInfo) -> Self {
+        let mut result = ModuleMetadata::default();
+        result.title = info.title.into();
+        result.artist = info.artist.into();
+        result.copyright = info.copyright.into();
+        result.driver = info.driver.into();
+        result.play_rate = info.play_rate.into();
+        result.extended_metadata = info.extended_metadata;
+        result.loop_detection = info.loop_detection;
+        result.extended_durations = slint_int_arr(info.extended_durations);
+        result.chips = slint_string_arr(info.chips);
+        result.tracks = slint_string_arr(info.tracks);
+        result.starting_track = info.starting_track;
+        result.region = info.region.into();
+        result.warnings = slint_string_arr(info.warnings);
+        result
+    }
+}
+
+fn probe_module(path: &str) -> Result<ModuleInfo> {
     let m3u_metadata = m3u_searcher::search(&path)?;
+    let tracklist_metadata = tracklist::search(path);
     let cart_data = fs::read(path).context("Failed to read NSF")?;
-    let nsf = Nsf::from(&cart_data);
+    let nsf = Nsf::from(&cart_data)?;
     let nsfe_metadata = nsf.nsfe_metadata();
 
     let (title, artist, copyright, extended_metadata) = match &nsfe_metadata {
@@ -66,12 +447,7 @@ fn get_module_metadata(path: &str) -> Result<ModuleMetadata> {
             (nsf.title().unwrap(), nsf.artist().unwrap(), nsf.copyright().unwrap(), false)
         }
     };
-    let driver = match nsf.driver_type() {
-        NsfDriverType::Unknown => "Unknown".to_string(),
-        NsfDriverType::FTClassic => "FamiTracker".to_string(),
-        NsfDriverType::FT0CC => "0CC-FamiTracker".to_string(),
-        NsfDriverType::FTDn => "Dn-FamiTracker".to_string()
-    };
+    let driver = driver_name(nsf.driver_type());
     let loop_detection = nsf.driver_type() != NsfDriverType::Unknown;
     let extended_durations = match &nsfe_metadata {
         Some(nsfe_metadata) => {
@@ -79,7 +455,19 @@ fn get_module_metadata(path: &str) -> Result<ModuleMetadata> {
                 .map(|i| nsfe_metadata.track_duration(i as usize + 1).unwrap_or(0) as i32)
                 .collect()
         },
-        None => vec![]
+        None => {
+            // Fall back to durations from a CUE sheet/tracklist, if there is one
+            let tracklist_durations: Vec<i32> = (0..nsf.songs())
+                .map(|i| match tracklist_metadata.get(&i) {
+                    Some((_title, Some(duration))) => (duration.as_secs_f64() * FRAME_RATE as f64) as i32,
+                    _ => 0
+                })
+                .collect();
+            match tracklist_durations.iter().any(|d| *d > 0) {
+                true => tracklist_durations,
+                false => vec![]
+            }
+        }
     };
 
     let mut chips: Vec<String> = vec!["2A03".to_string()];
@@ -92,50 +480,79 @@ fn get_module_metadata(path: &str) -> Result<ModuleMetadata> {
 
     let tracks: Vec<String> = (0..nsf.songs())
         .map(|i| {
-            if let Some(m) = &nsfe_metadata {
-                if let Some(title) = m.track_title(i as usize + 1) {
-                    return title;
-                }
-            }
-            if let Some((title, _duration)) = m3u_metadata.get(&i) {
-                return title.clone();
+            let title = nsfe_metadata.as_ref().and_then(|m| m.track_title(i as usize + 1))
+                .or_else(|| tracklist_metadata.get(&i).map(|(title, _duration)| title.clone()))
+                .or_else(|| m3u_metadata.get(&i).map(|(title, _duration)| title.clone()))
+                .unwrap_or(format!("Track {}", i + 1));
+
+            match nsfe_metadata.as_ref().map_or(false, |m| m.track_is_sound_effect(i as usize + 1)) {
+                true => format!("{} (sound effect)", title),
+                false => title
             }
-            format!("Track {}", i + 1)
         })
         .collect();
 
-    let mut result = ModuleMetadata::default();
-    result.title = title.into();
-    result.artist = artist.into();
-    result.copyright = copyright.into();
-    result.driver = driver.into();
-    result.extended_metadata = extended_metadata;
-    result.loop_detection = loop_detection;
-    result.extended_durations = slint_int_arr(extended_durations);
-    result.chips = slint_string_arr(chips);
-    result.tracks = slint_string_arr(tracks);
+    let starting_song = nsf.starting_song().clamp(1, nsf.songs().max(1));
+    let mut warnings = nsf.compatibility_warnings();
+    if nsf.magic_valid() {
+        // This plays several seconds of the track, so it stays here on the probe thread and out of
+        // the UI thread. Only the starting song is checked to keep loading modules quick.
+        match diagnostics::expansion_usage_warnings(path, starting_song) {
+            Ok(usage_warnings) => warnings.extend(usage_warnings),
+            Err(e) => warnings.push(format!("Expansion chip diagnostics failed: {}", e))
+        }
+    }
 
-    Ok(result)
+    Ok(ModuleInfo {
+        title,
+        artist,
+        copyright,
+        driver: driver.to_string(),
+        play_rate: format!("{:.2} Hz", nsf.ntsc_play_rate()),
+        extended_metadata,
+        loop_detection,
+        extended_durations,
+        chips,
+        tracks,
+        starting_track: starting_song as i32 - 1,
+        region: nsf.preferred_region().to_string().to_uppercase(),
+        warnings
+    })
 }
 
 fn get_emulator(import_path: Option<String>) -> Result<Emulator, String> {
     let mut emulator = Emulator::new();
     match import_path {
-        Some(p) => emulator.init(Some(fs::read_to_string(p).map_err(|e| e.to_string())?.as_str())),
+        Some(p) => emulator.init(Some(read_config_file(p).map_err(|e| e.to_string())?.as_str())),
         None => emulator.init(None)
     };
     Ok(emulator)
 }
 
 fn get_channel_settings(import_path: Option<String>) -> Result<HashMap<(String, String), ChannelSettings>, String> {
-    let emulator = get_emulator(import_path)?;
-    Ok(emulator.channel_settings())
+    let builder = match import_path {
+        Some(p) => ChannelSettingsBuilder::from_config(&p).map_err(|e| e.to_string())?,
+        None => ChannelSettingsBuilder::new()
+    };
+    Ok(builder.build())
 }
 
-fn export_channel_settings(import_path: Option<String>, channel_settings: HashMap<(String, String), ChannelSettings>) -> Result<String, String> {
-    let mut emulator = get_emulator(import_path)?;
-    emulator.apply_channel_settings(&channel_settings);
-    Ok(emulator.dump_config())
+fn export_channel_settings(options: &RendererOptions, minimal: bool) -> Result<String, String> {
+    let mut emulator = get_emulator(options.config_import_path.clone())?;
+    emulator.apply_channel_settings(&options.channel_settings);
+    if let Some((color, thickness)) = options.outline_style {
+        emulator.set_outline_style(color, thickness);
+    }
+    if let Some((color, width)) = options.divider_style {
+        emulator.set_divider_style(color, width);
+    }
+    for (key, value) in options.piano_roll_settings.iter() {
+        emulator.set_piano_roll_setting(key, value).map_err(|e| e.to_string())?;
+    }
+    match minimal {
+        true => Ok(emulator.dump_minimal_config()),
+        false => Ok(emulator.dump_config())
+    }
 }
 
 fn display_error_dialog(text: &str) {
@@ -147,9 +564,18 @@ fn display_error_dialog(text: &str) {
         .unwrap();
 }
 
+fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    let mut clipboard = ClipboardContext::new().map_err(|e| e.to_string())?;
+    clipboard.set_contents(text.to_string()).map_err(|e| e.to_string())
+}
+
+const MODULE_EXTENSIONS: [&str; 2] = ["nsf", "nsfe"];
+const VIDEO_BACKGROUND_EXTENSIONS: [&str; 6] = ["mp4", "mkv", "mov", "avi", "webm", "gif"];
+const IMAGE_BACKGROUND_EXTENSIONS: [&str; 8] = ["jpg", "jpeg", "png", "bmp", "tif", "tiff", "webp", "qoi"];
+
 fn browse_for_module_dialog() -> Option<String> {
     let file = FileDialog::new()
-        .add_filter("All supported formats", &["nsf", "nsfe"])
+        .add_filter("All supported formats", &MODULE_EXTENSIONS)
         .add_filter("Nintendo Sound Format module", &["nsf"])
         .add_filter("Extended Nintendo Sound Format module", &["nsfe"])
         .show_open_single_file();
@@ -162,9 +588,20 @@ fn browse_for_module_dialog() -> Option<String> {
 
 fn browse_for_background_dialog() -> Option<String> {
     let file = FileDialog::new()
-        .add_filter("All supported formats", &["mp4", "mkv", "mov", "avi", "webm", "gif", "jpg", "jpeg", "png", "bmp", "tif", "tiff", "webp", "qoi"])
-        .add_filter("Video background formats", &["mp4", "mkv", "mov", "avi", "webm", "gif"])
-        .add_filter("Image background formats", &["jpg", "jpeg", "png", "bmp", "tif", "tiff", "webp", "qoi"])
+        .add_filter("All supported formats", &[&VIDEO_BACKGROUND_EXTENSIONS[..], &IMAGE_BACKGROUND_EXTENSIONS[..]].concat())
+        .add_filter("Video background formats", &VIDEO_BACKGROUND_EXTENSIONS)
+        .add_filter("Image background formats", &IMAGE_BACKGROUND_EXTENSIONS)
+        .show_open_single_file();
+
+    match file {
+        Ok(Some(path)) => Some(path.to_str().unwrap().to_string()),
+        _ => None
+    }
+}
+
+fn browse_for_metadata_dialog() -> Option<String> {
+    let file = FileDialog::new()
+        .add_filter("Metadata file", &["json"])
         .show_open_single_file();
 
     match file {
@@ -175,10 +612,12 @@ fn browse_for_background_dialog() -> Option<String> {
 
 fn browse_for_video_dialog() -> Option<String> {
     let file = FileDialog::new()
-        .add_filter("All supported formats", &["mp4", "mkv", "mov"])
+        .add_filter("All supported formats", &["mp4", "mkv", "mov", "webm", "flac", "wav", "mp3", "ogg"])
         .add_filter("MPEG-4 Video", &["mp4"])
         .add_filter("Matroska Video", &["mkv"])
         .add_filter("QuickTime Video", &["mov"])
+        .add_filter("WebM Video", &["webm"])
+        .add_filter("Audio only", &["flac", "wav", "mp3", "ogg"])
         .show_save_single_file();
 
     match file {
@@ -187,9 +626,18 @@ fn browse_for_video_dialog() -> Option<String> {
     }
 }
 
+fn browse_for_output_directory_dialog() -> Option<String> {
+    match FileDialog::new().show_open_single_dir() {
+        Ok(Some(path)) => Some(path.to_str().unwrap().to_string()),
+        _ => None
+    }
+}
+
 fn browse_for_config_import_dialog() -> Option<String> {
     let file = FileDialog::new()
+        .add_filter("All supported formats", &["toml", "ini"])
         .add_filter("Configuration File", &["toml"])
+        .add_filter("FamiTracker Theme", &["ini"])
         .show_open_single_file();
 
     match file {
@@ -209,20 +657,167 @@ fn browse_for_config_export_dialog() -> Option<String> {
     }
 }
 
-fn confirm_prores_export_dialog() -> bool {
+fn browse_for_project_open_dialog() -> Option<String> {
+    let file = FileDialog::new()
+        .add_filter("NSFPresenter project", &["nsfp"])
+        .show_open_single_file();
+
+    match file {
+        Ok(Some(path)) => Some(path.to_str().unwrap().to_string()),
+        _ => None
+    }
+}
+
+fn browse_for_project_save_dialog() -> Option<String> {
+    let file = FileDialog::new()
+        .add_filter("NSFPresenter project", &["nsfp"])
+        .show_save_single_file();
+
+    match file {
+        Ok(Some(path)) => Some(path.to_str().unwrap().to_string()),
+        _ => None
+    }
+}
+
+// Shows what the output will be encoded as before the first render to each container type
+fn confirm_export_options_dialog(options: &RendererOptions, container: &str) -> bool {
+    let video_codec = preflight::output_video_codec(options).unwrap_or("none".to_string());
+    let audio_codec = preflight::output_audio_codec(options).unwrap_or("none".to_string());
+    let alpha = match (transparent_format(&options.video_options.output_path).is_some(), options.video_options.transparent) {
+        (true, true) => "yes",
+        (true, false) => "supported, enable Transparent to keep it",
+        (false, _) => "not supported"
+    };
+    let estimated_size = match preflight::estimate_output_size(options) {
+        Some(size) => HumanBytes(size).to_string(),
+        None => "unknown until the track ends".to_string()
+    };
+
     MessageDialog::new()
         .set_title("NSFPresenter")
-        .set_text("You have chosen to export a QuickTime video. Do you want to export in ProRes 4444 format to \
-                   preserve alpha information for video editing? Note that ProRes 4444 is a lossless codec, so \
-                   the exported file may be very large.")
+        .set_text(&format!(
+            "Container: {}\nVideo codec: {}\nAudio codec: {}\nAlpha channel: {}\nEstimated size: {}\n\nRender with these settings? You won't be asked again for .{} files.",
+            container.to_uppercase(), video_codec, audio_codec, alpha, estimated_size, container
+        ))
         .set_type(MessageType::Info)
         .show_confirm()
         .unwrap()
 }
 
-pub fn run() {
+// Shows the metadata that will be written to the output and shown under the piano roll
+fn confirm_metadata_dialog(metadata: &ExternalMetadata, lookup_result: Option<String>) -> bool {
+    let mut lines = metadata.describe();
+    if lines.is_empty() {
+        lines.push("No metadata to apply, the module's own is kept.".to_string());
+    }
+    lines.extend(lookup_result);
+
+    MessageDialog::new()
+        .set_title("NSFPresenter")
+        .set_text(&format!("{}\n\nRender with this metadata?", lines.join("\n")))
+        .set_type(MessageType::Info)
+        .show_confirm()
+        .unwrap()
+}
+
+fn confirm_preflight_warnings_dialog(warnings: &[String]) -> bool {
+    MessageDialog::new()
+        .set_title("NSFPresenter")
+        .set_text(&format!("{}\n\nDo you want to start the render anyway?", warnings.join("\n")))
+        .set_type(MessageType::Warning)
+        .show_confirm()
+        .unwrap()
+}
+
+// Probes the module on another thread so slow drives don't freeze the window. The window picks
+// it up in module-probed, unless another module was loaded in the meantime.
+fn load_module(main_window: &MainWindow, cache: &ModuleCache, path: &str) {
+    main_window.set_loading_module_path(path.into());
+    if cache.lock().unwrap().contains_key(path) {
+        main_window.invoke_module_probed(path.into(), "".into());
+        return;
+    }
+
+    main_window.set_module_loading(true);
+    let main_window_weak = main_window.as_weak();
+    let cache = cache.clone();
+    let path = path.to_string();
+    thread::spawn(move || {
+        let error = match probe_module(&path) {
+            Ok(info) => {
+                cache.lock().unwrap().insert(path.clone(), info);
+                String::new()
+            },
+            Err(e) => e.to_string()
+        };
+        slint::invoke_from_event_loop(move || {
+            main_window_weak.unwrap().invoke_module_probed(path.into(), error.into());
+        }).unwrap();
+    });
+}
+
+fn apply_module(main_window: &MainWindow, options: &RefCell<RendererOptions>, cache: &ModuleCache, path: &str, error: &str) {
+    if main_window.get_loading_module_path() != path {
+        return;
+    }
+    main_window.set_module_loading(false);
+    let info = cache.lock().unwrap().get(path).cloned();
+    match info {
+        Some(info) => {
+            main_window.set_module_path(path.into());
+            main_window.set_module_metadata(info.into());
+
+            // Default to the region the module was made for, PAL rips play too fast on NTSC
+            main_window.set_region(main_window.get_module_metadata().region);
+            main_window.set_track_duration_num("300".into());
+            main_window.set_track_duration_type("seconds".into());
+
+            // Start on the header's starting song, since track 1 is sometimes a sound effect bank
+            let starting_track = main_window.get_module_metadata().starting_track;
+            match main_window.get_module_metadata().tracks.row_data(starting_track as usize) {
+                Some(track) => {
+                    main_window.set_selected_track_index(starting_track);
+                    main_window.set_selected_track_text(track);
+                },
+                None => {
+                    main_window.set_selected_track_index(-1);
+                    main_window.set_selected_track_text("Select a track...".into());
+                }
+            }
+            main_window.invoke_update_formatted_duration();
+            main_window.invoke_analyze_track();
+
+            options.borrow_mut().input_path = path.to_string();
+        },
+        None => display_error_dialog(error)
+    }
+}
+
+pub fn is_module_path(path: &str) -> bool {
+    let path_lower = path.to_lowercase();
+    (path_lower.ends_with(".nsf") || path_lower.ends_with(".nsfe")) && path::Path::new(path).is_file()
+}
+
+pub fn run(initial_options: Option<RendererOptions>) {
     let main_window = MainWindow::new().unwrap();
 
+    // Options passed in take priority over the ones from the last time the window was closed
+    let restore_session = initial_options.is_none();
+    let initial_options = initial_options.or_else(|| match presets::load_last_session() {
+        Ok(session) => session,
+        Err(e) => {
+            status!("Warning: failed to restore the last session: {}", e);
+            None
+        }
+    });
+
+    let help_text = main_window.global::<HelpText>();
+    help_text.set_stop_condition(help::STOP_CONDITION.into());
+    help_text.set_famicom(help::FAMICOM.into());
+    help_text.set_high_quality(help::HIGH_QUALITY.into());
+    help_text.set_multiplexing(help::MULTIPLEXING.into());
+    help_text.set_piano_roll(help::PIANO_ROLL.into());
+
     main_window.global::<ColorUtils>().on_hex_to_color(|hex| {
         let rgb = u32::from_str_radix(hex.to_string().trim_start_matches("#"), 16).unwrap_or(0);
 
@@ -240,8 +835,16 @@ pub fn run() {
     main_window.set_version(env!("CARGO_PKG_VERSION").into());
     main_window.set_rusticnes_version("0.2.0-nsfp".into());
     main_window.set_ffmpeg_version(crate::video_builder::ffmpeg_version().into());
+    main_window.set_filename_template(batch::DEFAULT_FILENAME_TEMPLATE.into());
 
-    let options = Rc::new(RefCell::new(RendererOptions::default()));
+    let options = Rc::new(RefCell::new(initial_options.clone().unwrap_or_default()));
+    let module_cache: ModuleCache = Arc::new(Mutex::new(HashMap::new()));
+    // An output path given on the command line is used for the first render instead of asking
+    let prefilled_output_path: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(
+        initial_options.as_ref()
+            .map(|o| o.video_options.output_path.clone())
+            .filter(|p| !p.is_empty())
+    ));
 
     {
         let main_window_weak = main_window.as_weak();
@@ -255,49 +858,21 @@ pub fn run() {
                 }
             };
             for ((chip, channel), settings) in channel_settings.iter_mut() {
-                let configs_model = match chip.as_str() {
-                    "2A03" => main_window_weak.unwrap().get_config_2a03(),
-                    "MMC5" => main_window_weak.unwrap().get_config_mmc5(),
-                    "N163" => main_window_weak.unwrap().get_config_n163(),
-                    "VRC6" => main_window_weak.unwrap().get_config_vrc6(),
-                    "VRC7" => main_window_weak.unwrap().get_config_vrc7(),
-                    "YM2149F" => main_window_weak.unwrap().get_config_s5b(),
-                    "FDS" => main_window_weak.unwrap().get_config_fds(),
-                    "APU" => main_window_weak.unwrap().get_config_apu(),
-                    _ => continue
+                let mut configs = match get_chip_configs(&main_window_weak.unwrap(), chip) {
+                    Some(configs) => configs,
+                    None => continue
                 };
-                let mut configs: Vec<ChannelConfig> = configs_model
-                    .as_any()
-                    .downcast_ref::<slint::VecModel<ChannelConfig>>()
-                    .unwrap()
-                    .iter()
-                    .collect();
                 let config = configs.iter_mut()
                     .find(|cfg| cfg.name.to_string() == channel.clone())
                     .unwrap();
 
                 if !write_to_config {
-                    config.hidden = settings.hidden;
-                    config.colors = slint_color_component_arr(settings.colors.clone());
-                    // Hack to force Slint to recreate the ChannelConfigRow components
-                    // since the Switch component sometimes ignores the model update.
-                    // It can be removed when Slint adds 2-way bindings to struct elements.
-                    for configs in [Vec::new(), configs] {
-                        let new_config_model = slint::ModelRc::new(slint::VecModel::from(configs));
-                        match chip.as_str() {
-                            "2A03" => main_window_weak.unwrap().set_config_2a03(new_config_model),
-                            "MMC5" => main_window_weak.unwrap().set_config_mmc5(new_config_model),
-                            "N163" => main_window_weak.unwrap().set_config_n163(new_config_model),
-                            "VRC6" => main_window_weak.unwrap().set_config_vrc6(new_config_model),
-                            "VRC7" => main_window_weak.unwrap().set_config_vrc7(new_config_model),
-                            "YM2149F" => main_window_weak.unwrap().set_config_s5b(new_config_model),
-                            "FDS" => main_window_weak.unwrap().set_config_fds(new_config_model),
-                            "APU" => main_window_weak.unwrap().set_config_apu(new_config_model),
-                            _ => continue
-                        }
-                    }
+                    show_channel_settings(config, settings);
+                    set_chip_configs(&main_window_weak.unwrap(), chip, configs);
                 } else {
                     settings.hidden = config.hidden;
+                    settings.muted = config.muted;
+                    settings.outline_color = outline_color_from_slint(config.outline_color.clone());
                     settings.colors = config.colors
                         .as_any()
                         .downcast_ref::<slint::VecModel<slint::ModelRc<i32>>>()
@@ -319,14 +894,161 @@ pub fn run() {
                 }
             }
 
+            let emulator = match get_emulator(options.borrow().config_import_path.clone()) {
+                Ok(e) => e,
+                Err(e) => {
+                    display_error_dialog(&e);
+                    return;
+                }
+            };
+
             if write_to_config {
                 options.borrow_mut().channel_settings = channel_settings;
+
+                let main_window = main_window_weak.unwrap();
+                options.borrow_mut().piano_roll_settings = get_piano_roll_settings(&main_window, &emulator);
+                options.borrow_mut().outline_style = Some((
+                    slint_color_from_components(main_window.get_outline_color()),
+                    main_window.get_outline_thickness().max(0) as u32
+                ));
+                options.borrow_mut().divider_style = Some((
+                    slint_color_from_components(main_window.get_divider_color()),
+                    main_window.get_divider_width().max(0) as u32
+                ));
+            } else {
+                let main_window = main_window_weak.unwrap();
+                set_piano_roll_settings(&main_window, &emulator);
+                let (outline_color, outline_thickness) = emulator.outline_style();
+                main_window.set_outline_color(slint_int_arr([outline_color.r(), outline_color.g(), outline_color.b()]));
+                main_window.set_outline_thickness(outline_thickness as i32);
+                let (divider_color, divider_width) = emulator.divider_style();
+                main_window.set_divider_color(slint_int_arr([divider_color.r(), divider_color.g(), divider_color.b()]));
+                main_window.set_divider_width(divider_width as i32);
             }
             main_window_weak.unwrap().window().request_redraw();
         });
     }
+    init_chip_configs(&main_window);
     main_window.invoke_update_channel_configs(false);
 
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_scroll_direction_selected(move |label| {
+            let main_window = main_window_weak.unwrap();
+            let direction = setting_from_label(&label);
+            main_window.set_piano_strings(piano_strings_for(&main_window).contains(&direction));
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_piano_strings_toggled(move |checked| {
+            let main_window = main_window_weak.unwrap();
+            let direction = setting_from_label(&main_window.get_scroll_direction());
+            let mut strings_for = piano_strings_for(&main_window);
+            strings_for.retain(|d| *d != direction);
+            if checked {
+                strings_for.push(direction);
+            }
+            main_window.set_piano_strings_for(strings_for.join(",").into());
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_show_all_channels(move || {
+            let main_window = main_window_weak.unwrap();
+            for chip in CONFIG_CHIPS {
+                let mut configs = get_chip_configs(&main_window, chip).unwrap();
+                configs.iter_mut().for_each(|config| config.hidden = false);
+                set_chip_configs(&main_window, chip, configs);
+            }
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_hide_expansion_channels(move || {
+            let main_window = main_window_weak.unwrap();
+            for chip in CONFIG_CHIPS {
+                let mut configs = get_chip_configs(&main_window, chip).unwrap();
+                configs.iter_mut().for_each(|config| config.hidden = chip != "2A03");
+                set_chip_configs(&main_window, chip, configs);
+            }
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_reset_chip_config(move |chip| {
+            let main_window = main_window_weak.unwrap();
+            let chip = chip_config_name(chip.as_str());
+            let default_settings = get_channel_settings(None).unwrap();
+
+            let mut configs = match get_chip_configs(&main_window, chip) {
+                Some(configs) => configs,
+                None => return
+            };
+            for config in configs.iter_mut() {
+                if let Some(settings) = default_settings.get(&(chip.to_string(), config.name.to_string())) {
+                    config.hidden = settings.hidden;
+                    config.muted = settings.muted;
+                    config.colors = slint_color_component_arr(settings.colors.clone());
+                    config.outline_color = slint_outline_color(settings.outline_color);
+                }
+            }
+            set_chip_configs(&main_window, chip, configs);
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_apply_color_to_selected(move |chip, r, g, b| {
+            let main_window = main_window_weak.unwrap();
+            let chip = chip_config_name(chip.as_str());
+
+            let mut configs = match get_chip_configs(&main_window, chip) {
+                Some(configs) => configs,
+                None => return
+            };
+            for config in configs.iter_mut().filter(|config| config.selected) {
+                let color = drawing::Color::rgb(r as u8, g as u8, b as u8);
+                config.colors = slint_color_component_arr(vec![color; config.colors.row_count()]);
+                config.selected = false;
+            }
+            set_chip_configs(&main_window, chip, configs);
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_palette_from_background(move || {
+            let main_window = main_window_weak.unwrap();
+            let palette = match ChannelPalette::from_image(main_window.get_background_path().as_str()) {
+                Ok(palette) => palette,
+                Err(e) => {
+                    display_error_dialog(&e.to_string());
+                    return;
+                }
+            };
+
+            // Only the module's chips are colored, so their channels don't share palette colors with unused ones
+            let mut channel_index = 0;
+            for chip in main_window.get_module_metadata().chips.iter() {
+                let chip = chip_config_name(chip.as_str());
+                let mut configs = match get_chip_configs(&main_window, chip) {
+                    Some(configs) => configs,
+                    None => continue
+                };
+                for config in configs.iter_mut() {
+                    config.colors = slint_color_component_arr(palette.channel_colors(channel_index, config.colors.row_count()));
+                    channel_index += 1;
+                }
+                set_chip_configs(&main_window, chip, configs);
+            }
+        });
+    }
+
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
@@ -356,8 +1078,8 @@ pub fn run() {
                     main_window_weak.unwrap().invoke_update_channel_configs(true);
 
                     let config_str = match export_channel_settings(
-                        options.borrow().config_import_path.clone(),
-                        options.borrow().channel_settings.clone()
+                        &options.borrow(),
+                        main_window_weak.unwrap().get_minimal_config_export()
                     ) {
                         Ok(c) => c,
                         Err(e) => {
@@ -402,12 +1124,23 @@ pub fn run() {
                         main_window_weak.unwrap().set_progress_indeterminate(false);
                         main_window_weak.unwrap().set_progress_error(true);
                         main_window_weak.unwrap().set_progress_title("Idle".into());
+                        main_window_weak.unwrap().set_window_title("NSFPresenter".into());
                         main_window_weak.unwrap().set_progress_status(format!("Render error: {}", e).into());
                     }).unwrap();
                 }
+                RenderThreadMessage::Log(line) => {
+                    let main_window_weak = main_window_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let mut render_log = main_window_weak.unwrap().get_render_log().to_string();
+                        render_log.push_str(&line);
+                        render_log.push('\n');
+                        main_window_weak.unwrap().set_render_log(render_log.into());
+                    }).unwrap();
+                }
                 RenderThreadMessage::RenderStarting => {
                     let main_window_weak = main_window_weak.clone();
                     slint::invoke_from_event_loop(move || {
+                        main_window_weak.unwrap().set_render_log("".into());
                         main_window_weak.unwrap().set_rendering(true);
                         main_window_weak.unwrap().set_progress_indeterminate(true);
                         main_window_weak.unwrap().set_progress_error(false);
@@ -440,19 +1173,26 @@ pub fn run() {
                     let (progress, progress_title) = match (p.frame, p.expected_duration_frames) {
                         (frame, Some(exp_dur_frames)) => {
                             let progress = frame as f64 / exp_dur_frames as f64;
-                            (progress, "Rendering".to_string())
+                            match p.job_count {
+                                1 => (progress, "Rendering".to_string()),
+                                job_count => (progress, format!("Rendering {} of {}", p.job_index + 1, job_count))
+                            }
                         },
                         (0, None) => (0.0, "Initializing".to_string()),
                         (_, None) => (0.0, "Rendering to loop point".to_string())
                     };
-                    let progress_status = format!(
-                        "{}%, {} FPS, encoded {}/{} ({}), {} remaining",
+                    let mut progress_status = format!(
+                        "{}%, {} FPS, encoded {}/{} ({}), {} remaining, queue peak {}v/{}a",
                         (progress * 100.0).round(),
                         p.average_fps,
                         current_video_duration, expected_video_duration,
                         current_video_size,
-                        eta_duration
+                        eta_duration,
+                        p.video_queue_peak, p.audio_queue_peak
                     );
+                    if p.mux_stalls > 0 {
+                        progress_status.push_str(&format!(", muxer blocked {}x ({:.1}s)", p.mux_stalls, p.mux_stall_time.as_secs_f64()));
+                    }
 
                     let main_window_weak = main_window_weak.clone();
                     slint::invoke_from_event_loop(move || {
@@ -460,6 +1200,7 @@ pub fn run() {
                         main_window_weak.unwrap().set_progress(progress as f32);
                         main_window_weak.unwrap().set_progress_title(progress_title.into());
                         main_window_weak.unwrap().set_progress_status(progress_status.into());
+                        main_window_weak.unwrap().set_window_title(format!("NSFPresenter - {}", p.title).into());
                     }).unwrap();
                 }
                 RenderThreadMessage::RenderComplete => {
@@ -469,6 +1210,7 @@ pub fn run() {
                         main_window_weak.unwrap().set_progress_indeterminate(false);
                         main_window_weak.unwrap().set_progress(1.0);
                         main_window_weak.unwrap().set_progress_title("Idle".into());
+                        main_window_weak.unwrap().set_window_title("NSFPresenter".into());
                         main_window_weak.unwrap().set_progress_status("Finished".into());
                     }).unwrap();
                 }
@@ -478,6 +1220,7 @@ pub fn run() {
                         main_window_weak.unwrap().set_rendering(false);
                         main_window_weak.unwrap().set_progress_indeterminate(false);
                         main_window_weak.unwrap().set_progress_title("Idle".into());
+                        main_window_weak.unwrap().set_window_title("NSFPresenter".into());
                         main_window_weak.unwrap().set_progress_status("Render cancelled".into());
                     }).unwrap();
                 }
@@ -487,28 +1230,41 @@ pub fn run() {
 
     {
         let main_window_weak = main_window.as_weak();
-        let options = options.clone();
+        let module_cache = module_cache.clone();
         main_window.on_browse_for_module(move || {
             match browse_for_module_dialog() {
-                Some(path) => {
-                    match get_module_metadata(&path) {
-                        Ok(metadata) => {
-                            main_window_weak.unwrap().set_module_path(path.clone().into());
-                            main_window_weak.unwrap().set_module_metadata(metadata);
-
-                            main_window_weak.unwrap().set_selected_track_index(-1);
-                            main_window_weak.unwrap().set_selected_track_text("Select a track...".into());
+                Some(path) => load_module(&main_window_weak.unwrap(), &module_cache, &path),
+                None => ()
+            }
+        });
+    }
 
-                            main_window_weak.unwrap().set_track_duration_num("300".into());
-                            main_window_weak.unwrap().set_track_duration_type("seconds".into());
-                            main_window_weak.unwrap().invoke_update_formatted_duration();
+    // Module and track to select once the module loads, passed on the command line (e.g. from a
+    // file association or --gui) or from an opened project
+    let initial_track: Rc<RefCell<Option<(String, usize, StopCondition)>>> = Rc::new(RefCell::new(
+        initial_options.as_ref().map(|o| (o.input_path.clone(), o.track_index as usize, o.stop_condition))
+    ));
 
-                            options.borrow_mut().input_path = path.into();
-                        },
-                        Err(e) => display_error_dialog(&e.to_string())
-                    }
-                },
-                None => ()
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        let module_cache = module_cache.clone();
+        let initial_track = initial_track.clone();
+        main_window.on_module_probed(move |path, error| {
+            let main_window = main_window_weak.unwrap();
+            apply_module(&main_window, &options, &module_cache, &path, &error);
+
+            let (track_index, stop_condition) = match initial_track.borrow().as_ref() {
+                Some((initial_path, track_index, stop_condition)) if initial_path == path.as_str() => (*track_index, *stop_condition),
+                _ => return
+            };
+            initial_track.borrow_mut().take();
+            if let Some(track) = main_window.get_module_metadata().tracks.row_data(track_index.wrapping_sub(1)) {
+                main_window.set_selected_track_index(track_index as i32 - 1);
+                main_window.set_selected_track_text(track);
+                show_stop_condition(&main_window, stop_condition);
+                main_window.invoke_update_formatted_duration();
+                main_window.invoke_analyze_track();
             }
         });
     }
@@ -528,6 +1284,99 @@ pub fn run() {
         });
     }
 
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_browse_for_metadata(move || {
+            if let Some(path) = browse_for_metadata_dialog() {
+                match ExternalMetadata::from_json_file(&path) {
+                    Ok(metadata) => {
+                        main_window_weak.unwrap().set_metadata_path(path.into());
+                        options.borrow_mut().external_metadata = Some(metadata);
+                    },
+                    Err(e) => display_error_dialog(&format!("{:#}", e))
+                }
+            }
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        let module_cache = module_cache.clone();
+        // Dropped modules load like Browse does, dropped images and videos become the background
+        main_window.window().on_winit_window_event(move |_, event| {
+            let path = match event {
+                winit::event::WindowEvent::DroppedFile(path) => path,
+                _ => return WinitWindowEventResult::Propagate
+            };
+            let main_window = main_window_weak.unwrap();
+            if main_window.get_rendering() {
+                return WinitWindowEventResult::PreventDefault;
+            }
+
+            let extension = path.extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            let path = path.to_str().unwrap().to_string();
+            if MODULE_EXTENSIONS.contains(&extension.as_str()) {
+                load_module(&main_window, &module_cache, &path);
+            } else if VIDEO_BACKGROUND_EXTENSIONS.contains(&extension.as_str()) || IMAGE_BACKGROUND_EXTENSIONS.contains(&extension.as_str()) {
+                main_window.set_background_path(path.clone().into());
+                options.borrow_mut().video_options.background_path = Some(path);
+            } else {
+                display_error_dialog(&format!("Can't open {}: not a module, image or video", path));
+            }
+            WinitWindowEventResult::PreventDefault
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        // Bumped for every analysis, so results for a track that is no longer selected are dropped
+        let analysis_generation = Arc::new(AtomicUsize::new(0));
+        main_window.on_analyze_track(move || {
+            let main_window = main_window_weak.unwrap();
+            main_window.set_loop_intro_frames(-1);
+            main_window.set_loop_length_frames(-1);
+
+            let generation = analysis_generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let module_path = main_window.get_module_path().to_string();
+            let track_index = main_window.get_selected_track_index();
+            if module_path.is_empty() || track_index < 0 {
+                main_window.set_track_analysis("".into());
+                return;
+            }
+            let region = main_window.get_region().parse::<Region>().unwrap_or_default();
+            main_window.set_track_analysis("Analyzing track...".into());
+            main_window.invoke_update_formatted_duration();
+
+            let main_window_weak = main_window.as_weak();
+            let analysis_generation = analysis_generation.clone();
+            thread::spawn(move || {
+                let analysis = diagnostics::analyze_track(&module_path, track_index as u8 + 1, region);
+                slint::invoke_from_event_loop(move || {
+                    if analysis_generation.load(Ordering::SeqCst) != generation {
+                        return;
+                    }
+                    let main_window = main_window_weak.unwrap();
+                    match analysis {
+                        Ok(analysis) => {
+                            if let Some((intro_frames, loop_frames)) = analysis.loop_duration {
+                                main_window.set_loop_intro_frames(intro_frames as i32);
+                                main_window.set_loop_length_frames(loop_frames as i32);
+                            }
+                            main_window.set_track_analysis(describe_track_analysis(&analysis).into());
+                        },
+                        Err(e) => main_window.set_track_analysis(format!("Track analysis failed: {}", e).into())
+                    }
+                    main_window.invoke_update_formatted_duration();
+                }).unwrap();
+            });
+        });
+    }
+
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
@@ -559,7 +1408,18 @@ pub fn run() {
                         let seconds = frames as f64 / FRAME_RATE as f64;
                         FormattedDuration(Duration::from_secs_f64(seconds)).to_string()
                     },
-                    StopCondition::Loops(_) => "<unknown>".to_string(),
+                    StopCondition::Loops(loops) => {
+                        let intro_frames = main_window_weak.unwrap().get_loop_intro_frames();
+                        let loop_frames = main_window_weak.unwrap().get_loop_length_frames();
+                        match intro_frames >= 0 && loop_frames >= 0 {
+                            true => {
+                                let region = main_window_weak.unwrap().get_region().parse::<Region>().unwrap_or_default();
+                                let frames = intro_frames as usize + loop_frames as usize * loops;
+                                format!("~{}", format_frames(frames, emulator::frame_rate(region)))
+                            },
+                            false => "<unknown>".to_string()
+                        }
+                    },
                     StopCondition::NsfeLength => {
                         match extended_durations.get(selected_track_index as usize).cloned() {
                             Some(frames) => {
@@ -579,8 +1439,10 @@ pub fn run() {
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
+        let prefilled_output_path = prefilled_output_path.clone();
         let rt_tx = rt_tx.clone();
-        main_window.on_start_render(move || {
+        let confirmed_containers: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+        let start_render = Rc::new(move |all_tracks: bool| {
             let module_metadata = main_window_weak.unwrap().get_module_metadata();
 
             let input_path = options.borrow().input_path.clone();
@@ -593,20 +1455,20 @@ pub fn run() {
                 return;
             }
 
-            let output_path = match browse_for_video_dialog() {
+            // Rendering every track asks for the directory to put them in instead
+            let output_path = match all_tracks {
+                true => browse_for_output_directory_dialog(),
+                false => prefilled_output_path.borrow_mut().take().or_else(browse_for_video_dialog)
+            };
+            let output_path = match output_path {
                 Some(path) => path,
                 None => return
             };
 
-            if output_path.ends_with(".mov") && confirm_prores_export_dialog() {
-                // Fairly close approximation of the NES' frame rate with a timebase denominator <100000.
-                // Required to avoid "codec timebase is very high" warning from the QuickTime encoder.
-                options.borrow_mut().video_options.video_time_base = (800, 48_078).into();
-                // -c:v prores_ks -profile:v 4 -bits_per_mb 1000 -pix_fmt yuva444p10le
-                options.borrow_mut().video_options.video_codec = "prores_ks".to_string();
-                options.borrow_mut().video_options.video_codec_params.insert("profile".to_string(), "4".to_string());
-                options.borrow_mut().video_options.video_codec_params.insert("bits_per_mb".to_string(), "1000".to_string());
-                options.borrow_mut().video_options.pixel_format_out = "yuva444p10le".to_string();
+            let transparent = main_window_weak.unwrap().get_transparent();
+            if transparent && !all_tracks && transparent_format(&output_path).is_none() {
+                display_error_dialog("Transparent videos must be saved as .mov (ProRes 4444), .webm or .mkv (VP9).");
+                return;
             }
 
             options.borrow_mut().video_options.output_path = output_path;
@@ -618,7 +1480,8 @@ pub fn run() {
                         return;
                     }
                 },
-                StopCondition::NsfeLength => {
+                // Tracks without durations are checked when the jobs are created
+                StopCondition::NsfeLength if !all_tracks => {
                     if module_metadata.extended_durations.iter().len() == 0 {
                         display_error_dialog("This module does not contain extended duration data. Please select a different duration type.");
                         return;
@@ -628,6 +1491,7 @@ pub fn run() {
             };
 
             let track_index = match main_window_weak.unwrap().get_selected_track_index() {
+                _ if all_tracks => 1,
                 -1 => {
                     display_error_dialog("Please select a track to play.");
                     return;
@@ -636,27 +1500,107 @@ pub fn run() {
             };
             options.borrow_mut().track_index = track_index;
 
-            options.borrow_mut().fadeout_length = main_window_weak.unwrap().get_fadeout_duration() as u64;
-
-            let ow = main_window_weak.unwrap().get_output_width() as u32;
-            let oh = main_window_weak.unwrap().get_output_height() as u32;
+            let ow = main_window_weak.unwrap().get_output_width();
+            let oh = main_window_weak.unwrap().get_output_height();
             if ow < 960 || oh < 540 {
                 display_error_dialog("Output resolution must be at least 960x540.");
                 return;
             }
-            options.borrow_mut().set_resolution_smart(ow, oh);
+            read_window_options(&main_window_weak.unwrap(), &options);
+
+            // The lookup is done here so it can be confirmed, the renderer then uses the result as it is
+            let mut confirmed_metadata = None;
+            if options.borrow().external_metadata.is_some() || options.borrow().vgmdb_lookup {
+                let mut metadata = options.borrow().external_metadata.clone().unwrap_or_default();
+                let lookup_result = match options.borrow().vgmdb_lookup {
+                    true => match ExternalMetadata::lookup_vgmdb(module_metadata.title.as_str()) {
+                        Ok(Some(vgmdb_metadata)) => {
+                            metadata.merge(&vgmdb_metadata);
+                            None
+                        },
+                        Ok(None) => Some(format!("VGMdb has no results for {}.", module_metadata.title)),
+                        Err(e) => Some(format!("VGMdb lookup failed: {}", e))
+                    },
+                    false => None
+                };
+                if !confirm_metadata_dialog(&metadata, lookup_result) {
+                    return;
+                }
+                confirmed_metadata = Some(metadata);
+            }
 
-            options.borrow_mut().famicom = main_window_weak.unwrap().get_famicom_mode();
-            options.borrow_mut().high_quality = main_window_weak.unwrap().get_hq_filtering();
-            options.borrow_mut().multiplexing = main_window_weak.unwrap().get_multiplexing();
+            let mut jobs = match all_tracks {
+                true => {
+                    let output_dir = path::PathBuf::from(&options.borrow().video_options.output_path);
+                    let template = main_window_weak.unwrap().get_filename_template().to_string();
+                    // MP4 can't hold an alpha channel, so transparent tracks are saved as ProRes
+                    let template = match transparent && template.ends_with(".mp4") {
+                        true => template.replace(".mp4", ".mov"),
+                        false => template
+                    };
+                    let include_sound_effects = main_window_weak.unwrap().get_include_sound_effects();
+                    match batch::track_jobs(&options.borrow(), &output_dir, &template, include_sound_effects) {
+                        Ok(jobs) => jobs,
+                        Err(e) => {
+                            display_error_dialog(&e.to_string());
+                            return;
+                        }
+                    }
+                },
+                false => vec![options.borrow().clone()]
+            };
+            if let Some(metadata) = confirmed_metadata {
+                for job in jobs.iter_mut() {
+                    job.external_metadata = Some(metadata.clone());
+                    job.vgmdb_lookup = false;
+                }
+            }
 
-            main_window_weak.unwrap().invoke_update_channel_configs(true);
+            let container = path::Path::new(&jobs[0].video_options.output_path)
+                .extension()
+                .map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+            if !confirmed_containers.borrow().contains(&container) {
+                if !confirm_export_options_dialog(&jobs[0], &container) {
+                    return;
+                }
+                confirmed_containers.borrow_mut().insert(container);
+            }
 
-            if main_window_weak.unwrap().get_background_path().is_empty() {
-                options.borrow_mut().video_options.background_path = None;
+            match preflight::check_output(&jobs[0]) {
+                Ok(warnings) if !warnings.is_empty() => {
+                    if !confirm_preflight_warnings_dialog(&warnings) {
+                        return;
+                    }
+                },
+                Ok(_) => (),
+                Err(e) => {
+                    display_error_dialog(&e.to_string());
+                    return;
+                }
             }
 
-            rt_tx.send(RenderThreadRequest::StartRender(options.borrow().clone())).unwrap();
+            let monitor_speed = match (main_window_weak.unwrap().get_monitor_audio(), main_window_weak.unwrap().get_monitor_realtime()) {
+                (false, _) => None,
+                (true, false) => Some(MonitorSpeed::Render),
+                (true, true) => Some(MonitorSpeed::Realtime)
+            };
+            rt_tx.send(RenderThreadRequest::StartRender(jobs, monitor_speed)).unwrap();
+        });
+
+        {
+            let start_render = start_render.clone();
+            main_window.on_start_render(move || start_render(false));
+        }
+        main_window.on_start_render_all(move || start_render(true));
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        main_window.on_copy_render_log(move || {
+            let render_log = main_window_weak.unwrap().get_render_log().to_string();
+            if let Err(e) = copy_to_clipboard(&render_log) {
+                display_error_dialog(&format!("Failed to copy log to clipboard: {}", e));
+            }
         });
     }
 
@@ -667,8 +1611,155 @@ pub fn run() {
         });
     }
 
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_load_preset(move |name| {
+            let main_window = main_window_weak.unwrap();
+            let preset = match presets::load_preset(&name) {
+                Ok(preset) => preset,
+                Err(e) => {
+                    display_error_dialog(&format!("Failed to load preset: {}", e));
+                    return;
+                }
+            };
+
+            // The module stays loaded
+            let input_path = options.borrow().input_path.clone();
+            *options.borrow_mut() = RendererOptions { input_path, ..preset.clone() };
+            if let Err(e) = show_window_options(&main_window, &preset) {
+                display_error_dialog(&format!("Failed to load preset: {}", e));
+            }
+        });
+    }
+
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_save_preset(move |name| {
+            let main_window = main_window_weak.unwrap();
+            read_window_options(&main_window, &options);
+            match presets::save_preset(&name, &options.borrow()) {
+                Ok(_) => refresh_presets(&main_window),
+                Err(e) => display_error_dialog(&format!("Failed to save preset: {}", e))
+            }
+        });
+    }
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_reset_to_defaults(move || {
+            let main_window = main_window_weak.unwrap();
+            // The module stays loaded
+            let input_path = options.borrow().input_path.clone();
+            let defaults = RendererOptions { channel_settings: get_channel_settings(None).unwrap(), ..RendererOptions::default() };
+            *options.borrow_mut() = RendererOptions { input_path, ..defaults.clone() };
+            if let Err(e) = show_window_options(&main_window, &defaults) {
+                display_error_dialog(&format!("Failed to reset the options: {}", e));
+            }
+            show_stop_condition(&main_window, defaults.stop_condition);
+            main_window.invoke_update_formatted_duration();
+        });
+    }
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        let module_cache = module_cache.clone();
+        let prefilled_output_path = prefilled_output_path.clone();
+        let initial_track = initial_track.clone();
+        main_window.on_open_project(move || {
+            let main_window = main_window_weak.unwrap();
+            let path = match browse_for_project_open_dialog() {
+                Some(path) => path,
+                None => return
+            };
+            let project_options = match Project::open(&path).and_then(|project| project.first_track_options()) {
+                Ok(project_options) => project_options,
+                Err(e) => {
+                    display_error_dialog(&format!("Failed to open project: {}", e));
+                    return;
+                }
+            };
+
+            *options.borrow_mut() = project_options.clone();
+            if let Err(e) = show_window_options(&main_window, &project_options) {
+                display_error_dialog(&format!("Failed to open project: {}", e));
+            }
+            *prefilled_output_path.borrow_mut() = Some(project_options.video_options.output_path.clone());
+            *initial_track.borrow_mut() = Some((project_options.input_path.clone(), project_options.track_index as usize, project_options.stop_condition));
+            load_module(&main_window, &module_cache, &project_options.input_path);
+        });
+    }
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_save_project(move || {
+            let main_window = main_window_weak.unwrap();
+            if options.borrow().input_path.is_empty() {
+                display_error_dialog("Please select a module to save in the project.");
+                return;
+            }
+            read_window_options(&main_window, &options);
+            if main_window.get_selected_track_index() >= 0 {
+                options.borrow_mut().track_index = main_window.get_selected_track_index() as u8 + 1;
+            }
+
+            let path = match browse_for_project_save_dialog() {
+                Some(path) => path,
+                None => return
+            };
+            if let Err(e) = Project::from_renderer_options(&options.borrow()).and_then(|project| project.save(&path)) {
+                display_error_dialog(&format!("Failed to save project: {}", e));
+            }
+        });
+    }
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_copy_cli_command(move || {
+            let main_window = main_window_weak.unwrap();
+            read_window_options(&main_window, &options);
+            if main_window.get_selected_track_index() >= 0 {
+                options.borrow_mut().track_index = main_window.get_selected_track_index() as u8 + 1;
+            }
+            let command = match cli::equivalent_command(&options.borrow()) {
+                Ok(command) => command,
+                Err(e) => {
+                    display_error_dialog(&e.to_string());
+                    return;
+                }
+            };
+            if let Err(e) = copy_to_clipboard(&command) {
+                display_error_dialog(&format!("Failed to copy command to clipboard: {}", e));
+            }
+        });
+    }
+    refresh_presets(&main_window);
+
+    if let Some(initial_options) = &initial_options {
+        if restore_session {
+            if let Err(e) = show_window_options(&main_window, initial_options) {
+                status!("Warning: failed to restore the last session: {}", e);
+            }
+        }
+        // Shown now too, in case the module can't be loaded anymore
+        show_stop_condition(&main_window, initial_options.stop_condition);
+        main_window.invoke_update_formatted_duration();
+        if !initial_options.input_path.is_empty() {
+            load_module(&main_window, &module_cache, &initial_options.input_path);
+        }
+    }
+
     main_window.run().unwrap();
 
+    read_window_options(&main_window, &options);
+    if main_window.get_selected_track_index() >= 0 {
+        options.borrow_mut().track_index = main_window.get_selected_track_index() as u8 + 1;
+    }
+    if let Err(e) = presets::save_last_session(&options.borrow()) {
+        status!("Warning: failed to save the session: {}", e);
+    }
+
     if rt_tx.send(RenderThreadRequest::Terminate).is_ok() {
         // If the send failed, the channel is closed, so the thread is probably already dead.
         rt_handle.join().unwrap();
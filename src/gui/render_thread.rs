@@ -3,12 +3,15 @@ use std::thread;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 use crate::emulator::SongPosition;
-use crate::renderer::Renderer;
+use crate::renderer::{Renderer, gain};
 use crate::renderer::options::RendererOptions;
+use crate::renderer::summary::RenderSummary;
+use crate::renderer::monitor::{AudioMonitor, MonitorSpeed};
 
 #[derive(Clone)]
 pub enum RenderThreadRequest {
-    StartRender(RendererOptions),
+    // Rendered one after another, e.g. every track of a module, optionally playing the audio
+    StartRender(Vec<RendererOptions>, Option<MonitorSpeed>),
     CancelRender,
     Terminate
 }
@@ -24,22 +27,50 @@ pub struct RenderProgressInfo {
     pub elapsed_duration: Duration,
     pub encoded_duration: Duration,
     pub song_position: Option<SongPosition>,
-    pub loop_count: Option<usize>
+    pub loop_count: Option<usize>,
+    pub video_queue_peak: usize,
+    pub audio_queue_peak: usize,
+    pub mux_stalls: usize,
+    pub mux_stall_time: Duration,
+    pub title: String,
+    pub job_index: usize,
+    pub job_count: usize
 }
 
 pub enum RenderThreadMessage {
     Error(Error),
+    Log(String),
     RenderStarting,
     RenderProgress(RenderProgressInfo),
     RenderComplete,
     RenderCancelled
 }
 
+macro_rules! rt_log {
+    ($cb: tt, $($arg: tt)*) => {{
+        let line = format!($($arg)*);
+        status!("{}", line);
+        $cb(RenderThreadMessage::Log(line));
+    }};
+}
+
+// Moves FFmpeg's messages into the render log, so encoder problems show up next to our own
+macro_rules! rt_ffmpeg_log {
+    ($cb: tt) => {
+        for line in crate::video_builder::ffmpeg_take_log() {
+            rt_log!($cb, "FFmpeg: {}", line);
+        }
+    };
+}
+
 macro_rules! rt_unwrap {
     ($v: expr, $cb: tt) => {
         match $v {
             Ok(v) => v,
             Err(e) => {
+                rt_ffmpeg_log!($cb);
+                // Debug formatting includes the full context chain, which is what bug reports need
+                rt_log!($cb, "Error: {:?}", e);
                 $cb(RenderThreadMessage::Error(e));
                 return;
             }
@@ -53,11 +84,12 @@ where
 {
     let (tx, rx) = mpsc::channel();
     let handle = thread::spawn(move || {
-        println!("Renderer thread started");
+        status!("Renderer thread started");
+        crate::video_builder::ffmpeg_capture_log();
 
         'main: loop {
-            let options = match rx.recv().unwrap() {
-                RenderThreadRequest::StartRender(o) => o,
+            let (jobs, monitor_speed) = match rx.recv().unwrap() {
+                RenderThreadRequest::StartRender(jobs, monitor_speed) => (jobs, monitor_speed),
                 RenderThreadRequest::CancelRender => {
                     cb(RenderThreadMessage::Error(anyhow!("No active render to cancel.")));
                     continue;
@@ -66,50 +98,135 @@ where
             };
             cb(RenderThreadMessage::RenderStarting);
 
-            let mut renderer = rt_unwrap!(Renderer::new(options), cb);
-            rt_unwrap!(renderer.start_encoding(), cb);
+            rt_log!(cb, "NSFPresenter v{}, FFmpeg v{}", env!("CARGO_PKG_VERSION"), crate::video_builder::ffmpeg_version());
 
-            let mut last_progress_timestamp = Instant::now();
-            // Janky way to force an update
-            last_progress_timestamp.checked_sub(Duration::from_secs(2));
+            let job_count = jobs.len();
+            for (job_index, options) in jobs.into_iter().enumerate() {
+                if job_count > 1 {
+                    rt_log!(cb, "Job {} of {}", job_index + 1, job_count);
+                }
+                rt_log!(cb, "Input: {} (track {})", options.input_path, options.track_index);
+                rt_log!(cb, "Output: {}", options.video_options.output_path);
+                rt_log!(cb, "Stop condition: {}, fadeout: {} frames", options.stop_condition, options.fadeout_length);
+                rt_log!(
+                    cb, "Video: {} {} {}x{}, audio: {} {} {} Hz",
+                    options.video_options.video_codec,
+                    options.video_options.pixel_format_out,
+                    options.video_options.resolution_out.0,
+                    options.video_options.resolution_out.1,
+                    options.video_options.audio_codec,
+                    options.video_options.sample_format_out,
+                    options.video_options.sample_rate
+                );
 
-            'render: loop {
-                match rx.try_recv() {
-                    Ok(RenderThreadRequest::StartRender(_)) => {
-                        cb(RenderThreadMessage::Error(anyhow!("Cannot start a render while one is already being processed.")));
-                    },
-                    Ok(RenderThreadRequest::CancelRender) => {
-                        cb(RenderThreadMessage::RenderCancelled);
-                        break 'render
+                let sample_rate = options.emulation_sample_rate() as u32;
+                let mut renderer = rt_unwrap!(Renderer::new(options), cb);
+                // Kept until the render is done, the renderer only gets its sink
+                let monitor = match monitor_speed.map(|speed| AudioMonitor::start(sample_rate, speed)) {
+                    Some(Ok(monitor)) => Some(monitor),
+                    Some(Err(e)) => {
+                        rt_log!(cb, "Warning: audio monitor unavailable: {:#}", e);
+                        None
                     },
-                    Ok(RenderThreadRequest::Terminate) => break 'main,
-                    _ => ()
+                    None => None
+                };
+                if let Some(monitor) = monitor.as_ref() {
+                    let sink = monitor.sink();
+                    renderer.set_audio_callback(move |samples| sink.push(samples));
+                }
+                for warning in renderer.take_warnings() {
+                    rt_log!(cb, "Warning: {}", warning);
                 }
-                if !(rt_unwrap!(renderer.step(), cb)) {
-                    break;
+                if renderer.gain() != 1.0 {
+                    rt_log!(cb, "Auto gain: {:.2} dB", gain::gain_to_db(renderer.gain()));
                 }
+                rt_unwrap!(renderer.start_encoding(), cb);
+
+                let mut last_progress_timestamp = Instant::now();
+                // Janky way to force an update
+                last_progress_timestamp.checked_sub(Duration::from_secs(2));
+
+                let mut cancelled = false;
+                'render: loop {
+                    match rx.try_recv() {
+                        Ok(RenderThreadRequest::StartRender(..)) => {
+                            cb(RenderThreadMessage::Error(anyhow!("Cannot start a render while one is already being processed.")));
+                        },
+                        Ok(RenderThreadRequest::CancelRender) => {
+                            rt_log!(cb, "Render cancelled at frame {}", renderer.current_frame());
+                            cb(RenderThreadMessage::RenderCancelled);
+                            cancelled = true;
+                            break 'render
+                        },
+                        Ok(RenderThreadRequest::Terminate) => break 'main,
+                        _ => ()
+                    }
+                    if !(rt_unwrap!(renderer.step(), cb)) {
+                        break;
+                    }
+                    for warning in renderer.take_warnings() {
+                        rt_log!(cb, "Warning: {}", warning);
+                    }
+                    rt_ffmpeg_log!(cb);
 
-                if last_progress_timestamp.elapsed().as_secs_f64() >= 0.5 {
-                    last_progress_timestamp = Instant::now();
-
-                    let progress_info = RenderProgressInfo {
-                        frame: renderer.current_frame(),
-                        average_fps: renderer.average_fps(),
-                        encoded_size: renderer.encoded_size(),
-                        expected_duration_frames: renderer.expected_duration_frames(),
-                        expected_duration: renderer.expected_duration(),
-                        eta_duration: renderer.eta_duration(),
-                        elapsed_duration: renderer.elapsed(),
-                        encoded_duration: renderer.encoded_duration(),
-                        song_position: renderer.song_position(),
-                        loop_count: renderer.loop_count(),
-                    };
-
-                    cb(RenderThreadMessage::RenderProgress(progress_info));
+                    if last_progress_timestamp.elapsed().as_secs_f64() >= 0.5 {
+                        last_progress_timestamp = Instant::now();
+
+                        let (video_queue_peak, audio_queue_peak) = renderer.encoder_queue_peaks();
+                        let progress_info = RenderProgressInfo {
+                            frame: renderer.current_frame(),
+                            average_fps: renderer.average_fps(),
+                            encoded_size: renderer.encoded_size(),
+                            expected_duration_frames: renderer.expected_duration_frames(),
+                            expected_duration: renderer.expected_duration(),
+                            eta_duration: renderer.eta_duration(),
+                            elapsed_duration: renderer.elapsed(),
+                            encoded_duration: renderer.encoded_duration(),
+                            song_position: renderer.song_position(),
+                            loop_count: renderer.loop_count(),
+                            video_queue_peak,
+                            audio_queue_peak,
+                            mux_stalls: renderer.mux_stalls(),
+                            mux_stall_time: renderer.mux_stall_time(),
+                            title: renderer.progress_title(),
+                            job_index,
+                            job_count
+                        };
+
+                        cb(RenderThreadMessage::RenderProgress(progress_info));
+                    }
                 }
-            }
 
-            rt_unwrap!(renderer.finish_encoding(), cb);
+                rt_unwrap!(renderer.finish_encoding(), cb);
+                rt_ffmpeg_log!(cb);
+                if renderer.mux_stalls() > 0 {
+                    rt_log!(
+                        cb,
+                        "Warning: muxer blocked {} times for {:.2}s in total (longest {:.2}s), output storage may be too slow",
+                        renderer.mux_stalls(),
+                        renderer.mux_stall_time().as_secs_f64(),
+                        renderer.longest_mux_time().as_secs_f64()
+                    );
+                }
+                for warning in renderer.expansion_usage_warnings() {
+                    rt_log!(cb, "Warning: {}", warning);
+                }
+                if let Some(clipping_db) = renderer.clipping_db() {
+                    rt_log!(cb, "Warning: audio clipped on {} samples (peak {:.2} dB over full scale)", renderer.clipped_samples(), clipping_db);
+                }
+                let summary = RenderSummary::new(&renderer);
+                rt_log!(cb, "Render complete");
+                for line in summary.to_string().lines() {
+                    rt_log!(cb, "{}", line);
+                }
+                if let Err(e) = summary.save() {
+                    rt_log!(cb, "Warning: failed to save render summary: {}", e);
+                }
+                // Cancelling stops the rest of the queue too
+                if cancelled {
+                    continue 'main;
+                }
+            }
             cb(RenderThreadMessage::RenderComplete);
         }
     });
@@ -0,0 +1,25 @@
+// Longer explanations of the options that raise the most questions, shown in the CLI's --help
+// and in the GUI's help buttons so both say the same thing.
+
+pub const STOP_CONDITION: &str = "How long to render: a number of seconds (time:300), frames \
+(frames:180, 60 per second on NTSC) or loops (loops:2), which needs a driver whose loop point \
+can be detected, like FamiTracker's. NSFe and NSF2 files can also store each track's length \
+(time:nsfe). The fadeout is added after the stop point.";
+
+pub const FAMICOM: &str = "Use the Famicom's audio filters instead of the NES'. The Famicom \
+keeps more of the bass, and it is the console expansion audio chips were made for.";
+
+pub const HIGH_QUALITY: &str = "The audio filters run at full quality by default. The \
+low-quality filters speed up renders, but the sound gets noticeably dirtier.";
+
+pub const MULTIPLEXING: &str = "Emulate how chips like the N163 play their channels one at a \
+time in quick succession. This is more accurate, including the whine real hardware has with \
+many channels, but can introduce artifacts. Only the audio is affected: the oscilloscopes keep \
+showing each channel on its own.";
+
+pub const PIANO_ROLL: &str = "These change how the notes are drawn without touching the audio. \
+Top to bottom and bottom to top put the oscilloscopes above the roll, left to right and right \
+to left leave them out, player piano scrolls the notes towards a keyboard at the bottom, and \
+vertical stacks the oscilloscopes for portrait videos. The keyboard can be moved to the other \
+end of the roll, a higher speed scrolls the notes faster, and piano strings draw a line behind \
+each key. These override the imported configuration.";
@@ -1,18 +1,58 @@
+// Human-readable messages, which move to stderr when stdout carries JSON progress records
+macro_rules! status {
+    ($($arg: tt)*) => {
+        if crate::progress::json_to_stdout() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+// Details only worth printing with --verbose, like the contents of chunks we don't understand
+macro_rules! verbose {
+    ($($arg: tt)*) => {
+        if crate::progress::verbose() {
+            status!($($arg)*)
+        }
+    };
+}
+
 mod video_builder;
 mod emulator;
 mod renderer;
 mod cli;
 mod gui;
+mod project;
+mod manifest;
+mod help;
+mod progress;
 
 use std::env;
+use renderer::options::RendererOptions;
 use build_time::build_time_utc;
 
 fn main() {
-    println!("NSFPresenter started! (built {})", build_time_utc!("%Y-%m-%dT%H:%M:%S"));
+    let args: Vec<String> = env::args().collect();
+    // Parsed before anything is printed, since JSON progress moves the messages to stderr
+    let matches = match args.as_slice() {
+        [_] => None,
+        [_, path] if gui::is_module_path(path) || project::is_project_path(path) => None,
+        _ => Some(cli::parse_args())
+    };
+
+    status!("NSFPresenter started! (built {})", build_time_utc!("%Y-%m-%dT%H:%M:%S"));
     video_builder::init().unwrap();
 
-    match env::args().len() {
-        1 => gui::run(),
-        _ => cli::run()
+    match args.as_slice() {
+        [_] => gui::run(None),
+        // A lone module path opens it in the GUI, which is how file associations launch us
+        [_, path] if gui::is_module_path(path) => {
+            let mut options = RendererOptions::default();
+            options.input_path = path.clone();
+            gui::run(Some(options))
+        },
+        [_, path] if project::is_project_path(path) => cli::open_project(path),
+        _ => cli::run(matches.unwrap())
     };
 }
@@ -0,0 +1,117 @@
+use anyhow::{Result, Context, bail};
+use std::fs;
+use std::path::Path;
+use toml::Value;
+use toml::map::Map;
+use crate::project::Project;
+
+// A batch manifest lists renders of any number of modules, each in the same format as a project file.
+// `duration` is a shorthand for the stop_condition option. As TOML:
+//
+//   [[render]]
+//   module = "castlevania.nsf"
+//   output = "castlevania-02.mp4"
+//   tracks = 2
+//   duration = "loops:2"
+//   config = "palette.toml"
+//
+// or as CSV with a header row. module and output are required, and columns other than
+// track, duration and config set the project option of the same name:
+//
+//   module,track,duration,config,output,auto_gain
+//   castlevania.nsf,2,loops:2,palette.toml,castlevania-02.mp4,true
+
+// Moves the duration shorthand into the options, where Project expects it
+fn normalize_entry(mut entry: Map<String, Value>) -> Result<Value> {
+    if let Some(duration) = entry.remove("duration") {
+        let options = entry.entry("options")
+            .or_insert_with(|| Value::Table(Map::new()))
+            .as_table_mut()
+            .context("Manifest options must be a table")?;
+        options.insert("stop_condition".to_string(), duration);
+    }
+
+    Ok(Value::Table(entry))
+}
+
+fn parse_toml_manifest(manifest_str: &str) -> Result<Vec<Value>> {
+    let manifest: Value = toml::from_str(manifest_str)?;
+    let renders = match manifest.get("render") {
+        Some(Value::Array(renders)) => renders,
+        _ => bail!("Manifest has no [[render]] entries")
+    };
+
+    renders.iter()
+        .map(|entry| match entry {
+            Value::Table(entry) => normalize_entry(entry.clone()),
+            _ => bail!("Manifest render entries must be tables")
+        })
+        .collect()
+}
+
+// Fields are split on commas without any quoting, so paths can't contain commas
+fn parse_csv_manifest(manifest_str: &str) -> Result<Vec<Value>> {
+    let mut lines = manifest_str.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty());
+    let header: Vec<&str> = lines.next()
+        .context("Manifest is empty")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    lines.enumerate()
+        .map(|(i, line)| {
+            let mut entry = Map::new();
+            let mut options = Map::new();
+            for (column, field) in header.iter().zip(line.split(',').map(str::trim)) {
+                if field.is_empty() {
+                    continue;
+                }
+                match *column {
+                    "module" | "output" | "config" | "duration" => {
+                        entry.insert(column.to_string(), Value::String(field.to_string()));
+                    },
+                    "track" => {
+                        let track = field.parse::<i64>()
+                            .with_context(|| format!("Invalid track on manifest line {}: {}", i + 2, field))?;
+                        entry.insert("tracks".to_string(), Value::Integer(track));
+                    },
+                    option => {
+                        // CSV has no types, so anything that reads as a boolean or number is one
+                        let value = match field {
+                            "true" => Value::Boolean(true),
+                            "false" => Value::Boolean(false),
+                            _ => field.parse::<i64>().map(Value::Integer)
+                                .or_else(|_| field.parse::<f64>().map(Value::Float))
+                                .unwrap_or_else(|_| Value::String(field.to_string()))
+                        };
+                        options.insert(option.to_string(), value);
+                    }
+                }
+            }
+            entry.insert("options".to_string(), Value::Table(options));
+
+            normalize_entry(entry)
+        })
+        .collect()
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Vec<Project>> {
+    let manifest_str = fs::read_to_string(path.as_ref())
+        .with_context(|| format!("Failed to read manifest: {}", path.as_ref().display()))?;
+
+    let entries = match path.as_ref().extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("toml") => parse_toml_manifest(&manifest_str),
+        Some("csv") => parse_csv_manifest(&manifest_str),
+        _ => bail!("Manifest must be a .toml or .csv file")
+    }.with_context(|| format!("Failed to parse manifest: {}", path.as_ref().display()))?;
+
+    // Like projects, paths are relative to the manifest
+    let base_dir = path.as_ref().parent().unwrap_or(Path::new("."));
+    entries.iter()
+        .enumerate()
+        .map(|(i, entry)| Project::from_value(entry, base_dir)
+            .with_context(|| format!("Invalid manifest entry {}", i + 1)))
+        .collect()
+}
@@ -0,0 +1,135 @@
+use anyhow::{Result, Context};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::renderer::Renderer;
+
+// Progress records are written at most this often, plus one at the end
+const RECORD_INTERVAL: Duration = Duration::from_millis(250);
+
+// Set from the command line when progress is written as JSON, with the file to write it to.
+// Without a file the records go to stdout, and the status! messages move to stderr.
+static JSON_OUTPUT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+pub fn enable_json(output: Option<PathBuf>) {
+    JSON_OUTPUT.get_or_init(|| output);
+}
+
+pub fn json_to_stdout() -> bool {
+    matches!(JSON_OUTPUT.get(), Some(None))
+}
+
+// Set from the command line with --verbose
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_verbose() {
+    VERBOSE.store(true, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+// Stdout, or appended to a file or named pipe
+fn open_writer(path: &Option<PathBuf>) -> Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Failed to open progress output {}", path.display()))?)),
+        None => Ok(Box::new(io::stdout()))
+    }
+}
+
+fn write_record(writer: &mut dyn Write, record_type: &str, output_path: &str, mut record: Value) -> Result<()> {
+    record["type"] = json!(record_type);
+    record["output"] = json!(output_path);
+    // One write per line so records from concurrent renders don't interleave
+    writer.write_all(format!("{}\n", record).as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Ends the render for JSON readers when it fails, since no finished record will follow
+pub fn report_error(output_path: &str, error: &anyhow::Error) {
+    let path = match JSON_OUTPUT.get() {
+        Some(path) => path,
+        None => return
+    };
+    let result = open_writer(path)
+        .and_then(|mut writer| write_record(&mut writer, "error", output_path, json!({ "message": format!("{:#}", error) })));
+    if let Err(e) = result {
+        status!("Warning: failed to write progress record: {}", e);
+    }
+}
+
+// Newline-delimited JSON progress for --progress-format json, for frontends that wrap the CLI.
+// Every record names the output, so renders running side by side in a batch can share a stream.
+pub struct JsonProgress {
+    writer: Box<dyn Write>,
+    output_path: String,
+    last_record: Option<Instant>
+}
+
+impl JsonProgress {
+    // None when progress is shown as a bar
+    pub fn start(output_path: &str) -> Result<Option<Self>> {
+        let path = match JSON_OUTPUT.get() {
+            Some(path) => path,
+            None => return Ok(None)
+        };
+
+        Ok(Some(Self {
+            writer: open_writer(path)?,
+            output_path: output_path.to_string(),
+            last_record: None
+        }))
+    }
+
+    fn write_record(&mut self, record_type: &str, record: Value) -> Result<()> {
+        write_record(&mut self.writer, record_type, &self.output_path, record)
+    }
+
+    pub fn progress(&mut self, renderer: &Renderer) -> Result<()> {
+        if self.last_record.map_or(false, |t| t.elapsed() < RECORD_INTERVAL) {
+            return Ok(());
+        }
+        self.last_record = Some(Instant::now());
+
+        let song_position = renderer.song_position().map(|position| json!({
+            "frame": position.frame,
+            "row": position.row
+        }));
+        self.write_record("progress", json!({
+            "frame": renderer.current_frame(),
+            "total_frames": renderer.expected_duration_frames(),
+            "fps": renderer.instantaneous_fps(),
+            "average_fps": renderer.average_fps(),
+            "encoded_bytes": renderer.encoded_size(),
+            "encoded_seconds": renderer.encoded_duration().as_secs_f64(),
+            "elapsed_seconds": renderer.elapsed().as_secs_f64(),
+            "eta_seconds": renderer.eta_duration().map(|eta| eta.as_secs_f64()),
+            "loop_count": renderer.loop_count(),
+            "song_position": song_position
+        }))
+    }
+
+    pub fn warning(&mut self, message: &str) -> Result<()> {
+        self.write_record("warning", json!({ "message": message }))
+    }
+
+    pub fn finished(&mut self, renderer: &Renderer, interrupted: bool) -> Result<()> {
+        self.write_record("finished", json!({
+            "frame": renderer.current_frame(),
+            "encoded_bytes": renderer.encoded_size(),
+            "encoded_seconds": renderer.encoded_duration().as_secs_f64(),
+            "elapsed_seconds": renderer.elapsed().as_secs_f64(),
+            "interrupted": interrupted
+        }))
+    }
+}
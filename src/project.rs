@@ -0,0 +1,170 @@
+use anyhow::{Result, Context, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+use toml::map::Map;
+use crate::emulator::Emulator;
+use crate::renderer::options::RendererOptions;
+
+// A .nsfp project bundles a module with the tracks to render and the options to render them with.
+// Options are stored the same way as a .toml file from --save-options, so projects, presets and
+// saved options all cover the same settings:
+//
+//   module = "castlevania.nsf"
+//   output = "castlevania.mp4"
+//   tracks = [2, 3]       # or "all", which skips NSFe sound effects unless sound_effects = true
+//   config = "palette.toml"
+//
+//   [options]
+//   stop_condition = "loops:2"
+//   auto_gain = true
+pub struct Project {
+    pub module_path: PathBuf,
+    pub output_path: PathBuf,
+    pub tracks: Vec<u8>,
+    pub config_path: Option<PathBuf>,
+    options: Value
+}
+
+pub fn is_project_path(path: &str) -> bool {
+    path.to_lowercase().ends_with(".nsfp") && Path::new(path).is_file()
+}
+
+fn module_tracks(module_path: &Path, include_sound_effects: bool) -> Result<Vec<u8>> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    emulator.open(module_path.to_str().unwrap())?;
+
+    Ok((1..=emulator.track_count())
+        .filter(|t| include_sound_effects || !emulator.is_sound_effect(*t))
+        .collect())
+}
+
+impl Project {
+    // A project rendering the options' module and track, e.g. for the GUI's "Save project"
+    pub fn from_renderer_options(options: &RendererOptions) -> Result<Self> {
+        let mut project_options = options.to_toml()?;
+        // The project's own fields hold these
+        if let Some(table) = project_options.as_table_mut() {
+            table.remove("input_path");
+            table.remove("track_index");
+            table.remove("config_import_path");
+            if let Some(Value::Table(video_options)) = table.get_mut("video_options") {
+                video_options.remove("output_path");
+            }
+        }
+
+        let output_path = match options.video_options.output_path.is_empty() {
+            true => "output.mp4",
+            false => options.video_options.output_path.as_str()
+        };
+        Ok(Self {
+            module_path: PathBuf::from(&options.input_path),
+            output_path: PathBuf::from(output_path),
+            tracks: vec![options.track_index],
+            config_path: options.config_import_path.as_ref().map(PathBuf::from),
+            options: project_options
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let project_str = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read project: {}", path.as_ref().display()))?;
+        let project: Value = toml::from_str(&project_str)
+            .with_context(|| format!("Failed to parse project: {}", path.as_ref().display()))?;
+
+        // Paths in the project are relative to the project file so it can be moved along with the module
+        Self::from_value(&project, path.as_ref().parent().unwrap_or(Path::new(".")))
+    }
+
+    // Batch manifests hold several projects in the same format
+    pub fn from_value(project: &Value, base_dir: &Path) -> Result<Self> {
+        let project_path = |key: &str| project.get(key)
+            .and_then(Value::as_str)
+            .map(|p| base_dir.join(p));
+
+        let module_path = project_path("module").context("Project does not specify a module")?;
+        let output_path = project_path("output").context("Project does not specify an output")?;
+        let config_path = project_path("config");
+
+        let tracks = match project.get("tracks") {
+            Some(Value::Array(tracks)) => tracks.iter()
+                .map(|t| t.as_integer().and_then(|t| u8::try_from(t).ok()).context("Project tracks must be numbers from 1 to 255"))
+                .collect::<Result<Vec<u8>>>()?,
+            Some(Value::Integer(track)) => vec![u8::try_from(*track).context("Project tracks must be numbers from 1 to 255")?],
+            Some(Value::String(tracks)) if tracks == "all" => {
+                let include_sound_effects = project.get("sound_effects")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                module_tracks(&module_path, include_sound_effects)?
+            },
+            Some(_) => bail!("Project tracks must be a number, a list of numbers or \"all\""),
+            None => vec![1]
+        };
+
+        let options = match project.get("options") {
+            Some(Value::Table(options)) => Value::Table(options.clone()),
+            Some(_) => bail!("Project options must be a table"),
+            None => Value::Table(Map::new())
+        };
+
+        Ok(Self {
+            module_path,
+            output_path,
+            tracks,
+            config_path,
+            options
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        // Paths next to or below the project are saved relative to it, the way open() reads them
+        let base_dir = path.as_ref().parent().unwrap_or(Path::new("."));
+        let project_path = |p: &Path| Value::String(p.strip_prefix(base_dir).unwrap_or(p).to_string_lossy().to_string());
+
+        let mut project = Map::new();
+        project.insert("module".to_string(), project_path(&self.module_path));
+        project.insert("output".to_string(), project_path(&self.output_path));
+        project.insert("tracks".to_string(), Value::Array(self.tracks.iter().map(|t| Value::Integer(*t as i64)).collect()));
+        if let Some(config_path) = &self.config_path {
+            project.insert("config".to_string(), project_path(config_path));
+        }
+        project.insert("options".to_string(), self.options.clone());
+
+        let project_str = toml::to_string_pretty(&Value::Table(project)).context("Failed to serialize project")?;
+        fs::write(path.as_ref(), project_str)
+            .with_context(|| format!("Failed to write project: {}", path.as_ref().display()))
+    }
+
+    // Tracks get their own output when the project renders more than one of them
+    pub fn track_output_path(&self, track: u8) -> PathBuf {
+        if self.tracks.len() <= 1 {
+            return self.output_path.clone();
+        }
+
+        let stem = self.output_path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        let file_name = match self.output_path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{} - {:02}.{}", stem, track, ext),
+            None => format!("{} - {:02}", stem, track)
+        };
+        self.output_path.with_file_name(file_name)
+    }
+
+    // The options that render one of the project's tracks
+    pub fn renderer_options(&self, track: u8) -> Result<RendererOptions> {
+        let mut options = RendererOptions::from_toml(self.options.clone())?;
+        options.input_path = self.module_path.to_str().unwrap().to_string();
+        options.video_options.output_path = self.track_output_path(track).to_str().unwrap().to_string();
+        options.track_index = track;
+        if let Some(config_path) = &self.config_path {
+            options.config_import_path = Some(config_path.to_str().unwrap().to_string());
+        }
+
+        Ok(options)
+    }
+
+    // The GUI can only hold one track, so it opens projects at their first
+    pub fn first_track_options(&self) -> Result<RendererOptions> {
+        self.renderer_options(self.tracks.first().cloned().unwrap_or(1))
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{Result, Context, bail};
+use std::path::Path;
+use crate::emulator::{Emulator, tracklist};
+use super::options::{RendererOptions, StopCondition};
+
+// Output filenames for every track of a module. Placeholders are {title} (the module's title),
+// {artist}, {track} and {trackname}, and the track number takes a zero-padded width like {track:02}.
+// {part} is left for the renderer to fill in when the output is split, see the parts module.
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{title} - {track:02} - {trackname}.mp4";
+
+struct TrackFields {
+    title: String,
+    artist: String,
+    track: u8,
+    trackname: String
+}
+
+// Keeps metadata from turning into directories or names the filesystem rejects
+fn sanitize_filename(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn expand_template(template: &str, fields: &TrackFields) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .with_context(|| format!("Unclosed placeholder in filename template: {}", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => (name, Some(width.parse::<usize>()
+                .with_context(|| format!("Invalid width in filename template placeholder: {{{}}}", placeholder))?)),
+            None => (placeholder, None)
+        };
+
+        let value = match (name, width) {
+            ("track", Some(width)) => format!("{:0width$}", fields.track, width = width),
+            ("track", None) => fields.track.to_string(),
+            ("title", None) => sanitize_filename(&fields.title),
+            ("artist", None) => sanitize_filename(&fields.artist),
+            ("trackname", None) => sanitize_filename(&fields.trackname),
+            ("part", _) => format!("{{{}}}", placeholder),
+            (_, Some(_)) => bail!("Only {{track}} takes a width in filename templates: {{{}}}", placeholder),
+            _ => bail!("Unknown filename template placeholder: {{{}}}", placeholder)
+        };
+        result.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+// One render per track, written to output_dir. Tracks with known durations stop at them
+// instead of the stop condition in the options, and sound effects are skipped unless asked for.
+pub fn track_jobs(options: &RendererOptions, output_dir: &Path, template: &str, include_sound_effects: bool) -> Result<Vec<RendererOptions>> {
+    let mut emulator = Emulator::new();
+    emulator.init(None);
+    emulator.open(&options.input_path)?;
+
+    let tracklist = match &options.tracklist_path {
+        Some(p) => tracklist::parse(p)?,
+        None => tracklist::search(&options.input_path)
+    };
+    let external_metadata = options.external_metadata.clone().map(|mut metadata| {
+        if options.prefer_alternate_names {
+            metadata.prefer_alternate_names();
+        }
+        metadata
+    });
+    // The metadata file's title and artist apply to every track, in the preferred language
+    let module_title = external_metadata.as_ref()
+        .and_then(|metadata| metadata.title.clone())
+        .or(emulator.module_title())
+        .unwrap_or("Untitled".to_string());
+
+    let mut jobs = Vec::new();
+    for track in 1..=emulator.track_count() {
+        if !include_sound_effects && emulator.is_sound_effect(track) {
+            continue;
+        }
+        emulator.select_track(track);
+
+        let (tracklist_title, tracklist_duration) = match tracklist.get(&(track - 1)) {
+            Some((title, duration)) => (Some(title.clone()), *duration),
+            None => (None, None)
+        };
+        let trackname = tracklist_title
+            .or(emulator.track_title(track))
+            .unwrap_or(format!("Track {}", track));
+        let artist = match emulator.nsf_metadata() {
+            Ok(Some((_, artist, _))) => artist,
+            _ => String::new()
+        };
+        let artist = external_metadata.as_ref()
+            .and_then(|metadata| metadata.artist.clone())
+            .unwrap_or(artist);
+        let fields = TrackFields {
+            title: module_title.clone(),
+            artist,
+            track,
+            trackname
+        };
+
+        let mut job = options.clone();
+        job.track_index = track;
+        job.video_options.output_path = output_dir.join(expand_template(template, &fields)?)
+            .to_str()
+            .unwrap()
+            .to_string();
+        // Tracklist durations count too, the renderer falls back to them the same way
+        if emulator.nsfe_duration().is_some() || tracklist_duration.is_some() {
+            job.stop_condition = StopCondition::NsfeLength;
+        } else if matches!(job.stop_condition, StopCondition::NsfeLength) {
+            bail!("Track {} has no NSFe/NSF2 or tracklist duration, choose a different stop condition", track);
+        }
+        if let Some(fadeout) = emulator.nsfe_fadeout() {
+            job.fadeout_length = fadeout as u64;
+        }
+
+        jobs.push(job);
+    }
+
+    if jobs.is_empty() {
+        bail!("Module has no tracks to render");
+    }
+    Ok(jobs)
+}
@@ -0,0 +1,94 @@
+use anyhow::{Result, Context, ensure};
+use std::collections::HashMap;
+use rusticnes_ui_common::piano_roll_window::ChannelSettings;
+use rusticnes_ui_common::drawing::Color;
+use crate::emulator::{Emulator, read_config_file};
+
+// Builds the per-channel settings for RendererOptions without going through RusticNES config keys.
+// Chips and channels are named like in the GUI, e.g. ("2A03", "Pulse 1") or ("N163", "NAMCO 3").
+//
+//   options.channel_settings = ChannelSettingsBuilder::new()
+//       .set_color("2A03", "Triangle", 0, Color::rgb(255, 0, 0))?
+//       .hide("2A03", "Noise")?
+//       .build();
+#[derive(Clone)]
+pub struct ChannelSettingsBuilder {
+    settings: HashMap<(String, String), ChannelSettings>
+}
+
+impl ChannelSettingsBuilder {
+    // Starts from the default palette
+    pub fn new() -> Self {
+        let mut emulator = Emulator::new();
+        emulator.init(None);
+
+        Self {
+            settings: emulator.channel_settings()
+        }
+    }
+
+    // Starts from the palette in a RusticNES config or FamiTracker theme
+    pub fn from_config(path: &str) -> Result<Self> {
+        let mut emulator = Emulator::new();
+        emulator.init(Some(read_config_file(path)?.as_str()));
+
+        Ok(Self {
+            settings: emulator.channel_settings()
+        })
+    }
+
+    fn channel_mut(&mut self, chip: &str, channel: &str) -> Result<&mut ChannelSettings> {
+        self.settings.get_mut(&(chip.to_string(), channel.to_string()))
+            .with_context(|| format!("Unknown chip/channel specified: {} {}", chip, channel))
+    }
+
+    pub fn set_color(mut self, chip: &str, channel: &str, slot: usize, color: Color) -> Result<Self> {
+        let settings = self.channel_mut(chip, channel)?;
+        let color_count = settings.colors.len();
+        let slot_color = settings.colors.get_mut(slot)
+            .with_context(|| format!("{} {} only has {} colors", chip, channel, color_count))?;
+        *slot_color = color;
+
+        Ok(self)
+    }
+
+    pub fn set_colors(mut self, chip: &str, channel: &str, colors: &[Color]) -> Result<Self> {
+        let settings = self.channel_mut(chip, channel)?;
+        ensure!(
+            settings.colors.len() == colors.len(),
+            "Wrong number of colors specified for chip/channel {} {}: expected {} colors", chip, channel, settings.colors.len()
+        );
+        settings.colors = colors.to_vec();
+
+        Ok(self)
+    }
+
+    pub fn hide(mut self, chip: &str, channel: &str) -> Result<Self> {
+        self.channel_mut(chip, channel)?.hidden = true;
+        Ok(self)
+    }
+
+    pub fn mute(mut self, chip: &str, channel: &str) -> Result<Self> {
+        self.channel_mut(chip, channel)?.muted = true;
+        Ok(self)
+    }
+
+    // Draws the channel under the others and scales its colors by the brightness, e.g. for a
+    // pulse that only echoes the lead
+    pub fn echo(mut self, chip: &str, channel: &str, brightness: f32) -> Result<Self> {
+        let settings = self.channel_mut(chip, channel)?;
+        settings.draw_order = -1;
+        settings.brightness = brightness;
+        Ok(self)
+    }
+
+    pub fn build(self) -> HashMap<(String, String), ChannelSettings> {
+        self.settings
+    }
+}
+
+impl Default for ChannelSettingsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Result, Context, bail};
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::video_builder;
+use super::options::RendererOptions;
+
+// Checkpointed renders are written as numbered segments next to the output and joined when the
+// render finishes. After each segment the resume file records how far the render got. Emulation
+// is deterministic, so resuming replays the emulator up to the end of the last finished segment
+// without encoding anything and carries on from there.
+#[derive(Serialize, Deserialize)]
+struct ResumeFile {
+    // Resuming with different options would stitch two different renders together
+    options: serde_json::Value,
+    // The frame each finished segment ends on
+    segment_end_frames: Vec<u64>
+}
+
+pub struct Checkpoints {
+    output_path: String,
+    interval: u64,
+    resume_file: ResumeFile,
+    finished_size: usize
+}
+
+fn resume_file_path(output_path: &str) -> String {
+    format!("{}.resume.json", output_path)
+}
+
+// Keeps the output's extension so the segments are written in the same container
+fn segment_path(output_path: &str, index: usize) -> String {
+    let path = Path::new(output_path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+    path.with_extension(format!("part{:03}.{}", index + 1, extension))
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+impl Checkpoints {
+    pub fn new(options: &RendererOptions, interval: u64) -> Result<Self> {
+        let output_path = options.video_options.output_path.clone();
+        let options_value = serde_json::to_value(options)?;
+
+        let resume_path = resume_file_path(&output_path);
+        let resume_file = match options.resume && Path::new(&resume_path).exists() {
+            true => {
+                let resume_file: ResumeFile = serde_json::from_str(&fs::read_to_string(&resume_path)?)
+                    .with_context(|| format!("Failed to parse resume file: {}", resume_path))?;
+                if resume_file.options != options_value {
+                    bail!("The render in {} was started with different options, it can't be resumed with these", resume_path);
+                }
+                resume_file
+            },
+            false => {
+                if options.resume {
+                    status!("Warning: no checkpoint found for {}, starting from the beginning", output_path);
+                }
+                ResumeFile {
+                    options: options_value,
+                    segment_end_frames: Vec::new()
+                }
+            }
+        };
+
+        let mut finished_size = 0;
+        for index in 0..resume_file.segment_end_frames.len() {
+            let path = segment_path(&output_path, index);
+            let metadata = fs::metadata(&path)
+                .with_context(|| format!("Segment {} of the interrupted render is missing", path))?;
+            finished_size += metadata.len() as usize;
+        }
+
+        Ok(Self {
+            output_path,
+            interval,
+            resume_file,
+            finished_size
+        })
+    }
+
+    // Frame the render picks up from, 0 if it starts from the beginning
+    pub fn resume_frame(&self) -> u64 {
+        self.resume_file.segment_end_frames.last().cloned().unwrap_or(0)
+    }
+
+    pub fn current_segment_path(&self) -> String {
+        segment_path(&self.output_path, self.resume_file.segment_end_frames.len())
+    }
+
+    pub fn segment_due(&self, frame: u64) -> bool {
+        frame >= self.resume_frame() + self.interval
+    }
+
+    // Size of the segments that are already finished
+    pub fn finished_size(&self) -> usize {
+        self.finished_size
+    }
+
+    pub fn finish_segment(&mut self, frame: u64) -> Result<()> {
+        self.finished_size += fs::metadata(self.current_segment_path())?.len() as usize;
+        self.resume_file.segment_end_frames.push(frame);
+
+        // Written to a temporary file first so a crash while saving can't lose the checkpoint
+        let resume_path = resume_file_path(&self.output_path);
+        let temp_path = format!("{}.tmp", resume_path);
+        fs::write(&temp_path, serde_json::to_string_pretty(&self.resume_file)?)
+            .with_context(|| format!("Failed to write resume file: {}", temp_path))?;
+        fs::rename(&temp_path, &resume_path)
+            .with_context(|| format!("Failed to write resume file: {}", resume_path))?;
+
+        Ok(())
+    }
+
+    // Joins the finished segments into the output and removes them along with the resume file
+    pub fn join_segments(&self) -> Result<()> {
+        let segment_paths: Vec<String> = (0..self.resume_file.segment_end_frames.len())
+            .map(|index| segment_path(&self.output_path, index))
+            .collect();
+
+        match segment_paths.len() {
+            1 => fs::rename(&segment_paths[0], &self.output_path)
+                .with_context(|| format!("Failed to write output: {}", self.output_path))?,
+            _ => {
+                video_builder::concat_segments(&segment_paths, &self.output_path)?;
+                for path in segment_paths.iter() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        fs::remove_file(resume_file_path(&self.output_path))?;
+
+        Ok(())
+    }
+}
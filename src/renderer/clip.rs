@@ -0,0 +1,37 @@
+use anyhow::{Result, ensure};
+use ffmpeg_next::Rational;
+use crate::emulator;
+use crate::video_builder::ClipEncoder;
+use super::create_emulator;
+use super::options::RendererOptions;
+
+// GIFs store frame delays in hundredths of a second, so faster clips don't play back any smoother
+const MAX_CLIP_FPS: f64 = 50.0;
+
+// Renders a short stretch of the piano roll to an animated GIF or WebP without the audio. Only
+// every Nth emulated frame is kept to get close to the requested frame rate.
+pub fn render_clip(options: &RendererOptions, start_frame: u64, frame_count: u64, fps: f64, width: u32, output_path: &str) -> Result<()> {
+    ensure!(frame_count > 0, "Clip must be longer than one frame");
+    ensure!(fps > 0.0 && fps <= MAX_CLIP_FPS, "Clip frame rate must be between 0 and {} fps", MAX_CLIP_FPS);
+
+    let mut emulator = create_emulator(options)?;
+    let frame_step = (emulator.frame_rate() / fps).round().max(1.0) as u64;
+    let (num, den) = emulator::frame_time_base(options.region);
+    let time_base = Rational::new(num * frame_step as i32, den);
+
+    let mut encoder = ClipEncoder::new(output_path, options.video_options.resolution_in, width, time_base)?;
+    encoder.start()?;
+
+    // The audio isn't used, so drain it as the emulator runs to keep the buffer from growing
+    for frame in 0..start_frame + frame_count {
+        emulator.step();
+        emulator.buffer_audio_samples();
+        emulator.clear_sample_buffer();
+
+        if frame >= start_frame && (frame - start_frame) % frame_step == 0 {
+            encoder.push_frame(&emulator.get_piano_roll_frame())?;
+        }
+    }
+
+    encoder.finish()
+}
@@ -0,0 +1,73 @@
+use anyhow::{Result, Context, ensure};
+use image::RgbaImage;
+use rusticnes_ui_common::drawing::{self, Color, Font, SimpleBuffer};
+use crate::emulator::{m3u_searcher, tracklist};
+use super::create_emulator;
+use super::options::RendererOptions;
+
+const LABEL_HEIGHT: u32 = 12;
+const BACKGROUND_COLOR: Color = Color { data: [32, 32, 32, 255] };
+const LABEL_COLOR: Color = Color { data: [255, 255, 255, 255] };
+
+// Renders one frame of every track into a grid with the track titles underneath, so a whole
+// module can be skimmed before picking what to render. Sound effects are left out.
+pub fn render_contact_sheet(options: &RendererOptions, frame: u64, columns: u32, output_path: &str) -> Result<()> {
+    ensure!(output_path.to_lowercase().ends_with(".png"), "Contact sheet output must be a PNG file");
+    ensure!(columns > 0, "Contact sheet needs at least one column");
+
+    let font = Font::from_raw(include_bytes!("../../external/rusticnes-ui-common/src/assets/8x8_font.png"), 8);
+    let tracklist_metadata = tracklist::search(&options.input_path);
+    let m3u_metadata = m3u_searcher::search(&options.input_path)?;
+
+    let mut emulator = create_emulator(options)?;
+    let tracks: Vec<u8> = (1..=emulator.track_count())
+        .filter(|t| !emulator.is_sound_effect(*t))
+        .collect();
+    ensure!(!tracks.is_empty(), "Module has no tracks to render");
+
+    // Thumbnails are half of the piano roll's internal resolution
+    let (w, h) = options.video_options.resolution_in;
+    let (thumb_w, thumb_h) = (w / 2, h / 2);
+    let rows = (tracks.len() as u32 + columns - 1) / columns;
+    let mut sheet = SimpleBuffer::new(columns * thumb_w, rows * (thumb_h + LABEL_HEIGHT));
+    drawing::rect(&mut sheet, 0, 0, sheet.width, sheet.height, BACKGROUND_COLOR);
+
+    for (i, track) in tracks.iter().enumerate() {
+        status!("Rendering track {} of {}", track, emulator.track_count());
+
+        let mut track_options = options.clone();
+        track_options.track_index = *track;
+        emulator = create_emulator(&track_options)?;
+        for _ in 0..=frame {
+            emulator.step();
+            emulator.buffer_audio_samples();
+            emulator.clear_sample_buffer();
+        }
+
+        let piano_roll = RgbaImage::from_raw(w, h, emulator.get_piano_roll_frame())
+            .context("Piano roll frame does not match the input resolution")?;
+        let thumbnail = image::imageops::resize(&piano_roll, thumb_w, thumb_h, image::imageops::Triangle);
+
+        let x = (i as u32 % columns) * thumb_w;
+        let y = (i as u32 / columns) * (thumb_h + LABEL_HEIGHT);
+        for (tx, ty, pixel) in thumbnail.enumerate_pixels() {
+            sheet.blend_pixel(x + tx, y + ty, Color::from_slice(&pixel.data));
+        }
+
+        let title = emulator.track_title(*track)
+            .or_else(|| tracklist_metadata.get(&(track - 1)).map(|(title, _duration)| title.clone()))
+            .or_else(|| m3u_metadata.get(&(track - 1)).map(|(title, _duration)| title.clone()))
+            .unwrap_or_default();
+        // The font only has ASCII glyphs, and labels are cut off at the edge of the thumbnail
+        let label: String = format!("{:02} {}", track, title).chars()
+            .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' })
+            .take((thumb_w / font.glyph_width) as usize)
+            .collect();
+        drawing::text(&mut sheet, &font, x, y + thumb_h + 2, &label, LABEL_COLOR);
+    }
+
+    RgbaImage::from_raw(sheet.width, sheet.height, sheet.buffer)
+        .context("Contact sheet has the wrong size")?
+        .save(output_path)
+        .with_context(|| format!("Failed to write contact sheet: {}", output_path))
+}
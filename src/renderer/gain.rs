@@ -0,0 +1,96 @@
+use anyhow::Result;
+use rusticnes_core::apu::PlaybackRate;
+use super::create_emulator;
+use super::options::{FRAME_RATE, RendererOptions, StopCondition};
+
+// Leave a little room below full scale so the encoder's resampling doesn't clip either
+const HEADROOM: f64 = 0.97;
+// Songs that never reach their loop count are only analyzed for this long
+const MAX_ANALYSIS_FRAMES: u64 = 20 * 60 * FRAME_RATE as u64;
+// The automatic speed keeps about this many notes on screen, within these scroll times in seconds
+const NOTES_ON_SCREEN: f64 = 40.0;
+const MIN_SCROLL_TIME: f64 = 2.0;
+const MAX_SCROLL_TIME: f64 = 8.0;
+
+pub struct TrackAnalysis {
+    // Keeps the output from clipping, 1.0 for quiet mixes
+    pub safe_gain: f64,
+    // New notes over all channels, counting each step of an arpeggio
+    pub notes_per_second: f64
+}
+
+impl TrackAnalysis {
+    // Dense songs scroll faster so their notes don't bunch up, sparse ones slower so the roll isn't empty
+    pub fn scroll_time(&self) -> f64 {
+        (NOTES_ON_SCREEN / self.notes_per_second.max(0.1)).clamp(MIN_SCROLL_TIME, MAX_SCROLL_TIME)
+    }
+}
+
+pub fn gain_to_db(gain: f64) -> f64 {
+    20.0 * gain.log10()
+}
+
+// Semitones from A4 for pitched channels, so a new note is counted when the key changes
+fn note_key(rate: &PlaybackRate) -> Option<i32> {
+    match *rate {
+        PlaybackRate::FundamentalFrequency { frequency } if frequency > 0.0 => Some((12.0 * (frequency / 440.0).log2()).round() as i32),
+        PlaybackRate::FundamentalFrequency { .. } => None,
+        PlaybackRate::LfsrRate { index, .. } => Some(index as i32),
+        PlaybackRate::SampleRate { .. } => Some(0)
+    }
+}
+
+// Runs the track without drawing anything to find its loudest sample and how busy it is
+pub fn analyze_track(options: &RendererOptions, track_duration: Option<usize>) -> Result<TrackAnalysis> {
+    let mut emulator = create_emulator(options)?;
+
+    let frames = match options.stop_condition {
+        StopCondition::Frames(frames) => frames,
+        StopCondition::NsfeLength => track_duration.map(|d| d as u64).unwrap_or(MAX_ANALYSIS_FRAMES),
+        StopCondition::Loops(_) => MAX_ANALYSIS_FRAMES
+    };
+    let sample_count = (options.emulation_sample_rate() as f64 / emulator.frame_rate()) as usize;
+
+    let mut held_keys: Vec<Option<i32>> = Vec::new();
+    let mut note_count = 0;
+    let mut analyzed_frames = 0;
+    for _ in 0..frames.min(MAX_ANALYSIS_FRAMES) {
+        emulator.step();
+        emulator.get_audio_samples(sample_count, 1.0);
+        analyzed_frames += 1;
+
+        for (i, (_, _, playing, rate, amplitude)) in emulator.channel_rates().into_iter().enumerate() {
+            if held_keys.len() <= i {
+                held_keys.push(None);
+            }
+            let key = match playing && amplitude > 0.0 {
+                true => note_key(&rate),
+                false => None
+            };
+            if key.is_some() && key != held_keys[i] {
+                note_count += 1;
+            }
+            held_keys[i] = key;
+        }
+
+        if let StopCondition::Loops(stop_loop_count) = options.stop_condition {
+            let song_ended = emulator.get_song_position().map_or(false, |p| p.end);
+            let loops_reached = emulator.loop_count().map_or(false, |c| c >= stop_loop_count);
+            if song_ended || loops_reached {
+                break;
+            }
+        }
+    }
+
+    let peak_level = emulator.peak_level();
+    let safe_gain = match peak_level > i16::MAX as i32 {
+        true => HEADROOM * i16::MAX as f64 / peak_level as f64,
+        false => 1.0
+    };
+    let seconds = analyzed_frames.max(1) as f64 / emulator.frame_rate();
+
+    Ok(TrackAnalysis {
+        safe_gain,
+        notes_per_second: note_count as f64 / seconds
+    })
+}
@@ -0,0 +1,83 @@
+use std::mem;
+use rusticnes_ui_common::drawing::{self, Color, Font, SimpleBuffer};
+use super::options::StopCondition;
+
+const BORDER_WIDTH: u32 = 6;
+const LABEL_MARGIN: u32 = 8;
+const LABEL_PADDING: u32 = 4;
+const LABEL_BACKGROUND: Color = Color { data: [0, 0, 0, 192] };
+
+// Flashes a border around the frame and shows "Loop 2/3" in the corner when the song loops, so
+// viewers can tell where it repeats. Both fade out over `length` frames.
+pub struct LoopMarker {
+    color: Color,
+    length: u64,
+    font: Font,
+    stop_loop_count: Option<usize>,
+    last_loop_count: usize,
+    timer: u64,
+    label: String
+}
+
+impl LoopMarker {
+    pub fn new(color: Color, length: u64, stop_condition: &StopCondition) -> Self {
+        Self {
+            color,
+            length: length.max(1),
+            font: Font::from_raw(include_bytes!("../../external/rusticnes-ui-common/src/assets/8x8_font.png"), 8),
+            stop_loop_count: match stop_condition {
+                StopCondition::Loops(loops) => Some(*loops),
+                _ => None
+            },
+            last_loop_count: 0,
+            timer: 0,
+            label: String::new()
+        }
+    }
+
+    // Called once per rendered frame with the emulator's loop count
+    pub fn update(&mut self, loop_count: Option<usize>) {
+        self.timer = self.timer.saturating_sub(1);
+
+        let loop_count = loop_count.unwrap_or(0);
+        if loop_count > self.last_loop_count {
+            // A loop count of 1 means the song is on its second pass
+            let pass = loop_count + 1;
+            self.label = match self.stop_loop_count {
+                Some(loops) if pass <= loops => format!("Loop {}/{}", pass, loops),
+                _ => format!("Loop {}", pass)
+            };
+            self.timer = self.length;
+        }
+        self.last_loop_count = loop_count;
+    }
+
+    pub fn draw(&self, frame: &mut Vec<u8>, width: u32, height: u32) {
+        if self.timer == 0 || width < BORDER_WIDTH * 2 || height < BORDER_WIDTH * 2 {
+            return;
+        }
+
+        let fade = self.timer as f64 / self.length as f64;
+        let mut color = self.color;
+        color.set_alpha((self.color.alpha() as f64 * fade) as u8);
+        let mut background = LABEL_BACKGROUND;
+        background.set_alpha((LABEL_BACKGROUND.alpha() as f64 * fade) as u8);
+
+        let mut buffer = SimpleBuffer { buffer: mem::take(frame), width, height };
+
+        drawing::blend_rect(&mut buffer, 0, 0, width, BORDER_WIDTH, color);
+        drawing::blend_rect(&mut buffer, 0, height - BORDER_WIDTH, width, BORDER_WIDTH, color);
+        drawing::blend_rect(&mut buffer, 0, BORDER_WIDTH, BORDER_WIDTH, height - BORDER_WIDTH * 2, color);
+        drawing::blend_rect(&mut buffer, width - BORDER_WIDTH, BORDER_WIDTH, BORDER_WIDTH, height - BORDER_WIDTH * 2, color);
+
+        let label_width = self.label.len() as u32 * self.font.glyph_width + LABEL_PADDING * 2;
+        let label_height = 8 + LABEL_PADDING * 2;
+        let (x, y) = (BORDER_WIDTH + LABEL_MARGIN, BORDER_WIDTH + LABEL_MARGIN);
+        if x + label_width <= width && y + label_height <= height {
+            drawing::blend_rect(&mut buffer, x, y, label_width, label_height, background);
+            drawing::text(&mut buffer, &self.font, x + LABEL_PADDING, y + LABEL_PADDING, &self.label, color);
+        }
+
+        *frame = buffer.buffer;
+    }
+}
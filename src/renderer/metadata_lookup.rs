@@ -0,0 +1,123 @@
+use anyhow::{Result, Context};
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const VGMDB_API_URL: &str = "https://vgmdb.info";
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExternalMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub composer: Option<String>,
+    pub album: Option<String>,
+    pub date: Option<String>,
+    // A second set of names, e.g. the Japanese title when the main one is romanized.
+    // These are written as the sort title and artist.
+    pub alternate_title: Option<String>,
+    pub alternate_artist: Option<String>
+}
+
+impl ExternalMetadata {
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let json = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read metadata file: {}", path.as_ref().display()))?;
+
+        serde_json::from_str(&json).context("Invalid metadata file")
+    }
+
+    pub fn lookup_vgmdb(query: &str) -> Result<Option<Self>> {
+        let search_results: Value = ureq::get(&format!("{}/search", VGMDB_API_URL))
+            .query("q", query)
+            .query("format", "json")
+            .call()?
+            .into_json()?;
+
+        let album_link = match search_results["results"]["albums"][0]["link"].as_str() {
+            Some(link) => link.to_string(),
+            None => return Ok(None)
+        };
+
+        let album: Value = ureq::get(&format!("{}/{}", VGMDB_API_URL, album_link))
+            .query("format", "json")
+            .call()?
+            .into_json()?;
+
+        let composers: Vec<String> = album["composers"].as_array()
+            .map(|composers| composers.iter()
+                .filter_map(|c| c["names"]["en"].as_str().or(c["names"]["ja"].as_str()))
+                .map(|c| c.to_string())
+                .collect())
+            .unwrap_or_default();
+
+        Ok(Some(Self {
+            title: None,
+            artist: None,
+            composer: match composers.is_empty() {
+                true => None,
+                false => Some(composers.join(", "))
+            },
+            album: album["name"].as_str().map(|s| s.to_string()),
+            date: album["release_date"].as_str().map(|s| s.to_string()),
+            alternate_title: None,
+            alternate_artist: None
+        }))
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.title = self.title.clone().or(other.title.clone());
+        self.artist = self.artist.clone().or(other.artist.clone());
+        self.composer = self.composer.clone().or(other.composer.clone());
+        self.album = self.album.clone().or(other.album.clone());
+        self.date = self.date.clone().or(other.date.clone());
+        self.alternate_title = self.alternate_title.clone().or(other.alternate_title.clone());
+        self.alternate_artist = self.alternate_artist.clone().or(other.alternate_artist.clone());
+    }
+
+    // Makes the alternate names the main ones, keeping the main ones as the sort names
+    pub fn prefer_alternate_names(&mut self) {
+        if self.alternate_title.is_some() {
+            mem::swap(&mut self.title, &mut self.alternate_title);
+        }
+        if self.alternate_artist.is_some() {
+            mem::swap(&mut self.artist, &mut self.alternate_artist);
+        }
+    }
+
+    // One line per field that will be written, for confirming them before a render
+    pub fn describe(&self) -> Vec<String> {
+        let fields = [
+            ("Title", &self.title),
+            ("Artist", &self.artist),
+            ("Composer", &self.composer),
+            ("Album", &self.album),
+            ("Date", &self.date),
+            ("Sort title", &self.alternate_title),
+            ("Sort artist", &self.alternate_artist)
+        ];
+        fields.into_iter()
+            .filter_map(|(name, value)| value.as_ref().map(|value| format!("{}: {}", name, value)))
+            .collect()
+    }
+
+    pub fn apply(&self, metadata: &mut HashMap<String, String>) {
+        let fields = [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("composer", &self.composer),
+            ("album", &self.album),
+            ("date", &self.date),
+            ("sort_name", &self.alternate_title),
+            ("sort_artist", &self.alternate_artist)
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+}
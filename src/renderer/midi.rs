@@ -0,0 +1,263 @@
+use anyhow::{Result, Context, ensure};
+use std::fs;
+use rusticnes_core::apu::PlaybackRate;
+use super::create_emulator;
+use super::options::{FRAME_RATE, RendererOptions, StopCondition};
+
+const TICKS_PER_QUARTER: u16 = 480;
+// 120 BPM, which makes 960 ticks per second
+const MICROSECONDS_PER_QUARTER: u32 = 500_000;
+const TICKS_PER_SECOND: f64 = 960.0;
+// Songs that never reach their loop count are only logged for this long
+const MAX_MIDI_FRAMES: u64 = 20 * 60 * FRAME_RATE as u64;
+// The General MIDI default, so players don't need an RPN to agree on it
+const BEND_RANGE: f64 = 2.0;
+const PERCUSSION_CHANNEL: u8 = 9;
+// The channels of each MIDI port that aren't the percussion channel
+const MELODIC_CHANNELS_PER_PORT: usize = 15;
+// Noise is spread over 16 keys like on the piano roll, starting from the GM bass drum
+const NOISE_BASE_KEY: u8 = 35;
+// Channels that play samples have no pitch, they always play middle C
+const SAMPLE_KEY: u8 = 60;
+
+fn write_variable_length(data: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+struct Track {
+    data: Vec<u8>,
+    last_tick: u64
+}
+
+impl Track {
+    fn new(name: &str) -> Self {
+        let mut track = Self { data: Vec::new(), last_tick: 0 };
+        track.meta(0, 0x03, name.as_bytes());
+        track
+    }
+
+    fn event(&mut self, tick: u64, bytes: &[u8]) {
+        write_variable_length(&mut self.data, tick - self.last_tick);
+        self.data.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+
+    fn meta(&mut self, tick: u64, kind: u8, bytes: &[u8]) {
+        write_variable_length(&mut self.data, tick - self.last_tick);
+        self.data.extend_from_slice(&[0xFF, kind]);
+        write_variable_length(&mut self.data, bytes.len() as u64);
+        self.data.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+
+    fn finish(mut self, tick: u64) -> Vec<u8> {
+        self.meta(tick, 0x2F, &[]);
+        chunk(b"MTrk", &self.data)
+    }
+}
+
+// A key with the exact pitch it's bent towards, or an unpitched hit
+#[derive(Clone, Copy)]
+struct Note {
+    key: u8,
+    pitch: Option<f64>,
+    velocity: u8
+}
+
+impl Note {
+    // The bend that plays this note's pitch on another key
+    fn bend_from(&self, key: u8) -> u16 {
+        match self.pitch {
+            Some(pitch) => (8192.0 + (pitch - key as f64) / BEND_RANGE * 8192.0).clamp(0.0, 16383.0) as u16,
+            None => 8192
+        }
+    }
+
+    // Vibrato and slides stay on the held key as long as a bend can reach them
+    fn continues(&self, held: &Note) -> bool {
+        match (self.pitch, held.pitch) {
+            (Some(pitch), Some(_)) => (pitch - held.key as f64).abs() < BEND_RANGE,
+            (None, None) => self.key == held.key,
+            _ => false
+        }
+    }
+}
+
+struct ChannelTrack {
+    chip: String,
+    channel: String,
+    track: Track,
+    midi_channel: u8,
+    played: bool,
+    held: Option<Note>,
+    bend: u16,
+    expression: u8
+}
+
+impl ChannelTrack {
+    // Nearest key for the frequency, with the exact pitch kept for the bend
+    fn note(rate: &PlaybackRate, amplitude: f32) -> Option<Note> {
+        let velocity = (amplitude.clamp(0.0, 1.0) * 126.0) as u8 + 1;
+        match *rate {
+            PlaybackRate::FundamentalFrequency { frequency } if frequency > 0.0 => {
+                let pitch = 69.0 + 12.0 * (frequency as f64 / 440.0).log2();
+                let key = pitch.round();
+                if !(0.0..=127.0).contains(&key) {
+                    return None;
+                }
+                Some(Note { key: key as u8, pitch: Some(pitch), velocity })
+            },
+            PlaybackRate::FundamentalFrequency { .. } => None,
+            PlaybackRate::LfsrRate { index, max } => {
+                let key = NOISE_BASE_KEY + (index * 16 / (max + 1)) as u8;
+                Some(Note { key, pitch: None, velocity })
+            },
+            PlaybackRate::SampleRate { .. } => Some(Note { key: SAMPLE_KEY, pitch: None, velocity })
+        }
+    }
+
+    fn update(&mut self, tick: u64, note: Option<Note>) {
+        let channel = self.midi_channel;
+        match (self.held, note) {
+            (Some(held), Some(note)) if note.continues(&held) => {
+                let bend = note.bend_from(held.key);
+                if bend != self.bend {
+                    self.track.event(tick, &[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+                    self.bend = bend;
+                }
+                // Volume changes during the note, relative to how it started
+                let expression = (note.velocity as u32 * 127 / held.velocity as u32).min(127) as u8;
+                if expression != self.expression {
+                    self.track.event(tick, &[0xB0 | channel, 11, expression]);
+                    self.expression = expression;
+                }
+            },
+            (held, note) => {
+                if let Some(held) = held {
+                    self.track.event(tick, &[0x80 | channel, held.key, 0]);
+                }
+                if let Some(note) = note {
+                    let bend = note.bend_from(note.key);
+                    if bend != self.bend {
+                        self.track.event(tick, &[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+                        self.bend = bend;
+                    }
+                    if self.expression != 127 {
+                        self.track.event(tick, &[0xB0 | channel, 11, 127]);
+                        self.expression = 127;
+                    }
+                    self.track.event(tick, &[0x90 | channel, note.key, note.velocity]);
+                    self.played = true;
+                }
+                self.held = note;
+            }
+        }
+    }
+}
+
+// Quantizes each channel's notes to the emulated frames and saves them as a standard MIDI file
+// with one track per channel. Frequencies between keys are played with pitch bends, noise goes on
+// the percussion channel. Once a port's 16 MIDI channels run out, the next channels go on another
+// port so none of them share a pitch bend. Hidden channels and channels that never play are left out.
+pub fn export_midi(options: &RendererOptions, output_path: &str) -> Result<()> {
+    ensure!(output_path.to_lowercase().ends_with(".mid"), "MIDI output must be a .mid file");
+
+    let options = &options.region_scaled();
+    let mut emulator = create_emulator(options)?;
+    let frames = match options.stop_condition {
+        StopCondition::Frames(frames) => frames,
+        StopCondition::NsfeLength => emulator.nsfe_duration().map(|d| d as u64).unwrap_or(MAX_MIDI_FRAMES),
+        StopCondition::Loops(_) => MAX_MIDI_FRAMES
+    };
+
+    let mut channels: Vec<ChannelTrack> = Vec::new();
+    let mut melodic_count = 0;
+    let mut percussion_count = 0;
+    let mut tick = 0;
+    for frame in 0..frames.min(MAX_MIDI_FRAMES) {
+        emulator.step();
+        emulator.buffer_audio_samples();
+        emulator.clear_sample_buffer();
+        tick = (frame as f64 / emulator.frame_rate() * TICKS_PER_SECOND).round() as u64;
+
+        for (i, (chip, channel, playing, rate, amplitude)) in emulator.channel_rates().into_iter().enumerate() {
+            if channels.len() <= i {
+                // Melodic channels fill each port around its percussion channel
+                let (port, midi_channel) = match rate {
+                    PlaybackRate::LfsrRate { .. } => {
+                        percussion_count += 1;
+                        (percussion_count - 1, PERCUSSION_CHANNEL)
+                    },
+                    _ => {
+                        let index = melodic_count % MELODIC_CHANNELS_PER_PORT;
+                        melodic_count += 1;
+                        let midi_channel = match index as u8 {
+                            index if index >= PERCUSSION_CHANNEL => index + 1,
+                            index => index
+                        };
+                        ((melodic_count - 1) / MELODIC_CHANNELS_PER_PORT, midi_channel)
+                    }
+                };
+                ensure!(port < 128, "Too many channels to export to MIDI");
+                let mut track = Track::new(&format!("{} {}", chip, channel));
+                track.meta(0, 0x21, &[port as u8]);
+                channels.push(ChannelTrack { chip, channel, track, midi_channel, played: false, held: None, bend: 8192, expression: 127 });
+            }
+
+            let note = match playing && amplitude > 0.0 {
+                true => ChannelTrack::note(&rate, amplitude),
+                false => None
+            };
+            channels[i].update(tick, note);
+        }
+
+        if let StopCondition::Loops(stop_loop_count) = options.stop_condition {
+            let song_ended = emulator.get_song_position().map_or(false, |p| p.end);
+            let loops_reached = emulator.loop_count().map_or(false, |c| c >= stop_loop_count);
+            if song_ended || loops_reached {
+                break;
+            }
+        }
+    }
+
+    let tracks: Vec<Vec<u8>> = channels.into_iter()
+        .filter(|c| c.played)
+        .filter(|c| !options.channel_settings.get(&(c.chip.clone(), c.channel.clone())).map_or(false, |s| s.hidden))
+        .map(|mut c| {
+            c.update(tick, None);
+            c.track.finish(tick)
+        })
+        .collect();
+    ensure!(!tracks.is_empty(), "No channels played during the track");
+
+    let title = emulator.track_title(options.track_index)
+        .or_else(|| emulator.nsf_metadata().ok().flatten().map(|(title, _, _)| title))
+        .unwrap_or_default();
+    let mut conductor = Track::new(&title);
+    conductor.meta(0, 0x51, &MICROSECONDS_PER_QUARTER.to_be_bytes()[1..]);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&1u16.to_be_bytes());
+    header.extend_from_slice(&(tracks.len() as u16 + 1).to_be_bytes());
+    header.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+
+    let mut midi = chunk(b"MThd", &header);
+    midi.extend(conductor.finish(0));
+    for track in tracks {
+        midi.extend(track);
+    }
+    fs::write(output_path, midi).with_context(|| format!("Failed to write MIDI: {}", output_path))
+}
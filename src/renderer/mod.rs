@@ -1,105 +1,704 @@
 pub mod options;
+pub mod metadata_lookup;
+pub mod preflight;
+pub mod snapshot;
+pub mod gain;
+pub mod channel_settings;
+pub mod schema;
+pub mod contact_sheet;
+pub mod batch;
+pub mod waveform;
+pub mod checkpoint;
+pub mod palette;
+pub mod summary;
+pub mod clip;
+pub mod loop_marker;
+pub mod parts;
+pub mod overlay;
+pub mod vgm;
+pub mod midi;
+pub mod presets;
+pub mod monitor;
 
-use anyhow::Result;
-use std::collections::VecDeque;
+use anyhow::{Result, Context, bail};
+use std::collections::{HashMap, VecDeque};
 use std::fs;
+use std::path::Path;
 use std::time::{Duration, Instant};
 use crate::emulator;
 use crate::video_builder;
-use options::{RendererOptions, StopCondition};
-use crate::emulator::SongPosition;
+use crate::video_builder::video_options::{Attachment, VideoOptions};
+use options::{RendererOptions, StartCondition, StopCondition};
+use crate::emulator::{NsfDriverType, SongPosition, tracklist};
+use metadata_lookup::ExternalMetadata;
+use checkpoint::Checkpoints;
+use loop_marker::LoopMarker;
+use parts::Parts;
+use overlay::{OverlayFields, TextOverlay};
+
+// How long a render stopping after a number of loops runs if loop detection gives out partway, in seconds
+const LOOP_DETECTION_FALLBACK_LENGTH: f64 = 300.0;
+
+// Passed to the frame callback after each emulated frame is encoded
+pub struct FrameInfo<'a> {
+    pub frame: u64,
+    pub song_position: Option<SongPosition>,
+    pub loop_count: Option<usize>,
+    pub width: u32,
+    pub height: u32,
+    // RGBA at the input resolution, before any interpolated frames are blended in
+    pub rgba: &'a [u8]
+}
+
+pub type FrameCallback = Box<dyn FnMut(&FrameInfo) + Send>;
+// Passed the mono audio samples as they are encoded
+pub type AudioCallback = Box<dyn FnMut(&[i16]) + Send>;
 
 pub struct Renderer {
     options: RendererOptions,
+    // As given, before region scaling, for saving alongside the output
+    source_options: RendererOptions,
 
     video: video_builder::VideoBuilder,
+    video_options: VideoOptions,
     emulator: emulator::Emulator,
+    checkpoints: Option<Checkpoints>,
+    parts: Option<Parts>,
 
     encode_start: Instant,
     frame_timestamp: f64,
     frame_times: VecDeque<f64>,
+    encoded_frames: u64,
+    // Video frames pushed with an output frame rate, which don't line up with the emulated ones
+    output_frames: u64,
+    slowest_frame_time: f64,
     fadeout_timer: Option<u64>,
-    expected_duration: Option<usize>
+    expected_duration: Option<usize>,
+    track_duration: Option<usize>,
+    // Exact stop time when the duration is given in milliseconds rather than frames
+    stop_time: Option<f64>,
+    // Samples taken from the emulator since encoding started
+    audio_samples: u64,
+    // Emulated frames before the first encoded one
+    start_frame: u64,
+    previous_frame: Option<Vec<u8>>,
+    loop_marker: Option<LoopMarker>,
+    text_overlays: Vec<TextOverlay>,
+    frame_callback: Option<FrameCallback>,
+    audio_callback: Option<AudioCallback>,
+    track_title: String,
+    // Tracks to play after this one in the same video
+    queue: VecDeque<RendererOptions>,
+    // The previous track's emulator while it fades under the current one, with its frames left
+    crossfade: Option<(emulator::Emulator, u64)>,
+    // Set once the stop time limit has replaced a loop count that can no longer be detected
+    loop_fallback_reported: bool
+}
+
+// Blends two RGBA frames, with t = 0 giving the first frame and t = 1 the second
+fn blend_frames(a: &[u8], b: &[u8], t: f64) -> Vec<u8> {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&a, &b)| (a as f64 + (b as f64 - a as f64) * t) as u8)
+        .collect()
+}
+
+// The volume with this many fadeout frames left. The fadeout has always divided the audio by
+// floor(length / frames left), which steps down slowly at first and drops off at the end, so
+// renders keep sounding the way they did. It's a multiplier so fade-ins and crossfades can scale it.
+fn fadeout_volume(frames_left: u64, fadeout_length: u64) -> f64 {
+    match frames_left {
+        0 => 0.0,
+        t => 1.0 / (fadeout_length as f64 / t as f64).floor().max(1.0)
+    }
+}
+
+// Sets up the emulator and piano roll for the options, shared by renders and snapshots
+fn create_emulator(options: &RendererOptions) -> Result<emulator::Emulator> {
+    let mut emulator = emulator::Emulator::new();
+
+    match options.config_import_path.clone() {
+        Some(p) => emulator.init(Some(emulator::read_config_file(p)?.as_str())),
+        None => emulator.init(None)
+    };
+    emulator.set_seed(options.seed);
+    emulator.set_ram_init(options.ram_init.clone());
+    emulator.set_region(options.region);
+    emulator.open(&options.input_path)?;
+    emulator.select_track(options.track_index);
+    // The APU's buffer is emptied once per frame, a frame's samples that don't fit are lost
+    let samples_per_frame = (options.emulation_sample_rate() as f64 / emulator::frame_rate(options.region)).ceil() as usize;
+    if options.audio_buffer_size < samples_per_frame.max(256) {
+        bail!("The audio buffer must hold at least {} samples at {} Hz", samples_per_frame.max(256), options.emulation_sample_rate());
+    }
+    emulator.config_audio(options.emulation_sample_rate(), options.audio_buffer_size, options.famicom, options.high_quality, options.multiplexing);
+    emulator.set_output_gain_db(options.output_gain_db);
+    emulator.apply_channel_settings(&options.channel_settings);
+    if let Some((color, thickness)) = options.outline_style {
+        emulator.set_outline_style(color, thickness);
+    }
+    if let Some((color, width)) = options.divider_style {
+        emulator.set_divider_style(color, width);
+    }
+    if let Some((color, intensity)) = options.keyboard_glow {
+        emulator.set_keyboard_glow(color, intensity);
+    }
+    if options.minimal {
+        emulator.set_minimal(true);
+    }
+
+    if let Some(layout) = options.piano_roll_layout {
+        emulator.set_piano_roll_layout(layout.octave_count, layout.key_thickness, layout.key_length, layout.waveform_height);
+        if layout.vertical {
+            emulator.set_piano_roll_setting("scroll_direction", "vertical")?;
+        }
+    }
+    for (key, value) in options.piano_roll_settings.iter() {
+        emulator.set_piano_roll_setting(key, value)?;
+    }
+    // Transparent renders are composited over something else, which an opaque background would cover
+    if options.video_options.transparent {
+        emulator.set_piano_roll_setting("background_color", "rgba(0, 0, 0, 0)")?;
+    }
+    emulator.set_piano_roll_size(options.video_options.resolution_in.0, options.video_options.resolution_in.1);
+    if let Some(scroll_duration) = options.scroll_duration {
+        emulator.set_piano_roll_scroll_duration(scroll_duration);
+    }
+
+    Ok(emulator)
+}
+
+// The module and the emulator config it was rendered with, so the video can be reproduced later
+fn source_attachments(options: &RendererOptions, emulator: &emulator::Emulator) -> Result<Vec<Attachment>> {
+    let module_path = Path::new(&options.input_path);
+    let module_mimetype = match module_path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("nsfe") => "audio/x-nsfe",
+        _ => "audio/x-nsf"
+    };
+    let module = Attachment {
+        filename: module_path.file_name().and_then(|n| n.to_str()).unwrap_or("module.nsf").to_string(),
+        mimetype: module_mimetype.to_string(),
+        data: fs::read(module_path).with_context(|| format!("Failed to read input file: {}", options.input_path))?
+    };
+    let config = Attachment {
+        filename: "config.toml".to_string(),
+        mimetype: "application/toml".to_string(),
+        data: emulator.dump_config().into_bytes()
+    };
+
+    Ok(vec![module, config])
+}
+
+// The emulator and everything else that belongs to one track, replaced by each track of a concatenation
+struct Track {
+    emulator: emulator::Emulator,
+    title: String,
+    duration: Option<usize>,
+    stop_time: Option<f64>,
+    loop_marker: Option<LoopMarker>,
+    text_overlays: Vec<TextOverlay>
+}
+
+// Fills in the video metadata for the track along the way
+fn load_track(options: &RendererOptions, metadata: &mut HashMap<String, String>) -> Result<Track> {
+    let mut emulator = create_emulator(options)?;
+
+    if emulator.nonstandard_play_rate() {
+        status!(
+            "Warning: NSF play rate ({:.2} Hz) differs from the video frame rate ({:.2} Hz). Each video frame covers {:.2} play calls and {:.2} piano roll updates.",
+            emulator.play_rate(),
+            emulator.frame_rate(),
+            emulator.play_rate() / emulator.frame_rate(),
+            emulator.polls_per_frame()
+        );
+    }
+
+    match emulator.nsf_metadata() {
+        Ok(Some((title, artist, copyright))) => {
+            metadata.insert("title".to_string(), title);
+            metadata.insert("artist".to_string(), artist);
+            metadata.insert("album".to_string(), copyright);
+            metadata.insert("track".to_string(), format!("{}/{}", options.track_index, emulator.track_count()));
+            metadata.insert("comment".to_string(), format!("Encoded with NSFPresenter (seed {})", emulator.seed()));
+        },
+        _ => ()
+    }
+    let nsf_title = metadata.get("title").cloned();
+
+    let tracklist = match &options.tracklist_path {
+        Some(p) => tracklist::parse(p)?,
+        None => tracklist::search(&options.input_path)
+    };
+    let (tracklist_title, tracklist_duration) = match tracklist.get(&(options.track_index.saturating_sub(1))) {
+        Some((title, duration)) => (Some(title.clone()), duration.clone()),
+        None => (None, None)
+    };
+    let track_title = tracklist_title.clone()
+        .or(emulator.track_title(options.track_index))
+        .unwrap_or(format!("Track {}", options.track_index));
+    if let Some(title) = tracklist_title {
+        metadata.insert("title".to_string(), title);
+    }
+    // Tracklist durations stand in for NSFe durations when the module doesn't have them
+    let track_duration = emulator.nsfe_duration()
+        .or(tracklist_duration.map(|d| (d.as_secs_f64() * emulator.frame_rate()) as usize));
+
+    let stop_time = match options.stop_condition {
+        StopCondition::NsfeLength => emulator.nsfe_duration_seconds().or(tracklist_duration.map(|d| d.as_secs_f64())),
+        _ => None
+    };
+
+    if options.auto_gain || options.auto_speed {
+        let analysis = gain::analyze_track(options, track_duration)?;
+        if options.auto_gain {
+            emulator.set_gain(analysis.safe_gain);
+        }
+        if options.auto_speed {
+            status!("Auto speed: {:.1}s scroll time for {:.1} notes per second", analysis.scroll_time(), analysis.notes_per_second);
+            emulator.set_piano_roll_scroll_duration(analysis.scroll_time());
+        }
+    }
+
+    let mut external_metadata = options.external_metadata.clone().unwrap_or_default();
+    if options.vgmdb_lookup {
+        if let Some(title) = nsf_title {
+            match ExternalMetadata::lookup_vgmdb(&title) {
+                Ok(Some(vgmdb_metadata)) => external_metadata.merge(&vgmdb_metadata),
+                Ok(None) => status!("Warning: no VGMdb results for {}", title),
+                Err(e) => status!("Warning: VGMdb lookup failed: {}", e)
+            }
+        }
+    }
+    if options.prefer_alternate_names {
+        external_metadata.prefer_alternate_names();
+    }
+    external_metadata.apply(metadata);
+    let track_title = external_metadata.title.clone().unwrap_or(track_title);
+    let caption = [Some(&track_title), metadata.get("artist")].into_iter()
+        .flatten()
+        .filter(|line| !line.is_empty())
+        .cloned()
+        .collect();
+    emulator.set_piano_roll_caption(caption);
+
+    let loop_marker = options.loop_marker
+        .map(|(color, length)| LoopMarker::new(color, length, &options.stop_condition));
+    let overlay_fields = OverlayFields {
+        title: track_title.clone(),
+        artist: metadata.get("artist").cloned().unwrap_or_default(),
+        copyright: emulator.nsf_metadata().ok().flatten().map(|(_, _, copyright)| copyright).unwrap_or_default(),
+        track: format!("{}/{}", options.track_index, emulator.track_count())
+    };
+    if options.tracker_overlay.is_some() && emulator.driver_type() == NsfDriverType::Unknown {
+        status!("Warning: the tracker position is only known for FamiTracker NSFs, it will show as --");
+    }
+    let text_overlays = [&options.text_overlay, &options.tracker_overlay].into_iter()
+        .flatten()
+        .map(|overlay_options| TextOverlay::new(overlay_options, &overlay_fields))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Track {
+        emulator,
+        title: track_title,
+        duration: track_duration,
+        stop_time,
+        loop_marker,
+        text_overlays
+    })
 }
 
 impl Renderer {
     pub fn new(options: RendererOptions) -> Result<Self> {
-        let mut emulator = emulator::Emulator::new();
-
-        match options.config_import_path.clone() {
-            Some(p) => emulator.init(Some(fs::read_to_string(p)?.as_str())),
-            None => emulator.init(None)
-        };
-        emulator.open(&options.input_path)?;
-        emulator.select_track(options.track_index);
-        emulator.config_audio(options.video_options.sample_rate as _, 0x10000, options.famicom, options.high_quality, options.multiplexing);
-        emulator.apply_channel_settings(&options.channel_settings);
+        let source_options = options.clone();
+        let options = options.region_scaled();
 
         let mut video_options = options.video_options.clone();
-        emulator.set_piano_roll_size(video_options.resolution_in.0, video_options.resolution_in.1);
-
-        match emulator.nsf_metadata() {
-            Ok(Some((title, artist, copyright))) => {
-                video_options.metadata.insert("title".to_string(), title);
-                video_options.metadata.insert("artist".to_string(), artist);
-                video_options.metadata.insert("album".to_string(), copyright);
-                video_options.metadata.insert("track".to_string(), format!("{}/{}", options.track_index, emulator.track_count()));
-                video_options.metadata.insert("comment".to_string(), "Encoded with NSFPresenter".to_string());
+        let Track { emulator, title: track_title, duration: track_duration, stop_time, loop_marker, text_overlays } =
+            load_track(&options, &mut video_options.metadata)?;
+
+        if options.oversampling > 1 {
+            video_options.resample_from = Some((options.emulation_sample_rate() as i32, options.resampler));
+        }
+        // Skipped frames would leave gaps in the audio, so timelapses are silent
+        if options.frame_step > 1 && video_options.is_audio_only() {
+            bail!("Timelapses are silent, so they can't be rendered to an audio-only file");
+        }
+        if options.frame_step > 1 {
+            video_options.video_only = true;
+        }
+        if options.attach_sources {
+            video_options.attachments = source_attachments(&options, &emulator)?;
+        }
+        let checkpoints = match options.checkpoint_interval {
+            Some(interval) => {
+                // The sidecar would only hold the last segment's audio
+                if video_options.sidecar_audio_path.is_some() {
+                    bail!("Sidecar audio can't be written by checkpointed renders");
+                }
+                if video_options.transparent && video_options.output_path.contains('%') {
+                    bail!("Image sequences can't be written by checkpointed renders");
+                }
+                let checkpoints = Checkpoints::new(&options, interval.max(1))?;
+                video_options.output_path = checkpoints.current_segment_path();
+                Some(checkpoints)
+            },
+            None if options.resume => bail!("Only checkpointed renders can be resumed, set a checkpoint interval"),
+            None => None
+        };
+        let parts = match options.split_condition {
+            Some(condition) => {
+                if checkpoints.is_some() {
+                    bail!("Split renders can't be checkpointed");
+                }
+                if video_options.sidecar_audio_path.is_some() {
+                    bail!("Sidecar audio can't be written by split renders");
+                }
+                if video_options.transparent && video_options.output_path.contains('%') {
+                    bail!("Image sequences can't be split");
+                }
+                let parts = Parts::new(&video_options.output_path, condition)?;
+                video_options.output_path = parts.current_part_path();
+                Some(parts)
+            },
+            None => None
+        };
+        match options.output_frame_rate {
+            Some(frame_rate) => {
+                if options.interpolation > 1 || options.frame_step > 1 {
+                    bail!("An output frame rate can't be combined with interpolation or timelapses");
+                }
+                video_options.video_time_base = frame_rate.time_base().into();
             },
-            _ => ()
+            None => {
+                // Interpolated frames are slotted in between the emulated ones
+                let (num, den) = (video_options.video_time_base.numerator(), video_options.video_time_base.denominator());
+                video_options.video_time_base = (num, den * options.interpolation as i32).into();
+            }
         }
 
-        let video = video_builder::VideoBuilder::new(video_options)?;
+        let video = video_builder::VideoBuilder::new(video_options.clone())?;
 
         Ok(Self {
             options: options.clone(),
+            source_options,
             video,
+            video_options,
             emulator,
+            checkpoints,
+            parts,
             encode_start: Instant::now(),
             frame_timestamp: 0.0,
             frame_times: VecDeque::new(),
+            encoded_frames: 0,
+            output_frames: 0,
+            slowest_frame_time: 0.0,
             fadeout_timer: None,
-            expected_duration: None
+            expected_duration: None,
+            track_duration,
+            stop_time,
+            audio_samples: 0,
+            start_frame: 0,
+            previous_frame: None,
+            loop_marker,
+            text_overlays,
+            frame_callback: None,
+            audio_callback: None,
+            track_title,
+            queue: VecDeque::new(),
+            crossfade: None,
+            loop_fallback_reported: false
         })
     }
 
     pub fn start_encoding(&mut self) -> Result<()> {
         self.encode_start = Instant::now();
         self.video.start_encoding()?;
+        self.start_track()?;
+
+        // Frames before the last checkpoint are already encoded, so they're only emulated again
+        let resume_frame = self.checkpoints.as_ref().map_or(0, |c| c.resume_frame());
+        if resume_frame > 0 {
+            status!("Resuming from frame {}", resume_frame);
+        }
+        while self.current_frame() < resume_frame {
+            if !self.render_frame(false)? {
+                break;
+            }
+        }
+        // Otherwise the replay would count as the time taken by the first encoded frame
+        self.frame_timestamp = self.elapsed().as_secs_f64();
 
+        Ok(())
+    }
+
+    // Brings a freshly loaded track up to its first encoded frame
+    fn start_track(&mut self) -> Result<()> {
         // Run for a frame and clear the audio buffer to prevent the pop during initialization
         self.emulator.step();
         self.emulator.clear_sample_buffer();
 
+        // Run the piano roll ahead of the audio so notes reach the keyboard as they are heard. The
+        // scroll latency is added to the lead, whole frames are emulated ahead and the audio for the
+        // rest of the last one is dropped.
+        if self.options.visual_lead_frames > 0 {
+            let lead = self.options.visual_lead_frames as f64 + self.emulator.scroll_latency_frames();
+            let lead_frames = lead.ceil() as u64;
+            for _ in 0..lead_frames {
+                self.emulator.step();
+                self.emulator.buffer_audio_samples();
+            }
+            let samples_per_frame = self.options.emulation_sample_rate() as f64 / self.emulator.frame_rate();
+            self.emulator.discard_audio_samples(((lead_frames as f64 - lead) * samples_per_frame).round() as usize);
+        }
+
+        // Skipped frames are emulated the same way as encoded ones so the piano roll has its history
+        if let Some(start_condition) = self.options.start_condition {
+            loop {
+                let started = match start_condition {
+                    StartCondition::Frames(frames) => self.current_frame() >= frames,
+                    StartCondition::Loops(loops) => match self.emulator.loop_count() {
+                        Some(loop_count) => loop_count >= loops,
+                        None => bail!("Loop detection is not available for this track, try a start time instead")
+                    }
+                };
+                if started {
+                    break;
+                }
+                if !self.render_frame(false)? {
+                    bail!("The track ends before the start point");
+                }
+            }
+            status!("Starting at frame {}", self.current_frame());
+        }
+        self.start_frame = self.current_frame();
+        self.audio_samples = 0;
+
+        Ok(())
+    }
+
+    // Plays the track after the current one in the same video, see start_next_track
+    pub fn queue_track(&mut self, options: RendererOptions) -> Result<()> {
+        if self.checkpoints.is_some() || self.parts.is_some() {
+            bail!("Concatenated renders can't be checkpointed or split");
+        }
+        self.queue.push_back(options.region_scaled());
+        Ok(())
+    }
+
+    // Swaps in the next queued track without closing the video. Tracks ending on their own either
+    // fade under the next one or are followed by a gap of silence, holding their last frame.
+    fn start_next_track(&mut self) -> Result<()> {
+        let options = self.queue.pop_front().unwrap();
+        // The video's metadata was written with the first track
+        let track = load_track(&options, &mut HashMap::new())?;
+        if matches!(options.stop_condition, StopCondition::NsfeLength) && track.duration.is_none() {
+            bail!("Track {} of {} has no NSFe/NSF2 or tracklist duration", options.track_index, options.input_path);
+        }
+        status!("Starting track {} of {}", options.track_index, options.input_path);
+
+        let previous_emulator = std::mem::replace(&mut self.emulator, track.emulator);
+        match self.fadeout_timer {
+            Some(frames_left) if frames_left > 0 => self.crossfade = Some((previous_emulator, frames_left)),
+            _ => self.push_gap()?
+        }
+
+        self.options = options;
+        self.track_title = track.title;
+        self.track_duration = track.duration;
+        self.stop_time = track.stop_time;
+        self.loop_marker = track.loop_marker;
+        self.text_overlays = track.text_overlays;
+        self.fadeout_timer = None;
+        self.expected_duration = None;
+        self.loop_fallback_reported = false;
+        self.start_track()
+    }
+
+    // Silence between concatenated tracks, over the last frame of the previous one
+    fn push_gap(&mut self) -> Result<()> {
+        let frame = match self.previous_frame.clone() {
+            Some(frame) if self.options.track_gap > 0 => frame,
+            _ => return Ok(())
+        };
+
+        let samples = (self.options.track_gap as f64 / self.emulator.frame_rate() * self.options.emulation_sample_rate() as f64).round() as usize;
+        self.push_audio_data(&vec![0i16; samples])?;
+        for _ in 0..self.options.track_gap {
+            // Nothing scrolls during the gap, so the interpolated frames are the same too
+            for _ in 1..self.options.interpolation {
+                self.video.push_video_data(&frame)?;
+            }
+            self.push_video_frame(&frame)?;
+            self.video.step_encoding()?;
+            self.encoded_frames += 1;
+        }
+
         Ok(())
     }
 
     pub fn step(&mut self) -> Result<bool> {
+        let running = self.render_frame(true)?;
+
+        let frame = self.current_frame();
+        if running && self.checkpoints.as_ref().map_or(false, |c| c.segment_due(frame)) {
+            self.start_next_segment()?;
+        }
+        if running && self.parts.as_ref().map_or(false, |p| p.split_due(frame, self.video.encoded_video_size())) {
+            self.start_next_part()?;
+        }
+
+        Ok(running)
+    }
+
+    fn start_next_part(&mut self) -> Result<()> {
+        self.video.finish_encoding()?;
+
+        let frame = self.current_frame();
+        let parts = self.parts.as_mut().unwrap();
+        parts.finish_part(frame)?;
+
+        let path = parts.current_part_path();
+        status!("Starting part {}", path);
+        self.reopen_output(path)
+    }
+
+    fn start_next_segment(&mut self) -> Result<()> {
+        self.video.finish_encoding()?;
+
+        let frame = self.current_frame();
+        let checkpoints = self.checkpoints.as_mut().unwrap();
+        checkpoints.finish_segment(frame)?;
+
+        let path = checkpoints.current_segment_path();
+        self.reopen_output(path)
+    }
+
+    // Continues the render in a new file with the same video options, once the last one is finished
+    fn reopen_output(&mut self, path: String) -> Result<()> {
+        let mut video_options = self.video_options.clone();
+        video_options.output_path = path;
+        self.video = video_builder::VideoBuilder::new(video_options)?;
+        self.video.start_encoding()
+    }
+
+    // Without `encode`, the frame only advances the emulator and the render state
+    fn render_frame(&mut self, encode: bool) -> Result<bool> {
+        for _ in 1..self.options.frame_step {
+            self.emulator.step();
+            self.emulator.buffer_audio_samples();
+            self.emulator.clear_sample_buffer();
+        }
         self.emulator.step();
 
-        self.video.push_video_data(&self.emulator.get_piano_roll_frame())?;
-        let volume_divisor = match self.fadeout_timer {
-            Some(t) => (self.options.fadeout_length as f64 / t as f64) as i16,
-            None => 1i16
+        let fadein_volume = self.fadein_volume();
+        if let Some(loop_marker) = self.loop_marker.as_mut() {
+            loop_marker.update(self.emulator.loop_count());
+        }
+        // Interpolated frames scroll the roll part of the way from the last emulated frame,
+        // the rest of the picture is the same as this frame's
+        let mut subframes: Vec<Vec<u8>> = match encode {
+            true => (1..self.options.interpolation)
+                .map(|i| self.emulator.get_piano_roll_subframe(i as f64 / self.options.interpolation as f64))
+                .collect(),
+            false => Vec::new()
         };
-        if let Some(audio_data) = self.emulator.get_audio_samples(self.video.audio_frame_size(), volume_divisor) {
-            self.video.push_audio_data(video_builder::as_u8_slice(&audio_data))?;
+        let mut frame = self.emulator.get_piano_roll_frame();
+        for subframe in subframes.iter_mut() {
+            self.decorate_frame(subframe, fadein_volume);
         }
+        self.decorate_frame(&mut frame, fadein_volume);
 
-        self.video.step_encoding()?;
+        let crossfade_volume = match self.crossfade.as_mut() {
+            Some((previous_emulator, frames_left)) => {
+                previous_emulator.step();
+                // Crossfades start with the fadeout if it's shorter
+                let crossfade_length = self.options.crossfade_length.min(self.options.fadeout_length).max(1);
+                let progress = 1.0 - *frames_left as f64 / crossfade_length as f64;
+                let previous_track_frame = previous_emulator.get_piano_roll_frame();
+                for subframe in subframes.iter_mut() {
+                    *subframe = blend_frames(&previous_track_frame, subframe, progress);
+                }
+                frame = blend_frames(&previous_track_frame, &frame, progress);
+                progress
+            },
+            None => 1.0
+        };
+        if encode {
+            for subframe in subframes.iter() {
+                self.video.push_video_data(subframe)?;
+            }
+            self.push_video_frame(&frame)?;
+            if let Some(frame_callback) = self.frame_callback.as_mut() {
+                let (width, height) = self.options.video_options.resolution_in;
+                frame_callback(&FrameInfo {
+                    frame: self.emulator.last_frame() as u64,
+                    song_position: self.emulator.get_song_position(),
+                    loop_count: self.emulator.loop_count(),
+                    width,
+                    height,
+                    rgba: &frame
+                });
+            }
+        }
+        self.previous_frame = Some(frame);
 
-        let elapsed_secs = self.elapsed().as_secs_f64();
-        let frame_time = elapsed_secs - self.frame_timestamp;
-        self.frame_timestamp = elapsed_secs;
+        let fadeout_volume = match self.fadeout_timer {
+            Some(t) => fadeout_volume(t, self.options.fadeout_length),
+            None => 1.0
+        };
+        // Samples are still taken from the emulator when not encoding to keep its buffer in step
+        if let Some(mut audio_data) = self.emulator.get_audio_samples(self.video.audio_frame_size(), fadein_volume * fadeout_volume * crossfade_volume) {
+            if let Some((previous_emulator, frames_left)) = self.crossfade.as_mut() {
+                // The previous track carries on with its fadeout underneath
+                let volume = fadeout_volume(*frames_left, self.options.fadeout_length);
+                let previous_audio_data = previous_emulator.get_remaining_audio_samples(audio_data.len(), volume);
+                for (sample, previous_sample) in audio_data.iter_mut().zip(previous_audio_data) {
+                    *sample = sample.saturating_add(previous_sample);
+                }
+            }
+            // Anything past the end of the stop condition is cut off
+            let pushed_samples = audio_data.len().min(self.audio_samples_remaining().unwrap_or(usize::MAX));
+            if encode && pushed_samples > 0 {
+                self.push_audio_data(&audio_data[..pushed_samples])?;
+            }
+            self.audio_samples += audio_data.len() as u64;
+        }
 
-        self.frame_times.push_front(frame_time);
-        self.frame_times.truncate(600);
+        if encode {
+            self.video.step_encoding()?;
 
+            let elapsed_secs = self.elapsed().as_secs_f64();
+            let frame_time = elapsed_secs - self.frame_timestamp;
+            self.frame_timestamp = elapsed_secs;
+
+            self.frame_times.push_front(frame_time);
+            self.frame_times.truncate(600);
+            self.encoded_frames += 1;
+            self.slowest_frame_time = self.slowest_frame_time.max(frame_time);
+        }
+
+        self.crossfade = match self.crossfade.take() {
+            Some((previous_emulator, frames_left)) if frames_left > 1 => Some((previous_emulator, frames_left - 1)),
+            _ => None
+        };
         self.expected_duration = self.next_expected_duration();
         self.fadeout_timer = self.next_fadeout_timer();
 
+        if let (Some(t), true) = (self.fadeout_timer, encode && !self.queue.is_empty()) {
+            // The next track starts once this one is down to the crossfade, or right after it
+            if t == 0 || (t <= self.options.crossfade_length && self.crossfade.is_none()) {
+                if t == 0 {
+                    self.push_final_audio()?;
+                }
+                self.start_next_track()?;
+                return Ok(true);
+            }
+        }
         if let Some(t) = self.fadeout_timer {
             if t == 0 {
+                if encode {
+                    self.push_final_audio()?;
+                }
                 return Ok(false)
             }
         }
@@ -107,9 +706,123 @@ impl Renderer {
         Ok(true)
     }
 
+    // The loop marker, text overlays and fade-in drawn over the piano roll
+    fn decorate_frame(&self, frame: &mut Vec<u8>, fadein_volume: f64) {
+        let (width, height) = self.options.video_options.resolution_in;
+        if let Some(loop_marker) = self.loop_marker.as_ref() {
+            loop_marker.draw(frame, width, height);
+        }
+        if !self.text_overlays.is_empty() {
+            let total_frames = self.expected_duration.map(|d| d as u64);
+            let song_position = self.emulator.get_song_position();
+            for text_overlay in self.text_overlays.iter() {
+                text_overlay.draw(frame, width, height, self.current_frame(), total_frames, self.emulator.frame_rate(), song_position);
+            }
+        }
+        if self.options.fadein_video && fadein_volume < 1.0 {
+            // Scaling the alpha too fades the piano roll in over the background
+            frame.iter_mut().for_each(|c| *c = (*c as f64 * fadein_volume) as u8);
+        }
+    }
+
+    // Pushes an emulated frame, along with the frames blended in for the output frame rate
+    fn push_video_frame(&mut self, frame: &[u8]) -> Result<()> {
+        match self.options.output_frame_rate {
+            Some(frame_rate) => {
+                // Every output frame due by now, blended between the last emulated frame and this one
+                // by where it falls in time, so the scroll speed stays the same at any frame rate
+                let emulated_frame = self.encoded_frames as f64;
+                let frames_per_output_frame = self.emulator.frame_rate() / frame_rate.fps();
+                let previous_frame = self.previous_frame.as_deref().unwrap_or(frame);
+                while self.output_frames as f64 * frames_per_output_frame <= emulated_frame {
+                    let t = 1.0 - (emulated_frame - self.output_frames as f64 * frames_per_output_frame);
+                    self.video.push_video_data(&blend_frames(previous_frame, frame, t.clamp(0.0, 1.0)))?;
+                    self.output_frames += 1;
+                }
+                Ok(())
+            },
+            None => self.video.push_video_data(frame)
+        }
+    }
+
+    fn push_audio_data(&mut self, audio_data: &[i16]) -> Result<()> {
+        if let Some(audio_callback) = self.audio_callback.as_mut() {
+            audio_callback(audio_data);
+        }
+        self.video.push_audio_data(video_builder::as_u8_slice(audio_data))
+    }
+
+    fn audio_sample_target(&self) -> Option<u64> {
+        // Timelapses don't keep their audio in step with the video
+        if self.options.frame_step > 1 {
+            return None;
+        }
+
+        let frame_rate = self.emulator.frame_rate();
+        let seconds = match self.stop_time {
+            Some(stop_time) => stop_time + self.options.fadeout_length as f64 / frame_rate,
+            None => self.expected_duration? as f64 / frame_rate
+        };
+        // The audio of the frames emulated in start_encoding never gets pushed
+        let skipped_seconds = self.start_frame as f64 / frame_rate;
+        let sample_rate = self.options.emulation_sample_rate() as f64;
+        Some(((seconds - skipped_seconds).max(0.0) * sample_rate).round() as u64)
+    }
+
+    fn audio_samples_remaining(&self) -> Option<usize> {
+        Some(self.audio_sample_target()?.saturating_sub(self.audio_samples) as usize)
+    }
+
+    // Tops the audio up to the exact length of the stop condition, which rarely falls on a frame boundary
+    fn push_final_audio(&mut self) -> Result<()> {
+        if let Some(remaining) = self.audio_samples_remaining().filter(|r| *r > 0) {
+            // The fadeout has already gone silent by now, otherwise this is a hard stop
+            let volume = match self.options.fadeout_length {
+                0 => 1.0,
+                _ => 0.0
+            };
+            let audio_data = self.emulator.get_remaining_audio_samples(remaining, volume);
+            if !audio_data.is_empty() {
+                self.push_audio_data(&audio_data)?;
+            }
+            self.audio_samples += audio_data.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    // Lets embedders see every frame as it is rendered, e.g. to forward it to another sink
+    pub fn set_frame_callback<F: FnMut(&FrameInfo) + Send + 'static>(&mut self, callback: F) {
+        self.frame_callback = Some(Box::new(callback));
+    }
+
+    // Same as the frame callback but for the audio, e.g. to play it while rendering
+    pub fn set_audio_callback<F: FnMut(&[i16]) + Send + 'static>(&mut self, callback: F) {
+        self.audio_callback = Some(Box::new(callback));
+    }
+
+    pub fn stop_early(&mut self, fadeout_length: u64) {
+        if self.fadeout_timer.is_some() {
+            return;
+        }
+
+        self.options.fadeout_length = fadeout_length;
+        // The track no longer ends at its stored duration
+        self.stop_time = None;
+        self.queue.clear();
+        self.fadeout_timer = Some(fadeout_length);
+        self.expected_duration = Some(self.current_frame() as usize + fadeout_length as usize);
+    }
+
     pub fn finish_encoding(&mut self) -> Result<()> {
         self.video.finish_encoding()?;
 
+        let frame = self.current_frame();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.finish_segment(frame)?;
+            checkpoints.join_segments()?;
+        }
+
         Ok(())
     }
 
@@ -121,6 +834,13 @@ impl Renderer {
         self.encode_start.elapsed()
     }
 
+    fn fadein_volume(&self) -> f64 {
+        match self.options.fadein_length {
+            0 => 1.0,
+            fadein_length => ((self.current_frame() - self.start_frame) as f64 / fadein_length as f64).min(1.0)
+        }
+    }
+
     fn next_expected_duration(&self) -> Option<usize> {
         if self.expected_duration.is_some() {
             return self.expected_duration;
@@ -135,7 +855,7 @@ impl Renderer {
                 }
             },
             StopCondition::NsfeLength => {
-                Some(self.emulator.nsfe_duration().unwrap() + self.options.fadeout_length as usize)
+                Some(self.track_duration.unwrap() + self.options.fadeout_length as usize)
             }
         }
     }
@@ -143,7 +863,7 @@ impl Renderer {
     fn next_fadeout_timer(&self) -> Option<u64> {
         match self.fadeout_timer {
             Some(0) => Some(0),
-            Some(t) => Some(t - 1),
+            Some(t) => Some(t.saturating_sub(self.options.frame_step)),
             None => {
                 match self.options.stop_condition {
                     StopCondition::Loops(stop_loop_count) => {
@@ -155,13 +875,13 @@ impl Renderer {
                             return Some(self.options.fadeout_length);
                         }
 
-                        let loop_count = self.emulator.loop_count()
-                            .expect("Loop detection not supported for this NSF");
-
-                        if loop_count >= stop_loop_count {
-                            Some(self.options.fadeout_length)
-                        } else {
-                            None
+                        let stopped = match self.emulator.loop_count() {
+                            Some(loop_count) => loop_count >= stop_loop_count,
+                            None => self.current_frame() as f64 >= LOOP_DETECTION_FALLBACK_LENGTH * self.emulator.frame_rate()
+                        };
+                        match stopped {
+                            true => Some(self.options.fadeout_length),
+                            false => None
                         }
                     },
                     StopCondition::Frames(stop_duration) => {
@@ -172,8 +892,8 @@ impl Renderer {
                         }
                     },
                     StopCondition::NsfeLength => {
-                        let stop_duration = self.emulator.nsfe_duration()
-                            .expect("No NSFe/NSF2 duration specified for this track");
+                        let stop_duration = self.track_duration
+                            .expect("No NSFe/NSF2 or tracklist duration specified for this track");
 
                         if self.current_frame() >= stop_duration as u64 {
                             Some(self.options.fadeout_length)
@@ -186,6 +906,17 @@ impl Renderer {
         }
     }
 
+    // Short summary for terminal and window titles, e.g. "42% - Vampire Killer"
+    pub fn progress_title(&self) -> String {
+        match self.expected_duration_frames() {
+            Some(frames) => {
+                let percent = (self.current_frame() * 100 / frames.max(1) as u64).min(100);
+                format!("{}% - {}", percent, self.track_title)
+            },
+            None => self.track_title.clone()
+        }
+    }
+
     pub fn song_position(&self) -> Option<SongPosition> {
         self.emulator.get_song_position()
     }
@@ -210,15 +941,53 @@ impl Renderer {
     }
 
     pub fn encode_rate(&self) -> f64 {
-        self.average_fps() as f64 / emulator::NES_NTSC_FRAMERATE
+        (self.average_fps() as u64 * self.options.frame_step) as f64 / self.emulator.frame_rate()
     }
 
     pub fn encoded_duration(&self) -> Duration {
-        self.video.encoded_video_duration()
+        // Segments are cut on frame boundaries, so the finished ones are exactly this long
+        let finished_frames = self.checkpoints.as_ref().map(|c| c.resume_frame())
+            .or(self.parts.as_ref().map(|p| p.start_frame()))
+            .unwrap_or(0);
+        self.video.encoded_video_duration() + Duration::from_secs_f64(finished_frames as f64 / self.emulator.frame_rate())
+    }
+
+    pub fn gain(&self) -> f64 {
+        self.emulator.gain()
+    }
+
+    pub fn clipped_samples(&self) -> usize {
+        self.emulator.clipped_samples()
+    }
+
+    // How far the loudest sample went over full scale, if the output clipped
+    pub fn clipping_db(&self) -> Option<f64> {
+        match self.emulator.peak_level() > i16::MAX as i32 {
+            true => Some(gain::gain_to_db(self.emulator.peak_level() as f64 / i16::MAX as f64)),
+            false => None
+        }
+    }
+
+    pub fn encoder_queue_peaks(&self) -> (usize, usize) {
+        (self.video.video_queue_peak(), self.video.audio_queue_peak())
+    }
+
+    pub fn mux_stalls(&self) -> usize {
+        self.video.mux_stalls()
+    }
+
+    pub fn mux_stall_time(&self) -> Duration {
+        self.video.mux_stall_time()
+    }
+
+    pub fn longest_mux_time(&self) -> Duration {
+        self.video.longest_mux_time()
     }
 
     pub fn encoded_size(&self) -> usize {
         self.video.encoded_video_size()
+            + self.checkpoints.as_ref().map_or(0, |c| c.finished_size())
+            + self.parts.as_ref().map_or(0, |p| p.finished_size())
     }
 
     pub fn expected_duration_frames(&self) -> Option<usize> {
@@ -226,14 +995,14 @@ impl Renderer {
     }
 
     pub fn expected_duration(&self) -> Option<Duration> {
-        Some(Duration::from_secs_f64(self.expected_duration? as f64 / emulator::NES_NTSC_FRAMERATE))
+        Some(Duration::from_secs_f64(self.expected_duration? as f64 / self.emulator.frame_rate()))
     }
 
     pub fn eta_duration(&self) -> Option<Duration> {
         match self.expected_duration {
             Some(expected_duration) => {
-                let remaining_frames = expected_duration - self.current_frame() as usize;
-                let average_fps = u32::max(self.average_fps(), 1) as f64;
+                let remaining_frames = expected_duration.saturating_sub(self.current_frame() as usize);
+                let average_fps = (u32::max(self.average_fps(), 1) as u64 * self.options.frame_step) as f64;
                 let remaining_secs = remaining_frames as f64 / average_fps;
                 Some(Duration::from_secs_f64(self.elapsed().as_secs_f64() + remaining_secs))
             },
@@ -241,6 +1010,28 @@ impl Renderer {
         }
     }
 
+    pub fn expansion_register_writes(&self) -> Vec<(String, bool, u64)> {
+        self.emulator.expansion_register_writes()
+    }
+
+    pub fn expansion_usage_warnings(&self) -> Vec<String> {
+        self.emulator.expansion_usage_warnings()
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        let mut warnings = self.emulator.take_warnings();
+        if let StopCondition::Loops(stop_loop_count) = self.options.stop_condition {
+            if self.emulator.loop_count().is_none() && !self.loop_fallback_reported {
+                self.loop_fallback_reported = true;
+                warnings.push(format!(
+                    "Loops can't be counted for this track, stopping after {} seconds instead of {} loops",
+                    LOOP_DETECTION_FALLBACK_LENGTH, stop_loop_count
+                ));
+            }
+        }
+        warnings
+    }
+
     pub fn emulator_progress(&self) -> String {
         self.emulator.progress()
     }
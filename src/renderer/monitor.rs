@@ -0,0 +1,152 @@
+use anyhow::{Result, Context, bail};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+// How much audio the monitor holds before it drops the oldest or holds up the render, in seconds
+const MONITOR_BUFFER_LENGTH: f64 = 0.25;
+// How long realtime monitoring waits for the device to play anything before giving up on it
+const MONITOR_STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MonitorSpeed {
+    // Plays the most recent audio and skips whatever the device can't keep up with
+    Render,
+    // Holds the render back so every sample gets played
+    Realtime
+}
+
+impl Display for MonitorSpeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorSpeed::Render => write!(f, "render"),
+            MonitorSpeed::Realtime => write!(f, "realtime")
+        }
+    }
+}
+
+impl FromStr for MonitorSpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "render" => Ok(MonitorSpeed::Render),
+            "realtime" => Ok(MonitorSpeed::Realtime),
+            _ => Err(format!("Unknown monitor speed {}. Valid speeds are 'render' and 'realtime'.", s))
+        }
+    }
+}
+
+// The renderer's end of the monitor. It is Send, unlike the stream on some platforms, so it can
+// go in the renderer's audio callback while the monitor stays with whoever owns the render.
+#[derive(Clone)]
+pub struct MonitorSink {
+    queue: Arc<Mutex<VecDeque<i16>>>,
+    capacity: usize,
+    speed: MonitorSpeed,
+    // Set when the stream errors or stops playing, after which samples are dropped like in
+    // render speed so a lost device can't hold up the render
+    stalled: Arc<AtomicBool>
+}
+
+impl MonitorSink {
+    pub fn push(&self, samples: &[i16]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+        if self.speed == MonitorSpeed::Realtime && !self.stalled.load(Ordering::Relaxed) {
+            let deadline = Instant::now() + MONITOR_STALL_TIMEOUT;
+            while queue.len() > self.capacity && !self.stalled.load(Ordering::Relaxed) {
+                if Instant::now() >= deadline {
+                    status!("Warning: the audio monitor stopped playing, rendering without waiting for it");
+                    self.stalled.store(true, Ordering::Relaxed);
+                    break;
+                }
+                drop(queue);
+                thread::sleep(Duration::from_millis(5));
+                queue = self.queue.lock().unwrap();
+            }
+        }
+
+        let overflow = queue.len().saturating_sub(self.capacity);
+        queue.drain(..overflow);
+    }
+}
+
+// Plays the mono audio being rendered through the default output device
+pub struct AudioMonitor {
+    _stream: Stream,
+    sink: MonitorSink
+}
+
+impl AudioMonitor {
+    pub fn start(sample_rate: u32, speed: MonitorSpeed) -> Result<Self> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .context("No audio output device found")?;
+        let supported_config = device.default_output_config()
+            .context("Failed to get the audio output configuration")?;
+
+        let sink = MonitorSink {
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            capacity: (sample_rate as f64 * MONITOR_BUFFER_LENGTH) as usize,
+            speed,
+            stalled: Arc::new(AtomicBool::new(false))
+        };
+        let config: StreamConfig = supported_config.config();
+        let stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, sample_rate, &sink)?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, sample_rate, &sink)?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, sample_rate, &sink)?,
+            sample_format => bail!("Unsupported audio output sample format: {}", sample_format)
+        };
+        stream.play().context("Failed to start audio output")?;
+
+        Ok(Self {
+            _stream: stream,
+            sink
+        })
+    }
+
+    pub fn sink(&self) -> MonitorSink {
+        self.sink.clone()
+    }
+}
+
+fn build_stream<T: SizedSample + FromSample<i16>>(device: &cpal::Device, config: &StreamConfig, sample_rate: u32, sink: &MonitorSink) -> Result<Stream> {
+    let queue = sink.queue.clone();
+    let stalled = sink.stalled.clone();
+    let channels = config.channels as usize;
+    // The device rarely runs at the emulator's rate, so samples are repeated or skipped to match
+    let step = sample_rate as f64 / config.sample_rate.0 as f64;
+    let mut position = 0.0;
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                // Underruns play silence until the render catches up
+                let sample = queue.front().map_or(T::EQUILIBRIUM, |s| T::from_sample(*s));
+                frame.fill(sample);
+
+                position += step;
+                let consumed = (position as usize).min(queue.len());
+                queue.drain(..consumed);
+                position -= position.floor();
+            }
+        },
+        move |e| {
+            status!("Warning: audio monitor error: {}", e);
+            stalled.store(true, Ordering::Relaxed);
+        },
+        None
+    ).context("Failed to open audio output")?;
+
+    Ok(stream)
+}
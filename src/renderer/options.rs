@@ -2,10 +2,17 @@ use std::collections::HashMap;
 use std::str::FromStr;
 use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
 use rusticnes_ui_common::piano_roll_window::ChannelSettings;
-use crate::video_builder::video_options::VideoOptions;
+use rusticnes_ui_common::drawing::Color;
+use crate::video_builder::video_options::{Resampler, VideoOptions};
+use super::metadata_lookup::ExternalMetadata;
+use super::overlay::TextOverlayOptions;
+use crate::emulator::{self, RamInit, Region, NES_NTSC_FRAMERATE};
 
 pub const FRAME_RATE: i32 = 60;
+// Close to the 4/3 boost that was always applied before the gain was configurable
+pub const DEFAULT_OUTPUT_GAIN_DB: f64 = 2.5;
 
 macro_rules! extra_str_traits {
     ($t: ty) => {
@@ -78,20 +85,305 @@ impl FromStr for StopCondition {
 
 extra_str_traits!(StopCondition);
 
-#[derive(Clone)]
+// Where encoding begins, the frames before it are only emulated
+#[derive(Copy, Clone)]
+pub enum StartCondition {
+    Frames(u64),
+    Loops(usize)
+}
+
+impl Display for StartCondition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartCondition::Frames(frames) => {
+                if (*frames % FRAME_RATE as u64) == 0 {
+                    write!(f, "time:{}", *frames / FRAME_RATE as u64)
+                } else {
+                    write!(f, "frames:{}", *frames)
+                }
+            },
+            StartCondition::Loops(loops) => write!(f, "loops:{}", *loops)
+        }
+    }
+}
+
+impl FromStr for StartCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+        if parts.len() != 2 {
+            return Err("Start condition format invalid, try one of 'time:30', 'frames:1800', or 'loops:1'.".to_string());
+        }
+
+        match parts[0] {
+            "time" => {
+                let time = u64::from_str(parts[1]).map_err(|e| e.to_string())?;
+                Ok(StartCondition::Frames(time * FRAME_RATE as u64))
+            },
+            "frames" => {
+                let frames = u64::from_str(parts[1]).map_err(|e| e.to_string())?;
+                Ok(StartCondition::Frames(frames))
+            },
+            "loops" => {
+                let loops = usize::from_str(parts[1]).map_err(|e| e.to_string())?;
+                Ok(StartCondition::Loops(loops))
+            },
+            _ => Err(format!("Unknown condition type {}. Valid types are 'time', 'frames', and 'loops'", parts[0]))
+        }
+    }
+}
+
+extra_str_traits!(StartCondition);
+
+// A video frame rate other than the NES's, as frames per second over a denominator of 1 or 1001
+#[derive(Copy, Clone, PartialEq)]
+pub struct OutputFrameRate {
+    pub frames: i32,
+    pub seconds: i32
+}
+
+impl OutputFrameRate {
+    pub fn fps(&self) -> f64 {
+        self.frames as f64 / self.seconds as f64
+    }
+
+    pub fn time_base(&self) -> (i32, i32) {
+        (self.seconds, self.frames)
+    }
+}
+
+impl Display for OutputFrameRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.seconds {
+            1 => write!(f, "{}", self.frames),
+            _ => write!(f, "{:.2}", self.fps())
+        }
+    }
+}
+
+impl FromStr for OutputFrameRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fps = match s {
+            "ntsc" => 59.94,
+            "ntsc-film" => 23.976,
+            s => f64::from_str(s).map_err(|_| format!("Invalid frame rate {}, try e.g. '30', '60', '59.94' or 'ntsc'", s))?
+        };
+        if !(1.0..=240.0).contains(&fps) {
+            return Err("Frame rate must be between 1 and 240".to_string());
+        }
+
+        // 29.97, 59.94 and the like are the broadcast rates of 30000/1001 etc.
+        let broadcast_frames = (fps * 1.001).round();
+        if fps.fract() == 0.0 {
+            Ok(OutputFrameRate { frames: fps as i32, seconds: 1 })
+        } else if (broadcast_frames / 1.001 - fps).abs() < 0.01 {
+            Ok(OutputFrameRate { frames: broadcast_frames as i32 * 1000, seconds: 1001 })
+        } else {
+            Err(format!("Unsupported frame rate {}, use a whole number or a broadcast rate like 29.97", s))
+        }
+    }
+}
+
+// Where long renders are cut into separate files, see the parts module
+#[derive(Copy, Clone)]
+pub enum SplitCondition {
+    Frames(u64),
+    Megabytes(u64)
+}
+
+impl Display for SplitCondition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SplitCondition::Frames(frames) => {
+                if (*frames % (60 * FRAME_RATE as u64)) == 0 {
+                    write!(f, "minutes:{}", *frames / (60 * FRAME_RATE as u64))
+                } else {
+                    write!(f, "frames:{}", *frames)
+                }
+            },
+            SplitCondition::Megabytes(megabytes) => {
+                if (*megabytes % 1000) == 0 {
+                    write!(f, "gb:{}", *megabytes / 1000)
+                } else {
+                    write!(f, "mb:{}", *megabytes)
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for SplitCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':')
+            .ok_or("Split condition format invalid, try one of 'minutes:30', 'frames:108000', 'gb:2', or 'mb:500'.".to_string())?;
+        let value = u64::from_str(value).map_err(|e| e.to_string())?;
+        if value == 0 {
+            return Err("Split condition must be greater than 0".to_string());
+        }
+
+        match kind {
+            "minutes" => Ok(SplitCondition::Frames(value * 60 * FRAME_RATE as u64)),
+            "frames" => Ok(SplitCondition::Frames(value)),
+            "gb" => Ok(SplitCondition::Megabytes(value * 1000)),
+            "mb" => Ok(SplitCondition::Megabytes(value)),
+            _ => Err(format!("Unknown condition type {}. Valid types are 'minutes', 'frames', 'gb', and 'mb'", kind))
+        }
+    }
+}
+
+extra_str_traits!(SplitCondition);
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LayoutPreset {
+    Widescreen,
+    Vertical,
+    Square,
+    Ultrawide
+}
+
+// Piano roll settings that depend on the shape of the canvas
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PianoRollLayout {
+    pub octave_count: u32,
+    pub key_thickness: u32,
+    pub key_length: u32,
+    pub waveform_height: u32,
+    // Stack the oscilloscopes above the keyboard and put the track info below the roll
+    #[serde(default)]
+    pub vertical: bool
+}
+
+impl LayoutPreset {
+    pub const ALL: [LayoutPreset; 4] = [LayoutPreset::Widescreen, LayoutPreset::Vertical, LayoutPreset::Square, LayoutPreset::Ultrawide];
+
+    pub fn resolution(&self) -> (u32, u32) {
+        match self {
+            LayoutPreset::Widescreen => (1920, 1080),
+            LayoutPreset::Vertical => (1080, 1920),
+            LayoutPreset::Square => (1080, 1080),
+            LayoutPreset::Ultrawide => (2560, 1080)
+        }
+    }
+
+    // Key thicknesses are picked so the keyboard fills most of the canvas width
+    // that set_resolution_smart picks for each aspect ratio
+    pub fn piano_roll_layout(&self) -> PianoRollLayout {
+        match self {
+            LayoutPreset::Widescreen => PianoRollLayout { octave_count: 9, key_thickness: 5, key_length: 24, waveform_height: 48, vertical: false },
+            LayoutPreset::Vertical => PianoRollLayout { octave_count: 9, key_thickness: 4, key_length: 24, waveform_height: 96, vertical: true },
+            LayoutPreset::Square => PianoRollLayout { octave_count: 9, key_thickness: 7, key_length: 32, waveform_height: 96, vertical: false },
+            LayoutPreset::Ultrawide => PianoRollLayout { octave_count: 9, key_thickness: 7, key_length: 20, waveform_height: 40, vertical: false }
+        }
+    }
+}
+
+impl Display for LayoutPreset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutPreset::Widescreen => write!(f, "16:9"),
+            LayoutPreset::Vertical => write!(f, "9:16"),
+            LayoutPreset::Square => write!(f, "1:1"),
+            LayoutPreset::Ultrawide => write!(f, "21:9")
+        }
+    }
+}
+
+impl FromStr for LayoutPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LayoutPreset::ALL.iter()
+            .find(|preset| preset.to_string() == s)
+            .cloned()
+            .ok_or(format!("Unknown layout preset {}. Valid presets are '16:9', '9:16', '1:1', and '21:9'.", s))
+    }
+}
+
+extra_str_traits!(LayoutPreset);
+
+// Saved and loaded through the schema module, see OPTIONS_VERSION there before renaming fields
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RendererOptions {
     pub input_path: String,
     pub video_options: VideoOptions,
+    pub external_metadata: Option<ExternalMetadata>,
+    // Use the metadata file's alternate title and artist for the video and filenames
+    pub prefer_alternate_names: bool,
+    pub vgmdb_lookup: bool,
 
     pub track_index: u8,
+    #[serde(with = "super::schema::as_optional_string")]
+    pub start_condition: Option<StartCondition>,
+    #[serde(with = "super::schema::as_string")]
     pub stop_condition: StopCondition,
     pub fadeout_length: u64,
+    pub fadein_length: u64,
+    pub fadein_video: bool,
+    // Between tracks queued with Renderer::queue_track, in frames
+    pub track_gap: u64,
+    pub crossfade_length: u64,
+    pub tracklist_path: Option<String>,
+    pub attach_sources: bool,
+    // Renders in segments of this many frames that can be resumed, see the checkpoint module
+    pub checkpoint_interval: Option<u64>,
+    // Writes the render as numbered parts instead of one file
+    #[serde(with = "super::schema::as_optional_string")]
+    pub split_condition: Option<SplitCondition>,
+    // Whether to pick up an interrupted checkpointed render, so not saved
+    #[serde(skip)]
+    pub resume: bool,
+    pub visual_lead_frames: u64,
+    pub frame_step: u64,
+    pub interpolation: u64,
+    // Emulated frames are dropped, repeated or blended to fit, None keeps the NES frame rate
+    #[serde(with = "super::schema::as_optional_string")]
+    pub output_frame_rate: Option<OutputFrameRate>,
 
     pub famicom: bool,
     pub high_quality: bool,
     pub multiplexing: bool,
+    // The emulator runs at this multiple of the output sample rate and the audio is resampled
+    // down, which keeps the chips' high harmonics from folding back as aliasing
+    pub oversampling: u32,
+    #[serde(with = "super::schema::as_string")]
+    pub resampler: Resampler,
+    // Samples the APU collects before handing them to the emulator
+    pub audio_buffer_size: usize,
+    pub auto_gain: bool,
+    pub output_gain_db: f64,
+    pub seed: u64,
+    #[serde(with = "super::schema::as_string")]
+    pub ram_init: RamInit,
+    #[serde(with = "super::schema::as_string")]
+    pub region: Region,
 
+    pub scroll_duration: Option<f64>,
+    // Picks the scroll time from the track's note density, see gain::TrackAnalysis
+    pub auto_speed: bool,
+    pub piano_roll_layout: Option<PianoRollLayout>,
+    // `piano_roll.*` settings keyed without the prefix, applied over the layout and imported config
+    pub piano_roll_settings: HashMap<String, String>,
+    #[serde(with = "super::schema::channel_settings_list")]
     pub channel_settings: HashMap<(String, String), ChannelSettings>,
+    #[serde(with = "super::schema::color_style")]
+    pub outline_style: Option<(Color, u32)>,
+    #[serde(with = "super::schema::color_style")]
+    pub divider_style: Option<(Color, u32)>,
+    #[serde(with = "super::schema::color_style")]
+    pub keyboard_glow: Option<(Color, f32)>,
+    // Color and length in frames of the marker drawn when the song loops, see the loop_marker module
+    #[serde(with = "super::schema::color_style")]
+    pub loop_marker: Option<(Color, u64)>,
+    pub text_overlay: Option<TextOverlayOptions>,
+    // Current tracker frame and row, see TextOverlayOptions::tracker_position
+    pub tracker_overlay: Option<TextOverlayOptions>,
+    pub minimal: bool,
     pub config_import_path: Option<String>
 }
 
@@ -99,32 +391,50 @@ impl Default for RendererOptions {
     fn default() -> Self {
         Self {
             input_path: "".to_string(),
-            video_options: VideoOptions {
-                output_path: "".to_string(),
-                metadata: Default::default(),
-                background_path: None,
-                video_time_base: (29_781, 1_789_773).into(),
-                video_codec: "libx264".to_string(),
-                video_codec_params: Default::default(),
-                pixel_format_in: "rgba".to_string(),
-                pixel_format_out: "yuv420p".to_string(),
-                resolution_in: (960, 540),
-                resolution_out: (1920, 1080),
-                audio_time_base: (1, 44_100).into(),
-                audio_codec: "aac".to_string(),
-                audio_codec_params: Default::default(),
-                audio_channels: 1,
-                sample_format_in: "s16".to_string(),
-                sample_format_out: "fltp".to_string(),
-                sample_rate: 44_100,
-            },
+            video_options: VideoOptions::default(),
+            external_metadata: None,
+            prefer_alternate_names: false,
+            vgmdb_lookup: false,
             track_index: 0,
+            start_condition: None,
             stop_condition: StopCondition::Frames(300 * FRAME_RATE as u64),
             fadeout_length: 180,
+            fadein_length: 0,
+            track_gap: 0,
+            crossfade_length: 0,
+            fadein_video: false,
+            tracklist_path: None,
+            attach_sources: false,
+            checkpoint_interval: None,
+            split_condition: None,
+            resume: false,
+            visual_lead_frames: 0,
+            frame_step: 1,
+            interpolation: 1,
+            output_frame_rate: None,
             famicom: false,
             high_quality: true,
             multiplexing: false,
+            oversampling: 1,
+            resampler: Resampler::Sinc,
+            audio_buffer_size: 0x10000,
+            auto_gain: false,
+            output_gain_db: DEFAULT_OUTPUT_GAIN_DB,
+            seed: 0,
+            ram_init: RamInit::Zero,
+            region: Region::Ntsc,
+            scroll_duration: None,
+            auto_speed: false,
+            piano_roll_layout: None,
+            piano_roll_settings: HashMap::new(),
             channel_settings: HashMap::new(),
+            outline_style: None,
+            divider_style: None,
+            keyboard_glow: None,
+            loop_marker: None,
+            text_overlay: None,
+            tracker_overlay: None,
+            minimal: false,
             config_import_path: None
         }
     }
@@ -140,6 +450,50 @@ impl RendererOptions {
             (((960.0 / h as f32) * (w as f32)) as u32, 960)
         };
 
-        println!("{}x{}", self.video_options.resolution_in.0, self.video_options.resolution_in.1);
+        status!("{}x{}", self.video_options.resolution_in.0, self.video_options.resolution_in.1);
+    }
+
+    // The rate the emulator generates audio at
+    pub fn emulation_sample_rate(&self) -> u64 {
+        self.video_options.sample_rate as u64 * self.oversampling.max(1) as u64
+    }
+
+    pub fn apply_layout_preset(&mut self, preset: LayoutPreset) {
+        let (w, h) = preset.resolution();
+        self.set_resolution_smart(w, h);
+        self.piano_roll_layout = Some(preset.piano_roll_layout());
+    }
+
+    // Frame counts in the options are NTSC frames, so a duration in seconds means the same on every
+    // region. Renders count emulated frames, which are slower on PAL and Dendy.
+    pub fn region_scaled(&self) -> RendererOptions {
+        let frame_rate = emulator::frame_rate(self.region);
+        let scale = |frames: u64| (frames as f64 * frame_rate / NES_NTSC_FRAMERATE).round() as u64;
+
+        let mut options = self.clone();
+        if let Some(StartCondition::Frames(frames)) = options.start_condition {
+            options.start_condition = Some(StartCondition::Frames(scale(frames)));
+        }
+        if let StopCondition::Frames(frames) = options.stop_condition {
+            options.stop_condition = StopCondition::Frames(scale(frames));
+        }
+        options.fadeout_length = scale(options.fadeout_length);
+        options.fadein_length = scale(options.fadein_length);
+        options.track_gap = scale(options.track_gap);
+        options.crossfade_length = scale(options.crossfade_length);
+        options.visual_lead_frames = scale(options.visual_lead_frames);
+        options.checkpoint_interval = options.checkpoint_interval.map(scale);
+        if let Some(SplitCondition::Frames(frames)) = options.split_condition {
+            options.split_condition = Some(SplitCondition::Frames(scale(frames)));
+        }
+        options.loop_marker = options.loop_marker.map(|(color, length)| (color, scale(length)));
+        options.text_overlay = options.text_overlay.as_ref().map(|overlay| overlay.scaled(&scale));
+        options.tracker_overlay = options.tracker_overlay.as_ref().map(|overlay| overlay.scaled(&scale));
+        // Custom time bases are left alone, they were picked for this region already
+        if options.video_options.video_time_base == VideoOptions::default().video_time_base {
+            options.video_options.video_time_base = emulator::frame_time_base(self.region).into();
+        }
+
+        options
     }
 }
@@ -0,0 +1,265 @@
+use anyhow::{Result, Context, anyhow, ensure};
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use rusticnes_ui_common::drawing::{Color, Font, SimpleBuffer};
+use crate::emulator::SongPosition;
+
+const DEFAULT_FONT: &[u8] = include_bytes!("../../external/rusticnes-ui-common/src/assets/8x8_font.png");
+const MARGIN: u32 = 16;
+const LINE_SPACING: u32 = 2;
+const SHADOW_COLOR: Color = Color { data: [0, 0, 0, 160] };
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum OverlayPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight
+}
+
+impl OverlayPosition {
+    pub const ALL: [OverlayPosition; 6] = [
+        OverlayPosition::TopLeft, OverlayPosition::Top, OverlayPosition::TopRight,
+        OverlayPosition::BottomLeft, OverlayPosition::Bottom, OverlayPosition::BottomRight
+    ];
+}
+
+impl Display for OverlayPosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverlayPosition::TopLeft => write!(f, "top-left"),
+            OverlayPosition::Top => write!(f, "top"),
+            OverlayPosition::TopRight => write!(f, "top-right"),
+            OverlayPosition::BottomLeft => write!(f, "bottom-left"),
+            OverlayPosition::Bottom => write!(f, "bottom"),
+            OverlayPosition::BottomRight => write!(f, "bottom-right")
+        }
+    }
+}
+
+impl FromStr for OverlayPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OverlayPosition::ALL.iter()
+            .find(|position| position.to_string() == s)
+            .cloned()
+            .ok_or(format!("Unknown overlay position {}. Valid positions are 'top-left', 'top', 'top-right', 'bottom-left', 'bottom', and 'bottom-right'.", s))
+    }
+}
+
+// Text drawn over the video, e.g. the song title. Placeholders in the text are {title}, {artist},
+// {copyright}, {track} (like 3/12), {elapsed}, {total}, and the tracker {frame} and {row} in hex
+// like FamiTracker shows them. Frame counts are NTSC frames.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextOverlayOptions {
+    // Lines are separated with newlines
+    pub text: String,
+    #[serde(with = "super::schema::as_string")]
+    pub position: OverlayPosition,
+    // A PNG strip of the 96 printable ASCII characters, like the RusticNES font. Built in if None.
+    pub font_path: Option<String>,
+    pub glyph_width: u32,
+    // Whole pixels per font pixel, on the piano roll canvas
+    pub scale: u32,
+    pub color: String,
+    pub fade_in: u64,
+    pub fade_out: u64,
+    // How long the text stays up, including the fades. The whole render if None.
+    pub duration: Option<u64>
+}
+
+impl Default for TextOverlayOptions {
+    fn default() -> Self {
+        Self {
+            text: "{title}\n{artist}".to_string(),
+            position: OverlayPosition::BottomLeft,
+            font_path: None,
+            glyph_width: 8,
+            scale: 2,
+            color: "#FFFFFF".to_string(),
+            fade_in: 60,
+            fade_out: 60,
+            duration: None
+        }
+    }
+}
+
+impl TextOverlayOptions {
+    // A readout of the tracker frame and row, for FamiTracker NSFs
+    pub fn tracker_position(position: OverlayPosition) -> Self {
+        Self {
+            text: "Frame {frame} Row {row}".to_string(),
+            position,
+            fade_in: 0,
+            fade_out: 0,
+            ..Self::default()
+        }
+    }
+
+    // Frame counts in emulated frames of a region, see RendererOptions::region_scaled
+    pub fn scaled<F: Fn(u64) -> u64>(&self, scale: F) -> Self {
+        let mut options = self.clone();
+        options.fade_in = scale(options.fade_in);
+        options.fade_out = scale(options.fade_out);
+        options.duration = options.duration.map(scale);
+        options
+    }
+}
+
+// Values for the placeholders that don't change during the render
+pub struct OverlayFields {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub track: String
+}
+
+fn format_time(frames: u64, frame_rate: f64) -> String {
+    let seconds = (frames as f64 / frame_rate) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub struct TextOverlay {
+    options: TextOverlayOptions,
+    font: Font,
+    color: Color,
+    // With the fixed placeholders filled in
+    lines: Vec<String>
+}
+
+impl TextOverlay {
+    pub fn new(options: &TextOverlayOptions, fields: &OverlayFields) -> Result<Self> {
+        ensure!(options.glyph_width > 0 && options.scale > 0, "Overlay glyph width and scale must be at least 1");
+
+        let font = match &options.font_path {
+            Some(path) => {
+                let image = image::open(path)
+                    .with_context(|| format!("Failed to open overlay font: {}", path))?
+                    .to_rgba();
+                ensure!(image.width() >= options.glyph_width * 96, "Overlay font {} must have 96 characters {} pixels wide", path, options.glyph_width);
+                Font::from_image(image, options.glyph_width)
+            },
+            None => Font::from_raw(DEFAULT_FONT, 8)
+        };
+        let color = Color::from_string(&options.color)
+            .map_err(|e| anyhow!("Invalid overlay color {}: {}", options.color, e))?;
+
+        // The font only has ASCII characters
+        let lines = options.text
+            .replace("{title}", &fields.title)
+            .replace("{artist}", &fields.artist)
+            .replace("{copyright}", &fields.copyright)
+            .replace("{track}", &fields.track)
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '?' })
+            .collect::<String>()
+            .lines()
+            .map(str::to_string)
+            .collect();
+
+        Ok(Self {
+            options: options.clone(),
+            font,
+            color,
+            lines
+        })
+    }
+
+    fn opacity(&self, frame: u64) -> f64 {
+        let fade_in = match self.options.fade_in {
+            0 => 1.0,
+            fade_in => frame as f64 / fade_in as f64
+        };
+        let fade_out = match (self.options.duration, self.options.fade_out) {
+            (None, _) | (_, 0) => 1.0,
+            (Some(duration), fade_out) => duration.saturating_sub(frame) as f64 / fade_out as f64
+        };
+        fade_in.min(fade_out).clamp(0.0, 1.0)
+    }
+
+    fn draw_line(&self, buffer: &mut SimpleBuffer, x: u32, y: u32, line: &str, color: Color) {
+        let scale = self.options.scale;
+        for (i, c) in line.chars().enumerate() {
+            let code_point = c as u32;
+            if !(32..127).contains(&code_point) {
+                continue;
+            }
+            let glyph = &self.font.glyphs[(code_point - 32) as usize];
+            let glyph_x = x + i as u32 * self.font.glyph_width * scale;
+            for gy in 0..glyph.height {
+                for gx in 0..glyph.width {
+                    let glyph_alpha = glyph.get_pixel(gx, gy).alpha() as u32;
+                    if glyph_alpha == 0 {
+                        continue;
+                    }
+                    let mut pixel_color = color;
+                    pixel_color.set_alpha((color.alpha() as u32 * glyph_alpha / 255) as u8);
+                    for py in 0..scale {
+                        for px in 0..scale {
+                            let (dx, dy) = (glyph_x + gx * scale + px, y + gy * scale + py);
+                            if dx < buffer.width && dy < buffer.height {
+                                buffer.blend_pixel(dx, dy, pixel_color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // `total_frames` is the expected length of the render, if it's known yet
+    pub fn draw(&self, frame: &mut Vec<u8>, width: u32, height: u32, current_frame: u64, total_frames: Option<u64>, frame_rate: f64, song_position: Option<SongPosition>) {
+        let opacity = self.opacity(current_frame);
+        if opacity <= 0.0 || self.lines.is_empty() {
+            return;
+        }
+
+        let elapsed = format_time(current_frame, frame_rate);
+        let total = total_frames.map_or("--:--".to_string(), |t| format_time(t, frame_rate));
+        let (tracker_frame, tracker_row) = match song_position {
+            Some(position) if !position.end => (format!("{:02X}", position.frame), format!("{:02X}", position.row)),
+            _ => ("--".to_string(), "--".to_string())
+        };
+        let lines: Vec<String> = self.lines.iter()
+            .map(|line| line
+                .replace("{elapsed}", &elapsed)
+                .replace("{total}", &total)
+                .replace("{frame}", &tracker_frame)
+                .replace("{row}", &tracker_row))
+            .collect();
+
+        let scale = self.options.scale;
+        let line_height = self.font.glyphs[0].height * scale + LINE_SPACING * scale;
+        let block_height = lines.len() as u32 * line_height;
+        let top = match self.options.position {
+            OverlayPosition::TopLeft | OverlayPosition::Top | OverlayPosition::TopRight => MARGIN,
+            _ => height.saturating_sub(MARGIN + block_height)
+        };
+
+        let mut color = self.color;
+        color.set_alpha((self.color.alpha() as f64 * opacity) as u8);
+        let mut shadow_color = SHADOW_COLOR;
+        shadow_color.set_alpha((SHADOW_COLOR.alpha() as f64 * opacity) as u8);
+
+        let mut buffer = SimpleBuffer { buffer: mem::take(frame), width, height };
+        for (i, line) in lines.iter().enumerate() {
+            let line_width = line.len() as u32 * self.font.glyph_width * scale;
+            let x = match self.options.position {
+                OverlayPosition::TopLeft | OverlayPosition::BottomLeft => MARGIN,
+                OverlayPosition::Top | OverlayPosition::Bottom => width.saturating_sub(line_width) / 2,
+                OverlayPosition::TopRight | OverlayPosition::BottomRight => width.saturating_sub(MARGIN + line_width)
+            };
+            let y = top + i as u32 * line_height;
+            // A drop shadow keeps the text readable over light backgrounds
+            self.draw_line(&mut buffer, x + scale, y + scale, line, shadow_color);
+            self.draw_line(&mut buffer, x, y, line, color);
+        }
+        *frame = buffer.buffer;
+    }
+}
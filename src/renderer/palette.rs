@@ -0,0 +1,135 @@
+use anyhow::{Result, Context};
+use std::collections::HashMap;
+use rusticnes_ui_common::drawing::Color;
+
+// The image is shrunk to this size before counting colors, which also smooths out noise
+const SAMPLE_SIZE: u32 = 64;
+const MAX_PALETTE_SIZE: usize = 8;
+// Colors closer than this (per component) count as the same dominant color
+const MIN_COLOR_DISTANCE: i32 = 48;
+// WCAG contrast ratio each note color needs against the background's average color
+const MIN_CONTRAST: f64 = 3.0;
+
+fn relative_luminance(color: Color) -> f64 {
+    let linear = |v: u8| {
+        let v = v as f64 / 255.0;
+        match v <= 0.03928 {
+            true => v / 12.92,
+            false => ((v + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color.r()) + 0.7152 * linear(color.g()) + 0.0722 * linear(color.b())
+}
+
+fn contrast_ratio(a: Color, b: Color) -> f64 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn saturation(color: Color) -> f64 {
+    let max = color.r().max(color.g()).max(color.b()) as f64;
+    let min = color.r().min(color.g()).min(color.b()) as f64;
+    match max == 0.0 {
+        true => 0.0,
+        false => (max - min) / max
+    }
+}
+
+fn color_distance(a: Color, b: Color) -> i32 {
+    (a.r() as i32 - b.r() as i32).abs()
+        .max((a.g() as i32 - b.g() as i32).abs())
+        .max((a.b() as i32 - b.b() as i32).abs())
+}
+
+// Moves the color towards `target` (0 for black, 255 for white), t = 0 leaves it alone
+fn mix(color: Color, target: u8, t: f64) -> Color {
+    let mix_component = |v: u8| (v as f64 + (target as f64 - v as f64) * t).round() as u8;
+    Color::rgb(mix_component(color.r()), mix_component(color.g()), mix_component(color.b()))
+}
+
+// Dominant colors of a background image, for coloring the channels to match it
+pub struct ChannelPalette {
+    colors: Vec<Color>,
+    background: Color
+}
+
+impl ChannelPalette {
+    pub fn from_image(path: &str) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to open background image (video backgrounds aren't supported): {}", path))?;
+        let image = image::imageops::resize(&image.to_rgba(), SAMPLE_SIZE, SAMPLE_SIZE, image::imageops::Triangle);
+
+        // Components are bucketed to 4 bits, and each bucket keeps the sum of its pixels
+        let mut buckets: HashMap<(u8, u8, u8), (u64, [u64; 3])> = HashMap::new();
+        let mut total = [0u64; 3];
+        for pixel in image.pixels() {
+            let [r, g, b, _] = pixel.data;
+            let bucket = buckets.entry((r >> 4, g >> 4, b >> 4)).or_insert((0, [0; 3]));
+            bucket.0 += 1;
+            for (sum, v) in bucket.1.iter_mut().zip([r, g, b]) {
+                *sum += v as u64;
+            }
+            for (sum, v) in total.iter_mut().zip([r, g, b]) {
+                *sum += v as u64;
+            }
+        }
+
+        let pixel_count = (SAMPLE_SIZE * SAMPLE_SIZE) as u64;
+        let background = Color::rgb((total[0] / pixel_count) as u8, (total[1] / pixel_count) as u8, (total[2] / pixel_count) as u8);
+
+        // Saturated colors are preferred, otherwise a mostly grey image only gives shades of grey
+        let mut candidates: Vec<(f64, Color)> = buckets.values()
+            .map(|(count, sum)| {
+                let color = Color::rgb((sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8);
+                (*count as f64 * (0.25 + saturation(color)), color)
+            })
+            .collect();
+        candidates.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let mut colors: Vec<Color> = Vec::new();
+        for (_, color) in candidates {
+            if colors.len() >= MAX_PALETTE_SIZE {
+                break;
+            }
+            if colors.iter().all(|c| color_distance(*c, color) >= MIN_COLOR_DISTANCE) {
+                colors.push(color);
+            }
+        }
+
+        Ok(Self {
+            colors,
+            background
+        })
+    }
+
+    // Lightens colors on dark backgrounds and darkens them on light ones until they stand out
+    fn with_contrast(&self, color: Color) -> Color {
+        let target = match relative_luminance(self.background) < 0.18 {
+            true => 255,
+            false => 0
+        };
+        (0..=10)
+            .map(|step| mix(color, target, step as f64 / 10.0))
+            .find(|c| contrast_ratio(*c, self.background) >= MIN_CONTRAST)
+            .unwrap_or(mix(color, target, 1.0))
+    }
+
+    // Colors for the `index`th channel, which has `count` of them (e.g. one per duty cycle).
+    // Channels cycle through the palette, and extra colors are lighter and darker shades.
+    pub fn channel_colors(&self, index: usize, count: usize) -> Vec<Color> {
+        let base = self.colors[index % self.colors.len()];
+        (0..count)
+            .map(|i| {
+                let shade = match count {
+                    1 => 0.0,
+                    count => i as f64 / (count - 1) as f64 - 0.5
+                };
+                let color = match shade < 0.0 {
+                    true => mix(base, 255, -shade * 0.6),
+                    false => mix(base, 0, shade * 0.6)
+                };
+                self.with_contrast(color)
+            })
+            .collect()
+    }
+}
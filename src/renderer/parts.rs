@@ -0,0 +1,91 @@
+use anyhow::{Result, Context, bail};
+use std::fs;
+use std::path::Path;
+use super::options::SplitCondition;
+
+// Very long renders can be split into numbered parts that each play on their own. Parts are cut
+// on frame boundaries and the emulator keeps running across the cut, so playing them back to back
+// gives the same video as an unsplit render. The part number goes wherever the output path has a
+// {part} placeholder ({part:02} to pad it), or before the extension otherwise.
+pub struct Parts {
+    output_path: String,
+    condition: SplitCondition,
+    index: usize,
+    start_frame: u64,
+    finished_size: usize
+}
+
+fn part_path(output_path: &str, index: usize) -> Result<String> {
+    let number = index + 1;
+    let start = match output_path.find("{part") {
+        Some(start) => start,
+        None => {
+            let path = Path::new(output_path);
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+            return Ok(path.with_extension(format!("part{:02}.{}", number, extension))
+                .to_str()
+                .unwrap()
+                .to_string());
+        }
+    };
+
+    let end = output_path[start..].find('}')
+        .with_context(|| format!("Unclosed {{part}} placeholder in output path: {}", output_path))?;
+    let value = match &output_path[start + 5..start + end] {
+        "" => number.to_string(),
+        width => match width.strip_prefix(':').and_then(|w| w.parse::<usize>().ok()) {
+            Some(width) => format!("{:0width$}", number, width = width),
+            None => bail!("Invalid {{part}} placeholder in output path: {}", output_path)
+        }
+    };
+
+    Ok(format!("{}{}{}", &output_path[..start], value, &output_path[start + end + 1..]))
+}
+
+impl Parts {
+    pub fn new(output_path: &str, condition: SplitCondition) -> Result<Self> {
+        // Catches a bad placeholder before anything is rendered
+        part_path(output_path, 0)?;
+
+        Ok(Self {
+            output_path: output_path.to_string(),
+            condition,
+            index: 0,
+            start_frame: 0,
+            finished_size: 0
+        })
+    }
+
+    pub fn current_part_path(&self) -> String {
+        part_path(&self.output_path, self.index).unwrap()
+    }
+
+    // `size` is how much of the current part has been written so far
+    pub fn split_due(&self, frame: u64, size: usize) -> bool {
+        match self.condition {
+            SplitCondition::Frames(frames) => frame >= self.start_frame + frames,
+            SplitCondition::Megabytes(megabytes) => size as u64 >= megabytes * 1_000_000
+        }
+    }
+
+    // Frame the current part starts on
+    pub fn start_frame(&self) -> u64 {
+        self.start_frame
+    }
+
+    // Size of the parts that are already finished
+    pub fn finished_size(&self) -> usize {
+        self.finished_size
+    }
+
+    pub fn finish_part(&mut self, frame: u64) -> Result<()> {
+        let path = self.current_part_path();
+        self.finished_size += fs::metadata(&path)
+            .with_context(|| format!("Failed to read part: {}", path))?
+            .len() as usize;
+        self.index += 1;
+        self.start_frame = frame;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Result, Context};
+use std::fs;
+use std::path::Path;
+use indicatif::HumanBytes;
+use crate::video_builder::video_options::VideoOptions;
+use super::options::{FRAME_RATE, RendererOptions, StartCondition, StopCondition};
+
+// Rough bits per output pixel per frame, measured on typical piano roll renders
+fn video_bits_per_pixel(codec: &str) -> f64 {
+    match codec {
+        "prores" | "prores_ks" | "prores_aw" => 5.5,
+        "libx264" | "libx265" | "libvpx-vp9" | "libaom-av1" | "libsvtav1" => 0.1,
+        _ => 0.25
+    }
+}
+
+// The video options as the encoder will see them, with the output path's format applied. A path
+// that can't be encoded is left as it is, the render itself reports that.
+fn output_video_options(options: &RendererOptions) -> VideoOptions {
+    let mut video_options = options.video_options.clone();
+    if video_options.apply_output_format().is_err() {
+        return options.video_options.clone();
+    }
+    video_options
+}
+
+// The video codec the output will be encoded with
+pub fn output_video_codec(options: &RendererOptions) -> Option<String> {
+    let video_options = output_video_options(options);
+    match video_options.is_audio_only() {
+        true => None,
+        false => Some(video_options.video_codec)
+    }
+}
+
+pub fn output_audio_codec(options: &RendererOptions) -> Option<String> {
+    let video_options = output_video_options(options);
+    // Timelapses skip the audio since it wouldn't line up
+    if video_options.video_only || options.frame_step > 1 {
+        return None;
+    }
+    Some(video_options.audio_codec)
+}
+
+pub fn estimate_output_size(options: &RendererOptions) -> Option<u64> {
+    // Loop and NSFe durations aren't known until the module is running
+    let start_frames = match options.start_condition {
+        Some(StartCondition::Frames(frames)) => frames,
+        Some(StartCondition::Loops(_)) => return None,
+        None => 0
+    };
+    let frames = match options.stop_condition {
+        StopCondition::Frames(frames) => (frames.saturating_sub(start_frames) + options.fadeout_length) / options.frame_step,
+        _ => return None
+    };
+    let seconds = frames as f64 / FRAME_RATE as f64;
+
+    let (w, h) = options.video_options.resolution_out;
+    let video_frames = match options.output_frame_rate {
+        Some(frame_rate) => seconds * frame_rate.fps(),
+        None => (frames * options.interpolation) as f64
+    };
+    let video_bits = match output_video_codec(options) {
+        Some(codec) => video_bits_per_pixel(&codec) * (w * h) as f64 * video_frames,
+        None => 0.0
+    };
+    let sample_rate = options.video_options.sample_rate as f64;
+    let audio_bits = match output_audio_codec(options).as_deref() {
+        None => 0.0,
+        Some("pcm_s16le") => 16.0 * sample_rate * seconds,
+        // Chiptune compresses well, FLAC is usually around half the size of PCM
+        Some("flac") => 8.0 * sample_rate * seconds,
+        Some(_) => 192_000.0 * seconds
+    };
+
+    Some(((video_bits + audio_bits) / 8.0) as u64)
+}
+
+// Checks that the output can be written, returning warnings for problems that might not be fatal
+pub fn check_output(options: &RendererOptions) -> Result<Vec<String>> {
+    let mut warnings: Vec<String> = Vec::new();
+
+    let output_dir = match Path::new(&options.video_options.output_path).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => Path::new(".").to_path_buf()
+    };
+
+    let test_path = output_dir.join(".nsf-presenter-write-test");
+    fs::write(&test_path, [])
+        .with_context(|| format!("Output directory is not writable: {}", output_dir.display()))?;
+    let _ = fs::remove_file(&test_path);
+
+    if let Some(estimated_size) = estimate_output_size(options) {
+        match fs2::available_space(&output_dir) {
+            Ok(available) if available < estimated_size => warnings.push(format!(
+                "Estimated output size ({}) exceeds the free space on the output drive ({})",
+                HumanBytes(estimated_size),
+                HumanBytes(available)
+            )),
+            Ok(_) => (),
+            Err(e) => warnings.push(format!("Could not determine free disk space: {}", e))
+        }
+    }
+
+    Ok(warnings)
+}
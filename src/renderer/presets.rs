@@ -0,0 +1,100 @@
+use anyhow::{Result, Context, bail, ensure};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::options::RendererOptions;
+
+fn config_dir() -> Result<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    config_dir
+        .map(|dir| dir.join("NSFPresenter"))
+        .context("Failed to find a config directory to keep settings in")
+}
+
+// Presets are render options saved under a name, so codec settings, colors and the rest don't
+// have to be entered again for every render. They're TOML files in the user's config directory,
+// saved without the module and output paths.
+fn presets_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("presets"))
+}
+
+fn preset_path(name: &str) -> Result<PathBuf> {
+    ensure!(!name.trim().is_empty(), "Preset names can't be empty");
+    ensure!(!name.contains(['/', '\\', ':']) && !name.starts_with('.'), "Invalid preset name: {}", name);
+    Ok(presets_dir()?.join(format!("{}.toml", name)))
+}
+
+pub fn list_presets() -> Result<Vec<String>> {
+    let dir = presets_dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read presets: {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "toml"))
+        .filter_map(|path| path.file_stem().and_then(|s| s.to_str()).map(str::to_string))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub fn load_preset(name: &str) -> Result<RendererOptions> {
+    let path = preset_path(name)?;
+    if !path.is_file() {
+        bail!("No preset named {} (saved presets: {})", name, list_presets()?.join(", "));
+    }
+    RendererOptions::load(path)
+}
+
+// Returns where the preset was saved
+pub fn save_preset(name: &str, options: &RendererOptions) -> Result<PathBuf> {
+    let path = preset_path(name)?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create presets directory: {}", dir.display()))?;
+
+    let mut options = options.clone();
+    options.input_path = String::new();
+    options.video_options.output_path = String::new();
+    options.save(&path)?;
+    Ok(path)
+}
+
+// The GUI's options when it was last closed, including the module but not the output path
+fn last_session_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("last_session.toml"))
+}
+
+pub fn load_last_session() -> Result<Option<RendererOptions>> {
+    let path = last_session_path()?;
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let mut options = RendererOptions::load(path)?;
+    // Moved or deleted since
+    if !Path::new(&options.input_path).is_file() {
+        options.input_path = String::new();
+    }
+    Ok(Some(options))
+}
+
+pub fn save_last_session(options: &RendererOptions) -> Result<()> {
+    let path = last_session_path()?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create config directory: {}", dir.display()))?;
+
+    let mut options = options.clone();
+    options.video_options.output_path = String::new();
+    options.save(&path)
+}
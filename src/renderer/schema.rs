@@ -0,0 +1,249 @@
+use anyhow::{Result, Context, bail};
+use std::fs;
+use std::path::Path;
+use rusticnes_ui_common::drawing::Color;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use csscolorparser::Color as CssColor;
+use super::options::RendererOptions;
+
+// Saved options carry the schema version they were written with. Missing options take their
+// defaults and unknown ones are ignored, so files from other versions still load.
+pub const OPTIONS_VERSION: i64 = 1;
+
+fn check_version(version: Option<i64>) {
+    match version {
+        Some(version) if version > OPTIONS_VERSION => status!(
+            "Warning: options were saved by a newer version (schema {}, this version reads {}), newer options will be ignored",
+            version,
+            OPTIONS_VERSION
+        ),
+        _ => ()
+    }
+}
+
+impl RendererOptions {
+    // JSON or TOML, depending on the extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let options_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read options: {}", path.display()))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => {
+                let value: serde_json::Value = serde_json::from_str(&options_str)
+                    .with_context(|| format!("Failed to parse options: {}", path.display()))?;
+                check_version(value.get("version").and_then(serde_json::Value::as_i64));
+                serde_json::from_value(value).context("Invalid options")
+            },
+            Some("toml") => {
+                let value: toml::Value = toml::from_str(&options_str)
+                    .with_context(|| format!("Failed to parse options: {}", path.display()))?;
+                Self::from_toml(value)
+            },
+            _ => bail!("Options must be a .json or .toml file: {}", path.display())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let options_str = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => {
+                let mut value = serde_json::to_value(self)?;
+                value["version"] = OPTIONS_VERSION.into();
+                serde_json::to_string_pretty(&value)?
+            },
+            Some("toml") => toml::to_string_pretty(&self.to_toml()?)?,
+            _ => bail!("Options must be a .json or .toml file: {}", path.display())
+        };
+
+        fs::write(path, options_str)
+            .with_context(|| format!("Failed to write options: {}", path.display()))
+    }
+
+    // The TOML table for .toml options files and projects, with the schema version
+    pub fn to_toml(&self) -> Result<toml::Value> {
+        let mut value = toml::Value::try_from(self)?;
+        if let Some(table) = value.as_table_mut() {
+            table.insert("version".to_string(), OPTIONS_VERSION.into());
+        }
+        Ok(value)
+    }
+
+    pub fn from_toml(value: toml::Value) -> Result<Self> {
+        check_version(value.get("version").and_then(toml::Value::as_integer));
+        value.try_into().context("Invalid options")
+    }
+}
+
+// Colors are stored as CSS hex colors, e.g. "#FF8000" or "#FF800080"
+#[derive(Copy, Clone)]
+struct HexColor(Color);
+
+impl Serialize for HexColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let [r, g, b, a] = self.0.data;
+        match a {
+            255 => serializer.collect_str(&format_args!("#{:02X}{:02X}{:02X}", r, g, b)),
+            _ => serializer.collect_str(&format_args!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HexColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let color = String::deserialize(deserializer)?
+            .parse::<CssColor>()
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(HexColor(Color::rgba(
+            (color.r * 255.0) as u8,
+            (color.g * 255.0) as u8,
+            (color.b * 255.0) as u8,
+            (color.a * 255.0) as u8
+        )))
+    }
+}
+
+// For types that already round-trip through their CLI syntax, e.g. "loops:2"
+pub mod as_string {
+    use std::fmt::Display;
+    use std::str::FromStr;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>
+    {
+        T::from_str(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+// Same as as_string, with null for None
+pub mod as_optional_string {
+    use std::fmt::Display;
+    use std::str::FromStr;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => T::from_str(&s).map(Some).map_err(serde::de::Error::custom),
+            None => Ok(None)
+        }
+    }
+}
+
+// Outline, divider and glow styles are stored as [color, size]
+pub mod color_style {
+    use rusticnes_ui_common::drawing::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::HexColor;
+
+    pub fn serialize<T: Serialize + Copy, S: Serializer>(value: &Option<(Color, T)>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.map(|(color, size)| (HexColor(color), size)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(Color, T)>, D::Error> {
+        Ok(Option::<(HexColor, T)>::deserialize(deserializer)?.map(|(color, size)| (color.0, size)))
+    }
+}
+
+// Channel settings are keyed by (chip, channel), which neither JSON nor TOML can use as a key,
+// so they are stored as a list instead
+pub mod channel_settings_list {
+    use std::collections::HashMap;
+    use rusticnes_ui_common::piano_roll_window::ChannelSettings;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::HexColor;
+
+    #[derive(Serialize, Deserialize)]
+    struct ChannelSettingsEntry {
+        chip: String,
+        channel: String,
+        colors: Vec<HexColor>,
+        #[serde(default)]
+        hidden: bool,
+        #[serde(default)]
+        muted: bool,
+        #[serde(default)]
+        outline_color: Option<HexColor>,
+        #[serde(default = "default_amplitude_scale")]
+        amplitude_scale: f32,
+        #[serde(default)]
+        max_thickness: Option<f32>,
+        #[serde(default)]
+        scope_hidden: Option<bool>,
+        #[serde(default)]
+        scope_order: i32,
+        #[serde(default)]
+        draw_order: i32,
+        #[serde(default = "default_brightness")]
+        brightness: f32
+    }
+
+    fn default_amplitude_scale() -> f32 {
+        1.0
+    }
+
+    fn default_brightness() -> f32 {
+        1.0
+    }
+
+    pub fn serialize<S: Serializer>(value: &HashMap<(String, String), ChannelSettings>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<ChannelSettingsEntry> = value.iter()
+            .map(|((chip, channel), settings)| ChannelSettingsEntry {
+                chip: chip.clone(),
+                channel: channel.clone(),
+                colors: settings.colors.iter().cloned().map(HexColor).collect(),
+                hidden: settings.hidden,
+                muted: settings.muted,
+                outline_color: settings.outline_color.map(HexColor),
+                amplitude_scale: settings.amplitude_scale,
+                max_thickness: settings.max_thickness,
+                scope_hidden: settings.scope_hidden,
+                scope_order: settings.scope_order,
+                draw_order: settings.draw_order,
+                brightness: settings.brightness
+            })
+            .collect();
+        // Keeps saved files stable between runs
+        entries.sort_by(|a, b| (&a.chip, &a.channel).cmp(&(&b.chip, &b.channel)));
+
+        entries.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<(String, String), ChannelSettings>, D::Error> {
+        Ok(Vec::<ChannelSettingsEntry>::deserialize(deserializer)?
+            .into_iter()
+            .map(|entry| ((entry.chip, entry.channel), ChannelSettings {
+                colors: entry.colors.into_iter().map(|c| c.0).collect(),
+                hidden: entry.hidden,
+                muted: entry.muted,
+                outline_color: entry.outline_color.map(|c| c.0),
+                amplitude_scale: entry.amplitude_scale,
+                max_thickness: entry.max_thickness,
+                scope_hidden: entry.scope_hidden,
+                scope_order: entry.scope_order,
+                draw_order: entry.draw_order,
+                brightness: entry.brightness
+            }))
+            .collect())
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{Result, Context, bail};
+use std::path::Path;
+use image::{DynamicImage, RgbaImage};
+use crate::emulator::{Emulator, SongPosition};
+use super::create_emulator;
+use super::options::RendererOptions;
+
+fn is_jpeg_path(output_path: &str) -> Result<bool> {
+    match output_path.to_lowercase().rsplit_once('.') {
+        Some((_, "png")) => Ok(false),
+        Some((_, "jpg" | "jpeg")) => Ok(true),
+        _ => bail!("Snapshot output must be a PNG or JPEG file")
+    }
+}
+
+fn save_snapshot(options: &RendererOptions, emulator: &mut Emulator, output_path: &str, is_jpeg: bool) -> Result<()> {
+    let (w, h) = options.video_options.resolution_in;
+    let piano_roll = RgbaImage::from_raw(w, h, emulator.get_piano_roll_frame())
+        .context("Piano roll frame does not match the input resolution")?;
+
+    let (out_w, out_h) = options.video_options.resolution_out;
+    let piano_roll = match (out_w, out_h) == (w, h) {
+        true => piano_roll,
+        false => image::imageops::resize(&piano_roll, out_w, out_h, image::imageops::Nearest)
+    };
+
+    // JPEG has no alpha channel, so transparent areas end up black
+    let result = match is_jpeg {
+        true => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(piano_roll).to_rgb()).save(output_path),
+        false => piano_roll.save(output_path)
+    };
+    result.with_context(|| format!("Failed to write snapshot: {}", output_path))
+}
+
+// Several snapshots are numbered by frame, e.g. thumb.png becomes thumb.001234.png
+fn frame_snapshot_path(output_path: &str, frame: u64) -> String {
+    let path = Path::new(output_path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    path.with_extension(format!("{:06}.{}", frame, extension))
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+// Renders piano roll frames to images without setting up any encoders, one for each of `count`
+// frames from `frame` on so a moment can be stepped through frame by frame. Returns the paths.
+pub fn render_snapshot(options: &RendererOptions, frame: u64, count: u64, output_path: &str) -> Result<Vec<String>> {
+    let is_jpeg = is_jpeg_path(output_path)?;
+    let mut emulator = create_emulator(options)?;
+
+    let mut paths = Vec::new();
+    for current_frame in 0..frame + count.max(1) {
+        // The audio isn't used, so drain it as the emulator runs to keep the buffer from growing
+        emulator.step();
+        emulator.buffer_audio_samples();
+        emulator.clear_sample_buffer();
+
+        if current_frame >= frame {
+            let path = match count > 1 {
+                true => frame_snapshot_path(output_path, current_frame),
+                false => output_path.to_string()
+            };
+            save_snapshot(options, &mut emulator, &path, is_jpeg)?;
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+// Same as render_snapshot, but at the first frame the song reaches a tracker position. Returns that frame.
+pub fn render_snapshot_at_position(options: &RendererOptions, position: SongPosition, output_path: &str) -> Result<u32> {
+    let is_jpeg = is_jpeg_path(output_path)?;
+    let mut emulator = create_emulator(options)?;
+
+    let frame = emulator.seek_to_position(position)?;
+    save_snapshot(options, &mut emulator, output_path, is_jpeg)?;
+
+    Ok(frame)
+}
@@ -0,0 +1,151 @@
+use anyhow::{Result, Context};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::time::Duration;
+use indicatif::{FormattedDuration, HumanBytes};
+use super::Renderer;
+use super::options::RendererOptions;
+
+// What a finished render took and produced. It's printed when the render ends and saved next to
+// the output along with the options needed to render it again.
+pub struct RenderSummary {
+    options: RendererOptions,
+    wall_time: Duration,
+    frames: u64,
+    min_fps: f64,
+    output_size: u64,
+    video_duration: Duration,
+    loops: Option<usize>,
+    hidden_channels: Vec<String>
+}
+
+// Quotes an argument for pasting into a POSIX shell. Single quotes keep $, `, ! and the like from
+// being expanded, and a ' inside them has to close the quotes to be escaped.
+#[cfg(not(windows))]
+pub fn quote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    match plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// Quotes an argument the way Windows programs split their command line: in double quotes, with
+// backslashes doubled only where they come before a quote
+#[cfg(windows)]
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"&|<>^()".contains(c)) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push(c);
+                backslashes = 0;
+            },
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+impl RenderSummary {
+    pub fn new(renderer: &Renderer) -> Self {
+        let options = renderer.source_options.clone();
+        // The file on disk includes the container overhead the encoder doesn't know about
+        let output_size = fs::metadata(&options.video_options.output_path)
+            .map(|m| m.len())
+            .unwrap_or(renderer.encoded_size() as u64);
+
+        let mut hidden_channels: Vec<String> = options.channel_settings.iter()
+            .filter(|(_, settings)| settings.hidden)
+            .map(|((chip, channel), _)| format!("{} {}", chip, channel))
+            .collect();
+        hidden_channels.sort();
+
+        Self {
+            wall_time: renderer.elapsed(),
+            frames: renderer.encoded_frames,
+            min_fps: match renderer.slowest_frame_time {
+                t if t > 0.0 => 1.0 / t,
+                _ => 0.0
+            },
+            output_size,
+            video_duration: renderer.encoded_duration(),
+            loops: renderer.loop_count(),
+            hidden_channels,
+            options
+        }
+    }
+
+    pub fn average_fps(&self) -> f64 {
+        self.frames as f64 / self.wall_time.as_secs_f64().max(f64::EPSILON)
+    }
+
+    // In bits per second, over the whole file
+    pub fn bitrate(&self) -> f64 {
+        self.output_size as f64 * 8.0 / self.video_duration.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn options_path(&self) -> String {
+        format!("{}.options.json", self.options.video_options.output_path)
+    }
+
+    fn summary_path(&self) -> String {
+        format!("{}.summary.txt", self.options.video_options.output_path)
+    }
+
+    pub fn reproduce_command(&self) -> String {
+        [
+            "nsf-presenter-rs",
+            "--options",
+            &self.options_path(),
+            &self.options.input_path,
+            &self.options.video_options.output_path
+        ].iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // Writes the summary to <output>.summary.txt and the options to <output>.options.json
+    pub fn save(&self) -> Result<()> {
+        self.options.save(self.options_path())?;
+        fs::write(self.summary_path(), self.to_string())
+            .with_context(|| format!("Failed to write render summary: {}", self.summary_path()))
+    }
+}
+
+impl Display for RenderSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Output: {}", self.options.video_options.output_path)?;
+        writeln!(f, "Wall time: {}", FormattedDuration(self.wall_time))?;
+        writeln!(f, "Frames: {} (average {:.1} fps, minimum {:.1} fps)", self.frames, self.average_fps(), self.min_fps)?;
+        writeln!(
+            f, "Size: {} for {} ({:.0} kb/s)",
+            HumanBytes(self.output_size),
+            FormattedDuration(self.video_duration),
+            self.bitrate() / 1000.0
+        )?;
+        match self.loops {
+            Some(loops) => writeln!(f, "Loops: {}", loops)?,
+            None => writeln!(f, "Loops: unknown")?
+        };
+        match self.hidden_channels.is_empty() {
+            true => writeln!(f, "Hidden channels: none")?,
+            false => writeln!(f, "Hidden channels: {}", self.hidden_channels.join(", "))?
+        };
+        write!(f, "Reproduce with: {}", self.reproduce_command())
+    }
+}
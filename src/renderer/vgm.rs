@@ -0,0 +1,225 @@
+use anyhow::{Result, Context, ensure};
+use std::fs;
+use crate::emulator::Emulator;
+use super::create_emulator;
+use super::options::{FRAME_RATE, RendererOptions, StopCondition};
+
+const VGM_SAMPLE_RATE: u64 = 44100;
+// 1.71 added the VRC7 flag on the YM2413 clock
+const VGM_VERSION: u32 = 0x171;
+const HEADER_SIZE: usize = 0x100;
+const GD3_VERSION: u32 = 0x100;
+// Songs that never reach their loop count are only logged for this long
+const MAX_VGM_FRAMES: u64 = 20 * 60 * FRAME_RATE as u64;
+// VGM has no commands for these
+const UNSUPPORTED_CHIPS: [&str; 3] = ["VRC6", "MMC5", "N163"];
+// DPCM samples live at $C000-$FFFF
+const DPCM_BASE: usize = 0xC000;
+
+// Turns the emulator's sound register writes into VGM commands. The 2A03 and FDS go to the NES
+// APU, VRC7 to a YM2413 in VRC7 mode and the Sunsoft 5B to a YM2149.
+struct VgmWriter {
+    commands: Vec<u8>,
+    cpu_clock_rate: u64,
+    // Time of the last command, in samples
+    sample: u64,
+    fds: bool,
+    vrc7: bool,
+    s5b: bool,
+    vrc7_address: u8,
+    s5b_address: u8,
+    dmc_address: u8,
+    dmc_length: u8,
+    // What the player's DPCM memory holds so far, None if nothing was uploaded there yet
+    dpcm_memory: Vec<Option<u8>>
+}
+
+impl VgmWriter {
+    fn new(cpu_clock_rate: u64, fds: bool, vrc7: bool, s5b: bool) -> Self {
+        let mut writer = Self {
+            commands: Vec::new(),
+            cpu_clock_rate,
+            sample: 0,
+            fds,
+            vrc7,
+            s5b,
+            vrc7_address: 0,
+            s5b_address: 0,
+            dmc_address: 0,
+            dmc_length: 0,
+            dpcm_memory: vec![None; 0x10000 - DPCM_BASE]
+        };
+        if fds {
+            // $4023, the FDS registers are ignored until sound I/O is enabled
+            writer.commands.extend_from_slice(&[0xB4, 0x3F, 0x02]);
+        }
+        writer
+    }
+
+    fn wait_until(&mut self, cycle: u64) {
+        let target = cycle * VGM_SAMPLE_RATE / self.cpu_clock_rate;
+        while target > self.sample {
+            let samples = (target - self.sample).min(0xFFFF);
+            match samples {
+                1..=16 => self.commands.push(0x70 + (samples - 1) as u8),
+                _ => {
+                    self.commands.push(0x61);
+                    self.commands.extend_from_slice(&(samples as u16).to_le_bytes());
+                }
+            }
+            self.sample += samples;
+        }
+    }
+
+    fn write(&mut self, emulator: &Emulator, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4017 => {
+                self.commands.extend_from_slice(&[0xB4, (address - 0x4000) as u8, data]);
+                match address {
+                    0x4012 => self.dmc_address = data,
+                    0x4013 => self.dmc_length = data,
+                    _ => {}
+                }
+                if (0x4012..=0x4013).contains(&address) || (address == 0x4015 && data & 0x10 != 0) {
+                    self.upload_dpcm_sample(emulator);
+                }
+            },
+            0x4040..=0x407F if self.fds => self.commands.extend_from_slice(&[0xB4, (address - 0x4040 + 0x40) as u8, data]),
+            0x4080..=0x409E if self.fds => self.commands.extend_from_slice(&[0xB4, (address - 0x4080 + 0x20) as u8, data]),
+            0x9010 if self.vrc7 => self.vrc7_address = data,
+            0x9030 if self.vrc7 => self.commands.extend_from_slice(&[0x51, self.vrc7_address, data]),
+            0xC000 if self.s5b => self.s5b_address = data & 0x0F,
+            0xE000 if self.s5b => self.commands.extend_from_slice(&[0xA0, self.s5b_address, data]),
+            _ => {}
+        }
+    }
+
+    // The player has its own copy of the sample memory, so the sample about to play is copied over
+    // unless the player already has it. Samples past $FFFF would wrap to $8000 on hardware, those
+    // are cut short.
+    fn upload_dpcm_sample(&mut self, emulator: &Emulator) {
+        let start = DPCM_BASE + self.dmc_address as usize * 64;
+        let end = (start + self.dmc_length as usize * 16 + 1).min(0x10000);
+        let sample: Vec<u8> = (start..end).map(|address| emulator.peek_memory(address as u16)).collect();
+
+        let shadow = &mut self.dpcm_memory[start - DPCM_BASE..end - DPCM_BASE];
+        if shadow.iter().zip(sample.iter()).all(|(known, byte)| *known == Some(*byte)) {
+            return;
+        }
+        for (known, byte) in shadow.iter_mut().zip(sample.iter()) {
+            *known = Some(*byte);
+        }
+
+        // Data block of type 0xC2, NES APU RAM writes, starting with the address
+        self.commands.extend_from_slice(&[0x67, 0x66, 0xC2]);
+        self.commands.extend_from_slice(&(sample.len() as u32 + 2).to_le_bytes());
+        self.commands.extend_from_slice(&(start as u16).to_le_bytes());
+        self.commands.extend_from_slice(&sample);
+    }
+}
+
+fn gd3_tags(strings: &[&str]) -> Vec<u8> {
+    let mut text: Vec<u8> = Vec::new();
+    for string in strings {
+        for unit in string.encode_utf16().chain([0]) {
+            text.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    let mut gd3 = b"Gd3 ".to_vec();
+    gd3.extend_from_slice(&GD3_VERSION.to_le_bytes());
+    gd3.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    gd3.extend_from_slice(&text);
+    gd3
+}
+
+// Logs the register writes over the whole track and saves them as a VGM file, for players and
+// tools that work with the chips directly. The expansion chips VGM can't represent are left out.
+pub fn export_vgm(options: &RendererOptions, output_path: &str) -> Result<()> {
+    ensure!(output_path.to_lowercase().ends_with(".vgm"), "VGM output must be a .vgm file");
+
+    let options = &options.region_scaled();
+    let mut emulator = create_emulator(options)?;
+    let frames = match options.stop_condition {
+        StopCondition::Frames(frames) => frames,
+        StopCondition::NsfeLength => emulator.nsfe_duration().map(|d| d as u64).unwrap_or(MAX_VGM_FRAMES),
+        StopCondition::Loops(_) => MAX_VGM_FRAMES
+    };
+
+    let declared: Vec<String> = emulator.expansion_register_writes()
+        .into_iter()
+        .filter(|(_, declared, _)| *declared)
+        .map(|(chip, _, _)| chip)
+        .collect();
+    for chip in declared.iter().filter(|chip| UNSUPPORTED_CHIPS.contains(&chip.as_str())) {
+        status!("Warning: VGM doesn't support {}, its channels will be missing", chip);
+    }
+    let has_chip = |name: &str| declared.iter().any(|chip| chip == name);
+    let cpu_clock_rate = emulator.cpu_clock_rate();
+    let mut writer = VgmWriter::new(cpu_clock_rate, has_chip("FDS"), has_chip("VRC7"), has_chip("S5B"));
+
+    emulator.log_audio_writes();
+    let mut last_cycle = 0;
+    for _ in 0..frames.min(MAX_VGM_FRAMES) {
+        emulator.step();
+        emulator.buffer_audio_samples();
+        emulator.clear_sample_buffer();
+
+        for (cycle, address, data) in emulator.take_audio_writes() {
+            writer.wait_until(cycle);
+            writer.write(&emulator, address, data);
+            last_cycle = cycle;
+        }
+
+        if let StopCondition::Loops(stop_loop_count) = options.stop_condition {
+            let song_ended = emulator.get_song_position().map_or(false, |p| p.end);
+            let loops_reached = emulator.loop_count().map_or(false, |c| c >= stop_loop_count);
+            if song_ended || loops_reached {
+                break;
+            }
+        }
+    }
+    // Pads out to the end of the last frame so trailing silence isn't lost
+    let end_cycle = ((emulator.last_frame() as f64 / emulator.frame_rate()) * cpu_clock_rate as f64) as u64;
+    writer.wait_until(end_cycle.max(last_cycle));
+    writer.commands.push(0x66);
+
+    let (nsf_title, artist, copyright) = emulator.nsf_metadata().ok().flatten().unwrap_or_default();
+    let track_title = emulator.track_title(options.track_index).unwrap_or_else(|| nsf_title.clone());
+    let gd3 = gd3_tags(&[
+        track_title.as_str(), "",
+        nsf_title.as_str(), "",
+        "Nintendo Entertainment System", "",
+        artist.as_str(), "",
+        copyright.as_str(),
+        "",
+        ""
+    ]);
+
+    let mut header = vec![0u8; HEADER_SIZE];
+    let mut set = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    let gd3_offset = HEADER_SIZE + writer.commands.len();
+    set(0x00, u32::from_le_bytes(*b"Vgm "));
+    set(0x04, (gd3_offset + gd3.len() - 0x04) as u32);
+    set(0x08, VGM_VERSION);
+    if writer.vrc7 {
+        // Bit 31 selects the VRC7's built-in patches
+        set(0x10, (cpu_clock_rate * 2) as u32 | 0x8000_0000);
+    }
+    set(0x14, (gd3_offset - 0x14) as u32);
+    set(0x18, writer.sample as u32);
+    set(0x34, (HEADER_SIZE - 0x34) as u32);
+    if writer.s5b {
+        // The 5B divides its clock by 2 before the tone counters. Type 0x10 is the YM2149, with
+        // the default flags of 0x01 in the next byte.
+        set(0x74, (cpu_clock_rate / 2) as u32);
+        set(0x78, 0x0110);
+    }
+    // Bit 31 adds the FDS
+    set(0x84, cpu_clock_rate as u32 | if writer.fds { 0x8000_0000 } else { 0 });
+
+    let mut vgm = header;
+    vgm.extend_from_slice(&writer.commands);
+    vgm.extend_from_slice(&gd3);
+    fs::write(output_path, vgm).with_context(|| format!("Failed to write VGM: {}", output_path))
+}
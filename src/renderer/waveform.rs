@@ -0,0 +1,107 @@
+use anyhow::{Result, Context, ensure};
+use image::RgbaImage;
+use rusticnes_ui_common::drawing::{self, Color, Font, SimpleBuffer};
+use super::create_emulator;
+use super::options::{FRAME_RATE, RendererOptions, StopCondition};
+
+const LABEL_WIDTH: u32 = 120;
+const BACKGROUND_COLOR: Color = Color { data: [32, 32, 32, 255] };
+const LABEL_COLOR: Color = Color { data: [255, 255, 255, 255] };
+// Songs that never reach their loop count are only drawn for this long
+const MAX_WAVEFORM_FRAMES: u64 = 20 * 60 * FRAME_RATE as u64;
+
+struct ChannelWaveform {
+    chip: String,
+    channel: String,
+    played: bool,
+    // Lowest and highest sample of each frame
+    frames: Vec<(f32, f32)>
+}
+
+// Draws the whole track's waveform for each channel in its own row, like the overview in an
+// audio editor. Hidden channels and channels that never play are left out.
+pub fn render_waveform_strip(options: &RendererOptions, width: u32, row_height: u32, output_path: &str) -> Result<()> {
+    ensure!(output_path.to_lowercase().ends_with(".png"), "Waveform output must be a PNG file");
+    ensure!(width > LABEL_WIDTH, "Waveform must be wider than {} pixels", LABEL_WIDTH);
+    ensure!(row_height >= 8, "Waveform rows must be at least 8 pixels tall");
+
+    let options = &options.region_scaled();
+    let mut emulator = create_emulator(options)?;
+    let frames = match options.stop_condition {
+        StopCondition::Frames(frames) => frames,
+        StopCondition::NsfeLength => emulator.nsfe_duration().map(|d| d as u64).unwrap_or(MAX_WAVEFORM_FRAMES),
+        StopCondition::Loops(_) => MAX_WAVEFORM_FRAMES
+    };
+    let sample_count = (options.emulation_sample_rate() as f64 / emulator.frame_rate()) as usize;
+
+    let mut waveforms: Vec<ChannelWaveform> = Vec::new();
+    for _ in 0..frames.min(MAX_WAVEFORM_FRAMES) {
+        emulator.step();
+        emulator.get_audio_samples(sample_count, 1.0);
+
+        for (i, (chip, channel, playing, samples)) in emulator.channel_samples(sample_count).into_iter().enumerate() {
+            if waveforms.len() <= i {
+                waveforms.push(ChannelWaveform { chip, channel, played: false, frames: Vec::new() });
+            }
+            let min = samples.iter().cloned().fold(1.0, f32::min);
+            let max = samples.iter().cloned().fold(0.0, f32::max);
+            waveforms[i].played |= playing;
+            waveforms[i].frames.push((min, max));
+        }
+
+        if let StopCondition::Loops(stop_loop_count) = options.stop_condition {
+            let song_ended = emulator.get_song_position().map_or(false, |p| p.end);
+            let loops_reached = emulator.loop_count().map_or(false, |c| c >= stop_loop_count);
+            if song_ended || loops_reached {
+                break;
+            }
+        }
+    }
+
+    let waveforms: Vec<(ChannelWaveform, Color)> = waveforms.into_iter()
+        .filter(|w| w.played)
+        .filter_map(|w| {
+            let settings = options.channel_settings.get(&(w.chip.clone(), w.channel.clone()));
+            match settings {
+                Some(settings) if settings.hidden => None,
+                Some(settings) => Some((w, settings.colors.first().cloned().unwrap_or(LABEL_COLOR))),
+                None => Some((w, LABEL_COLOR))
+            }
+        })
+        .collect();
+    ensure!(!waveforms.is_empty(), "No channels played during the track");
+
+    let font = Font::from_raw(include_bytes!("../../external/rusticnes-ui-common/src/assets/8x8_font.png"), 8);
+    let mut strip = SimpleBuffer::new(width, waveforms.len() as u32 * row_height);
+    drawing::rect(&mut strip, 0, 0, strip.width, strip.height, BACKGROUND_COLOR);
+
+    let columns = width - LABEL_WIDTH;
+    for (row, (waveform, color)) in waveforms.iter().enumerate() {
+        let y = row as u32 * row_height;
+        let label: String = format!("{} {}", waveform.chip, waveform.channel).chars()
+            .take((LABEL_WIDTH / font.glyph_width) as usize)
+            .collect();
+        drawing::text(&mut strip, &font, 2, y + (row_height - 8) / 2, &label, LABEL_COLOR);
+
+        // Each column covers the lowest and highest samples of the frames that fall into it
+        let frame_count = waveform.frames.len();
+        for column in 0..columns {
+            let start = column as usize * frame_count / columns as usize;
+            let end = ((column as usize + 1) * frame_count / columns as usize).max(start + 1).min(frame_count);
+            if start >= end {
+                continue;
+            }
+            let (min, max) = waveform.frames[start..end].iter()
+                .fold((1.0f32, 0.0f32), |(min, max), &(lo, hi)| (min.min(lo), max.max(hi)));
+
+            let top = y + ((1.0 - max) * (row_height - 1) as f32) as u32;
+            let bottom = y + ((1.0 - min) * (row_height - 1) as f32) as u32;
+            drawing::rect(&mut strip, LABEL_WIDTH + column, top, 1, bottom.saturating_sub(top) + 1, *color);
+        }
+    }
+
+    RgbaImage::from_raw(strip.width, strip.height, strip.buffer)
+        .context("Waveform has the wrong size")?
+        .save(output_path)
+        .with_context(|| format!("Failed to write waveform: {}", output_path))
+}
@@ -8,7 +8,7 @@ use super::VideoBackground;
 fn spawn_decoding_thread(frames: Arc<Mutex<VecDeque<frame::Video>>>, path: &str, w: u32, h: u32) -> JoinHandle<()> {
     let path = path.to_string();
     thread::spawn(move || {
-        println!("[MTVBG] Decoding thread started");
+        status!("[MTVBG] Decoding thread started");
 
         let mut in_ctx = format::input(&path).unwrap();
         let in_stream = in_ctx
@@ -32,7 +32,7 @@ fn spawn_decoding_thread(frames: Arc<Mutex<VecDeque<frame::Video>>>, path: &str,
             scaling::Flags::FAST_BILINEAR
         ).unwrap();
 
-        println!("[MTVBG] Starting to decode...");
+        status!("[MTVBG] Starting to decode...");
 
         let mut decoded_frame = frame::Video::empty();
         let mut rgba_frame = frame::Video::empty();
@@ -69,7 +69,7 @@ fn spawn_decoding_thread(frames: Arc<Mutex<VecDeque<frame::Video>>>, path: &str,
             }
         }
 
-        println!("[MTVBG] Decoding thread stopping");
+        status!("[MTVBG] Decoding thread stopping");
     })
 }
 
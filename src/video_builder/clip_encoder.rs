@@ -0,0 +1,153 @@
+use anyhow::{Result, Context, bail, ensure};
+use ffmpeg_next::{format, encoder, filter, frame, Dictionary, Packet, Rational};
+use super::vb_unwrap::VideoBuilderUnwrap;
+use super::ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context};
+
+// Short looping animation for sharing, GIF or animated WebP. Frames go through an FFmpeg filter
+// graph that scales them down and, for GIF, builds a palette for each frame so the colors hold up
+// better than with a fixed palette.
+pub struct ClipEncoder {
+    out_ctx: format::context::Output,
+    encoder: encoder::Video,
+    graph: filter::Graph,
+    stream_idx: usize,
+    time_base: Rational,
+    resolution_in: (u32, u32),
+    pts: i64
+}
+
+fn filter_spec(codec_name: &str, resolution_out: (u32, u32)) -> String {
+    let scale = format!("scale={}:{}:flags=lanczos", resolution_out.0, resolution_out.1);
+    match codec_name {
+        "gif" => format!("{},split[a][b];[a]palettegen=stats_mode=single[p];[b][p]paletteuse=new=1", scale),
+        _ => format!("{},format=yuv420p", scale)
+    }
+}
+
+impl ClipEncoder {
+    pub fn new(path: &str, resolution_in: (u32, u32), width: u32, time_base: Rational) -> Result<Self> {
+        let (codec_name, pixel_format) = match path.to_lowercase().rsplit_once('.') {
+            Some((_, "gif")) => ("gif", format::Pixel::PAL8),
+            Some((_, "webp")) => ("libwebp_anim", format::Pixel::YUV420P),
+            _ => bail!("Clip output must be a GIF or WebP file")
+        };
+        ensure!((16..=resolution_in.0).contains(&width), "Clip width must be between 16 and {} pixels", resolution_in.0);
+
+        // Keeps the aspect ratio, rounded to an even height for the WebP chroma planes
+        let height = ((resolution_in.1 as u64 * width as u64 / resolution_in.0 as u64) as u32 & !1).max(2);
+
+        let mut graph = filter::Graph::new();
+        let buffer_args = format!(
+            "video_size={}x{}:pix_fmt=rgba:time_base={}/{}:pixel_aspect=1/1",
+            resolution_in.0, resolution_in.1, time_base.numerator(), time_base.denominator()
+        );
+        graph.add(&filter::find("buffer").context("FFmpeg is missing the buffer filter")?, "in", &buffer_args).vb_unwrap()?;
+        graph.add(&filter::find("buffersink").context("FFmpeg is missing the buffersink filter")?, "out", "").vb_unwrap()?;
+        graph.output("in", 0).vb_unwrap()?
+            .input("out", 0).vb_unwrap()?
+            .parse(&filter_spec(codec_name, (width, height))).vb_unwrap()?;
+        graph.validate().vb_unwrap()?;
+
+        let mut out_ctx = format::output(&path).vb_unwrap()?;
+        let codec = encoder::find_by_name(codec_name)
+            .with_context(|| format!("Unknown codec {}", codec_name))?;
+
+        let mut stream = out_ctx.add_stream(codec).vb_unwrap()?;
+        let mut context = ffmpeg_create_context(codec, stream.parameters())?
+            .encoder()
+            .video()
+            .vb_unwrap()?;
+
+        context.set_format(pixel_format);
+        context.set_width(width);
+        context.set_height(height);
+        context.set_time_base(time_base);
+
+        ffmpeg_copy_codec_params(&mut stream, &context, &codec)?;
+
+        stream.set_time_base(time_base);
+
+        let mut context_options = Dictionary::new();
+        if codec_name == "libwebp_anim" {
+            context_options.set("quality", "80");
+        }
+
+        let encoder = context.open_as_with(codec, context_options).vb_unwrap()?;
+        let stream_idx = stream.index();
+
+        ffmpeg_copy_context_params(&mut stream, encoder.as_ref())?;
+
+        Ok(Self {
+            out_ctx,
+            encoder,
+            graph,
+            stream_idx,
+            time_base,
+            resolution_in,
+            pts: 0
+        })
+    }
+
+    fn encode_filtered_frames(&mut self) -> Result<()> {
+        let mut filtered_frame = frame::Video::empty();
+        while self.graph.get("out").unwrap().sink().frame(&mut filtered_frame).is_ok() {
+            self.encoder.send_frame(&filtered_frame).vb_unwrap()?;
+            self.mux_packets()?;
+        }
+
+        Ok(())
+    }
+
+    fn mux_packets(&mut self) -> Result<()> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            let out_time_base = self.out_ctx.stream(self.stream_idx)
+                .unwrap()
+                .time_base();
+
+            packet.rescale_ts(self.time_base, out_time_base);
+            packet.set_stream(self.stream_idx);
+            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.out_ctx.write_header().vb_unwrap()
+    }
+
+    // Takes an RGBA frame at the input resolution. Transparent areas end up black, the same as
+    // in a video rendered without a background.
+    pub fn push_frame(&mut self, rgba: &[u8]) -> Result<()> {
+        let (w, h) = self.resolution_in;
+        ensure!(rgba.len() == (w * h * 4) as usize, "Improperly sized clip frame");
+
+        let mut input_frame = frame::Video::new(format::Pixel::RGBA, w, h);
+        let stride = input_frame.stride(0);
+        for (in_line, out_line) in rgba.chunks_exact(w as usize * 4).zip(input_frame.data_mut(0).chunks_exact_mut(stride)) {
+            for (in_pixel, out_pixel) in in_line.chunks_exact(4).zip(out_line.chunks_exact_mut(4)) {
+                let a = in_pixel[3] as u32;
+                for (out_v, in_v) in out_pixel[..3].iter_mut().zip(in_pixel[..3].iter()) {
+                    *out_v = (*in_v as u32 * a / 255) as u8;
+                }
+                out_pixel[3] = 255;
+            }
+        }
+        input_frame.set_pts(Some(self.pts));
+        self.pts += 1;
+
+        self.graph.get("in").unwrap().source().add(&input_frame).vb_unwrap()?;
+        self.encode_filtered_frames()
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.graph.get("in").unwrap().source().flush().vb_unwrap()?;
+        self.encode_filtered_frames()?;
+
+        self.encoder.send_eof().vb_unwrap()?;
+        self.mux_packets()?;
+
+        self.out_ctx.write_trailer().vb_unwrap()
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Result, Context};
+use ffmpeg_next::{format, media};
+use ffmpeg_next::format::stream::Disposition;
+use ffmpeg_next::Rescale;
+use super::ffmpeg_hacks::{ffmpeg_copy_stream, ffmpeg_get_initial_padding};
+use super::vb_unwrap::VideoBuilderUnwrap;
+
+// Joins files written with the same options into one without re-encoding. Streams, attachments
+// and cover art come from the first segment, the later ones only add their audio and video,
+// shifted to start where the previous segment ended.
+//
+// Every segment has its own audio encoder, so the joins aren't gapless: the encoder priming
+// (initial_padding) at the start of each later segment is dropped, and its audio is lined up
+// with the video so the padding can't add up to drift, but the frames either side of a join
+// are still decoded without each other and can click.
+pub fn concat_segments(segment_paths: &[String], output_path: &str) -> Result<()> {
+    let first_segment = format::input(&segment_paths[0])
+        .with_context(|| format!("Failed to open segment: {}", segment_paths[0]))?;
+    let mut out_ctx = format::output(&output_path).vb_unwrap()?;
+    out_ctx.set_metadata(first_segment.metadata().to_owned());
+
+    let mut stream_mapping = Vec::new();
+    for stream in first_segment.streams() {
+        stream_mapping.push(ffmpeg_copy_stream(&mut out_ctx, &stream)?);
+    }
+    drop(first_segment);
+    out_ctx.write_header().vb_unwrap()?;
+
+    // Where the previous segments ended in each output stream's time base
+    let mut stream_offsets = vec![0i64; stream_mapping.len()];
+    for (segment_index, segment_path) in segment_paths.iter().enumerate() {
+        let mut in_ctx = format::input(segment_path)
+            .with_context(|| format!("Failed to open segment: {}", segment_path))?;
+        let mut stream_ends = stream_offsets.clone();
+        // Where each stream's priming ends in this segment, once its first packet is seen
+        let mut priming_ends: Vec<Option<i64>> = vec![None; stream_mapping.len()];
+
+        for (stream, mut packet) in in_ctx.packets() {
+            let index = stream.index();
+            let out_index = *stream_mapping.get(index)
+                .with_context(|| format!("Segment {} has more streams than the first one", segment_path))?;
+            let timed = matches!(stream.parameters().medium(), media::Type::Video | media::Type::Audio)
+                && !stream.disposition().contains(Disposition::ATTACHED_PIC);
+            if segment_index > 0 && !timed {
+                continue;
+            }
+
+            let audio = stream.parameters().medium() == media::Type::Audio;
+            if segment_index > 0 && audio {
+                let priming_end = *priming_ends[index].get_or_insert_with(|| {
+                    packet.pts().unwrap_or(0) + ffmpeg_get_initial_padding(&stream)
+                });
+                if packet.pts().map_or(false, |pts| pts + packet.duration() <= priming_end) {
+                    continue;
+                }
+                packet.set_pts(packet.pts().map(|pts| pts - priming_end));
+                packet.set_dts(packet.dts().map(|dts| dts - priming_end));
+            }
+
+            let out_time_base = out_ctx.stream(out_index).unwrap().time_base();
+            packet.rescale_ts(stream.time_base(), out_time_base);
+            if timed {
+                let offset = stream_offsets[index];
+                packet.set_pts(packet.pts().map(|pts| pts + offset));
+                packet.set_dts(packet.dts().map(|dts| dts + offset));
+                if let Some(pts) = packet.pts() {
+                    stream_ends[index] = stream_ends[index].max(pts + packet.duration());
+                }
+            }
+            packet.set_position(-1);
+            packet.set_stream(out_index);
+            packet.write_interleaved(&mut out_ctx).vb_unwrap()?;
+        }
+
+        // The next segment's audio starts where its video does, if there is any
+        let video_end = in_ctx.streams()
+            .find(|s| s.parameters().medium() == media::Type::Video && !s.disposition().contains(Disposition::ATTACHED_PIC))
+            .map(|s| (stream_ends[s.index()], out_ctx.stream(stream_mapping[s.index()]).unwrap().time_base()));
+        if let Some((video_end, video_time_base)) = video_end {
+            for stream in in_ctx.streams().filter(|s| s.parameters().medium() == media::Type::Audio) {
+                let out_time_base = out_ctx.stream(stream_mapping[stream.index()]).unwrap().time_base();
+                stream_ends[stream.index()] = video_end.rescale(video_time_base, out_time_base);
+            }
+        }
+        stream_offsets = stream_ends;
+    }
+
+    out_ctx.write_trailer().vb_unwrap()?;
+
+    Ok(())
+}
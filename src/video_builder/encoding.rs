@@ -1,7 +1,7 @@
 use anyhow::{Result, ensure};
 use std::iter::zip;
-use std::time::Duration;
-use ffmpeg_next::{Dictionary, frame, Packet};
+use std::time::{Duration, Instant};
+use ffmpeg_next::{Dictionary, frame, packet, Packet, Rational, Rescale};
 use crate::video_builder::ffmpeg_hacks::ffmpeg_context_bytes_written;
 use super::vb_unwrap::VideoBuilderUnwrap;
 use super::VideoBuilder;
@@ -51,6 +51,15 @@ fn fast_background_blit(fg: &mut frame::Video, bg: &frame::Video) {
     }
 }
 
+// Writes slower than this are counted as the muxer blocking the render
+const MUX_STALL_THRESHOLD: Duration = Duration::from_millis(250);
+
+fn offset_packet(packet: &mut Packet, offset_ms: i64, time_base: Rational) {
+    let offset = offset_ms.rescale((1, 1000), time_base);
+    packet.set_pts(packet.pts().map(|pts| pts + offset));
+    packet.set_dts(packet.dts().map(|dts| dts + offset));
+}
+
 impl VideoBuilder {
     fn push_video_data_no_bg(&mut self, video: &[u8]) -> Result<()> {
         let mut input_frame = frame::Video::new(self.v_swc_ctx.input().format, self.v_swc_ctx.input().width, self.v_swc_ctx.input().height);
@@ -63,6 +72,7 @@ impl VideoBuilder {
         self.v_sws_ctx.run(&resize_frame, &mut output_frame).vb_unwrap()?;
 
         self.v_frame_buf.push_back(output_frame);
+        self.v_queue_peak = self.v_queue_peak.max(self.v_frame_buf.len());
 
         Ok(())
     }
@@ -81,19 +91,108 @@ impl VideoBuilder {
         self.v_swc_ctx.run(&resize_frame, &mut output_frame).vb_unwrap()?;
 
         self.v_frame_buf.push_back(output_frame);
+        self.v_queue_peak = self.v_queue_peak.max(self.v_frame_buf.len());
 
         Ok(())
     }
 
     pub fn push_video_data(&mut self, video: &[u8]) -> Result<()> {
-        if self.options.background_path.is_some() {
+        if self.v_encoder.is_none() {
+            // Audio-only output, the video is discarded
+            return Ok(());
+        }
+
+        if self.background.is_some() {
             self.push_video_data_bg(video)
         } else {
             self.push_video_data_no_bg(video)
         }
     }
 
+    // Takes any number of samples, only whole frames are encoded until the audio is flushed
     pub fn push_audio_data(&mut self, audio: &[u8]) -> Result<()> {
+        match self.a_resampler.is_some() {
+            true => self.resample_audio(audio)?,
+            false => self.a_sample_buf.extend_from_slice(audio)
+        }
+        self.push_whole_audio_frames()
+    }
+
+    fn push_whole_audio_frames(&mut self) -> Result<()> {
+        let frame_bytes = self.a_frame_size * self.a_swr_ctx.input().channel_layout.channels() as usize * self.a_swr_ctx.input().format.bytes();
+        while self.a_sample_buf.len() >= frame_bytes {
+            let frame: Vec<u8> = self.a_sample_buf.drain(..frame_bytes).collect();
+            self.push_audio_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+
+    // Pushes the partial frame left over at the end of the audio, encoders only take one of those
+    fn flush_audio_data(&mut self) -> Result<()> {
+        if self.a_sample_buf.is_empty() {
+            return Ok(());
+        }
+
+        let frame: Vec<u8> = self.a_sample_buf.drain(..).collect();
+        self.push_audio_frame(&frame)
+    }
+
+    // Resampled audio comes out in uneven chunks, so it's collected until there's a whole frame
+    fn resample_audio(&mut self, audio: &[u8]) -> Result<()> {
+        let resampler = self.a_resampler.as_mut().unwrap();
+        let bytes_per_sample = resampler.input().channel_layout.channels() as usize * resampler.input().format.bytes();
+        let samples = audio.len() / bytes_per_sample;
+
+        let mut input_frame = frame::Audio::new(resampler.input().format, samples, resampler.input().channel_layout);
+        input_frame.set_rate(resampler.input().rate);
+        input_frame.data_mut(0)[..audio.len()].copy_from_slice(audio);
+
+        // Leaves room for the samples the resampler held back from the last call
+        let capacity = samples * resampler.output().rate as usize / resampler.input().rate as usize + 256;
+        let mut output_frame = frame::Audio::new(resampler.output().format, capacity, resampler.output().channel_layout);
+        output_frame.set_rate(resampler.output().rate);
+        resampler.run(&input_frame, &mut output_frame).vb_unwrap()?;
+
+        let output_bytes = output_frame.samples() * bytes_per_sample;
+        self.a_sample_buf.extend_from_slice(&output_frame.data(0)[..output_bytes]);
+
+        Ok(())
+    }
+
+    // Drains the samples still in the resampler's filter at the end of the audio
+    fn flush_resampler(&mut self) -> Result<()> {
+        let resampler = match self.a_resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(())
+        };
+        let bytes_per_sample = resampler.output().channel_layout.channels() as usize * resampler.output().format.bytes();
+
+        loop {
+            let mut output_frame = frame::Audio::new(resampler.output().format, 1024, resampler.output().channel_layout);
+            output_frame.set_rate(resampler.output().rate);
+            let delay = resampler.flush(&mut output_frame).vb_unwrap()?;
+
+            let output_bytes = output_frame.samples() * bytes_per_sample;
+            self.a_sample_buf.extend_from_slice(&output_frame.data(0)[..output_bytes]);
+            if delay.is_none() || output_frame.samples() == 0 {
+                break;
+            }
+        }
+
+        self.push_whole_audio_frames()
+    }
+
+    fn push_audio_frame(&mut self, audio: &[u8]) -> Result<()> {
+        if let Some(sidecar_audio) = self.sidecar_audio.as_mut() {
+            sidecar_audio.push_audio_data(audio)?;
+        }
+
+        if self.a_encoder.is_none() {
+            // Video-only output, the audio is discarded
+            return Ok(());
+        }
+
         let bytes_per_sample = self.a_swr_ctx.input().channel_layout.channels() as usize * self.a_swr_ctx.input().format.bytes();
         let samples = audio.len() / bytes_per_sample;
 
@@ -106,14 +205,29 @@ impl VideoBuilder {
         self.a_swr_ctx.run(&input_frame, &mut output_frame).vb_unwrap()?;
 
         self.a_frame_buf.push_back(output_frame);
+        self.a_queue_peak = self.a_queue_peak.max(self.a_frame_buf.len());
+
+        Ok(())
+    }
+
+    fn write_packet_timed(&mut self, packet: &mut Packet) -> Result<()> {
+        let write_start = Instant::now();
+        packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+
+        let write_time = write_start.elapsed();
+        if write_time > MUX_STALL_THRESHOLD {
+            self.mux_stalls += 1;
+            self.mux_stall_time += write_time;
+        }
+        self.longest_mux_time = self.longest_mux_time.max(write_time);
 
         Ok(())
     }
 
     fn send_video_to_encoder(&mut self) -> Result<()> {
-        if let Some(mut frame) = self.v_frame_buf.pop_front() {
+        if let (Some(v_encoder), Some(mut frame)) = (self.v_encoder.as_mut(), self.v_frame_buf.pop_front()) {
             frame.set_pts(Some(self.v_pts));
-            self.v_encoder.send_frame(&frame).vb_unwrap()?;
+            v_encoder.send_frame(&frame).vb_unwrap()?;
 
             self.v_pts += 1;
         }
@@ -122,14 +236,23 @@ impl VideoBuilder {
     }
 
     fn mux_video_frame(&mut self, packet: &mut Packet) -> Result<bool> {
-        if self.v_encoder.receive_packet(packet).is_ok() {
+        let received = match self.v_encoder.as_mut() {
+            Some(v_encoder) => v_encoder.receive_packet(packet).is_ok(),
+            None => false
+        };
+
+        if received {
             let out_time_base = self.out_ctx.stream(self.v_stream_idx)
                 .unwrap()
                 .time_base();
 
             packet.rescale_ts(self.options.video_time_base, out_time_base);
+            if self.options.av_offset_ms < 0 && self.a_encoder.is_some() {
+                // Delay the video instead of advancing the audio to keep timestamps positive
+                offset_packet(packet, -self.options.av_offset_ms, out_time_base);
+            }
             packet.set_stream(self.v_stream_idx);
-            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+            self.write_packet_timed(packet)?;
 
             self.v_pts_muxed += 1;
 
@@ -140,25 +263,33 @@ impl VideoBuilder {
     }
 
     fn send_audio_to_encoder(&mut self) -> Result<()> {
-        if let Some(mut frame) = self.a_frame_buf.pop_front() {
+        if let (Some(a_encoder), Some(mut frame)) = (self.a_encoder.as_mut(), self.a_frame_buf.pop_front()) {
             frame.set_pts(Some(self.a_pts));
-            self.a_encoder.send_frame(&frame).vb_unwrap()?;
+            a_encoder.send_frame(&frame).vb_unwrap()?;
 
-            self.a_pts += self.a_encoder.frame_size() as i64;
+            self.a_pts += a_encoder.frame_size() as i64;
         }
 
         Ok(())
     }
 
     fn mux_audio_frame(&mut self, packet: &mut Packet) -> Result<bool> {
-        if self.a_encoder.receive_packet(packet).is_ok() {
+        let received = match self.a_encoder.as_mut() {
+            Some(a_encoder) => a_encoder.receive_packet(packet).is_ok(),
+            None => false
+        };
+
+        if received {
             let out_time_base = self.out_ctx.stream(self.a_stream_idx)
                 .unwrap()
                 .time_base();
 
             packet.rescale_ts(self.options.audio_time_base, out_time_base);
+            if self.options.av_offset_ms > 0 {
+                offset_packet(packet, self.options.av_offset_ms, out_time_base);
+            }
             packet.set_stream(self.a_stream_idx);
-            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+            self.write_packet_timed(packet)?;
 
             self.a_pts_muxed += 1;
 
@@ -170,7 +301,7 @@ impl VideoBuilder {
 
     pub fn start_encoding(&mut self) -> Result<()> {
         let mut opts = Dictionary::new();
-        println!("{}", self.out_ctx.format().name());
+        status!("{}", self.out_ctx.format().name());
         match self.out_ctx.format().name() {
             "mp4" => opts.set("movflags", "faststart"),
             _ => ()
@@ -178,6 +309,17 @@ impl VideoBuilder {
 
         self.out_ctx.write_header_with(opts).vb_unwrap()?;
 
+        if let Some((stream_idx, data)) = &self.cover_art {
+            let mut packet = Packet::copy(data);
+            packet.set_stream(*stream_idx);
+            packet.set_flags(packet::Flags::KEY);
+            packet.write(&mut self.out_ctx).vb_unwrap()?;
+        }
+
+        if let Some(sidecar_audio) = self.sidecar_audio.as_mut() {
+            sidecar_audio.start()?;
+        }
+
         Ok(())
     }
 
@@ -185,7 +327,9 @@ impl VideoBuilder {
         let mut packet = Packet::empty();
 
         loop {
-            if self.a_pts_muxed <= self.v_pts_muxed && !self.a_frame_buf.is_empty() {
+            // Without a video stream there is nothing to interleave the audio with
+            let audio_turn = self.v_encoder.is_none() || self.a_pts_muxed <= self.v_pts_muxed;
+            if audio_turn && !self.a_frame_buf.is_empty() {
                 self.send_audio_to_encoder()?;
                 if !(self.mux_audio_frame(&mut packet)?) {
                     break;
@@ -204,8 +348,16 @@ impl VideoBuilder {
     }
 
     pub fn finish_encoding(&mut self) -> Result<()> {
-        self.v_encoder.send_eof().vb_unwrap()?;
-        self.a_encoder.send_eof().vb_unwrap()?;
+        self.flush_resampler()?;
+        self.flush_audio_data()?;
+        self.step_encoding()?;
+
+        if let Some(v_encoder) = self.v_encoder.as_mut() {
+            v_encoder.send_eof().vb_unwrap()?;
+        }
+        if let Some(a_encoder) = self.a_encoder.as_mut() {
+            a_encoder.send_eof().vb_unwrap()?;
+        }
 
         let mut packet = Packet::empty();
         loop {
@@ -219,19 +371,53 @@ impl VideoBuilder {
 
         self.out_ctx.write_trailer().vb_unwrap()?;
 
+        if let Some(sidecar_audio) = self.sidecar_audio.as_mut() {
+            sidecar_audio.finish()?;
+        }
+
         Ok(())
     }
 
+    // Samples to push at a time. Oversampled audio takes more than one encoded frame's worth.
     pub fn audio_frame_size(&self) -> usize {
-        self.a_frame_size
+        match self.options.resample_from {
+            Some((rate_in, _)) => self.a_frame_size * rate_in as usize / self.options.sample_rate as usize,
+            None => self.a_frame_size
+        }
     }
 
     pub fn encoded_video_duration(&self) -> Duration {
-        let time_base_fraction = self.options.video_time_base.numerator() as f64 / self.options.video_time_base.denominator() as f64;
-        let seconds = time_base_fraction * self.v_pts as f64;
+        let (time_base, pts) = match self.v_encoder {
+            Some(_) => (self.options.video_time_base, self.v_pts),
+            None => (self.options.audio_time_base, self.a_pts)
+        };
+        let time_base_fraction = time_base.numerator() as f64 / time_base.denominator() as f64;
+        let seconds = time_base_fraction * pts as f64;
         Duration::from_secs_f64(seconds)
     }
 
+    // The most frames that have waited for the encoder at once, since the queues are usually
+    // drained again by the time progress is shown
+    pub fn video_queue_peak(&self) -> usize {
+        self.v_queue_peak
+    }
+
+    pub fn audio_queue_peak(&self) -> usize {
+        self.a_queue_peak
+    }
+
+    pub fn mux_stalls(&self) -> usize {
+        self.mux_stalls
+    }
+
+    pub fn mux_stall_time(&self) -> Duration {
+        self.mux_stall_time
+    }
+
+    pub fn longest_mux_time(&self) -> Duration {
+        self.longest_mux_time
+    }
+
     pub fn encoded_video_size(&self) -> usize {
         ffmpeg_context_bytes_written(&self.out_ctx)
     }
@@ -1,7 +1,9 @@
 use anyhow::{Result, anyhow};
-use std::ffi::{CStr, CString};
-use ffmpeg_next::{codec, Codec, Error, format, StreamMut};
-use ffmpeg_sys_next::{av_get_sample_fmt, avcodec_alloc_context3, avcodec_parameters_from_context, avcodec_parameters_to_context, av_version_info};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::{mem, ptr};
+use std::sync::Mutex;
+use ffmpeg_next::{codec, Codec, Error, format, Rescale, software, Stream, StreamMut};
+use ffmpeg_sys_next::{av_dict_copy, av_dict_set, av_get_sample_fmt, av_mallocz, av_opt_set, swr_init, avcodec_alloc_context3, avcodec_parameters_copy, avcodec_parameters_from_context, avcodec_parameters_to_context, av_version_info, avformat_new_stream, av_log_set_callback, av_log_format_line2, av_log_get_level, va_list, AVMediaType, AV_DISPOSITION_ATTACHED_PIC, AV_INPUT_BUFFER_PADDING_SIZE};
 
 pub fn ffmpeg_version() -> &'static str {
     // ffmpeg-next does not provide a way to get the FFmpeg version number. It does provide the
@@ -13,6 +15,52 @@ pub fn ffmpeg_version() -> &'static str {
     }
 }
 
+// FFmpeg's own messages, collected for the render log while a render thread is capturing them
+static FFMPEG_LOG: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+unsafe extern "C" fn ffmpeg_log_callback(ptr: *mut c_void, level: c_int, fmt: *const c_char, vl: va_list) {
+    if level > av_log_get_level() {
+        return;
+    }
+
+    // Safety: av_log_format_line2() writes at most line_size bytes, including the terminating
+    //         null, so the line is always a valid C string within the buffer.
+    let mut line = [0 as c_char; 1024];
+    let mut print_prefix: c_int = 1;
+    av_log_format_line2(ptr, level, fmt, vl, line.as_mut_ptr(), line.len() as c_int, &mut print_prefix);
+    let line = CStr::from_ptr(line.as_ptr()).to_string_lossy();
+    let line = line.trim_end();
+    if line.is_empty() {
+        return;
+    }
+
+    // Panicking here would unwind into FFmpeg, so a poisoned lock just drops the message
+    if let Ok(mut log) = FFMPEG_LOG.lock() {
+        match log.as_mut() {
+            Some(log) => log.push(line.to_string()),
+            None => eprintln!("{}", line)
+        }
+    }
+}
+
+pub fn ffmpeg_install_log_callback() {
+    // ffmpeg-next only exposes the log level, not the callback, so messages can't be redirected
+    // without going through the C API.
+    // Safety: The callback only formats the message into a local buffer and never unwinds.
+    unsafe {
+        av_log_set_callback(Some(ffmpeg_log_callback));
+    }
+}
+
+// Holds FFmpeg's messages for ffmpeg_take_log() instead of printing them to stderr
+pub fn ffmpeg_capture_log() {
+    FFMPEG_LOG.lock().unwrap().get_or_insert_with(Vec::new);
+}
+
+pub fn ffmpeg_take_log() -> Vec<String> {
+    FFMPEG_LOG.lock().unwrap().as_mut().map(mem::take).unwrap_or_default()
+}
+
 pub fn ffmpeg_create_context(codec: Codec, parameters: codec::Parameters) -> Result<codec::Context> {
     // ffmpeg-next does not provide a way to pass a codec to avcodec_alloc_context3, which
     // is necessary for initializing certain contexts (e.g. mp4/libx264).
@@ -62,6 +110,101 @@ pub fn ffmpeg_copy_codec_params(stream: &mut StreamMut, context: &codec::Context
     Ok(())
 }
 
+pub fn ffmpeg_add_attached_pic_stream(out_ctx: &mut format::context::Output, codec_id: codec::Id, width: u32, height: u32) -> Result<usize> {
+    // Cover art is stored as a stream with the attached picture disposition, which ffmpeg-next
+    // does not provide a way to set.
+    // Safety: The return value of avformat_new_stream() is checked to ensure that the allocation
+    //         succeeded. The stream is owned and later freed by the output context.
+    // Safety: All mutable pointer dereferences are done strictly on initialized memory since they
+    //         come from a freshly allocated stream.
+    unsafe {
+        let stream = avformat_new_stream(out_ctx.as_mut_ptr(), ptr::null());
+        if stream.is_null() {
+            return Err(anyhow!("FFMPEG error: avformat_new_stream() failed"));
+        }
+
+        (*stream).disposition = AV_DISPOSITION_ATTACHED_PIC as _;
+        (*(*stream).codecpar).codec_type = AVMediaType::AVMEDIA_TYPE_VIDEO;
+        (*(*stream).codecpar).codec_id = codec_id.into();
+        (*(*stream).codecpar).width = width as _;
+        (*(*stream).codecpar).height = height as _;
+
+        Ok((*stream).index as usize)
+    }
+}
+
+pub fn ffmpeg_add_attachment_stream(out_ctx: &mut format::context::Output, filename: &str, mimetype: &str, data: &[u8]) -> Result<usize> {
+    // Matroska attachments are streams whose extradata holds the file, which ffmpeg-next does not
+    // provide a way to create. The muxer requires the filename and mimetype tags.
+    // Safety: The return values of avformat_new_stream() and av_mallocz() are checked to ensure that
+    //         the allocations succeeded. Both are owned and later freed by the output context.
+    // Safety: The extradata buffer is allocated with the padding FFmpeg expects, and data is copied
+    //         within the bounds of both buffers.
+    unsafe {
+        let stream = avformat_new_stream(out_ctx.as_mut_ptr(), ptr::null());
+        if stream.is_null() {
+            return Err(anyhow!("FFMPEG error: avformat_new_stream() failed"));
+        }
+
+        let extradata = av_mallocz(data.len() + AV_INPUT_BUFFER_PADDING_SIZE as usize) as *mut u8;
+        if extradata.is_null() {
+            return Err(anyhow!("FFMPEG error: av_mallocz() failed"));
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), extradata, data.len());
+        (*(*stream).codecpar).codec_type = AVMediaType::AVMEDIA_TYPE_ATTACHMENT;
+        (*(*stream).codecpar).extradata = extradata;
+        (*(*stream).codecpar).extradata_size = data.len() as _;
+
+        for (key, value) in [("filename", filename), ("mimetype", mimetype)] {
+            let key = CString::new(key).unwrap();
+            let value = CString::new(value)?;
+            av_dict_set(&mut (*stream).metadata, key.as_ptr(), value.as_ptr(), 0);
+        }
+
+        Ok((*stream).index as usize)
+    }
+}
+
+pub fn ffmpeg_copy_stream(out_ctx: &mut format::context::Output, stream: &Stream) -> Result<usize> {
+    // Remuxing needs the disposition and tags copied along with the codec parameters, which
+    // ffmpeg-next does not provide a way to do. Attachments and cover art depend on both.
+    // Safety: The return value of avformat_new_stream() is checked to ensure that the allocation
+    //         succeeded. The stream is owned and later freed by the output context.
+    // Safety: The value of avcodec_parameters_copy is checked to ensure errors are handled.
+    // Safety: The input stream pointer comes from a reference to a safe abstraction, so it is
+    //         valid for the duration of this call.
+    unsafe {
+        let out_stream = avformat_new_stream(out_ctx.as_mut_ptr(), ptr::null());
+        if out_stream.is_null() {
+            return Err(anyhow!("FFMPEG error: avformat_new_stream() failed"));
+        }
+
+        match avcodec_parameters_copy((*out_stream).codecpar, (*stream.as_ptr()).codecpar) {
+            0 => (),
+            e => return Err(anyhow!(Error::from(e)))
+        }
+        // The tag is specific to the input's container, so let the muxer pick its own
+        (*(*out_stream).codecpar).codec_tag = 0;
+        (*out_stream).time_base = (*stream.as_ptr()).time_base;
+        (*out_stream).disposition = (*stream.as_ptr()).disposition;
+        av_dict_copy(&mut (*out_stream).metadata, (*stream.as_ptr()).metadata, 0);
+
+        Ok((*out_stream).index as usize)
+    }
+}
+
+pub fn ffmpeg_get_initial_padding(stream: &Stream) -> i64 {
+    // ffmpeg-next does not expose the encoder delay of an audio stream, in the stream's time base
+    let (initial_padding, sample_rate) = unsafe {
+        let codecpar = (*stream.as_ptr()).codecpar;
+        ((*codecpar).initial_padding as i64, (*codecpar).sample_rate)
+    };
+    match sample_rate {
+        0 => 0,
+        sample_rate => initial_padding.rescale((1, sample_rate), stream.time_base())
+    }
+}
+
 pub fn ffmpeg_sample_format_from_string(value: &str) -> format::Sample {
     // This is provided by ffmpeg-next, but only for `&'static str`, presumably due to
     // some confusion over the `const char*` in the method signature?
@@ -72,6 +215,28 @@ pub fn ffmpeg_sample_format_from_string(value: &str) -> format::Sample {
     }
 }
 
+pub fn ffmpeg_set_resampler_options(context: &mut software::resampling::Context, options: &[(&str, &str)]) -> Result<()> {
+    // ffmpeg-next sets up and initializes the resampler in one go without a way to pass options,
+    // so they are set afterwards and the resampler is initialized again.
+    // Safety: The context pointer comes from a mutable reference to a safe abstraction, and the
+    //         return values of av_opt_set() and swr_init() are checked to ensure errors are handled.
+    unsafe {
+        let context = context.as_mut_ptr();
+        for (key, value) in options {
+            let key = CString::new(*key).unwrap();
+            let value = CString::new(*value).unwrap();
+            match av_opt_set(context as *mut c_void, key.as_ptr(), value.as_ptr(), 0) {
+                0 => (),
+                e => return Err(anyhow!(Error::from(e)))
+            }
+        }
+        match swr_init(context) {
+            0 => Ok(()),
+            e => Err(anyhow!(Error::from(e)))
+        }
+    }
+}
+
 pub fn ffmpeg_get_audio_context_frame_size(context: &codec::Context, variable_frame_size: usize) -> usize {
     let frame_size = unsafe { (*context.as_ptr()).frame_size as usize };
     let ctx_codec = context.codec().unwrap();
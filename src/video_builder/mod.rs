@@ -2,21 +2,31 @@ pub mod video_options;
 mod vb_unwrap;
 mod ffmpeg_hacks;
 mod encoding;
+mod sidecar_audio;
+mod concat;
+mod clip_encoder;
 pub mod backgrounds;
 
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, bail};
 use std::collections::VecDeque;
-use std::{mem, slice};
+use std::{fs, mem, slice};
 use std::str::FromStr;
-use ffmpeg_next::{self, format, encoder, codec, ChannelLayout, Dictionary, software, frame};
+use std::time::Duration;
+use image::GenericImage;
+use ffmpeg_next::{self, format, encoder, codec, Codec, ChannelLayout, Dictionary, software, frame};
 use video_options::VideoOptions;
 use vb_unwrap::VideoBuilderUnwrap;
 use backgrounds::{get_video_background, VideoBackground};
-use ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_sample_format_from_string, ffmpeg_get_audio_context_frame_size};
-pub use ffmpeg_hacks::ffmpeg_version;
+use sidecar_audio::SidecarAudio;
+use ffmpeg_hacks::{ffmpeg_install_log_callback, ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_sample_format_from_string, ffmpeg_get_audio_context_frame_size, ffmpeg_set_resampler_options, ffmpeg_add_attached_pic_stream, ffmpeg_add_attachment_stream};
+pub use ffmpeg_hacks::{ffmpeg_version, ffmpeg_capture_log, ffmpeg_take_log};
+pub use concat::concat_segments;
+pub use clip_encoder::ClipEncoder;
 
 pub fn init() -> Result<()> {
-    ffmpeg_next::init().context("Initializing FFmpeg")
+    ffmpeg_next::init().context("Initializing FFmpeg")?;
+    ffmpeg_install_log_callback();
+    Ok(())
 }
 
 pub fn as_u8_slice<T: Sized>(s: &[T]) -> &[u8] {
@@ -35,25 +45,55 @@ pub struct VideoBuilder {
 
     out_ctx: format::context::Output,
 
-    v_encoder: encoder::Video,
+    v_encoder: Option<encoder::Video>,
     v_swc_ctx: software::scaling::Context,
     v_sws_ctx: software::scaling::Context,
     v_frame_buf: VecDeque<frame::Video>,
+    v_queue_peak: usize,
     v_stream_idx: usize,
     v_pts: i64,
     v_pts_muxed: i64,
 
-    a_encoder: encoder::Audio,
+    a_encoder: Option<encoder::Audio>,
     a_swr_ctx: software::resampling::Context,
+    // Brings oversampled audio down to the output rate before the format conversion
+    a_resampler: Option<software::resampling::Context>,
+    // Audio waiting for a whole encoder frame's worth of samples
+    a_sample_buf: Vec<u8>,
     a_frame_buf: VecDeque<frame::Audio>,
+    a_queue_peak: usize,
     a_stream_idx: usize,
     a_frame_size: usize,
     a_pts: i64,
-    a_pts_muxed: i64
+    a_pts_muxed: i64,
+
+    cover_art: Option<(usize, Vec<u8>)>,
+    sidecar_audio: Option<SidecarAudio>,
+
+    mux_stalls: usize,
+    mux_stall_time: Duration,
+    longest_mux_time: Duration
+}
+
+// Encoders that need a GPU or OS support, which isn't there on every machine a preset is used on
+const HARDWARE_ENCODER_SUFFIXES: [&str; 7] = ["_nvenc", "_qsv", "_vaapi", "_amf", "_videotoolbox", "_mf", "_v4l2m2m"];
+
+// Takes the hardware encoder's name without its suffix, e.g. h264 for h264_nvenc
+fn software_encoder(family: &str) -> Option<&'static str> {
+    match family {
+        "h264" => Some("libx264"),
+        "hevc" | "h265" => Some("libx265"),
+        "av1" => Some("libsvtav1"),
+        "vp9" => Some("libvpx-vp9"),
+        "vp8" => Some("libvpx"),
+        "mjpeg" => Some("mjpeg"),
+        _ => None
+    }
 }
 
 impl VideoBuilder {
-    pub fn new(options: VideoOptions) -> Result<Self> {
+    pub fn new(mut options: VideoOptions) -> Result<Self> {
+        options.apply_output_format()?;
         let mut out_ctx = format::output(&options.output_path).vb_unwrap()?;
 
         let mut metadata = Dictionary::new();
@@ -71,13 +111,14 @@ impl VideoBuilder {
         let scaling_flags = if aspect_in == aspect_out {
             software::scaling::Flags::POINT
         } else {
-            println!("Warning: input and output aspect do not match. Falling back to bilinear scaling");
+            status!("Warning: input and output aspect do not match. Falling back to bilinear scaling");
             software::scaling::Flags::FAST_BILINEAR
         };
 
-        let background = match &options.background_path {
-            Some(p) => get_video_background(p, options.resolution_out.0, options.resolution_out.1),
-            None => None
+        // Fall back to the cover art if no background was chosen
+        let background = match options.background_path.as_ref().or(options.cover_art_path.as_ref()) {
+            Some(p) if !options.audio_only && !options.transparent => get_video_background(p, options.resolution_out.0, options.resolution_out.1),
+            _ => None
         };
         let v_swc_ctx: software::scaling::Context;
         let v_sws_ctx: software::scaling::Context;
@@ -123,9 +164,57 @@ impl VideoBuilder {
             options.sample_rate as u32
         );
         let a_swr_ctx = software::resampler(swr_in, swr_out).vb_unwrap()?;
+        let a_resampler = match options.resample_from {
+            Some((rate_in, resampler)) => {
+                let resample_in = (swr_in.0, channel_layout, rate_in as u32);
+                let mut context = software::resampler(resample_in, swr_in).vb_unwrap()?;
+                if let Err(e) = ffmpeg_set_resampler_options(&mut context, resampler.swr_options()) {
+                    let fallback_options = resampler.fallback_swr_options()
+                        .ok_or(e)
+                        .with_context(|| format!("Failed to set up the {} resampler", resampler))?;
+                    status!("Warning: the {} resampler isn't available in this FFmpeg build, using FFmpeg's own filter instead", resampler);
+                    context = software::resampler(resample_in, swr_in).vb_unwrap()?;
+                    ffmpeg_set_resampler_options(&mut context, fallback_options)
+                        .with_context(|| format!("Failed to set up the {} resampler", resampler))?;
+                }
+                Some(context)
+            },
+            None => None
+        };
 
-        let (v_encoder, v_stream_idx) = Self::create_video_encoder(options.clone(), &mut out_ctx)?;
-        let (a_encoder, a_stream_idx, a_frame_size) = Self::create_audio_encoder(options.clone(), &mut out_ctx)?;
+        let (v_encoder, v_stream_idx) = match options.audio_only {
+            true => (None, 0),
+            false => {
+                let (v_encoder, v_stream_idx) = Self::create_video_encoder(options.clone(), &mut out_ctx)?;
+                (Some(v_encoder), v_stream_idx)
+            }
+        };
+        let (a_encoder, a_stream_idx, a_frame_size) = match options.video_only {
+            true => (None, 0, 1024),
+            false => {
+                let (a_encoder, a_stream_idx, a_frame_size) = Self::create_audio_encoder(options.clone(), &mut out_ctx)?;
+                (Some(a_encoder), a_stream_idx, a_frame_size)
+            }
+        };
+        let cover_art = match &options.cover_art_path {
+            Some(p) => Some(Self::create_cover_art_stream(p, &mut out_ctx)?),
+            None => None
+        };
+        if !options.attachments.is_empty() {
+            // Attachments are written with the header, so they don't need to be kept around
+            if out_ctx.format().name() == "matroska" {
+                for attachment in options.attachments.iter() {
+                    ffmpeg_add_attachment_stream(&mut out_ctx, &attachment.filename, &attachment.mimetype, &attachment.data)
+                        .with_context(|| format!("Failed to attach {}", attachment.filename))?;
+                }
+            } else {
+                status!("Warning: attachments are only supported in MKV output, leaving them out");
+            }
+        }
+        let sidecar_audio = match &options.sidecar_audio_path {
+            Some(p) => Some(SidecarAudio::new(p, &options)?),
+            None => None
+        };
 
         Ok(Self {
             options,
@@ -135,24 +224,72 @@ impl VideoBuilder {
             v_swc_ctx,
             v_sws_ctx,
             v_frame_buf: VecDeque::new(),
+            v_queue_peak: 0,
             v_stream_idx,
             v_pts: 0,
             v_pts_muxed: 0,
             a_encoder,
             a_swr_ctx,
+            a_resampler,
+            a_sample_buf: Vec::new(),
             a_frame_buf: VecDeque::new(),
+            a_queue_peak: 0,
             a_stream_idx,
             a_frame_size,
             a_pts: 0,
-            a_pts_muxed: 0
+            a_pts_muxed: 0,
+            cover_art,
+            sidecar_audio,
+            mux_stalls: 0,
+            mux_stall_time: Duration::ZERO,
+            longest_mux_time: Duration::ZERO
         })
     }
 
-    fn create_video_encoder(options: VideoOptions, out_ctx: &mut format::context::Output) -> Result<(encoder::Video, usize)> {
+    // Opens the encoder on its own without adding a stream, since a stream can't be taken back out
+    // of the output if the encoder fails
+    fn video_encoder_opens(codec: Codec, options: &VideoOptions, output_format: format::Pixel) -> bool {
+        let mut context = match ffmpeg_create_context(codec, codec::Parameters::new()).map(|c| c.encoder().video()) {
+            Ok(Ok(context)) => context,
+            _ => return false
+        };
+        context.set_format(output_format);
+        context.set_width(options.resolution_out.0);
+        context.set_height(options.resolution_out.1);
+        context.set_time_base(options.video_time_base);
+
+        let mut context_options = Dictionary::new();
+        for (k, v) in options.video_codec_params.iter() {
+            context_options.set(k.as_str(), v.as_str());
+        }
+        context.open_as_with(codec, context_options).is_ok()
+    }
+
+    fn create_video_encoder(mut options: VideoOptions, out_ctx: &mut format::context::Output) -> Result<(encoder::Video, usize)> {
         let global_header = out_ctx.format().flags().contains(format::Flags::GLOBAL_HEADER);
         let output_format = format::Pixel::from_str(&options.pixel_format_out).vb_unwrap()?;
-        let codec = encoder::find_by_name(&options.video_codec)
-            .with_context(|| format!("Unknown codec {}", options.video_codec))?;
+        let hardware_family = HARDWARE_ENCODER_SUFFIXES.iter()
+            .find_map(|suffix| options.video_codec.strip_suffix(suffix));
+        let codec = encoder::find_by_name(&options.video_codec);
+
+        // Hardware encoders are missing from some FFmpeg builds and fail to open when the driver or GPU
+        // is missing, so shared presets still render on other machines with the equivalent software encoder
+        let codec = match hardware_family {
+            Some(family) if !codec.map_or(false, |c| Self::video_encoder_opens(c, &options, output_format)) => {
+                let fallback = software_encoder(family)
+                    .and_then(encoder::find_by_name)
+                    .with_context(|| format!("Hardware encoder {} is unavailable and there is no software encoder to fall back to", options.video_codec))?;
+                status!(
+                    "Warning: hardware encoder {} is unavailable, falling back to {}. Codec parameters meant for {} are left out.",
+                    options.video_codec,
+                    fallback.name(),
+                    options.video_codec
+                );
+                options.video_codec_params.clear();
+                fallback
+            },
+            _ => codec.with_context(|| format!("Unknown codec {}", options.video_codec))?
+        };
 
         let mut stream = out_ctx.add_stream(codec).vb_unwrap()?;
         let mut context = ffmpeg_create_context(codec, stream.parameters())?
@@ -163,8 +300,8 @@ impl VideoBuilder {
         context.set_format(output_format);
         context.set_width(options.resolution_out.0);
         context.set_height(options.resolution_out.1);
-        context.set_max_b_frames(2);
-        context.set_gop(12);
+        context.set_max_b_frames(options.max_b_frames);
+        context.set_gop(options.gop_size);
         context.set_time_base(options.video_time_base);
 
         let mut flags = codec::Flags::empty();
@@ -185,6 +322,9 @@ impl VideoBuilder {
                 context_options.set("preset", "veryfast");
                 context_options.set("crf", "20");
                 context_options.set("tune", "film");
+                // The piano roll scrolls continuously, so scene-cut detection only wastes bits on
+                // extra keyframes. Rely on the fixed GOP size for seek points instead.
+                context_options.set("sc_threshold", "0");
             },
             _ => ()
         };
@@ -244,4 +384,21 @@ impl VideoBuilder {
 
         Ok((a_encoder, a_stream_idx, a_frame_size))
     }
+
+    fn create_cover_art_stream(path: &str, out_ctx: &mut format::context::Output) -> Result<(usize, Vec<u8>)> {
+        let codec_id = match path.to_lowercase().rsplit_once('.') {
+            Some((_, "png")) => codec::Id::PNG,
+            Some((_, "jpg" | "jpeg")) => codec::Id::MJPEG,
+            _ => bail!("Cover art must be a PNG or JPEG image")
+        };
+        let (width, height) = image::open(path)
+            .with_context(|| format!("Failed to open cover art: {}", path))?
+            .dimensions();
+        let data = fs::read(path)
+            .with_context(|| format!("Failed to read cover art: {}", path))?;
+
+        let stream_idx = ffmpeg_add_attached_pic_stream(out_ctx, codec_id, width, height)?;
+
+        Ok((stream_idx, data))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use anyhow::{Result, Context, bail};
+use ffmpeg_next::{format, encoder, ChannelLayout, frame, Packet, Rational};
+use super::video_options::VideoOptions;
+use super::vb_unwrap::VideoBuilderUnwrap;
+use super::ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_sample_format_from_string, ffmpeg_get_audio_context_frame_size};
+
+// Lossless copy of the mix, written from the same samples as the video's audio stream
+pub struct SidecarAudio {
+    out_ctx: format::context::Output,
+    encoder: encoder::Audio,
+    stream_idx: usize,
+    time_base: Rational,
+    sample_format: format::Sample,
+    channel_layout: ChannelLayout,
+    sample_rate: i32,
+    frame_size: usize,
+    sample_buf: Vec<u8>,
+    pts: i64
+}
+
+impl SidecarAudio {
+    pub fn new(path: &str, options: &VideoOptions) -> Result<Self> {
+        let codec_name = match path.to_lowercase().rsplit_once('.') {
+            Some((_, "wav")) => "pcm_s16le",
+            Some((_, "flac")) => "flac",
+            _ => bail!("Sidecar audio must be a WAV or FLAC file")
+        };
+
+        let mut out_ctx = format::output(&path).vb_unwrap()?;
+        let sample_format = ffmpeg_sample_format_from_string(&options.sample_format_in);
+        let channel_layout = ChannelLayout::default(options.audio_channels);
+        let codec = encoder::find_by_name(codec_name)
+            .with_context(|| format!("Unknown codec {}", codec_name))?;
+
+        let mut stream = out_ctx.add_stream(codec).vb_unwrap()?;
+        let mut context = ffmpeg_create_context(codec, stream.parameters())?
+            .encoder()
+            .audio()
+            .vb_unwrap()?;
+
+        context.set_rate(options.sample_rate);
+        context.set_format(sample_format);
+        context.set_channels(options.audio_channels);
+        context.set_channel_layout(channel_layout);
+        context.set_time_base(options.audio_time_base);
+
+        ffmpeg_copy_codec_params(&mut stream, &context, &codec)?;
+
+        stream.set_time_base(options.audio_time_base);
+
+        let encoder = context.open_as(codec).vb_unwrap()?;
+        let stream_idx = stream.index();
+
+        ffmpeg_copy_context_params(&mut stream, encoder.as_ref())?;
+
+        // FLAC only knows its frame size once the encoder is open
+        let frame_size = ffmpeg_get_audio_context_frame_size(&encoder, 1024);
+
+        Ok(Self {
+            out_ctx,
+            encoder,
+            stream_idx,
+            time_base: options.audio_time_base,
+            sample_format,
+            channel_layout,
+            sample_rate: options.sample_rate,
+            frame_size,
+            sample_buf: Vec::new(),
+            pts: 0
+        })
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        self.channel_layout.channels() as usize * self.sample_format.bytes()
+    }
+
+    fn encode_samples(&mut self, byte_count: usize) -> Result<()> {
+        let samples = byte_count / self.bytes_per_sample();
+
+        let mut audio_frame = frame::Audio::new(self.sample_format, samples, self.channel_layout);
+        audio_frame.set_rate(self.sample_rate as _);
+        audio_frame.data_mut(0)[..byte_count].copy_from_slice(&self.sample_buf[..byte_count]);
+        audio_frame.set_pts(Some(self.pts));
+        self.sample_buf.drain(..byte_count);
+
+        self.encoder.send_frame(&audio_frame).vb_unwrap()?;
+        self.pts += samples as i64;
+
+        self.mux_packets()
+    }
+
+    fn mux_packets(&mut self) -> Result<()> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            let out_time_base = self.out_ctx.stream(self.stream_idx)
+                .unwrap()
+                .time_base();
+
+            packet.rescale_ts(self.time_base, out_time_base);
+            packet.set_stream(self.stream_idx);
+            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.out_ctx.write_header().vb_unwrap()
+    }
+
+    pub fn push_audio_data(&mut self, audio: &[u8]) -> Result<()> {
+        self.sample_buf.extend_from_slice(audio);
+
+        let frame_bytes = self.frame_size * self.bytes_per_sample();
+        while self.sample_buf.len() >= frame_bytes {
+            self.encode_samples(frame_bytes)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.sample_buf.is_empty() {
+            self.encode_samples(self.sample_buf.len())?;
+        }
+
+        self.encoder.send_eof().vb_unwrap()?;
+        self.mux_packets()?;
+
+        self.out_ctx.write_trailer().vb_unwrap()
+    }
+}
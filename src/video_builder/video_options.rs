@@ -1,12 +1,110 @@
+use anyhow::{Result, bail};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
 use ffmpeg_next::Rational;
+use serde::{Deserialize, Serialize};
 
+// Audio codec and sample format for each container that can be written without a video stream
+const AUDIO_ONLY_FORMATS: [(&str, &str, &str); 4] = [
+    ("flac", "flac", "s16"),
+    ("wav", "pcm_s16le", "s16"),
+    ("mp3", "libmp3lame", "fltp"),
+    ("ogg", "libvorbis", "fltp")
+];
+
+pub fn audio_only_format(path: &str) -> Option<(&'static str, &'static str)> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    AUDIO_ONLY_FORMATS.iter()
+        .find(|(e, _, _)| *e == extension)
+        .map(|(_, codec, sample_format)| (*codec, *sample_format))
+}
+
+// Video codec and pixel format that keep the alpha channel, for each container that has one
+const TRANSPARENT_FORMATS: [(&str, &str, &str); 4] = [
+    ("mov", "prores_ks", "yuva444p10le"),
+    ("webm", "libvpx-vp9", "yuva420p"),
+    ("mkv", "libvpx-vp9", "yuva420p"),
+    ("png", "png", "rgba")
+];
+
+pub fn transparent_format(path: &str) -> Option<(&'static str, &'static str)> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    TRANSPARENT_FORMATS.iter()
+        .find(|(e, _, _)| *e == extension)
+        .map(|(_, codec, pixel_format)| (*codec, *pixel_format))
+}
+
+// How audio pushed at a higher rate than the output's gets brought down to it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Resampler {
+    // FFmpeg's own resampler with the shortest filter, fast but lets some aliasing through
+    Linear,
+    // SoX's windowed sinc resampler, or FFmpeg's own sinc filter when FFmpeg is built without libsoxr
+    Sinc
+}
+
+impl Resampler {
+    // libswresample options for the resampler
+    pub fn swr_options(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Resampler::Linear => &[("filter_size", "1"), ("linear_interp", "1")],
+            Resampler::Sinc => &[("resampler", "soxr"), ("precision", "28")]
+        }
+    }
+
+    // Options to try when the first ones can't be set up, e.g. without libsoxr
+    pub fn fallback_swr_options(&self) -> Option<&'static [(&'static str, &'static str)]> {
+        match self {
+            Resampler::Linear => None,
+            Resampler::Sinc => Some(&[("resampler", "swr"), ("filter_size", "64"), ("phase_shift", "10")])
+        }
+    }
+}
+
+impl Display for Resampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resampler::Linear => write!(f, "linear"),
+            Resampler::Sinc => write!(f, "sinc")
+        }
+    }
+}
+
+impl FromStr for Resampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Resampler::Linear),
+            "sinc" => Ok(Resampler::Sinc),
+            _ => Err(format!("Unknown resampler {}. Valid resamplers are 'linear' and 'sinc'.", s))
+        }
+    }
+}
+
+// A file stored in the output next to the streams. Only Matroska supports these.
 #[derive(Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub mimetype: String,
+    pub data: Vec<u8>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VideoOptions {
     pub output_path: String,
     pub metadata: HashMap<String, String>,
     pub background_path: Option<String>,
+    pub cover_art_path: Option<String>,
+    pub sidecar_audio_path: Option<String>,
+    // Filled in by the renderer for each render, so not saved
+    #[serde(skip)]
+    pub attachments: Vec<Attachment>,
 
+    #[serde(with = "rational")]
     pub video_time_base: Rational,
     pub video_codec: String,
     pub video_codec_params: HashMap<String, String>,
@@ -14,12 +112,140 @@ pub struct VideoOptions {
     pub pixel_format_out: String,
     pub resolution_in: (u32, u32),
     pub resolution_out: (u32, u32),
+    pub gop_size: u32,
+    pub max_b_frames: usize,
 
+    #[serde(with = "rational")]
     pub audio_time_base: Rational,
     pub audio_codec: String,
     pub audio_codec_params: HashMap<String, String>,
     pub audio_channels: i32,
     pub sample_format_in: String,
     pub sample_format_out: String,
-    pub sample_rate: i32
-}
\ No newline at end of file
+    pub sample_rate: i32,
+    // The rate audio is pushed at and how it's resampled, when it's higher than sample_rate.
+    // Filled in by the renderer from its oversampling option, so not saved.
+    #[serde(skip)]
+    pub resample_from: Option<(i32, Resampler)>,
+    pub av_offset_ms: i64,
+    pub video_only: bool,
+    pub audio_only: bool,
+    // Leave out the background and pick a codec with alpha, for compositing in a video editor
+    pub transparent: bool
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            output_path: "".to_string(),
+            metadata: Default::default(),
+            background_path: None,
+            cover_art_path: None,
+            sidecar_audio_path: None,
+            attachments: Vec::new(),
+            video_time_base: (29_781, 1_789_773).into(),
+            video_codec: "libx264".to_string(),
+            video_codec_params: Default::default(),
+            pixel_format_in: "rgba".to_string(),
+            pixel_format_out: "yuv420p".to_string(),
+            resolution_in: (960, 540),
+            resolution_out: (1920, 1080),
+            // 2 seconds at 60 FPS
+            gop_size: 120,
+            max_b_frames: 2,
+            audio_time_base: (1, 44_100).into(),
+            audio_codec: "aac".to_string(),
+            audio_codec_params: Default::default(),
+            audio_channels: 1,
+            sample_format_in: "s16".to_string(),
+            sample_format_out: "fltp".to_string(),
+            sample_rate: 44_100,
+            resample_from: None,
+            av_offset_ms: 0,
+            video_only: false,
+            audio_only: false,
+            transparent: false
+        }
+    }
+}
+
+impl VideoOptions {
+    // Audio file extensions leave out the video stream even without the audio_only option
+    pub fn is_audio_only(&self) -> bool {
+        self.audio_only || audio_only_format(&self.output_path).is_some()
+    }
+
+    // Picks the codecs the output path and flags call for, before anything is encoded
+    pub fn apply_output_format(&mut self) -> Result<()> {
+        if self.is_audio_only() {
+            self.apply_audio_only_format()
+        } else if self.transparent {
+            self.apply_transparent_format()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Picks the audio codec for an audio-only output's extension
+    fn apply_audio_only_format(&mut self) -> Result<()> {
+        let (codec, sample_format) = match audio_only_format(&self.output_path) {
+            Some(format) => format,
+            None => bail!("Audio-only output must be a .flac, .wav, .mp3 or .ogg file")
+        };
+        if self.video_only {
+            bail!("Output can't be both video-only and audio-only");
+        }
+
+        self.audio_only = true;
+        self.audio_codec = codec.to_string();
+        self.sample_format_out = sample_format.to_string();
+        Ok(())
+    }
+
+    // Picks a codec and pixel format with alpha for a transparent output's extension
+    fn apply_transparent_format(&mut self) -> Result<()> {
+        let (codec, pixel_format) = match transparent_format(&self.output_path) {
+            Some(format) => format,
+            None => bail!("Transparent output must be a .mov, .webm or .mkv file, or a numbered PNG sequence like frame_%05d.png")
+        };
+
+        self.video_codec = codec.to_string();
+        self.pixel_format_out = pixel_format.to_string();
+        match codec {
+            "prores_ks" => {
+                // -c:v prores_ks -profile:v 4 -bits_per_mb 1000
+                self.video_codec_params.insert("profile".to_string(), "4".to_string());
+                self.video_codec_params.insert("bits_per_mb".to_string(), "1000".to_string());
+                // Approximate the frame rate with a timebase denominator <100000.
+                // Required to avoid "codec timebase is very high" warning from the QuickTime encoder.
+                let frame_rate = self.video_time_base.denominator() as f64 / self.video_time_base.numerator() as f64;
+                let numerator = (99_999.0 / frame_rate).floor() as i32;
+                self.video_time_base = (numerator, (frame_rate * numerator as f64).round() as i32).into();
+            },
+            "png" => {
+                if !self.output_path.contains('%') {
+                    bail!("PNG sequences need a frame number in the file name, like frame_%05d.png");
+                }
+                // Image sequences can't hold audio or cover art, the audio can go in a sidecar file
+                self.video_only = true;
+                self.cover_art_path = None;
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+// Time bases are stored as [numerator, denominator]
+mod rational {
+    use ffmpeg_next::Rational;
+    use serde::{Deserialize, Deserializer, Serializer, Serialize};
+
+    pub fn serialize<S: Serializer>(value: &Rational, serializer: S) -> Result<S::Ok, S::Error> {
+        (value.numerator(), value.denominator()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rational, D::Error> {
+        <(i32, i32)>::deserialize(deserializer).map(Rational::from)
+    }
+}